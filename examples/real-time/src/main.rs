@@ -50,7 +50,7 @@ impl SynthBackend {
             let font = oxisynth::SoundFont::load(&mut file).unwrap();
 
             synth.add_font(font, true);
-            synth.set_sample_rate(sample_rate);
+            synth.set_sample_rate(sample_rate).unwrap();
             synth.set_gain(1.0);
 
             synth
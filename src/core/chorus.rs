@@ -1,285 +1,409 @@
 mod public;
 pub use public::*;
 
+use super::resampler::hermite;
+use super::settings::MAX_BLOCK_SIZE;
+use super::snapshot::snapshot;
+use super::utils::{Ramp, EFFECT_RAMP_MS};
+
 const MIN_SPEED_HZ: f32 = 0.29;
+const MAX_SPEED_HZ: f32 = 5.0;
+const MAX_VOICES: u32 = 99;
 
-/* Length of one delay line in samples:
- * Set through MAX_SAMPLES_LN2.
- * For example:
- * MAX_SAMPLES_LN2=12
- * => MAX_SAMPLES=pow(2,12)=4096
- * => MAX_SAMPLES_ANDMASK=4095
- */
-const MAX_SAMPLES_LN2: usize = 12;
-const MAX_SAMPLES: usize = 1 << (MAX_SAMPLES_LN2 - 1);
-// const MAX_SAMPLES_ANDMASK: usize = MAX_SAMPLES - 1;
+/// Length of the delay line, the longest delay is a few samples shorter
+const MAX_DELAY_MS: f32 = 100.0;
+/// Shortest delay in samples, keeping the interpolation taps in the past
+const MIN_DELAY: f32 = 2.0;
+/// Longest modulation depth of the flanger
+const FLANGER_MAX_DEPTH_MS: f32 = 10.0;
+
+/// Rate and depth of the second LFO of the ensemble, relative to the main one
+const ENSEMBLE_RATE: f32 = 6.3;
+const ENSEMBLE_DEPTH: f32 = 0.2;
+
+impl ChorusMode {
+    /// LFO value at `phase` (0-1), in the -1..1 range
+    fn lfo(self, phase: f32) -> f32 {
+        match self {
+            ChorusMode::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
+            ChorusMode::Triangle => 4.0 * (phase - 0.5).abs() - 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ChorusVoice {
+    /// LFO phase, in the 0-1 range
+    phase: f32,
+    /// Phase of the second LFO of the ensemble
+    phase2: f32,
+    /// 1 for the playing voices, fades to 0 for the removed ones
+    gain: Ramp,
+}
 
+snapshot!(ChorusVoice {
+    phase,
+    phase2,
+    gain
+});
+
+/**
+Chorus on the chorus send bus
+
+A floating point delay line read by up to 99 voices, each with its own
+LFO phase and stereo position.
+ */
 #[derive(Clone)]
 pub struct Chorus {
     active: bool,
-
-    type_0: ChorusMode,
-    new_type: ChorusMode,
-    depth_ms: f32,
-    new_depth_ms: f32,
-    level: f32,
-    new_level: f32,
-    speed_hz: f32,
-    new_speed_hz: f32,
-    number_blocks: u32,
-    new_number_blocks: u32,
-    chorusbuf: [f32; MAX_SAMPLES],
-    counter: i32,
-    phase: [isize; 99],
-    modulation_period_samples: isize,
-    lookup_tab: Vec<i32>,
+    params: ChorusParams,
     sample_rate: f32,
-    sinc_table: [[f32; 128]; 5],
+    ramp_time: f32,
+
+    buffer: Vec<f32>,
+    pos: usize,
+    voices: Vec<ChorusVoice>,
+    /// Number of voices to process, including the fading out ones
+    voice_count: usize,
+    feedback_sample: f32,
+
+    depth: Ramp,
+    level: Ramp,
+    spread: Ramp,
+    feedback: Ramp,
 }
 
+// The delay line, the LFOs and the parameter ramps
+snapshot!(Chorus {
+    buffer,
+    pos,
+    voices,
+    voice_count,
+    feedback_sample,
+    depth,
+    level,
+    spread,
+    feedback,
+});
+
 impl Chorus {
     pub(crate) fn new(sample_rate: f32, active: bool) -> Self {
+        let len = (MAX_DELAY_MS / 1000.0 * sample_rate) as usize;
+
         let mut chorus = Self {
             active,
-
-            type_0: ChorusMode::Sine,
-            new_type: ChorusMode::Sine,
-            depth_ms: 0f32,
-            new_depth_ms: 0f32,
-            level: 0f32,
-            new_level: 0f32,
-            speed_hz: 0f32,
-            new_speed_hz: 0f32,
-            number_blocks: 0,
-            new_number_blocks: 0,
-            chorusbuf: [0.0; MAX_SAMPLES],
-            counter: 0,
-            phase: [0; 99],
-            modulation_period_samples: 0,
-            lookup_tab: vec![0; (sample_rate / MIN_SPEED_HZ) as usize],
+            params: ChorusParams::default(),
             sample_rate,
-            sinc_table: [[0f32; 128]; 5],
-        };
-        for i in 0..5 {
-            for ii in 0..((1 as i32) << 8 as i32 - 1 as i32) {
-                let i_shifted: f64 = i as f64 - 5 as i32 as f64 / 2.0f64
-                    + ii as f64 / ((1 as i32) << 8 as i32 - 1 as i32) as f64;
-                if f64::abs(i_shifted) < 0.000001f64 {
-                    chorus.sinc_table[i as usize][ii as usize] = 1.0f32
-                } else {
-                    chorus.sinc_table[i as usize][ii as usize] =
-                        (f64::sin(i_shifted * std::f64::consts::PI) as f32 as f64
-                            / (std::f64::consts::PI * i_shifted)) as f32;
-                    chorus.sinc_table[i as usize][ii as usize] =
-                        (chorus.sinc_table[i as usize][ii as usize] as f64
-                            * (0.5f64
-                                * (1.0f64
-                                    + f64::cos(
-                                        2.0f64 * std::f64::consts::PI * i_shifted
-                                            / 5 as i32 as f32 as f64,
-                                    )))) as f32
-                }
-            }
-        }
-        chorus.init();
+            ramp_time: EFFECT_RAMP_MS,
 
+            buffer: vec![0.0; len.next_power_of_two()],
+            pos: 0,
+            voices: vec![
+                ChorusVoice {
+                    phase: 0.0,
+                    phase2: 0.0,
+                    gain: Ramp::new(0.0),
+                };
+                MAX_VOICES as usize
+            ],
+            voice_count: 0,
+            feedback_sample: 0.0,
+
+            depth: Ramp::new(0.0),
+            level: Ramp::new(0.0),
+            spread: Ramp::new(0.0),
+            feedback: Ramp::new(0.0),
+        };
+        chorus.set_chorus(&Default::default());
+        chorus.reset();
         chorus
     }
 
-    pub(crate) fn init(&mut self) {
-        // Init
-        {
-            for i in 0..((1 as i32) << 12 as i32 - 1 as i32) {
-                self.chorusbuf[i as usize] = 0.0f32;
-            }
-            self.set_chorus(&Default::default());
-            self.update();
-        }
-    }
+    /**
+    Validate the new parameters and ramp the continuous ones to their new value.
+     */
+    fn update(&mut self, params: &ChorusParams) {
+        let mut params = *params;
 
-    pub(crate) fn update(&mut self) {
-        let mut modulation_depth_samples: i32;
-        if self.new_number_blocks > 99 {
+        if params.nr > MAX_VOICES {
             log::warn!(
                 "chorus: number blocks larger than max. allowed! Setting value to {}.",
-                99
+                MAX_VOICES
             );
-            self.new_number_blocks = 99;
+            params.nr = MAX_VOICES;
         }
-        if (self.new_speed_hz as f64) < 0.29f64 {
+        if params.speed < MIN_SPEED_HZ {
             log::warn!(
                 "chorus: speed is too low (min {})! Setting value to min.",
-                0.29f64
+                MIN_SPEED_HZ
             );
-            self.new_speed_hz = 0.29f32
-        } else if self.new_speed_hz > 5 as i32 as f32 {
+            params.speed = MIN_SPEED_HZ;
+        } else if params.speed > MAX_SPEED_HZ {
             log::warn!(
                 "chorus: speed must be below {} Hz! Setting value to max.",
-                5
+                MAX_SPEED_HZ
             );
-            self.new_speed_hz = 5 as i32 as f32
+            params.speed = MAX_SPEED_HZ;
         }
-        if (self.new_depth_ms as f64) < 0.0f64 {
+        if params.depth < 0.0 {
             log::warn!("chorus: depth must be positive! Setting value to 0.",);
-            self.new_depth_ms = 0.0f32
+            params.depth = 0.0;
         }
-        if (self.new_level as f64) < 0.0f64 {
+        if params.level < 0.0 {
             log::warn!("chorus: level must be positive! Setting value to 0.",);
-            self.new_level = 0.0f32
-        } else if self.new_level > 10 as i32 as f32 {
+            params.level = 0.0;
+        } else if params.level > 10.0 {
             log::warn!(
                 "chorus: level must be < 10. A reasonable level is << 1! Setting it to 0.1.",
             );
-            self.new_level = 0.1f32
+            params.level = 0.1;
         }
-        self.modulation_period_samples = (self.sample_rate / self.new_speed_hz) as isize;
-        modulation_depth_samples =
-            (self.new_depth_ms as f64 / 1000.0f64 * self.sample_rate as f64) as i32;
-        if modulation_depth_samples > (1 as i32) << 12 as i32 - 1 as i32 {
-            log::warn!(
-                "chorus: Too high depth. Setting it to max ({}).",
-                (1) << 12 - 1
-            );
-            modulation_depth_samples = (1 as i32) << 12 as i32 - 1 as i32
-        }
-        if self.type_0 == ChorusMode::Sine {
-            modulate_sine(
-                &mut self.lookup_tab,
-                self.modulation_period_samples as usize,
-                modulation_depth_samples,
-            );
-        } else if self.type_0 == ChorusMode::Triangle {
-            modulate_triangle(
-                &mut self.lookup_tab,
-                self.modulation_period_samples as i32,
-                modulation_depth_samples,
-            );
-        } else {
-            log::warn!("chorus: Unknown modulation type. Using sinewave.",);
-            self.type_0 = ChorusMode::Sine;
-            modulate_sine(
-                &mut self.lookup_tab,
-                self.modulation_period_samples as usize,
-                modulation_depth_samples,
-            );
+        params.spread = params.spread.clamp(0.0, 1.0);
+        params.feedback = params.feedback.clamp(-0.95, 0.95);
+
+        let max_depth_ms = match params.ty {
+            ChorusType::Flanger => FLANGER_MAX_DEPTH_MS,
+            _ => MAX_DELAY_MS,
+        };
+        let max_depth = (self.buffer.len() - 4) as f32 - MIN_DELAY;
+        let mut depth = params.depth.min(max_depth_ms) / 1000.0 * self.sample_rate;
+        if depth > max_depth {
+            log::warn!("chorus: Too high depth. Setting it to max ({}).", max_depth);
+            depth = max_depth;
         }
-        for i in 0..(self.number_blocks as usize) {
-            self.phase[i] = (self.modulation_period_samples as f64 * i as f64
-                / self.number_blocks as f64) as i32 as isize;
+
+        let len = Ramp::samples(self.ramp_time, self.sample_rate);
+        self.depth.set(depth, len);
+        self.level.set(params.level, len);
+        let spread = match params.ty {
+            ChorusType::Vibrato => 0.0,
+            _ => params.spread,
+        };
+        self.spread.set(spread, len);
+        self.feedback.set(params.feedback, len);
+
+        // Fade the voices in and out, new ones start evenly spaced after the first one
+        let count = params.voice_count();
+        let first_phase = self.voices[0].phase;
+        for (id, voice) in self.voices.iter_mut().enumerate() {
+            let playing = id < count;
+            if playing && voice.gain.value() == 0.0 {
+                voice.phase = (first_phase + id as f32 / count as f32).fract();
+                voice.phase2 = voice.phase;
+            }
+            voice.gain.set(if playing { 1.0 } else { 0.0 }, len);
         }
-        self.counter = 0 as i32;
-        self.type_0 = self.new_type;
-        self.depth_ms = self.new_depth_ms;
-        self.level = self.new_level;
-        self.speed_hz = self.new_speed_hz;
-        self.number_blocks = self.new_number_blocks;
+        self.voice_count = self.voice_count.max(count);
+
+        self.params = params;
     }
 
     pub(crate) fn process_mix(
         &mut self,
-        in_0: &mut [f32; 64],
-        left_out: &mut [f32; 64],
-        right_out: &mut [f32; 64],
+        in_0: &mut [f32],
+        left_out: &mut [f32],
+        right_out: &mut [f32],
     ) {
-        for sample_index in 0..64 {
-            let d_in = in_0[sample_index as usize];
-            let mut d_out = 0.0f32;
-            self.chorusbuf[self.counter as usize] = d_in;
-
-            for i in 0..(self.number_blocks as usize) {
-                let mut pos_subsamples: i32 = ((1 as i32) << 8 as i32 - 1 as i32) * self.counter
-                    - self.lookup_tab[self.phase[i as usize] as usize];
-                let mut pos_samples: i32 = pos_subsamples / ((1 as i32) << 8 as i32 - 1 as i32);
-                pos_subsamples &= ((1 as i32) << 8 as i32 - 1 as i32) - 1 as i32;
-
-                for ii in 0..5 {
-                    d_out += self.chorusbuf
-                        [(pos_samples & ((1 as i32) << 12 as i32 - 1 as i32) - 1 as i32) as usize]
-                        * self.sinc_table[ii as usize][pos_subsamples as usize];
-                    pos_samples -= 1;
+        let mask = self.buffer.len() - 1;
+        let incr = self.params.speed / self.sample_rate;
+        let mode = self.params.mode;
+        let ensemble = self.params.ty == ChorusType::Ensemble;
+        let count = self.params.voice_count();
+        let lfo_scale = if ensemble {
+            1.0 / (1.0 + ENSEMBLE_DEPTH)
+        } else {
+            1.0
+        };
+
+        for sample_index in 0..in_0.len() {
+            let depth = self.depth.step();
+            let level = self.level.step();
+            let spread = self.spread.step();
+            let feedback = self.feedback.step();
+
+            self.buffer[self.pos] = in_0[sample_index] + self.feedback_sample * feedback;
+
+            let mut left = 0.0;
+            let mut right = 0.0;
+            let mut mono = 0.0;
+            for (id, voice) in self.voices[..self.voice_count].iter_mut().enumerate() {
+                let gain = voice.gain.step();
+
+                let mut lfo = mode.lfo(voice.phase);
+                if ensemble {
+                    lfo += ENSEMBLE_DEPTH * ChorusMode::Sine.lfo(voice.phase2);
+                    voice.phase2 = (voice.phase2 + incr * ENSEMBLE_RATE).fract();
                 }
+                voice.phase = (voice.phase + incr).fract();
 
-                self.phase[i as usize] += 1;
-                self.phase[i as usize] %= self.modulation_period_samples;
+                let delay = MIN_DELAY + depth * (1.0 + lfo * lfo_scale) / 2.0;
+                let read = (self.pos + self.buffer.len()) as f32 - delay;
+                let id0 = read as usize;
+                let t = read - id0 as f32;
+                let out = hermite(
+                    self.buffer[id0.wrapping_sub(1) & mask],
+                    self.buffer[id0 & mask],
+                    self.buffer[(id0 + 1) & mask],
+                    self.buffer[(id0 + 2) & mask],
+                    t,
+                ) * gain;
+
+                // Voices are spread evenly from left to right
+                let pan = if count > 1 {
+                    spread * (2.0 * id as f32 / (count - 1) as f32 - 1.0)
+                } else {
+                    0.0
+                };
+                left += out * (1.0 - pan).min(1.0);
+                right += out * (1.0 + pan).min(1.0);
+                mono += out;
             }
 
-            d_out *= self.level;
+            self.feedback_sample = if count > 0 { mono / count as f32 } else { 0.0 };
+            self.pos = (self.pos + 1) & mask;
 
-            left_out[sample_index] += d_out;
-            right_out[sample_index] += d_out;
+            left_out[sample_index] += left * level;
+            right_out[sample_index] += right * level;
+        }
 
-            self.counter += 1;
-            self.counter %= (1 as i32) << 12 as i32 - 1 as i32;
+        // Stop processing the faded out voices
+        while self.voice_count > count && self.voices[self.voice_count - 1].gain.value() == 0.0 {
+            self.voice_count -= 1;
         }
     }
 
-    pub(crate) fn process_replace(&mut self, left_out: &mut [f32; 64], right_out: &mut [f32; 64]) {
-        for sample_index in 0..64 {
-            // Don't ask me why only left buf is considered an input...
-            let d_in = left_out[sample_index];
-            let mut d_out = 0.0f32;
-
-            self.chorusbuf[self.counter as usize] = d_in;
-
-            for i in 0..(self.number_blocks as usize) {
-                let mut pos_subsamples: i32 = ((1 as i32) << 8 as i32 - 1 as i32) * self.counter
-                    - self.lookup_tab[self.phase[i as usize] as usize];
-                let mut pos_samples: i32 = pos_subsamples / ((1 as i32) << 8 as i32 - 1 as i32);
-                pos_subsamples &= ((1 as i32) << 8 as i32 - 1 as i32) - 1 as i32;
-
-                for ii in 0..5 {
-                    d_out += self.chorusbuf
-                        [(pos_samples & ((1 as i32) << 12 as i32 - 1 as i32) - 1 as i32) as usize]
-                        * self.sinc_table[ii as usize][pos_subsamples as usize];
-                    pos_samples -= 1;
-                }
+    pub(crate) fn process_replace(&mut self, left_out: &mut [f32], right_out: &mut [f32]) {
+        // Don't ask me why only left buf is considered an input...
+        let mut input = [0f32; MAX_BLOCK_SIZE];
+        let input = &mut input[..left_out.len()];
+        input.copy_from_slice(left_out);
+        left_out.iter_mut().for_each(|v| *v = 0.0);
+        right_out.iter_mut().for_each(|v| *v = 0.0);
 
-                self.phase[i] += 1;
-                self.phase[i] %= self.modulation_period_samples;
-            }
-            d_out *= self.level;
+        self.process_mix(input, left_out, right_out);
+    }
 
-            left_out[sample_index as usize] = d_out;
-            right_out[sample_index as usize] = d_out;
+    /**
+    Clear the delay line and jump to the parameter targets.
+     */
+    /// Set the time for the parameter changes to reach their new value, in ms
+    pub(crate) fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.ramp_time = ramp_time;
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.buffer.iter_mut().for_each(|v| *v = 0.0);
+        self.feedback_sample = 0.0;
 
-            self.counter += 1;
-            self.counter %= (1 as i32) << 12 as i32 - 1 as i32;
+        for value in [
+            &mut self.depth,
+            &mut self.level,
+            &mut self.spread,
+            &mut self.feedback,
+        ] {
+            value.finish();
         }
+        for voice in self.voices.iter_mut() {
+            voice.gain.finish();
+        }
+        self.voice_count = self.params.voice_count();
     }
 
-    pub(crate) fn reset(&mut self) {
-        self.init();
+    /**
+    Rebuild the delay line for a new sample rate, keeping the chorus parameters.
+     */
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: f32) {
+        let params = self.params;
+        *self = Self {
+            ramp_time: self.ramp_time,
+            ..Self::new(sample_rate, self.active)
+        };
+        self.set_chorus(&params);
+        self.reset();
     }
 }
 
-fn modulate_sine(buf: &mut [i32], len: usize, depth: i32) {
-    let mut val: f64;
-    for i in 0..len {
-        val = f64::sin(i as f64 / len as f64 * 2.0f64 * std::f64::consts::PI);
-        buf[i] = ((1.0f64 + val) * depth as f64 / 2.0f64
-            * ((1 as i32) << 8 as i32 - 1 as i32) as f64) as i32;
-        buf[i] -=
-            3 as i32 * ((1 as i32) << 12 as i32 - 1 as i32) * ((1 as i32) << 8 as i32 - 1 as i32);
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Energy of the left and right output for a noise input
+    fn process(chorus: &mut Chorus, blocks: usize) -> (f32, f32) {
+        let mut seed = 1u32;
+        let mut energy = (0.0, 0.0);
+        for _ in 0..blocks {
+            let mut input = [0f32; 64];
+            for v in input.iter_mut() {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                *v = (seed >> 8) as f32 / (1 << 24) as f32 - 0.5;
+            }
+            let mut left = [0f32; 64];
+            let mut right = [0f32; 64];
+            chorus.process_mix(&mut input, &mut left, &mut right);
+            energy.0 += left.iter().map(|v| v * v).sum::<f32>();
+            energy.1 += right.iter().map(|v| v * v).sum::<f32>();
+        }
+        energy
+    }
+
+    #[test]
+    fn modes() {
+        let mut chorus = Chorus::new(44100.0, true);
+
+        // Without spread, both sides are the same
+        chorus.set_chorus(&ChorusParams {
+            spread: 0.0,
+            ..Default::default()
+        });
+        chorus.reset();
+        let (left, right) = process(&mut chorus, 100);
+        assert!(left > 0.0);
+        assert_eq!(left, right);
+
+        for ty in [
+            ChorusType::Chorus,
+            ChorusType::Flanger,
+            ChorusType::Ensemble,
+            ChorusType::Vibrato,
+        ] {
+            chorus.set_chorus(&ChorusParams {
+                ty,
+                spread: 1.0,
+                feedback: 0.5,
+                ..Default::default()
+            });
+            // Long enough for the removed voices to fade out
+            let (left, right) = process(&mut chorus, 200);
+            assert!(left.is_finite() && left > 0.0);
+            if ty == ChorusType::Vibrato {
+                assert_eq!(chorus.voice_count, 1);
+            } else {
+                assert!(left != right);
+            }
+        }
     }
-}
 
-fn modulate_triangle(buf: &mut [i32], len: i32, depth: i32) {
-    let mut i: i32 = 0 as i32;
-    let mut ii: i32 = len - 1 as i32;
-    let mut val: f64;
-    let mut val2: f64;
-    while i <= ii {
-        val = i as f64 * 2.0f64 / len as f64
-            * depth as f64
-            * ((1 as i32) << 8 as i32 - 1 as i32) as f64;
-        val2 = ((val + 0.5f64) as i32
-            - 3 as i32 * ((1 as i32) << 12 as i32 - 1 as i32) * ((1 as i32) << 8 as i32 - 1 as i32))
-            as f64;
-        let fresh2 = i;
-        i = i + 1;
-        buf[fresh2 as usize] = val2 as i32;
-        let fresh3 = ii;
-        ii = ii - 1;
-        buf[fresh3 as usize] = val2 as i32
+    #[test]
+    fn smoothing() {
+        let mut chorus = Chorus::new(44100.0, true);
+        let mut input = [0.5f32; 64];
+        for _ in 0..100 {
+            chorus.process_mix(&mut input, &mut [0.0; 64], &mut [0.0; 64]);
+        }
+
+        // A level change ramps in, instead of jumping
+        let params = chorus.get_chorus();
+        chorus.set_chorus(&ChorusParams {
+            level: params.level * 2.0,
+            ..params
+        });
+        let mut left = [0f32; 64];
+        chorus.process_mix(&mut input, &mut left, &mut [0.0; 64]);
+        let step = left
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0f32, f32::max);
+        assert!(step < 0.05, "{}", step);
+        assert_eq!(chorus.level(), params.level * 2.0);
     }
 }
@@ -4,6 +4,7 @@ use super::Chorus;
 Chorus type
  */
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum ChorusMode {
     Sine = 0,
@@ -16,16 +17,40 @@ impl Default for ChorusMode {
     }
 }
 
+/**
+Chorus algorithm
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChorusType {
+    /// `nr` voices with evenly spaced LFO phases
+    #[default]
+    Chorus,
+    /// Two voices with opposite LFO phases and a short delay, best with some feedback
+    Flanger,
+    /// Like the chorus, with a second faster LFO per voice, for string ensemble sounds
+    Ensemble,
+    /// A single centered voice: a pitch vibrato, when the dry signal is not heard
+    Vibrato,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChorusParams {
+    /// Number of voices (0-99)
     pub nr: u32,
     pub level: f32,
     /// Speed in Hz
     pub speed: f32,
     /// Depth in mS
     pub depth: f32,
-    /// Mode
+    /// LFO shape
     pub mode: ChorusMode,
+    pub ty: ChorusType,
+    /// Stereo spread of the voices (0-1), 0 for a mono chorus
+    pub spread: f32,
+    /// Amount of the output fed back into the delay line (-0.95-0.95)
+    pub feedback: f32,
 }
 
 impl Default for ChorusParams {
@@ -36,6 +61,20 @@ impl Default for ChorusParams {
             speed: 0.3,
             depth: 8.0,
             mode: ChorusMode::default(),
+            ty: ChorusType::default(),
+            spread: 0.5,
+            feedback: 0.0,
+        }
+    }
+}
+
+impl ChorusParams {
+    /// Number of voices the algorithm uses
+    pub(crate) fn voice_count(&self) -> usize {
+        match self.ty {
+            ChorusType::Chorus | ChorusType::Ensemble => self.nr as usize,
+            ChorusType::Flanger => 2,
+            ChorusType::Vibrato => 1,
         }
     }
 }
@@ -51,77 +90,60 @@ impl Chorus {
         self.active
     }
 
-    /// Set the current chorus nr
-    fn set_nr(&mut self, nr: u32) {
-        self.new_number_blocks = nr;
-    }
-
     /// Query the current chorus nr
     pub fn nr(&self) -> u32 {
-        self.number_blocks
-    }
-
-    /// Set the current chorus level
-    fn set_level(&mut self, level: f32) {
-        self.new_level = level;
+        self.params.nr
     }
 
     /// Query the current chorus level
     pub fn level(&self) -> f32 {
-        self.level
-    }
-
-    /// Set the current chorus speed (Hz)
-    fn set_speed_hz(&mut self, speed_hz: f32) {
-        self.new_speed_hz = speed_hz;
+        self.params.level
     }
 
     /// Query the current chorus speed (Hz)
     pub fn speed_hz(&self) -> f32 {
-        self.speed_hz
-    }
-
-    /// Set the current chorus depth (mS)
-    fn set_depth_ms(&mut self, depth_ms: f32) {
-        self.new_depth_ms = depth_ms;
+        self.params.speed
     }
 
     /// Query the current chorus depth (mS)
     pub fn depth_ms(&self) -> f32 {
-        self.depth_ms
-    }
-
-    /// Set the current chorus mode
-    fn set_mode(&mut self, mode: ChorusMode) {
-        self.new_type = mode;
+        self.params.depth
     }
 
     /// Query the current chorus mode
     pub fn mode(&self) -> ChorusMode {
-        self.type_0
+        self.params.mode
+    }
+
+    /// Query the current chorus algorithm
+    pub fn ty(&self) -> ChorusType {
+        self.params.ty
+    }
+
+    /// Query the current stereo spread
+    pub fn spread(&self) -> f32 {
+        self.params.spread
+    }
+
+    /// Query the current feedback
+    pub fn feedback(&self) -> f32 {
+        self.params.feedback
     }
 }
 
 impl Chorus {
     /**
     Set up the chorus. It should be turned on with Chorus::set_active().
-    If faulty parameters are given, all new settings are discarded.
+    Out of range parameters are clamped, the changes are smoothed out while playing.
     Keep in mind, that the needed CPU time is proportional to `nr`.
      */
     pub fn set_chorus(&mut self, params: &ChorusParams) {
-        self.set_chorus_params(
-            params.nr,
-            params.level,
-            params.speed,
-            params.depth,
-            params.mode,
-        );
+        self.update(params);
     }
 
     /**
-    Set up the chorus. It should be turned on with Chorus::set_active().
-    If faulty parameters are given, all new settings are discarded.
-    Keep in mind, that the needed CPU time is proportional to `nr`.
+    Set up the chorus, keeping the algorithm, spread and feedback.
+    It should be turned on with Chorus::set_active().
      */
     pub fn set_chorus_params(
         &mut self,
@@ -131,24 +153,20 @@ impl Chorus {
         depth_ms: f32,
         type_0: ChorusMode,
     ) {
-        self.set_nr(nr);
-        self.set_level(level);
-        self.set_speed_hz(speed);
-        self.set_depth_ms(depth_ms);
-        self.set_mode(type_0);
-        self.update();
+        self.update(&ChorusParams {
+            nr,
+            level,
+            speed,
+            depth: depth_ms,
+            mode: type_0,
+            ..self.params
+        });
     }
 
     /**
     Query the current chorus params
      */
     pub fn get_chorus(&self) -> ChorusParams {
-        ChorusParams {
-            nr: self.nr(),
-            level: self.level(),
-            speed: self.speed_hz(),
-            depth: self.depth_ms(),
-            mode: self.mode(),
-        }
+        self.params
     }
 }
@@ -0,0 +1,259 @@
+mod public;
+pub use public::*;
+
+use std::any::Any;
+
+use crate::core::effects::Effect;
+use crate::core::snapshot::{snapshot, Snapshot, StateReader, StateWriter};
+use crate::core::utils::{Ramp, EFFECT_RAMP_MS};
+
+/// Longest delay time, in ms
+const MAX_DELAY_MS: f32 = 3000.0;
+
+/**
+Stereo echo, for a send bus
+
+The channels send to it at the level of the bus controller, see
+`Effects::add_delay_bus()`. It outputs the echoes only, of the sum of both input sides.
+The echoes can be fed across the two sides for a ping-pong effect, and are
+darkened by a lowpass filter in the feedback path.
+ */
+#[derive(Clone)]
+pub struct Delay {
+    params: DelayParams,
+    tempo: f32,
+    sample_rate: f32,
+    ramp_time: f32,
+
+    lines: [Vec<f32>; 2],
+    pos: usize,
+    /// Current delay time, in samples
+    len: usize,
+    damp_state: [f32; 2],
+    feedback: Ramp,
+    level: Ramp,
+}
+
+impl Snapshot for DelayTime {
+    fn save(&self, w: &mut StateWriter) {
+        let (beats, time) = match self {
+            DelayTime::Ms(ms) => (false, ms),
+            DelayTime::Beats(beats) => (true, beats),
+        };
+        w.write_bool(beats);
+        w.write_f32(*time);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        let beats = r.read_bool();
+        let time = r.read_f32();
+        *self = if beats {
+            DelayTime::Beats(time)
+        } else {
+            DelayTime::Ms(time)
+        };
+    }
+}
+
+snapshot!(DelayParams {
+    time,
+    feedback,
+    cross_feed,
+    damping,
+    level,
+});
+
+// The running state, the parameters are the ones of the effect
+snapshot!(Delay {
+    lines,
+    pos,
+    len,
+    damp_state,
+    feedback,
+    level,
+});
+
+impl Delay {
+    pub fn new(sample_rate: f32) -> Self {
+        let size = (MAX_DELAY_MS / 1000.0 * sample_rate) as usize + 1;
+        let mut delay = Self {
+            params: DelayParams::default(),
+            tempo: 120.0,
+            sample_rate,
+            ramp_time: EFFECT_RAMP_MS,
+
+            lines: [vec![0.0; size], vec![0.0; size]],
+            pos: 0,
+            len: 1,
+            damp_state: [0.0; 2],
+            feedback: Ramp::new(DelayParams::default().feedback),
+            level: Ramp::new(DelayParams::default().level),
+        };
+        delay.update_len();
+        delay
+    }
+
+    fn update_len(&mut self) {
+        let ms = match self.params.time {
+            DelayTime::Ms(ms) => ms,
+            DelayTime::Beats(beats) => beats * 60000.0 / self.tempo,
+        };
+        let len = (ms.clamp(1.0, MAX_DELAY_MS) / 1000.0 * self.sample_rate) as usize;
+        self.len = len.clamp(1, self.lines[0].len() - 1);
+    }
+
+    /// Ramp the feedback and the level to their new value
+    fn update_gains(&mut self) {
+        let len = Ramp::samples(self.ramp_time, self.sample_rate);
+        self.feedback.set(self.params.feedback, len);
+        self.level.set(self.params.level, len);
+    }
+}
+
+impl Effect for Delay {
+    /// Keeps the delay parameters, tempo and ramp time
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        let params = self.params;
+        *self = Self {
+            params,
+            tempo: self.tempo,
+            ramp_time: self.ramp_time,
+            ..Self::new(sample_rate)
+        };
+        self.feedback = Ramp::new(params.feedback);
+        self.level = Ramp::new(params.level);
+        self.update_len();
+    }
+
+    fn reset(&mut self) {
+        self.lines
+            .iter_mut()
+            .for_each(|line| line.iter_mut().for_each(|v| *v = 0.0));
+        self.damp_state = [0.0; 2];
+        self.feedback.finish();
+        self.level.finish();
+    }
+
+    fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.ramp_time = ramp_time;
+    }
+
+    fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let DelayParams {
+            cross_feed,
+            damping,
+            ..
+        } = self.params;
+        let size = self.lines[0].len();
+
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            let input = (*left + *right) * 0.5;
+
+            let read = (self.pos + size - self.len) % size;
+            let delayed = [self.lines[0][read], self.lines[1][read]];
+
+            for (state, delayed) in self.damp_state.iter_mut().zip(delayed.iter()) {
+                *state = delayed * (1.0 - damping) + *state * damping;
+            }
+            let [l, r] = self.damp_state;
+            let feedback = self.feedback.step();
+            let level = self.level.step();
+            let fb_left = (l * (1.0 - cross_feed) + r * cross_feed) * feedback;
+            let fb_right = (r * (1.0 - cross_feed) + l * cross_feed) * feedback;
+
+            // With a full cross feed, the input only enters the left side and bounces across
+            self.lines[0][self.pos] = input + fb_left;
+            self.lines[1][self.pos] = input * (1.0 - cross_feed) + fb_right;
+            self.pos = (self.pos + 1) % size;
+
+            *left = delayed[0] * level;
+            *right = delayed[1] * level;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.save(state);
+    }
+
+    fn restore_state(&mut self, state: &mut StateReader) {
+        self.restore(state);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ping_pong() {
+        let mut delay = Delay::new(48000.0);
+        delay.set_tempo(120.0);
+        delay.set_delay(&DelayParams {
+            time: DelayTime::Beats(0.5),
+            feedback: 0.5,
+            cross_feed: 1.0,
+            damping: 0.0,
+            level: 1.0,
+        });
+
+        // An eighth note at 120 BPM is 250ms (12000 samples, 187.5 blocks)
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+        for block in 0..600 {
+            let mut l = [0f32; 64];
+            let mut r = [0f32; 64];
+            if block == 0 {
+                l[0] = 1.0;
+                r[0] = 1.0;
+            }
+            delay.process(&mut l, &mut r);
+            left.extend_from_slice(&l);
+            right.extend_from_slice(&r);
+        }
+
+        assert_eq!(left[12000], 1.0);
+        assert_eq!(right[12000], 0.0);
+        assert_eq!(right[24000], 0.5);
+        assert_eq!(left[36000], 0.25);
+        assert_eq!(left.iter().filter(|v| **v != 0.0).count(), 2);
+    }
+
+    #[test]
+    fn ramp_time() {
+        // Echo of a constant input, ~10 ms after raising the level from 0.5 to 1.0
+        let echo = |ramp_time: f32| {
+            let params = DelayParams {
+                time: DelayTime::Ms(1.0),
+                feedback: 0.0,
+                cross_feed: 0.0,
+                damping: 0.0,
+                level: 0.5,
+            };
+            let mut delay = Delay::new(48000.0);
+            delay.set_ramp_time(0.0);
+            delay.set_delay(&params);
+            delay.set_ramp_time(ramp_time);
+            delay.set_delay(&DelayParams {
+                level: 1.0,
+                ..params
+            });
+
+            let mut out = 0.0;
+            for _ in 0..8 {
+                let mut l = [1f32; 64];
+                let mut r = [1f32; 64];
+                delay.process(&mut l, &mut r);
+                out = l[63];
+            }
+            out
+        };
+
+        assert_eq!(echo(0.0), 1.0);
+        let ramped = echo(20.0);
+        assert!(ramped > 0.7 && ramped < 0.8, "{}", ramped);
+    }
+}
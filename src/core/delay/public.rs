@@ -0,0 +1,74 @@
+use super::Delay;
+
+/**
+Delay time, absolute or synced to the tempo
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DelayTime {
+    /// Time in ms
+    Ms(f32),
+    /// Note length in quarter notes at the delay tempo,
+    /// e.g. 0.5 for an eighth note, 0.75 for a dotted eighth, 1/3 for an eighth triplet.
+    Beats(f32),
+}
+
+impl Default for DelayTime {
+    fn default() -> Self {
+        DelayTime::Beats(0.75)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DelayParams {
+    pub time: DelayTime,
+    /// Level of each echo relative to the previous one (0-0.99)
+    pub feedback: f32,
+    /// Amount of the echoes fed to the opposite side, 1 for a ping-pong delay (0-1)
+    pub cross_feed: f32,
+    /// High frequency damping of the echoes (0-1)
+    pub damping: f32,
+    pub level: f32,
+}
+
+impl Default for DelayParams {
+    fn default() -> Self {
+        Self {
+            time: DelayTime::default(),
+            feedback: 0.4,
+            cross_feed: 1.0,
+            damping: 0.2,
+            level: 0.5,
+        }
+    }
+}
+
+impl Delay {
+    /// Set the tempo the `DelayTime::Beats` delay times are synced to, in BPM
+    pub fn set_tempo(&mut self, bpm: f32) {
+        self.tempo = bpm.clamp(10.0, 1000.0);
+        self.update_len();
+    }
+
+    /// Query the delay tempo, in BPM
+    pub fn tempo(&self) -> f32 {
+        self.tempo
+    }
+
+    /// Set the delay parameters
+    pub fn set_delay(&mut self, params: &DelayParams) {
+        self.params = DelayParams {
+            time: params.time,
+            feedback: params.feedback.clamp(0.0, 0.99),
+            cross_feed: params.cross_feed.clamp(0.0, 1.0),
+            damping: params.damping.clamp(0.0, 1.0),
+            level: params.level.max(0.0),
+        };
+        self.update_len();
+        self.update_gains();
+    }
+
+    /// Query the delay parameters
+    pub fn delay(&self) -> DelayParams {
+        self.params
+    }
+}
@@ -0,0 +1,521 @@
+use std::any::Any;
+
+use super::delay::Delay;
+use super::snapshot::{Snapshot, StateReader, StateWriter};
+use super::synth::Channel;
+use super::utils::{Ramp, EFFECT_RAMP_MS};
+use super::OxiError;
+
+mod compressor;
+pub use compressor::{Compressor, CompressorParams};
+
+mod equalizer;
+pub use equalizer::{EqBand, EqBandType, Equalizer};
+
+mod limiter;
+pub use limiter::{Limiter, LimiterParams};
+
+/**
+An audio effect, processing stereo blocks in place. The blocks are
+`SynthDescriptor::block_size` frames long.
+
+Implement this trait to add custom processing to the synth, as a channel
+insert, on a send bus, or on the master bus (see `Effects`).
+ */
+pub trait Effect: Send {
+    /// Adapt the effect to a new sample rate, keeping its parameters.
+    fn set_sample_rate(&mut self, sample_rate: f32);
+
+    /// Clear the internal state (delay lines, envelopes...), keeping the parameters.
+    fn reset(&mut self);
+
+    /// Set the time for the parameter changes to reach their new value, in ms.
+    fn set_ramp_time(&mut self, _ramp_time: f32) {}
+
+    /// Process a block of stereo frames in place.
+    fn process(&mut self, left: &mut [f32], right: &mut [f32]);
+
+    /// Save the internal state (delay lines, envelopes...), for `Synth::save_dsp_state()`.
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    /// Restore the internal state written by `save_state()`. Clears it by default.
+    fn restore_state(&mut self, _state: &mut StateReader) {
+        self.reset();
+    }
+
+    /// Used by `EffectChain::get_mut()` to access effect specific parameters.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/**
+Effects processed in series
+ */
+pub struct EffectChain {
+    sample_rate: f32,
+    ramp_time: f32,
+    effects: Vec<Box<dyn Effect>>,
+}
+
+impl EffectChain {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            ramp_time: EFFECT_RAMP_MS,
+            effects: Vec::new(),
+        }
+    }
+
+    /**
+    Append an effect to the end of the chain, returning its position.
+
+    The effect is set to the current sample rate and ramp time.
+     */
+    pub fn push(&mut self, mut effect: Box<dyn Effect>) -> usize {
+        effect.set_sample_rate(self.sample_rate);
+        effect.set_ramp_time(self.ramp_time);
+        self.effects.push(effect);
+        self.effects.len() - 1
+    }
+
+    /// Remove the effect at `id`, shifting the following ones down.
+    pub fn remove(&mut self, id: usize) -> Option<Box<dyn Effect>> {
+        if id < self.effects.len() {
+            Some(self.effects.remove(id))
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    /**
+    Get the effect at `id`, if it is of type `T`.
+     */
+    pub fn get_mut<T: Effect + 'static>(&mut self, id: usize) -> Option<&mut T> {
+        self.effects.get_mut(id)?.as_any_mut().downcast_mut()
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.effects
+            .iter_mut()
+            .for_each(|fx| fx.set_sample_rate(sample_rate));
+    }
+
+    fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.ramp_time = ramp_time;
+        self.effects
+            .iter_mut()
+            .for_each(|fx| fx.set_ramp_time(ramp_time));
+    }
+
+    fn reset(&mut self) {
+        self.effects.iter_mut().for_each(|fx| fx.reset());
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.effects.len() as u32);
+        for fx in self.effects.iter() {
+            state.section(|state| fx.save_state(state));
+        }
+    }
+
+    /// The chain must hold as many effects as the saved one
+    fn restore_state(&mut self, state: &mut StateReader) {
+        if state.read_u32() != self.effects.len() as u32 {
+            state.invalidate();
+        }
+        for fx in self.effects.iter_mut() {
+            state.section(|state| fx.restore_state(state));
+        }
+    }
+
+    fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for fx in self.effects.iter_mut() {
+            fx.process(left, right);
+        }
+    }
+}
+
+/**
+An additional send bus, next to the reverb and chorus ones.
+
+Each MIDI channel sends its output to the bus at the level of a MIDI
+controller, e.g. CC94 (Effects 4 / Celeste depth, used as the XG "variation" send).
+The bus effects should output the wet signal only, it is added to the main mix.
+ */
+pub struct SendBus {
+    ctrl: u8,
+    chain: EffectChain,
+    /// Send level of each MIDI channel
+    levels: Vec<Ramp>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+impl SendBus {
+    /// The controller setting the send level of the channels
+    pub fn ctrl(&self) -> u8 {
+        self.ctrl
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u8) {
+        self.ctrl = ctrl & 0x7f;
+    }
+
+    pub fn chain(&self) -> &EffectChain {
+        &self.chain
+    }
+
+    pub fn chain_mut(&mut self) -> &mut EffectChain {
+        &mut self.chain
+    }
+}
+
+/// Output of a MIDI channel, rendered apart from the others when it goes through effects
+#[derive(Clone)]
+pub(crate) struct ChannelStrip {
+    pub active: bool,
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+}
+
+/**
+User effects of the synth: per channel inserts, send buses and master bus effects.
+
+Signal flow:
+- Channels with inserts or sends are rendered separately and go through their insert chain.
+- Their output is sent to the send buses, then mixed into the output of the channel audio group.
+- The send buses are processed and added to the main mix.
+- The master chain processes the main mix, after the reverb and chorus.
+
+The reverb and chorus sends are taken from the voices, before the channel inserts.
+
+The built-in `Equalizer`, `Compressor` and `Limiter` are meant for the master
+chain, e.g. `synth.effects_mut().master_mut().push(Box::new(Limiter::new(sample_rate)))`.
+Their parameters can be changed while playing, through `EffectChain::get_mut()`,
+or the `Synth::set_master_*()` setters.
+ */
+pub struct Effects {
+    sample_rate: f32,
+    block_size: usize,
+    ramp_time: f32,
+    inserts: Vec<EffectChain>,
+    sends: Vec<SendBus>,
+    master: EffectChain,
+    strips: Vec<ChannelStrip>,
+}
+
+impl Effects {
+    pub(crate) fn new(midi_channels: usize, sample_rate: f32, block_size: usize) -> Self {
+        Self {
+            sample_rate,
+            block_size,
+            ramp_time: EFFECT_RAMP_MS,
+            inserts: (0..midi_channels)
+                .map(|_| EffectChain::new(sample_rate))
+                .collect(),
+            sends: Vec::new(),
+            master: EffectChain::new(sample_rate),
+            strips: (0..midi_channels)
+                .map(|_| ChannelStrip {
+                    active: false,
+                    left: vec![0.0; block_size],
+                    right: vec![0.0; block_size],
+                })
+                .collect(),
+        }
+    }
+
+    /// Insert effects of a MIDI channel
+    pub fn insert(&self, chan: usize) -> Result<&EffectChain, OxiError> {
+        self.inserts.get(chan).ok_or(OxiError::ChannelOutOfRange)
+    }
+
+    /// Insert effects of a MIDI channel
+    pub fn insert_mut(&mut self, chan: usize) -> Result<&mut EffectChain, OxiError> {
+        self.inserts
+            .get_mut(chan)
+            .ok_or(OxiError::ChannelOutOfRange)
+    }
+
+    /**
+    Add a send bus, fed by the channels at the level of the `ctrl` controller.
+    Returns the bus id.
+     */
+    pub fn add_send_bus(&mut self, ctrl: u8) -> usize {
+        let mut chain = EffectChain::new(self.sample_rate);
+        chain.ramp_time = self.ramp_time;
+        self.sends.push(SendBus {
+            ctrl: ctrl & 0x7f,
+            chain,
+            levels: vec![Ramp::new(0.0); self.strips.len()],
+            left: vec![0.0; self.block_size],
+            right: vec![0.0; self.block_size],
+        });
+        self.sends.len() - 1
+    }
+
+    /**
+    Add a send bus with a `Delay`, fed by the channels at the level of the `ctrl`
+    controller (CC94 for the XG variation send). Returns the bus id.
+     */
+    pub fn add_delay_bus(&mut self, ctrl: u8) -> usize {
+        let id = self.add_send_bus(ctrl);
+        let delay = Delay::new(self.sample_rate);
+        self.sends[id].chain.push(Box::new(delay));
+        id
+    }
+
+    /// Remove a send bus, shifting the ids of the following ones down.
+    pub fn remove_send_bus(&mut self, id: usize) -> Option<SendBus> {
+        if id < self.sends.len() {
+            Some(self.sends.remove(id))
+        } else {
+            None
+        }
+    }
+
+    pub fn send_bus(&self, id: usize) -> Option<&SendBus> {
+        self.sends.get(id)
+    }
+
+    pub fn send_bus_mut(&mut self, id: usize) -> Option<&mut SendBus> {
+        self.sends.get_mut(id)
+    }
+
+    pub fn count_send_buses(&self) -> usize {
+        self.sends.len()
+    }
+
+    /// Effects of the master bus
+    pub fn master(&self) -> &EffectChain {
+        &self.master
+    }
+
+    /// Effects of the master bus
+    pub fn master_mut(&mut self) -> &mut EffectChain {
+        &mut self.master
+    }
+}
+
+impl Effects {
+    /// Set the tempo of all the `Delay` effects, in BPM
+    pub fn set_delay_tempo(&mut self, bpm: f32) {
+        let chains = self
+            .inserts
+            .iter_mut()
+            .chain(self.sends.iter_mut().map(|bus| &mut bus.chain))
+            .chain(std::iter::once(&mut self.master));
+        for chain in chains {
+            for id in 0..chain.len() {
+                if let Some(delay) = chain.get_mut::<Delay>(id) {
+                    delay.set_tempo(bpm);
+                }
+            }
+        }
+    }
+}
+
+impl Effects {
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.inserts
+            .iter_mut()
+            .chain(self.sends.iter_mut().map(|bus| &mut bus.chain))
+            .chain(std::iter::once(&mut self.master))
+            .for_each(|chain| chain.set_sample_rate(sample_rate));
+    }
+
+    pub(crate) fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.ramp_time = ramp_time;
+        self.inserts
+            .iter_mut()
+            .chain(self.sends.iter_mut().map(|bus| &mut bus.chain))
+            .chain(std::iter::once(&mut self.master))
+            .for_each(|chain| chain.set_ramp_time(ramp_time));
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.sends
+            .iter_mut()
+            .for_each(|bus| bus.levels.iter_mut().for_each(Ramp::finish));
+        self.inserts
+            .iter_mut()
+            .chain(self.sends.iter_mut().map(|bus| &mut bus.chain))
+            .chain(std::iter::once(&mut self.master))
+            .for_each(|chain| chain.reset());
+    }
+
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.sends.len() as u32);
+        self.sends.iter().for_each(|bus| bus.levels.save(state));
+        self.inserts
+            .iter()
+            .chain(self.sends.iter().map(|bus| &bus.chain))
+            .chain(std::iter::once(&self.master))
+            .for_each(|chain| chain.save_state(state));
+    }
+
+    /// The effects must be set up like the saved ones
+    pub(crate) fn restore_state(&mut self, state: &mut StateReader) {
+        if state.read_u32() != self.sends.len() as u32 {
+            state.invalidate();
+        }
+        self.sends
+            .iter_mut()
+            .for_each(|bus| bus.levels.restore(state));
+        self.inserts
+            .iter_mut()
+            .chain(self.sends.iter_mut().map(|bus| &mut bus.chain))
+            .chain(std::iter::once(&mut self.master))
+            .for_each(|chain| chain.restore_state(state));
+    }
+
+    /// Buffers of the channels rendered through the inserts or the send buses
+    #[cfg(feature = "parallel")]
+    pub(crate) fn strips(&self) -> &[ChannelStrip] {
+        &self.strips
+    }
+
+    /**
+    Select and clear the channel strips to render into, before the voices are written.
+    A strip stays selected until its send levels have ramped down to zero.
+     */
+    pub(crate) fn prepare(&mut self, channels: &[Channel]) -> &mut [ChannelStrip] {
+        for (id, ((strip, inserts), channel)) in self
+            .strips
+            .iter_mut()
+            .zip(self.inserts.iter())
+            .zip(channels.iter())
+            .enumerate()
+        {
+            strip.active = !inserts.is_empty()
+                || self
+                    .sends
+                    .iter()
+                    .any(|bus| channel.cc(bus.ctrl as usize) != 0 || bus.levels[id].value() != 0.0);
+
+            if strip.active {
+                strip.left.iter_mut().for_each(|v| *v = 0.0);
+                strip.right.iter_mut().for_each(|v| *v = 0.0);
+            }
+        }
+
+        &mut self.strips
+    }
+
+    /**
+    Run the channel inserts and the send buses, mixing their output into `left_buf` and `right_buf`.
+    The send levels ramp to the controller values over the effect ramp time.
+     */
+    pub(crate) fn mix_channels(
+        &mut self,
+        channels: &[Channel],
+        audio_groups: u8,
+        left_buf: &mut [Vec<f32>],
+        right_buf: &mut [Vec<f32>],
+    ) {
+        for bus in self.sends.iter_mut() {
+            bus.left.iter_mut().for_each(|v| *v = 0.0);
+            bus.right.iter_mut().for_each(|v| *v = 0.0);
+        }
+
+        let ramp_len = Ramp::samples(self.ramp_time, self.sample_rate);
+        for (id, ((strip, inserts), channel)) in self
+            .strips
+            .iter_mut()
+            .zip(self.inserts.iter_mut())
+            .zip(channels.iter())
+            .enumerate()
+            .filter(|(_, ((strip, _), _))| strip.active)
+        {
+            inserts.process(&mut strip.left, &mut strip.right);
+
+            for bus in self.sends.iter_mut() {
+                let level = &mut bus.levels[id];
+                level.set(channel.cc_value(bus.ctrl as usize) / 127.0, ramp_len);
+                if !level.is_steady() {
+                    for i in 0..strip.left.len() {
+                        let gain = level.step();
+                        bus.left[i] += strip.left[i] * gain;
+                        bus.right[i] += strip.right[i] * gain;
+                    }
+                } else if level.value() > 0.0 {
+                    let gain = level.value();
+                    for i in 0..strip.left.len() {
+                        bus.left[i] += strip.left[i] * gain;
+                        bus.right[i] += strip.right[i] * gain;
+                    }
+                }
+            }
+
+            let auchan = id % audio_groups as usize;
+            for i in 0..strip.left.len() {
+                left_buf[auchan][i] += strip.left[i];
+                right_buf[auchan][i] += strip.right[i];
+            }
+        }
+
+        for bus in self.sends.iter_mut() {
+            bus.chain.process(&mut bus.left, &mut bus.right);
+            for i in 0..bus.left.len() {
+                left_buf[0][i] += bus.left[i];
+                right_buf[0][i] += bus.right[i];
+            }
+        }
+    }
+
+    pub(crate) fn process_master(&mut self, left: &mut [f32], right: &mut [f32]) {
+        self.master.process(left, right);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Test effect applying a gain
+    pub(crate) struct Gain(pub f32);
+
+    impl Effect for Gain {
+        fn set_sample_rate(&mut self, _sample_rate: f32) {}
+        fn reset(&mut self) {}
+        fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+            left.iter_mut()
+                .chain(right.iter_mut())
+                .for_each(|v| *v *= self.0);
+        }
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn chain() {
+        let mut chain = EffectChain::new(44100.0);
+        assert_eq!(chain.push(Box::new(Gain(2.0))), 0);
+        assert_eq!(chain.push(Box::new(Gain(3.0))), 1);
+
+        let mut left = [1.0; 64];
+        let mut right = [-1.0; 64];
+        chain.process(&mut left, &mut right);
+        assert_eq!((left[0], right[63]), (6.0, -6.0));
+
+        chain.get_mut::<Gain>(1).unwrap().0 = 0.5;
+        assert!(chain.remove(0).is_some());
+        chain.process(&mut left, &mut right);
+        assert_eq!((left[0], right[63]), (3.0, -3.0));
+        assert!(chain.remove(1).is_none());
+    }
+}
@@ -0,0 +1,186 @@
+use std::any::Any;
+
+use super::Effect;
+use crate::core::snapshot::{snapshot, Snapshot, StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressorParams {
+    /// Level above which the signal is compressed, in dBFS
+    pub threshold: f32,
+    /// Input to output level ratio above the threshold (1 or more)
+    pub ratio: f32,
+    /// Attack time constant, in ms
+    pub attack: f32,
+    /// Release time constant, in ms
+    pub release: f32,
+    /// Gain applied after the compression, in dB
+    pub makeup: f32,
+}
+
+impl Default for CompressorParams {
+    fn default() -> Self {
+        Self {
+            threshold: -12.0,
+            ratio: 4.0,
+            attack: 10.0,
+            release: 200.0,
+            makeup: 0.0,
+        }
+    }
+}
+
+/// Window of the RMS level detector, in ms
+const RMS_TIME: f32 = 5.0;
+
+/**
+Feed-forward RMS compressor
+
+The level is measured on the mean square of both channels, which share the same gain.
+ */
+pub struct Compressor {
+    params: CompressorParams,
+    sample_rate: f32,
+
+    rms_coef: f32,
+    attack_coef: f32,
+    release_coef: f32,
+    makeup: f32,
+
+    mean_square: f32,
+    /// Smoothed gain reduction, in dB (0 or negative)
+    reduction: f32,
+}
+
+snapshot!(Compressor {
+    mean_square,
+    reduction
+});
+
+impl Compressor {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut comp = Self {
+            params: CompressorParams::default(),
+            sample_rate,
+
+            rms_coef: 0.0,
+            attack_coef: 0.0,
+            release_coef: 0.0,
+            makeup: 1.0,
+
+            mean_square: 0.0,
+            reduction: 0.0,
+        };
+        comp.set_compressor(&CompressorParams::default());
+        comp
+    }
+
+    /// Set the compressor parameters
+    pub fn set_compressor(&mut self, params: &CompressorParams) {
+        let params = CompressorParams {
+            threshold: params.threshold.min(0.0),
+            ratio: params.ratio.max(1.0),
+            attack: params.attack.max(0.01),
+            release: params.release.max(1.0),
+            makeup: params.makeup,
+        };
+
+        let sample_rate = self.sample_rate;
+        let coef = |ms: f32| 1.0 - (-1000.0 / (ms * sample_rate)).exp();
+        self.rms_coef = coef(RMS_TIME);
+        self.attack_coef = coef(params.attack);
+        self.release_coef = coef(params.release);
+        self.makeup = 10f32.powf(params.makeup / 20.0);
+
+        self.params = params;
+    }
+
+    /// Query the compressor parameters
+    pub fn compressor(&self) -> CompressorParams {
+        self.params
+    }
+
+    /// Current gain reduction, in dB (0 or negative)
+    pub fn gain_reduction(&self) -> f32 {
+        self.reduction
+    }
+}
+
+impl Effect for Compressor {
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        let params = self.params;
+        self.set_compressor(&params);
+    }
+
+    fn reset(&mut self) {
+        self.mean_square = 0.0;
+        self.reduction = 0.0;
+    }
+
+    fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let slope = 1.0 / self.params.ratio - 1.0;
+
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let square = (*l * *l + *r * *r) * 0.5;
+            self.mean_square += (square - self.mean_square) * self.rms_coef;
+
+            let level = 10.0 * self.mean_square.max(1e-12).log10();
+            let target = (level - self.params.threshold).max(0.0) * slope;
+
+            let coef = if target < self.reduction {
+                self.attack_coef
+            } else {
+                self.release_coef
+            };
+            self.reduction += (target - self.reduction) * coef;
+
+            let gain = 10f32.powf(self.reduction / 20.0) * self.makeup;
+            *l *= gain;
+            *r *= gain;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.save(state);
+    }
+
+    fn restore_state(&mut self, state: &mut StateReader) {
+        self.restore(state);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ratio() {
+        let mut comp = Compressor::new(44100.0);
+        comp.set_compressor(&CompressorParams {
+            threshold: -20.0,
+            ratio: 4.0,
+            ..Default::default()
+        });
+
+        // A 0 dB RMS square wave ends up 15 dB below its input level
+        let mut left = [0.0; 64];
+        for _ in 0..1000 {
+            left = [1.0; 64];
+            let mut right = [-1.0; 64];
+            comp.process(&mut left, &mut right);
+        }
+        assert!((comp.gain_reduction() + 15.0).abs() < 0.1);
+        assert!((20.0 * left[0].log10() + 15.0).abs() < 0.1);
+
+        // Signals under the threshold are left alone, after the release
+        for _ in 0..1000 {
+            left = [0.01; 64];
+            comp.process(&mut left, &mut [0.01; 64]);
+        }
+        assert!((left[0] - 0.01).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,259 @@
+use std::any::Any;
+
+use super::Effect;
+use crate::core::snapshot::{snapshot, Snapshot, StateReader, StateWriter};
+
+/**
+Filter shape of an equalizer band
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EqBandType {
+    /// Bell boost or cut around the frequency
+    #[default]
+    Peak,
+    /// Boost or cut below the frequency
+    LowShelf,
+    /// Boost or cut above the frequency
+    HighShelf,
+    /// Cut below the frequency, the gain is ignored
+    HighPass,
+    /// Cut above the frequency, the gain is ignored
+    LowPass,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqBand {
+    pub ty: EqBandType,
+    /// Center or corner frequency, in Hz
+    pub freq: f32,
+    /// Boost (positive) or cut (negative), in dB
+    pub gain: f32,
+    /// Quality factor: bandwidth of peaks, resonance of shelves and passes
+    pub q: f32,
+}
+
+impl Default for EqBand {
+    fn default() -> Self {
+        Self {
+            ty: EqBandType::Peak,
+            freq: 1000.0,
+            gain: 0.0,
+            q: std::f32::consts::FRAC_1_SQRT_2,
+        }
+    }
+}
+
+/// Biquad filter of a band, with the state of both channels
+#[derive(Clone, Default)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    /// Transposed direct form II state, per channel
+    state: [[f32; 2]; 2],
+}
+
+snapshot!(Biquad { state });
+
+impl Biquad {
+    /// Coefficients from the "Audio EQ Cookbook" by Robert Bristow-Johnson
+    fn new(band: &EqBand, sample_rate: f32) -> Self {
+        let freq = band.freq.clamp(10.0, sample_rate * 0.49);
+        let q = band.q.max(0.05);
+        let a = 10f32.powf(band.gain / 40.0);
+        let w0 = 2.0 * std::f32::consts::PI * freq / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+
+        let (b0, b1, b2, a0, a1, a2) = match band.ty {
+            EqBandType::Peak => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            EqBandType::LowShelf => {
+                let k = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + k),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - k),
+                    (a + 1.0) + (a - 1.0) * cos + k,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - k,
+                )
+            }
+            EqBandType::HighShelf => {
+                let k = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + k),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - k),
+                    (a + 1.0) - (a - 1.0) * cos + k,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - k,
+                )
+            }
+            EqBandType::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            EqBandType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            state: [[0.0; 2]; 2],
+        }
+    }
+
+    fn process(&mut self, chan: usize, x: f32) -> f32 {
+        let s = &mut self.state[chan];
+        let y = self.b0 * x + s[0];
+        s[0] = self.b1 * x - self.a1 * y + s[1];
+        s[1] = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/**
+Parametric equalizer, made of any number of bands in series
+ */
+pub struct Equalizer {
+    sample_rate: f32,
+    bands: Vec<EqBand>,
+    filters: Vec<Biquad>,
+}
+
+// The filter states, the equalizer must have the saved number of bands
+snapshot!(Equalizer { filters });
+
+impl Equalizer {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            bands: Vec::new(),
+            filters: Vec::new(),
+        }
+    }
+
+    /**
+    Set the equalizer bands.
+
+    The filter state of the existing bands is kept, so the bands can be
+    tweaked while playing.
+     */
+    pub fn set_bands(&mut self, bands: &[EqBand]) {
+        self.filters.resize_with(bands.len(), Default::default);
+        for (filter, band) in self.filters.iter_mut().zip(bands.iter()) {
+            let state = filter.state;
+            *filter = Biquad::new(band, self.sample_rate);
+            filter.state = state;
+        }
+        self.bands = bands.to_vec();
+    }
+
+    /// Query the equalizer bands
+    pub fn bands(&self) -> &[EqBand] {
+        &self.bands
+    }
+}
+
+impl Effect for Equalizer {
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        let bands = std::mem::take(&mut self.bands);
+        self.set_bands(&bands);
+    }
+
+    fn reset(&mut self) {
+        self.filters
+            .iter_mut()
+            .for_each(|filter| filter.state = [[0.0; 2]; 2]);
+    }
+
+    fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for filter in self.filters.iter_mut() {
+            for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+                *l = filter.process(0, *l);
+                *r = filter.process(1, *r);
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.save(state);
+    }
+
+    fn restore_state(&mut self, state: &mut StateReader) {
+        self.restore(state);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Steady state peak gain of a sine going through the equalizer, in dB
+    fn response(eq: &mut Equalizer, freq: f32) -> f32 {
+        eq.reset();
+        let mut peak = 0f32;
+        for block in 0..200 {
+            let mut left = [0.0; 64];
+            for (i, v) in left.iter_mut().enumerate() {
+                let t = (block * 64 + i) as f32 / 44100.0;
+                *v = (t * freq * 2.0 * std::f32::consts::PI).sin();
+            }
+            eq.process(&mut left, &mut [0.0; 64]);
+            if block >= 100 {
+                peak = left.iter().fold(peak, |p, v| p.max(v.abs()));
+            }
+        }
+        20.0 * peak.log10()
+    }
+
+    #[test]
+    fn bands() {
+        let mut eq = Equalizer::new(44100.0);
+        eq.set_bands(&[
+            EqBand {
+                ty: EqBandType::Peak,
+                freq: 1000.0,
+                gain: 6.0,
+                q: 2.0,
+            },
+            EqBand {
+                ty: EqBandType::HighPass,
+                freq: 100.0,
+                ..Default::default()
+            },
+        ]);
+
+        assert!((response(&mut eq, 1000.0) - 6.0).abs() < 0.1);
+        assert!(response(&mut eq, 5000.0).abs() < 0.5);
+        assert!(response(&mut eq, 25.0) < -20.0);
+    }
+}
@@ -0,0 +1,239 @@
+use std::any::Any;
+use std::collections::VecDeque;
+
+use super::Effect;
+use crate::core::snapshot::{snapshot, Snapshot, StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterParams {
+    /// Output ceiling, in dBFS
+    pub threshold: f32,
+    /// Look-ahead time, in ms (0.1-20), also the attack time
+    pub lookahead: f32,
+    /// Time for the gain to recover by ~63% (one time constant), in ms
+    pub release: f32,
+}
+
+impl Default for LimiterParams {
+    fn default() -> Self {
+        Self {
+            threshold: -0.3,
+            lookahead: 5.0,
+            release: 100.0,
+        }
+    }
+}
+
+/**
+Look-ahead brickwall limiter
+
+The signal is delayed by the look-ahead time, so the gain reduction is
+ramped in before each peak reaches the output: the output never exceeds
+the threshold. Both channels share the same gain.
+ */
+pub struct Limiter {
+    params: LimiterParams,
+    sample_rate: f32,
+
+    ceiling: f32,
+    release_coef: f32,
+    /// Look-ahead length, in samples
+    len: usize,
+
+    delay: VecDeque<(f32, f32)>,
+    /// Sliding minimum of the required gains: (sample index, gain)
+    min_hold: VecDeque<(usize, f32)>,
+    /// Moving average of the held gain, smoothing the attack
+    ramp: VecDeque<f32>,
+    ramp_sum: f64,
+    gain: f32,
+    index: usize,
+}
+
+snapshot!(Limiter {
+    delay,
+    min_hold,
+    ramp,
+    ramp_sum,
+    gain,
+    index,
+});
+
+impl Limiter {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut limiter = Self {
+            params: LimiterParams::default(),
+            sample_rate,
+
+            ceiling: 1.0,
+            release_coef: 0.0,
+            len: 1,
+
+            delay: VecDeque::new(),
+            min_hold: VecDeque::new(),
+            ramp: VecDeque::new(),
+            ramp_sum: 0.0,
+            gain: 1.0,
+            index: 0,
+        };
+        limiter.set_limiter(&LimiterParams::default());
+        limiter
+    }
+
+    /// Set the limiter parameters
+    pub fn set_limiter(&mut self, params: &LimiterParams) {
+        let params = LimiterParams {
+            threshold: params.threshold.min(0.0),
+            lookahead: params.lookahead.clamp(0.1, 20.0),
+            release: params.release.max(1.0),
+        };
+
+        self.ceiling = 10f32.powf(params.threshold / 20.0);
+        self.release_coef = 1.0 - (-1000.0 / (params.release * self.sample_rate)).exp();
+
+        let len = ((params.lookahead / 1000.0 * self.sample_rate) as usize).max(1);
+        if len != self.len || self.delay.is_empty() {
+            self.len = len;
+            self.reset();
+        }
+
+        self.params = params;
+    }
+
+    /// Query the limiter parameters
+    pub fn limiter(&self) -> LimiterParams {
+        self.params
+    }
+
+    /// Current gain reduction, in dB (0 or negative)
+    pub fn gain_reduction(&self) -> f32 {
+        20.0 * self.gain.log10()
+    }
+
+    fn process_frame(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let peak = left.abs().max(right.abs());
+        let required = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+
+        // Minimum of the required gains over the look-ahead window
+        while matches!(self.min_hold.back(), Some((_, g)) if *g >= required) {
+            self.min_hold.pop_back();
+        }
+        self.min_hold.push_back((self.index, required));
+        while matches!(self.min_hold.front(), Some((id, _)) if id + self.len < self.index) {
+            self.min_hold.pop_front();
+        }
+        let held = self.min_hold.front().map(|(_, g)| *g).unwrap_or(1.0);
+
+        // Instant attack, smooth release
+        self.gain = if held < self.gain {
+            held
+        } else {
+            self.gain + (held - self.gain) * self.release_coef
+        };
+
+        self.ramp_sum += self.gain as f64;
+        self.ramp.push_back(self.gain);
+        self.ramp_sum -= self.ramp.pop_front().unwrap_or(1.0) as f64;
+        let gain = (self.ramp_sum / self.len as f64) as f32;
+
+        self.index += 1;
+        self.delay.push_back((left, right));
+        let (l, r) = self.delay.pop_front().unwrap_or_default();
+
+        // Rounding of the moving average may leave the output a hair above the ceiling
+        (
+            (l * gain).clamp(-self.ceiling, self.ceiling),
+            (r * gain).clamp(-self.ceiling, self.ceiling),
+        )
+    }
+}
+
+impl Effect for Limiter {
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.delay.clear();
+        let params = self.params;
+        self.set_limiter(&params);
+    }
+
+    fn reset(&mut self) {
+        self.delay = vec![(0.0, 0.0); self.len].into();
+        self.ramp = vec![1.0; self.len].into();
+        self.ramp_sum = self.len as f64;
+        self.min_hold.clear();
+        self.gain = 1.0;
+        self.index = 0;
+    }
+
+    fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let (out_l, out_r) = self.process_frame(*l, *r);
+            *l = out_l;
+            *r = out_r;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.save(state);
+    }
+
+    fn restore_state(&mut self, state: &mut StateReader) {
+        self.restore(state);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ceiling() {
+        let mut limiter = Limiter::new(44100.0);
+        limiter.set_limiter(&LimiterParams {
+            threshold: -6.0,
+            ..Default::default()
+        });
+        let ceiling = 10f32.powf(-6.0 / 20.0);
+
+        let mut peak = 0f32;
+        for block in 0..100 {
+            let mut left = [0.0; 64];
+            let mut right = [0.0; 64];
+            for i in 0..64 {
+                let t = (block * 64 + i) as f32 / 44100.0;
+                left[i] = 4.0 * (t * 440.0 * 2.0 * std::f32::consts::PI).sin();
+                right[i] = -left[i] / 2.0;
+            }
+            limiter.process(&mut left, &mut right);
+            peak = left
+                .iter()
+                .chain(right.iter())
+                .fold(peak, |p, v| p.max(v.abs()));
+        }
+
+        assert!(peak <= ceiling);
+        assert!(peak > ceiling * 0.9);
+        assert!(limiter.gain_reduction() < -10.0);
+
+        // Quiet signals go through unchanged, after the look-ahead delay
+        limiter.reset();
+        let mut left = [0.25; 64];
+        let mut right = [0.25; 64];
+        limiter.process(&mut left, &mut right);
+        assert_eq!(left[0], 0.0);
+        for _ in 0..4 {
+            left = [0.25; 64];
+            right = [0.25; 64];
+            limiter.process(&mut left, &mut right);
+        }
+        assert_eq!((left[0], right[63]), (0.25, 0.25));
+    }
+}
@@ -22,6 +22,25 @@ pub enum OxiError {
     PithBendOutOfRange,
     #[error("Channel has no preset")]
     ChannelHasNoPreset,
+    #[error("MPE zone is not active")]
+    MpeZoneInactive,
+    #[error("Effect instance out of range")]
+    FxInstanceOutOfRange,
+    #[error("No effect of this type at this position")]
+    EffectNotFound,
+    #[error("SoundFont {0:#018x} of the synth state is not loaded")]
+    FontNotLoaded(u64),
+    #[error("The DSP state does not match the synth")]
+    DspStateMismatch,
+    #[error("Reverb instance {0} of the synth state has an engine which is not set up")]
+    ReverbEngineNotSet(usize),
+    #[error("Polyphony out of range (1-65535)")]
+    PolyphonyOutOfRange,
+    #[error("Tuning bank or program out of range (0-127)")]
+    TuningOutOfRange,
+    #[cfg(feature = "parallel")]
+    #[error("Could not start the render threads: {0}")]
+    RenderThreads(String),
     #[error(
         "There is no preset with bank number {bank_id} and preset number {preset_id} in SoundFont {sfont_id}"
     )]
@@ -0,0 +1,212 @@
+use super::{utils::RangeCheck, OxiError};
+
+use super::midi_event::{U14, U7};
+
+/// Note On / Note Off attribute (MIDI 2.0 Protocol)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteAttribute {
+    None,
+    ManufacturerSpecific(u16),
+    ProfileSpecific(u16),
+    /// Pitch 7.9: 7 bits of note number and 9 bits of fraction of a semitone
+    Pitch7_9(u16),
+    Unknown {
+        ty: u8,
+        data: u16,
+    },
+}
+
+impl NoteAttribute {
+    pub fn new(ty: u8, data: u16) -> Self {
+        match ty {
+            0 => NoteAttribute::None,
+            1 => NoteAttribute::ManufacturerSpecific(data),
+            2 => NoteAttribute::ProfileSpecific(data),
+            3 => NoteAttribute::Pitch7_9(data),
+            ty => NoteAttribute::Unknown { ty, data },
+        }
+    }
+}
+
+/**
+MIDI 2.0 Protocol channel voice message
+
+Channels 0-255 address the 16 channels of the 16 UMP groups (`group * 16 + channel`).
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Midi2Event {
+    /// Send a noteon message with 16 bit velocity.
+    NoteOn {
+        channel: u8,
+        key: U7,
+        vel: u16,
+        attribute: NoteAttribute,
+    },
+    /// Send a noteoff message.
+    NoteOff {
+        channel: u8,
+        key: U7,
+        vel: u16,
+        attribute: NoteAttribute,
+    },
+    /// Set key pressure (aftertouch)
+    PolyphonicKeyPressure { channel: u8, key: U7, value: u32 },
+    /// Send a control change message with 32 bit value.
+    ControlChange { channel: u8, ctrl: U7, value: u32 },
+    /// Registered Controller (RPN) with 32 bit value.
+    RegisteredController {
+        channel: u8,
+        bank: U7,
+        index: U7,
+        value: u32,
+    },
+    /// Assignable Controller (NRPN) with 32 bit value.
+    AssignableController {
+        channel: u8,
+        bank: U7,
+        index: U7,
+        value: u32,
+    },
+    /// Registered Per-Note Controller, index 3 is the absolute Pitch 7.25 of the note
+    RegisteredPerNoteController {
+        channel: u8,
+        key: U7,
+        index: u8,
+        value: u32,
+    },
+    /// Assignable Per-Note Controller, indices 0-127 address the per-note value of the
+    /// corresponding control change
+    AssignablePerNoteController {
+        channel: u8,
+        key: U7,
+        index: u8,
+        value: u32,
+    },
+    /// Per-Note Pitch Bend, using the pitch bend range of the channel
+    PerNotePitchBend { channel: u8, key: U7, value: u32 },
+    /// Per-Note Management
+    ///
+    /// `detach` detaches the sounding notes from further per-note controllers,
+    /// `reset` resets the per-note controllers of the note to their default values.
+    PerNoteManagement {
+        channel: u8,
+        key: U7,
+        detach: bool,
+        reset: bool,
+    },
+    /// Send a program change message, with optional bank select.
+    ProgramChange {
+        channel: u8,
+        program_id: U7,
+        bank: Option<U14>,
+    },
+    /// Set channel pressure
+    ChannelPressure { channel: u8, value: u32 },
+    /// Send a pitch bend message with 32 bit value.
+    PitchBend { channel: u8, value: u32 },
+}
+
+impl Midi2Event {
+    pub fn check(self) -> Result<Self, OxiError> {
+        match &self {
+            Midi2Event::NoteOn { key, .. }
+            | Midi2Event::NoteOff { key, .. }
+            | Midi2Event::PolyphonicKeyPressure { key, .. }
+            | Midi2Event::RegisteredPerNoteController { key, .. }
+            | Midi2Event::AssignablePerNoteController { key, .. }
+            | Midi2Event::PerNotePitchBend { key, .. }
+            | Midi2Event::PerNoteManagement { key, .. } => {
+                RangeCheck::check(0..=127, key, OxiError::KeyOutOfRange)?;
+            }
+            Midi2Event::ControlChange { ctrl, .. } => {
+                RangeCheck::check(0..=127, ctrl, OxiError::CtrlOutOfRange)?;
+            }
+            Midi2Event::RegisteredController { bank, index, .. }
+            | Midi2Event::AssignableController { bank, index, .. } => {
+                RangeCheck::check(0..=127, bank, OxiError::CtrlOutOfRange)?;
+                RangeCheck::check(0..=127, index, OxiError::CtrlOutOfRange)?;
+            }
+            Midi2Event::ProgramChange {
+                program_id, bank, ..
+            } => {
+                RangeCheck::check(0..=127, program_id, OxiError::ProgramOutOfRange)?;
+                if let Some(bank) = bank {
+                    RangeCheck::check(0..=16383, bank, OxiError::ProgramOutOfRange)?;
+                }
+            }
+            Midi2Event::ChannelPressure { .. } => {}
+            Midi2Event::PitchBend { .. } => {}
+        };
+
+        Ok(self)
+    }
+}
+
+/**
+Maps a `bits` wide MIDI 2.0 value onto the 0-127 range of MIDI 1.0 values,
+keeping the fractional part.
+
+The mapping is the inverse of the MIDI 2.0 min-center-max upscaling:
+0, the center value and the max value land exactly on 0, 64 and 127.
+ */
+pub(crate) fn to_u7_range(value: u32, bits: u32) -> f32 {
+    let value = value as f64;
+    let center = (1u64 << (bits - 1)) as f64;
+    let max = ((1u64 << bits) - 1) as f64;
+
+    let v = if value <= center {
+        value / center * 64.0
+    } else {
+        64.0 + (value - center) / (max - center) * 63.0
+    };
+    v as f32
+}
+
+/**
+MIDI 2.0 min-center-max upscaling of a 7 bit value to 32 bits.
+ */
+pub(crate) fn from_u7(value: u8) -> u32 {
+    let value = (value & 0x7f) as u32;
+    let shifted = value << 25;
+    if value <= 64 {
+        return shifted;
+    }
+
+    // Repeat the 6 bits below the MSB to fill up the lower bits
+    let repeat = value & 0x3f;
+    let mut out = shifted;
+    let mut shift = 25i32 - 6;
+    while shift > -6 {
+        out |= if shift >= 0 {
+            repeat << shift
+        } else {
+            repeat >> -shift
+        };
+        shift -= 6;
+    }
+    out
+}
+
+/**
+Maps a 32 bit MIDI 2.0 pitch bend value onto the 0-16383 range of MIDI 1.0
+pitch bend, keeping the fractional part.
+ */
+pub(crate) fn to_u14_range(value: u32) -> f32 {
+    (value as f64 / (1u64 << 18) as f64) as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scaling() {
+        for value in 0..=127 {
+            assert_eq!(to_u7_range(from_u7(value), 32), value as f32);
+        }
+        assert_eq!(from_u7(64), 0x8000_0000);
+        assert_eq!(from_u7(127), 0xFFFF_FFFF);
+        assert_eq!(to_u7_range(0xFFFF, 16), 127.0);
+        assert_eq!(to_u14_range(0x8000_0000), 8192.0);
+    }
+}
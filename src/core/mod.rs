@@ -1,8 +1,14 @@
 #![forbid(unsafe_code)]
 
 pub mod chorus;
+pub mod delay;
+pub mod effects;
 pub mod reverb;
 
+mod resampler;
+
+pub mod snapshot;
+
 pub mod settings;
 pub use settings::{Settings, SettingsError, SynthDescriptor};
 
@@ -10,11 +16,17 @@ pub mod tuning;
 pub use tuning::{Tuning, TuningManager};
 
 pub mod synth;
-pub use synth::{font_bank, InterpolationMethod, Synth};
+#[cfg(feature = "parallel")]
+pub use synth::RenderThreads;
+pub use synth::{
+    font_bank, ChannelLevel, DspState, EnvelopeStage, FilterType, InterpolationMethod, MpeZone,
+    MpeZoneLayout, StealPolicy, StolenVoice, Synth, SynthEvent, SynthState, VoiceInfo,
+};
 
 pub use synth::soundfont::{self, SoundFont};
 
-mod utils;
+pub(crate) mod simd;
+pub(crate) mod utils;
 pub use utils::TypedIndex;
 
 pub mod error;
@@ -22,3 +34,8 @@ pub use error::OxiError;
 
 pub mod midi_event;
 pub use midi_event::MidiEvent;
+
+pub mod midi2_event;
+pub use midi2_event::{Midi2Event, NoteAttribute};
+
+pub mod ump;
@@ -0,0 +1,96 @@
+use super::snapshot::snapshot;
+
+/**
+Output sample rate converter
+
+Used when the synth renders at a fixed internal sample rate, and the host
+runs at a different one. Frames are interpolated with a 4 point, 3rd order
+Hermite polynomial.
+
+Downsampling (internal rate above the output rate) is not band limited,
+so the internal rate should be close to, or below, the output rate.
+ */
+#[derive(Clone)]
+pub(crate) struct Resampler {
+    /// Input frames consumed per output frame
+    step: f64,
+    /// Position between `history[1]` and `history[2]`
+    pos: f64,
+    history: [(f32, f32); 4],
+}
+
+snapshot!(Resampler { pos, history });
+
+impl Resampler {
+    pub fn new(input_rate: f32, output_rate: f32) -> Self {
+        Self {
+            step: input_rate as f64 / output_rate as f64,
+            // Fill up the history before the first output frame
+            pos: 3.0,
+            history: [(0.0, 0.0); 4],
+        }
+    }
+
+    /**
+    Produce the next output frame, pulling as many input frames as needed.
+     */
+    pub fn next<F: FnMut() -> (f32, f32)>(&mut self, mut input: F) -> (f32, f32) {
+        while self.pos >= 1.0 {
+            self.history.rotate_left(1);
+            self.history[3] = input();
+            self.pos -= 1.0;
+        }
+
+        let t = self.pos as f32;
+        let [a, b, c, d] = self.history;
+        let out = (
+            hermite(a.0, b.0, c.0, d.0, t),
+            hermite(a.1, b.1, c.1, d.1, t),
+        );
+
+        self.pos += self.step;
+        out
+    }
+}
+
+/// Interpolate between `x1` and `x2`, at `t` in the 0..1 range
+pub(crate) fn hermite(x0: f32, x1: f32, x2: f32, x3: f32, t: f32) -> f32 {
+    let c1 = 0.5 * (x2 - x0);
+    let c2 = x0 - 2.5 * x1 + 2.0 * x2 - 0.5 * x3;
+    let c3 = 0.5 * (x3 - x0) + 1.5 * (x1 - x2);
+    ((c3 * t + c2) * t + c1) * t + x1
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resample_ratio() {
+        // 2x upsampling of a ramp lands on the midpoints
+        let mut resampler = Resampler::new(22050.0, 44100.0);
+        let mut n = 0.0;
+        let out: Vec<f32> = (0..8)
+            .map(|_| {
+                resampler
+                    .next(|| {
+                        n += 1.0;
+                        (n, n)
+                    })
+                    .0
+            })
+            .collect();
+        assert_eq!(out[4..], [3.0, 3.5, 4.0, 4.5]);
+
+        // Same rate is a plain delay line
+        let mut resampler = Resampler::new(44100.0, 44100.0);
+        let mut n = 0.0;
+        for _ in 0..3 {
+            resampler.next(|| {
+                n += 1.0;
+                (n, -n)
+            });
+        }
+        assert_eq!(resampler.next(|| (6.0, -6.0)), (4.0, -4.0));
+    }
+}
@@ -1,313 +1,227 @@
+use std::any::Any;
+
+use super::settings::MAX_BLOCK_SIZE;
+use super::snapshot::{StateReader, StateWriter};
+use super::utils::EFFECT_RAMP_MS;
+
 mod public;
 pub use public::*;
 
-const DC_OFFSET: f32 = 1e-8;
-const STEREO_SPREAD: usize = 23;
-
-const COMBTUNING_L1: usize = 1116;
-const COMBTUNING_R1: usize = 1116 + STEREO_SPREAD;
-const COMBTUNING_L2: usize = 1188;
-const COMBTUNING_R2: usize = 1188 + STEREO_SPREAD;
-const COMBTUNING_L3: usize = 1277;
-const COMBTUNING_R3: usize = 1277 + STEREO_SPREAD;
-const COMBTUNING_L4: usize = 1356;
-const COMBTUNING_R4: usize = 1356 + STEREO_SPREAD;
-const COMBTUNING_L5: usize = 1422;
-const COMBTUNING_R5: usize = 1422 + STEREO_SPREAD;
-const COMBTUNING_L6: usize = 1491;
-const COMBTUNING_R6: usize = 1491 + STEREO_SPREAD;
-const COMBTUNING_L7: usize = 1557;
-const COMBTUNING_R7: usize = 1557 + STEREO_SPREAD;
-const COMBTUNING_L8: usize = 1617;
-const COMBTUNING_R8: usize = 1617 + STEREO_SPREAD;
-const ALLPASSTUNING_L1: usize = 556;
-const ALLPASSTUNING_R1: usize = 556 + STEREO_SPREAD;
-const ALLPASSTUNING_L2: usize = 441;
-const ALLPASSTUNING_R2: usize = 441 + STEREO_SPREAD;
-const ALLPASSTUNING_L3: usize = 341;
-const ALLPASSTUNING_R3: usize = 341 + STEREO_SPREAD;
-const ALLPASSTUNING_L4: usize = 225;
-const ALLPASSTUNING_R4: usize = 225 + STEREO_SPREAD;
-
-#[derive(Clone)]
-struct Comb {
-    feedback: f32,
-    filterstore: f32,
-    damp1: f32,
-    damp2: f32,
-    buffer: Vec<f32>,
-    bufidx: usize,
+mod freeverb;
+pub use freeverb::Freeverb;
+
+mod plate;
+pub use plate::{PlateParams, PlateReverb};
+
+#[cfg(feature = "convolution")]
+mod convolution;
+#[cfg(feature = "convolution")]
+pub use convolution::{ConvolutionReverb, ImpulseResponse, Partitioning};
+
+/**
+Built-in reverb algorithm
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReverbType {
+    /// Freeverb, the FluidSynth reverb
+    #[default]
+    Freeverb,
+    /// Dattorro plate reverb, with pre-delay and diffusion control
+    Plate,
 }
 
-impl Comb {
-    pub fn new(size: usize) -> Self {
-        return Self {
-            feedback: 0f32,
-            filterstore: 0f32,
-            damp1: 0f32,
-            damp2: 0f32,
-            buffer: vec![DC_OFFSET; size],
-            bufidx: 0,
-        };
-    }
+/**
+A reverb algorithm, processing the reverb send bus of the synth.
 
-    pub fn set_damp(&mut self, val: f32) {
-        self.damp1 = val;
-        self.damp2 = 1f32 - val;
-    }
+Implement this trait to plug a custom reverb into the synth with `Reverb::set_engine()`.
+ */
+pub trait ReverbEngine: Send {
+    /// Adapt the engine to a new sample rate, keeping its parameters.
+    fn set_sample_rate(&mut self, sample_rate: f32);
 
-    pub fn set_feedback(&mut self, val: f32) {
-        self.feedback = val;
-    }
+    /// Clear the delay lines, silencing the reverb tail.
+    fn reset(&mut self);
 
-    pub fn process(&mut self, input: f32) -> f32 {
-        let mut _tmp = self.buffer[self.bufidx];
-        self.filterstore = _tmp * self.damp2 + self.filterstore * self.damp1;
-        self.buffer[self.bufidx] = input + self.filterstore * self.feedback;
-        self.bufidx += 1;
-        if self.bufidx >= self.buffer.len() {
-            self.bufidx = 0
-        }
-        return _tmp;
-    }
-}
+    /// Apply the generic reverb parameters.
+    fn set_params(&mut self, params: &ReverbParams);
 
-#[derive(Clone)]
-struct AllPass {
-    feedback: f32,
-    buffer: Vec<f32>,
-    bufidx: usize,
-}
+    /// Adapt the engine to the block size of the synth, the length of the blocks given to `process_mix`.
+    fn set_block_size(&mut self, _block_size: usize) {}
 
-impl AllPass {
-    pub fn new(size: usize, feedback: f32) -> Self {
-        return Self {
-            feedback,
-            buffer: vec![DC_OFFSET; size],
-            bufidx: 0,
-        };
-    }
+    /// Set the time for the parameter changes to reach their new value, in ms.
+    fn set_ramp_time(&mut self, _ramp_time: f32) {}
 
-    pub fn process(self: &mut Self, input: f32) -> f32 {
-        let bufout: f32 = self.buffer[self.bufidx];
-        let output: f32 = bufout - input;
-        self.buffer[self.bufidx] = input + bufout * self.feedback;
-        self.bufidx += 1;
-        if self.bufidx >= self.buffer.len() {
-            self.bufidx = 0
-        }
-        return output;
+    /// Process a block of the mono reverb send, adding the stereo output to `left_out` and `right_out`.
+    fn process_mix(&mut self, input: &[f32], left_out: &mut [f32], right_out: &mut [f32]);
+
+    /// Save the running state (delay lines, filter memories...), for `Synth::save_dsp_state()`.
+    fn save_state(&self, _state: &mut StateWriter) {}
+
+    /// Restore the running state written by `save_state()`. Clears the delay lines by default.
+    fn restore_state(&mut self, _state: &mut StateReader) {
+        self.reset();
     }
-}
 
-#[derive(Clone)]
-struct LRPair<T> {
-    pub l: T,
-    pub r: T,
+    /// Used by `Reverb::engine_mut()` to access engine specific parameters.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
-#[derive(Clone)]
 pub struct Reverb {
     active: bool,
-
-    roomsize: f32,
-    damp: f32,
-    wet: f32,
-    wet1: f32,
-    wet2: f32,
-    width: f32,
-    gain: f32,
-    comb: [LRPair<Comb>; 8],
-    allpass: [LRPair<AllPass>; 4],
+    /// Built-in algorithm of the engine, `None` for the other engines
+    ty: Option<ReverbType>,
+    params: ReverbParams,
+    sample_rate: f32,
+    block_size: usize,
+    ramp_time: f32,
+    engine: Box<dyn ReverbEngine>,
 }
 
 impl Reverb {
-    pub(crate) fn new(active: bool) -> Self {
+    pub(crate) fn new(sample_rate: f32, block_size: usize, active: bool, ty: ReverbType) -> Self {
+        let mut engine = Self::builtin_engine(ty, sample_rate);
+        engine.set_block_size(block_size);
+
         let mut rev = Self {
             active,
-
-            roomsize: 0.5 * 0.28 + 0.7,
-            damp: 0.2 * 1.0,
-            wet: 1.0 * 3.0,
-            wet1: 0.0,
-            wet2: 0.0,
-            width: 1.0,
-            gain: 0.015,
-            comb: [
-                LRPair {
-                    l: Comb::new(COMBTUNING_L1),
-                    r: Comb::new(COMBTUNING_R1),
-                },
-                LRPair {
-                    l: Comb::new(COMBTUNING_L2),
-                    r: Comb::new(COMBTUNING_R2),
-                },
-                LRPair {
-                    l: Comb::new(COMBTUNING_L3),
-                    r: Comb::new(COMBTUNING_R3),
-                },
-                LRPair {
-                    l: Comb::new(COMBTUNING_L4),
-                    r: Comb::new(COMBTUNING_R4),
-                },
-                LRPair {
-                    l: Comb::new(COMBTUNING_L5),
-                    r: Comb::new(COMBTUNING_R5),
-                },
-                LRPair {
-                    l: Comb::new(COMBTUNING_L6),
-                    r: Comb::new(COMBTUNING_R6),
-                },
-                LRPair {
-                    l: Comb::new(COMBTUNING_L7),
-                    r: Comb::new(COMBTUNING_R7),
-                },
-                LRPair {
-                    l: Comb::new(COMBTUNING_L8),
-                    r: Comb::new(COMBTUNING_R8),
-                },
-            ],
-            allpass: [
-                LRPair {
-                    l: AllPass::new(ALLPASSTUNING_L1, 0.5f32),
-                    r: AllPass::new(ALLPASSTUNING_R1, 0.5f32),
-                },
-                LRPair {
-                    l: AllPass::new(ALLPASSTUNING_L2, 0.5f32),
-                    r: AllPass::new(ALLPASSTUNING_R2, 0.5f32),
-                },
-                LRPair {
-                    l: AllPass::new(ALLPASSTUNING_L3, 0.5f32),
-                    r: AllPass::new(ALLPASSTUNING_R3, 0.5f32),
-                },
-                LRPair {
-                    l: AllPass::new(ALLPASSTUNING_L4, 0.5f32),
-                    r: AllPass::new(ALLPASSTUNING_R4, 0.5f32),
-                },
-            ],
+            ty: Some(ty),
+            params: Default::default(),
+            sample_rate,
+            block_size,
+            ramp_time: EFFECT_RAMP_MS,
+            engine,
         };
         rev.set_reverb(&Default::default());
-        return rev;
+        rev
+    }
+
+    fn builtin_engine(ty: ReverbType, sample_rate: f32) -> Box<dyn ReverbEngine> {
+        match ty {
+            ReverbType::Freeverb => Box::new(Freeverb::new(sample_rate)),
+            ReverbType::Plate => Box::new(PlateReverb::new(sample_rate)),
+        }
     }
 
-    pub(crate) fn reset(self: &mut Self) {
-        self.comb = [
-            LRPair {
-                l: Comb::new(COMBTUNING_L1),
-                r: Comb::new(COMBTUNING_R1),
-            },
-            LRPair {
-                l: Comb::new(COMBTUNING_L2),
-                r: Comb::new(COMBTUNING_R2),
-            },
-            LRPair {
-                l: Comb::new(COMBTUNING_L3),
-                r: Comb::new(COMBTUNING_R3),
-            },
-            LRPair {
-                l: Comb::new(COMBTUNING_L4),
-                r: Comb::new(COMBTUNING_R4),
-            },
-            LRPair {
-                l: Comb::new(COMBTUNING_L5),
-                r: Comb::new(COMBTUNING_R5),
-            },
-            LRPair {
-                l: Comb::new(COMBTUNING_L6),
-                r: Comb::new(COMBTUNING_R6),
-            },
-            LRPair {
-                l: Comb::new(COMBTUNING_L7),
-                r: Comb::new(COMBTUNING_R7),
-            },
-            LRPair {
-                l: Comb::new(COMBTUNING_L8),
-                r: Comb::new(COMBTUNING_R8),
-            },
-        ];
-        self.allpass = [
-            LRPair {
-                l: AllPass::new(ALLPASSTUNING_L1, 0.5f32),
-                r: AllPass::new(ALLPASSTUNING_R1, 0.5f32),
-            },
-            LRPair {
-                l: AllPass::new(ALLPASSTUNING_L2, 0.5f32),
-                r: AllPass::new(ALLPASSTUNING_R2, 0.5f32),
-            },
-            LRPair {
-                l: AllPass::new(ALLPASSTUNING_L3, 0.5f32),
-                r: AllPass::new(ALLPASSTUNING_R3, 0.5f32),
-            },
-            LRPair {
-                l: AllPass::new(ALLPASSTUNING_L4, 0.5f32),
-                r: AllPass::new(ALLPASSTUNING_R4, 0.5f32),
-            },
-        ];
+    /// Built-in algorithm of an engine, `None` for the other ones
+    fn builtin_type(engine: &mut dyn ReverbEngine) -> Option<ReverbType> {
+        let engine = engine.as_any_mut();
+        if engine.is::<Freeverb>() {
+            Some(ReverbType::Freeverb)
+        } else if engine.is::<PlateReverb>() {
+            Some(ReverbType::Plate)
+        } else {
+            None
+        }
     }
 
-    pub(crate) fn process_replace(&mut self, left_out: &mut [f32; 64], right_out: &mut [f32; 64]) {
-        for k in 0..64 {
-            let mut out_r = 0f32;
-            let mut out_l = 0f32;
+    /// Replace the engine by a built-in one with default engine specific parameters
+    pub(crate) fn set_type(&mut self, ty: ReverbType) {
+        self.set_engine(Self::builtin_engine(ty, self.sample_rate));
+    }
 
-            // Don't ask me why only left buf is considered an input...
-            let input = (2.0 * left_out[k] + DC_OFFSET) * self.gain;
+    pub(crate) fn reset(&mut self) {
+        self.engine.reset();
+    }
 
-            for comb in self.comb.iter_mut() {
-                out_l += comb.l.process(input);
-                out_r += comb.r.process(input);
-            }
+    pub(crate) fn save_state(&self, state: &mut StateWriter) {
+        state.section(|state| self.engine.save_state(state));
+    }
 
-            for allpass in self.allpass.iter_mut() {
-                out_l = allpass.l.process(out_l);
-                out_r = allpass.r.process(out_r);
-            }
+    pub(crate) fn restore_state(&mut self, state: &mut StateReader) {
+        state.section(|state| self.engine.restore_state(state));
+    }
 
-            out_l -= DC_OFFSET;
-            out_r -= DC_OFFSET;
+    /**
+    Adapt the reverb to a new sample rate, keeping the reverb parameters.
+     */
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.engine.set_sample_rate(sample_rate);
+    }
 
-            left_out[k] = out_l * self.wet1 + out_r * self.wet2;
-            right_out[k] = out_r * self.wet1 + out_l * self.wet2;
-        }
+    pub(crate) fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.ramp_time = ramp_time;
+        self.engine.set_ramp_time(ramp_time);
+    }
+
+    pub(crate) fn process_replace(&mut self, left_out: &mut [f32], right_out: &mut [f32]) {
+        // Don't ask me why only left buf is considered an input...
+        let mut input = [0f32; MAX_BLOCK_SIZE];
+        let input = &mut input[..left_out.len()];
+        input.copy_from_slice(left_out);
+        left_out.iter_mut().for_each(|v| *v = 0.0);
+        right_out.iter_mut().for_each(|v| *v = 0.0);
+
+        self.engine.process_mix(input, left_out, right_out);
     }
 
     pub(crate) fn process_mix(
         &mut self,
-        in_0: &mut [f32; 64],
-        left_out: &mut [f32; 64],
-        right_out: &mut [f32; 64],
+        in_0: &mut [f32],
+        left_out: &mut [f32],
+        right_out: &mut [f32],
     ) {
-        for k in 0..64 {
-            let mut out_r = 0f32;
-            let mut out_l = out_r;
-            let input = (2.0 * in_0[k] + DC_OFFSET) * self.gain;
-
-            for comb in self.comb.iter_mut() {
-                out_l += comb.l.process(input);
-                out_r += comb.r.process(input);
-            }
-
-            for allpass in self.allpass.iter_mut() {
-                out_l = allpass.l.process(out_l);
-                out_r = allpass.r.process(out_r);
-            }
-
-            out_l -= DC_OFFSET;
-            out_r -= DC_OFFSET;
-
-            left_out[k] += out_l * self.wet1 + out_r * self.wet2;
-            right_out[k] += out_r * self.wet1 + out_l * self.wet2;
-        }
+        self.engine.process_mix(in_0, left_out, right_out);
     }
+}
 
-    pub(crate) fn update(&mut self) {
-        self.wet1 = self.wet * (self.width / 2f32 + 0.5f32);
-        self.wet2 = self.wet * ((1f32 - self.width) / 2f32);
-        for comb in self.comb.iter_mut() {
-            comb.l.set_feedback(self.roomsize);
-            comb.r.set_feedback(self.roomsize);
-            comb.l.set_damp(self.damp);
-            comb.r.set_damp(self.damp);
-        }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Feed an impulse and return the energy of the left output of the following blocks
+    fn impulse_response(rev: &mut Reverb, blocks: usize) -> Vec<f32> {
+        let mut input = [0f32; 64];
+        input[0] = 1.0;
+
+        (0..blocks)
+            .map(|_| {
+                let mut left = [0f32; 64];
+                let mut right = [0f32; 64];
+                rev.process_mix(&mut input, &mut left, &mut right);
+                input[0] = 0.0;
+                left.iter().map(|v| v * v).sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plate() {
+        let mut rev = Reverb::new(44100.0, 64, true, ReverbType::Plate);
+        assert!(rev.engine_mut::<Freeverb>().is_none());
+
+        let plate = rev.engine_mut::<PlateReverb>().unwrap();
+        plate.set_plate(&PlateParams {
+            pre_delay: 100.0,
+            ..plate.plate()
+        });
+
+        // Nothing before the pre-delay (~69 blocks), a decaying tail after it
+        let response = impulse_response(&mut rev, 1000);
+        assert!(response[..60].iter().all(|e| *e == 0.0));
+        assert!(response[70..200].iter().sum::<f32>() > response[800..930].iter().sum::<f32>());
+
+        // The generic parameters are mapped onto the plate ones
+        rev.set_reverb_params(1.0, 0.5, 1.0, 0.5);
+        let plate = rev.engine_mut::<PlateReverb>().unwrap().plate();
+        assert_eq!(plate.pre_delay, 100.0);
+        assert_eq!(plate.damping, 0.5);
+        assert!(plate.decay > 10.0);
+
+        rev.reset();
+        assert!(impulse_response(&mut rev, 60).iter().all(|e| *e == 0.0));
+    }
+
+    #[test]
+    fn set_engine() {
+        let mut rev = Reverb::new(44100.0, 64, true, ReverbType::Freeverb);
+        rev.set_reverb_params(0.3, 0.1, 0.5, 0.5);
+
+        rev.set_engine(Box::new(PlateReverb::new(22050.0)));
+        assert_eq!(rev.reverb().roomsize, 0.3);
+        let plate = rev.engine_mut::<PlateReverb>().unwrap().plate();
+        assert_eq!(plate.damping, 0.1);
+        assert_eq!(plate.level, 0.5);
     }
 }
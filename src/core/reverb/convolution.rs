@@ -0,0 +1,719 @@
+/*!
+Partitioned FFT convolution reverb
+
+The impulse response is split into partitions, each convolved with the
+input in the frequency domain (uniformly partitioned overlap-save, with a
+frequency domain delay line of the input spectra, using real FFTs).
+
+In the uniform mode, every partition is one synth block long (at most 64
+samples), giving a constant CPU load per block, without added latency. The non-uniform mode
+convolves the head of the impulse response the same way, and the tail with
+longer partitions, which is much cheaper for long impulse responses. The
+work of a tail partition is spread over the synth blocks, so the CPU load
+per block stays constant.
+
+All buffers are allocated up front: processing does not allocate.
+ */
+
+use std::any::Any;
+use std::io::Read;
+use std::ops::Range;
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+
+use super::{ReverbEngine, ReverbParams};
+use crate::core::resampler::Resampler;
+use crate::core::snapshot::{snapshot, Snapshot, StateReader, StateWriter};
+use crate::core::utils::{Ramp, EFFECT_RAMP_MS};
+
+/// Longest partition of the head of the impulse response, shorter synth blocks use their size
+const HEAD_BLOCK: usize = 64;
+
+/// Output gain, matching the loudness of Freeverb at the same level
+const GAIN: f32 = 2.1;
+
+/**
+Partitioning scheme of the impulse response
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Partitioning {
+    /// Synth block sized partitions (at most 64 samples), suited for real-time use
+    /// with short impulse responses
+    #[default]
+    Uniform,
+    /// Synth block sized partitions for the first `2 * tail_block` samples of the impulse response,
+    /// `tail_block` sample partitions (a power of two, 128 or more) for the rest
+    NonUniform { tail_block: usize },
+}
+
+/**
+Stereo impulse response
+ */
+#[derive(Debug, Clone)]
+pub struct ImpulseResponse {
+    left: Vec<f32>,
+    right: Vec<f32>,
+    sample_rate: f32,
+}
+
+impl ImpulseResponse {
+    /**
+    Create an impulse response from its left and right channels.
+     */
+    pub fn new(mut left: Vec<f32>, mut right: Vec<f32>, sample_rate: f32) -> Self {
+        let len = left.len().max(right.len());
+        left.resize(len, 0.0);
+        right.resize(len, 0.0);
+
+        Self {
+            left,
+            right,
+            sample_rate,
+        }
+    }
+
+    /**
+    Load an impulse response from a WAV file.
+
+    Mono files are used for both channels, only the first two channels of
+    multichannel files are used.
+     */
+    pub fn load<R: Read>(file: &mut R) -> Result<Self, ()> {
+        let wav = match hound::WavReader::new(file) {
+            Ok(wav) => wav,
+            Err(err) => {
+                log::error!("{:#?}", err);
+                return Err(());
+            }
+        };
+
+        let spec = wav.spec();
+        let samples: Result<Vec<f32>, _> = match spec.sample_format {
+            hound::SampleFormat::Float => wav.into_samples::<f32>().collect(),
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1u32 << (spec.bits_per_sample - 1)) as f32;
+                wav.into_samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 * scale))
+                    .collect()
+            }
+        };
+        let samples = match samples {
+            Ok(samples) => samples,
+            Err(err) => {
+                log::error!("{:#?}", err);
+                return Err(());
+            }
+        };
+
+        let channels = spec.channels as usize;
+        if channels == 0 {
+            log::error!("Impulse response has no channels");
+            return Err(());
+        }
+
+        let frames = samples.chunks_exact(channels);
+        let left = frames.clone().map(|frame| frame[0]).collect();
+        let right = frames.map(|frame| frame[1.min(channels - 1)]).collect();
+
+        Ok(Self::new(left, right, spec.sample_rate as f32))
+    }
+
+    /// Length in frames
+    pub fn len(&self) -> usize {
+        self.left.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left.is_empty()
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /**
+    Both channels at `sample_rate`, normalized to a constant energy.
+     */
+    fn prepare(&self, sample_rate: f32) -> [Vec<f32>; 2] {
+        let (mut left, mut right) = if self.sample_rate == sample_rate {
+            (self.left.clone(), self.right.clone())
+        } else {
+            let len = (self.len() as f64 * sample_rate as f64 / self.sample_rate as f64).ceil();
+            let mut resampler = Resampler::new(self.sample_rate, sample_rate);
+            let mut input = self.left.iter().zip(self.right.iter());
+
+            (0..len as usize)
+                .map(|_| {
+                    resampler.next(|| match input.next() {
+                        Some((l, r)) => (*l, *r),
+                        None => (0.0, 0.0),
+                    })
+                })
+                .unzip()
+        };
+
+        let energy: f32 = left.iter().chain(right.iter()).map(|v| v * v).sum();
+        if energy > 0.0 {
+            let scale = (2.0 / energy).sqrt();
+            left.iter_mut()
+                .chain(right.iter_mut())
+                .for_each(|v| *v *= scale);
+        }
+
+        [left, right]
+    }
+}
+
+/**
+Uniformly partitioned overlap-save convolver, with a mono input and a stereo output.
+
+A block is convolved in three steps: `push()` the input, `accumulate()` the
+partitions, and `output()` the result, which lets the work of a long block
+be spread over several calls.
+ */
+#[derive(Clone)]
+struct Convolver {
+    block: usize,
+    partitions: usize,
+
+    fft: Arc<dyn RealToComplex<f32>>,
+    ifft: Arc<dyn ComplexToReal<f32>>,
+    scratch: Vec<Complex<f32>>,
+    /// Input of the forward transform and output of the inverse one, both overwrite their input
+    frame: Vec<f32>,
+
+    /// Last two blocks of input
+    input: Vec<f32>,
+    /// Frequency domain delay line: spectra of the last `partitions` input windows
+    fdl: Vec<Complex<f32>>,
+    fdl_pos: usize,
+    /// Spectra of the impulse response partitions, per channel
+    ir: [Vec<Complex<f32>>; 2],
+    /// Output spectra being accumulated, per channel
+    acc: [Vec<Complex<f32>>; 2],
+}
+
+impl Snapshot for Complex<f32> {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_f32(self.re);
+        w.write_f32(self.im);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        self.re = r.read_f32();
+        self.im = r.read_f32();
+    }
+}
+
+// The input history, the impulse response spectra come from the engine
+snapshot!(Convolver {
+    input,
+    fdl,
+    fdl_pos,
+    acc
+});
+
+impl Convolver {
+    fn new(ir: [&[f32]; 2], block: usize) -> Self {
+        let size = block * 2;
+        let bins = block + 1;
+        let partitions = ir[0].len().div_ceil(block).max(1);
+
+        let mut planner = RealFftPlanner::new();
+        let fft = planner.plan_fft_forward(size);
+        let ifft = planner.plan_fft_inverse(size);
+        let mut scratch =
+            vec![Complex::default(); fft.get_scratch_len().max(ifft.get_scratch_len())];
+        let mut frame = vec![0.0; size];
+
+        let ir = ir.map(|ir| {
+            let mut spectra = vec![Complex::default(); partitions * bins];
+            for (chunk, spectrum) in ir.chunks(block).zip(spectra.chunks_mut(bins)) {
+                frame.iter_mut().for_each(|v| *v = 0.0);
+                frame[..chunk.len()].copy_from_slice(chunk);
+                // The buffer lengths are the planned ones
+                fft.process_with_scratch(&mut frame, spectrum, &mut scratch)
+                    .ok();
+            }
+            spectra
+        });
+
+        Self {
+            block,
+            partitions,
+
+            fft,
+            ifft,
+            scratch,
+            frame,
+
+            input: vec![0.0; size],
+            fdl: vec![Complex::default(); partitions * bins],
+            fdl_pos: 0,
+            ir,
+            acc: [
+                vec![Complex::default(); bins],
+                vec![Complex::default(); bins],
+            ],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.input.iter_mut().for_each(|v| *v = 0.0);
+        self.fdl.iter_mut().for_each(|v| *v = Complex::default());
+        self.acc
+            .iter_mut()
+            .flatten()
+            .for_each(|v| *v = Complex::default());
+    }
+
+    /// Convolve one block of input, replacing one block of output
+    fn process(&mut self, input: &[f32], left_out: &mut [f32], right_out: &mut [f32]) {
+        self.push(input);
+        self.accumulate(0..self.partitions);
+        self.output(left_out, right_out);
+    }
+
+    /// Add one block of input to the delay line
+    fn push(&mut self, input: &[f32]) {
+        let block = self.block;
+        let bins = block + 1;
+
+        self.input.copy_within(block.., 0);
+        self.input[block..].copy_from_slice(input);
+        self.frame.copy_from_slice(&self.input);
+
+        let spectrum = &mut self.fdl[self.fdl_pos * bins..(self.fdl_pos + 1) * bins];
+        self.fft
+            .process_with_scratch(&mut self.frame, spectrum, &mut self.scratch)
+            .ok();
+    }
+
+    /// Accumulate the products of the last input spectra with the `partitions`
+    fn accumulate(&mut self, partitions: Range<usize>) {
+        let bins = self.block + 1;
+
+        for (ir, acc) in self.ir.iter().zip(self.acc.iter_mut()) {
+            // Input spectrum of `p` blocks ago times the `p`th partition
+            for p in partitions.clone() {
+                let slot = (self.fdl_pos + self.partitions - p) % self.partitions;
+                let x = &self.fdl[slot * bins..(slot + 1) * bins];
+                let h = &ir[p * bins..(p + 1) * bins];
+
+                for ((acc, x), h) in acc.iter_mut().zip(x).zip(h) {
+                    *acc += x * h;
+                }
+            }
+        }
+    }
+
+    /// Replace one block of output with the accumulated spectra, and start the next block
+    fn output(&mut self, left_out: &mut [f32], right_out: &mut [f32]) {
+        let block = self.block;
+
+        let scale = 1.0 / (block * 2) as f32;
+        for (acc, out) in self.acc.iter_mut().zip([left_out, right_out]) {
+            self.ifft
+                .process_with_scratch(acc, &mut self.frame, &mut self.scratch)
+                .ok();
+            for (out, v) in out.iter_mut().zip(self.frame[block..].iter()) {
+                *out = v * scale;
+            }
+            acc.iter_mut().for_each(|v| *v = Complex::default());
+        }
+
+        self.fdl_pos = (self.fdl_pos + 1) % self.partitions;
+    }
+}
+
+/**
+Convolution of the tail of the impulse response, in long blocks.
+
+The tail starts two `block`s into the impulse response: the input
+collected over a block is convolved a share of the partitions at a time
+over the next block, and its output is played back over the one after,
+which keeps the load of every synth block the same.
+ */
+#[derive(Clone)]
+struct TailStage {
+    convolver: Convolver,
+    /// Input being collected
+    input: Vec<f32>,
+    /// Output being played back
+    output: [Vec<f32>; 2],
+    pos: usize,
+}
+
+snapshot!(TailStage {
+    convolver,
+    input,
+    output,
+    pos
+});
+
+impl TailStage {
+    fn new(ir: [&[f32]; 2], block: usize) -> Self {
+        Self {
+            convolver: Convolver::new(ir, block),
+            input: vec![0.0; block],
+            output: [vec![0.0; block], vec![0.0; block]],
+            pos: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.convolver.reset();
+        self.input.iter_mut().for_each(|v| *v = 0.0);
+        self.output.iter_mut().flatten().for_each(|v| *v = 0.0);
+        self.pos = 0;
+    }
+
+    /// Play back and collect one head block, adding the output to `left` and `right`
+    fn process(&mut self, input: &[f32], left: &mut [f32], right: &mut [f32]) {
+        let len = input.len();
+        let range = self.pos..self.pos + len;
+        for (out, tail_out) in [left, right].iter_mut().zip(self.output.iter()) {
+            for (out, v) in out.iter_mut().zip(tail_out[range.clone()].iter()) {
+                *out += v;
+            }
+        }
+
+        let steps = self.input.len() / len;
+        let step = self.pos / len;
+        if step == 0 {
+            // The input collected over the last block
+            self.convolver.push(&self.input);
+        }
+        self.input[range].copy_from_slice(input);
+
+        let partitions = self.convolver.partitions;
+        self.convolver
+            .accumulate(step * partitions / steps..(step + 1) * partitions / steps);
+
+        self.pos += len;
+        if self.pos == self.input.len() {
+            // Everything from this block has been played back
+            let [left, right] = &mut self.output;
+            self.convolver.output(left, right);
+            self.pos = 0;
+        }
+    }
+}
+
+/**
+Convolution reverb, with a user supplied impulse response.
+
+The impulse response is normalized, and resampled to the synth sample rate when needed.
+ */
+#[derive(Clone)]
+pub struct ConvolutionReverb {
+    ir: ImpulseResponse,
+    partitioning: Partitioning,
+    sample_rate: f32,
+    ramp_time: f32,
+    /// Partition length of the head
+    head_block: usize,
+
+    head: Convolver,
+    tail: Option<TailStage>,
+
+    wet1: Ramp,
+    wet2: Ramp,
+}
+
+impl ConvolutionReverb {
+    pub fn new(sample_rate: f32, ir: ImpulseResponse, partitioning: Partitioning) -> Self {
+        Self::with_head_block(sample_rate, ir, partitioning, HEAD_BLOCK)
+    }
+
+    fn with_head_block(
+        sample_rate: f32,
+        ir: ImpulseResponse,
+        partitioning: Partitioning,
+        head_block: usize,
+    ) -> Self {
+        let [left, right] = ir.prepare(sample_rate);
+
+        let (head, tail) = match partitioning {
+            Partitioning::Uniform => (Convolver::new([&left, &right], head_block), None),
+            Partitioning::NonUniform { tail_block } => {
+                let tail_block = tail_block.next_power_of_two().max(HEAD_BLOCK * 2);
+                let head_len = (tail_block * 2).min(left.len());
+
+                let head = Convolver::new([&left[..head_len], &right[..head_len]], head_block);
+                let tail = if left.len() > head_len {
+                    Some(TailStage::new(
+                        [&left[head_len..], &right[head_len..]],
+                        tail_block,
+                    ))
+                } else {
+                    None
+                };
+                (head, tail)
+            }
+        };
+
+        let mut rev = Self {
+            ir,
+            partitioning,
+            sample_rate,
+            ramp_time: EFFECT_RAMP_MS,
+            head_block,
+
+            head,
+            tail,
+
+            wet1: Ramp::default(),
+            wet2: Ramp::default(),
+        };
+        rev.set_params(&Default::default());
+        rev.wet1.finish();
+        rev.wet2.finish();
+        rev
+    }
+
+    pub fn impulse_response(&self) -> &ImpulseResponse {
+        &self.ir
+    }
+
+    pub fn partitioning(&self) -> Partitioning {
+        self.partitioning
+    }
+}
+
+impl ReverbEngine for ConvolutionReverb {
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate != self.sample_rate {
+            let (mut wet1, mut wet2, ramp_time) = (self.wet1, self.wet2, self.ramp_time);
+            *self = Self::with_head_block(
+                sample_rate,
+                self.ir.clone(),
+                self.partitioning,
+                self.head_block,
+            );
+            wet1.finish();
+            wet2.finish();
+            self.ramp_time = ramp_time;
+            self.wet1 = wet1;
+            self.wet2 = wet2;
+        }
+    }
+
+    /**
+    Blocks shorter than 64 samples become the partition length of the head,
+    longer ones are split into 64 sample partitions.
+     */
+    fn set_block_size(&mut self, block_size: usize) {
+        let head_block = block_size.min(HEAD_BLOCK);
+        if head_block != self.head_block {
+            let (wet1, wet2, ramp_time) = (self.wet1, self.wet2, self.ramp_time);
+            *self = Self::with_head_block(
+                self.sample_rate,
+                self.ir.clone(),
+                self.partitioning,
+                head_block,
+            );
+            self.ramp_time = ramp_time;
+            self.wet1 = wet1;
+            self.wet2 = wet2;
+        }
+    }
+
+    fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.ramp_time = ramp_time;
+    }
+
+    fn reset(&mut self) {
+        self.head.reset();
+        if let Some(tail) = self.tail.as_mut() {
+            tail.reset();
+        }
+    }
+
+    /**
+    Only the level and the width apply, the room is given by the impulse response.
+     */
+    fn set_params(&mut self, params: &ReverbParams) {
+        let wet = params.level * GAIN;
+        let len = Ramp::samples(self.ramp_time, self.sample_rate);
+        self.wet1.set(wet * (params.width / 2.0 + 0.5), len);
+        self.wet2.set(wet * ((1.0 - params.width) / 2.0), len);
+    }
+
+    fn process_mix(&mut self, input: &[f32], left_out: &mut [f32], right_out: &mut [f32]) {
+        let head_block = self.head_block;
+        let outputs = left_out
+            .chunks_mut(head_block)
+            .zip(right_out.chunks_mut(head_block));
+
+        for (input, (left_out, right_out)) in input.chunks(head_block).zip(outputs) {
+            let mut left = [0f32; HEAD_BLOCK];
+            let mut right = [0f32; HEAD_BLOCK];
+            let (left, right) = (&mut left[..head_block], &mut right[..head_block]);
+            self.head.process(input, left, right);
+
+            if let Some(tail) = self.tail.as_mut() {
+                tail.process(input, left, right);
+            }
+
+            for k in 0..head_block {
+                let wet1 = self.wet1.step();
+                let wet2 = self.wet2.step();
+                left_out[k] += left[k] * wet1 + right[k] * wet2;
+                right_out[k] += right[k] * wet1 + left[k] * wet2;
+            }
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.head.save(state);
+        state.write_bool(self.tail.is_some());
+        if let Some(tail) = self.tail.as_ref() {
+            tail.save(state);
+        }
+        self.wet1.save(state);
+        self.wet2.save(state);
+    }
+
+    fn restore_state(&mut self, state: &mut StateReader) {
+        self.head.restore(state);
+        // The impulse response and the partitioning must be the saved ones
+        if state.read_bool() != self.tail.is_some() {
+            state.invalidate();
+        }
+        if let Some(tail) = self.tail.as_mut() {
+            tail.restore(state);
+        }
+        self.wet1.restore(state);
+        self.wet2.restore(state);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn noise(len: usize, seed: &mut u32) -> Vec<f32> {
+        (0..len)
+            .map(|_| {
+                *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (*seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+            })
+            .collect()
+    }
+
+    /// Left output of the reverb for `input`, processed block by block
+    fn render(rev: &mut ConvolutionReverb, input: &[f32]) -> Vec<f32> {
+        render_blocks(rev, input, 64)
+    }
+
+    fn render_blocks(rev: &mut ConvolutionReverb, input: &[f32], block_size: usize) -> Vec<f32> {
+        rev.set_block_size(block_size);
+        input
+            .chunks(block_size)
+            .flat_map(|chunk| {
+                let mut left = vec![0f32; block_size];
+                let mut right = vec![0f32; block_size];
+                rev.process_mix(chunk, &mut left, &mut right);
+                left
+            })
+            .collect()
+    }
+
+    #[test]
+    fn convolve() {
+        let mut seed = 1;
+        let ir = noise(300, &mut seed);
+        let input = noise(64 * 12, &mut seed);
+
+        let mut convolver = Convolver::new([&ir, &ir], 64);
+        let mut output = vec![];
+        for block in input.chunks(64) {
+            let mut left = [0f32; 64];
+            let mut right = [0f32; 64];
+            convolver.process(block, &mut left, &mut right);
+            output.extend_from_slice(&left);
+        }
+
+        for (n, out) in output.iter().enumerate() {
+            let expected: f32 = (0..=n.min(ir.len() - 1))
+                .map(|k| ir[k] * input[n - k])
+                .sum();
+            assert!(
+                (out - expected).abs() < 1e-4,
+                "{}: {} != {}",
+                n,
+                out,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn non_uniform() {
+        let mut seed = 2;
+        let ir = ImpulseResponse::new(noise(2000, &mut seed), noise(1500, &mut seed), 44100.0);
+        let input = noise(64 * 64, &mut seed);
+
+        let mut uniform = ConvolutionReverb::new(44100.0, ir.clone(), Partitioning::Uniform);
+        let mut non_uniform =
+            ConvolutionReverb::new(44100.0, ir, Partitioning::NonUniform { tail_block: 256 });
+
+        let a = render(&mut uniform, &input);
+        let b = render(&mut non_uniform, &input);
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        // Short blocks use shorter head partitions, long ones several partitions
+        for block_size in [16, 256] {
+            non_uniform.reset();
+            let c = render_blocks(&mut non_uniform.clone(), &input, block_size);
+            for (a, c) in a.iter().zip(c.iter()) {
+                assert!((a - c).abs() < 1e-4);
+            }
+        }
+
+        non_uniform.reset();
+        assert!(render(&mut non_uniform, &[0.0; 64 * 64])
+            .iter()
+            .all(|v| *v == 0.0));
+    }
+
+    #[test]
+    fn load_wav() {
+        let mut wav = std::io::Cursor::new(Vec::new());
+        {
+            let spec = hound::WavSpec {
+                channels: 1,
+                sample_rate: 48000,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            };
+            let mut writer = hound::WavWriter::new(&mut wav, spec).unwrap();
+            for sample in [16384i16, -16384, 0] {
+                writer.write_sample(sample).unwrap();
+            }
+            writer.finalize().unwrap();
+        }
+        wav.set_position(0);
+
+        let ir = ImpulseResponse::load(&mut wav).unwrap();
+        assert_eq!(ir.len(), 3);
+        assert_eq!(ir.sample_rate(), 48000.0);
+        assert_eq!(ir.left, [0.5, -0.5, 0.0]);
+        assert_eq!(ir.right, ir.left);
+
+        // Resampled to the synth rate
+        let [left, _] = ir.prepare(96000.0);
+        assert_eq!(left.len(), 6);
+
+        assert!(ImpulseResponse::load(&mut std::io::Cursor::new(b"RIFF")).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use std::any::Any;
+
+use super::{ReverbEngine, ReverbParams};
+use crate::core::snapshot::{snapshot, Snapshot, StateReader, StateWriter};
+use crate::core::utils::{Ramp, EFFECT_RAMP_MS};
+
+const DC_OFFSET: f32 = 1e-8;
+const STEREO_SPREAD: usize = 23;
+
+/// Delay line lengths of the left channel, in samples at 44.1kHz
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+
+/// Sample rate the delay line lengths are tuned for
+const TUNING_SAMPLE_RATE: f32 = 44100.0;
+
+#[derive(Clone)]
+struct Comb {
+    feedback: f32,
+    filterstore: f32,
+    damp1: f32,
+    damp2: f32,
+    buffer: Vec<f32>,
+    bufidx: usize,
+}
+
+snapshot!(Comb {
+    filterstore,
+    buffer,
+    bufidx
+});
+
+impl Comb {
+    pub fn new(size: usize) -> Self {
+        return Self {
+            feedback: 0f32,
+            filterstore: 0f32,
+            damp1: 0f32,
+            damp2: 0f32,
+            buffer: vec![DC_OFFSET; size],
+            bufidx: 0,
+        };
+    }
+
+    pub fn set_damp(&mut self, val: f32) {
+        self.damp1 = val;
+        self.damp2 = 1f32 - val;
+    }
+
+    pub fn set_feedback(&mut self, val: f32) {
+        self.feedback = val;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let mut _tmp = self.buffer[self.bufidx];
+        self.filterstore = _tmp * self.damp2 + self.filterstore * self.damp1;
+        self.buffer[self.bufidx] = input + self.filterstore * self.feedback;
+        self.bufidx += 1;
+        if self.bufidx >= self.buffer.len() {
+            self.bufidx = 0
+        }
+        return _tmp;
+    }
+}
+
+#[derive(Clone)]
+struct AllPass {
+    feedback: f32,
+    buffer: Vec<f32>,
+    bufidx: usize,
+}
+
+snapshot!(AllPass { buffer, bufidx });
+
+impl AllPass {
+    pub fn new(size: usize, feedback: f32) -> Self {
+        return Self {
+            feedback,
+            buffer: vec![DC_OFFSET; size],
+            bufidx: 0,
+        };
+    }
+
+    pub fn process(self: &mut Self, input: f32) -> f32 {
+        let bufout: f32 = self.buffer[self.bufidx];
+        let output: f32 = bufout - input;
+        self.buffer[self.bufidx] = input + bufout * self.feedback;
+        self.bufidx += 1;
+        if self.bufidx >= self.buffer.len() {
+            self.bufidx = 0
+        }
+        return output;
+    }
+}
+
+#[derive(Clone)]
+struct LRPair<T> {
+    pub l: T,
+    pub r: T,
+}
+
+impl<T: Snapshot> Snapshot for LRPair<T> {
+    fn save(&self, w: &mut StateWriter) {
+        self.l.save(w);
+        self.r.save(w);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        self.l.restore(r);
+        self.r.restore(r);
+    }
+}
+
+/**
+Freeverb, the classic Schroeder/Moorer reverb of 8 parallel comb
+filters followed by 4 serial allpass filters per channel.
+ */
+#[derive(Clone)]
+pub struct Freeverb {
+    roomsize: f32,
+    damp: f32,
+    wet: f32,
+    wet1: Ramp,
+    wet2: Ramp,
+    width: f32,
+    gain: f32,
+    sample_rate: f32,
+    ramp_time: f32,
+    comb: [LRPair<Comb>; 8],
+    allpass: [LRPair<AllPass>; 4],
+}
+
+snapshot!(Freeverb {
+    wet1,
+    wet2,
+    comb,
+    allpass
+});
+
+impl Freeverb {
+    pub fn new(sample_rate: f32) -> Self {
+        let (comb, allpass) = Self::delay_lines(sample_rate);
+        let mut rev = Self {
+            roomsize: 0.5 * 0.28 + 0.7,
+            damp: 0.2 * 1.0,
+            wet: 1.0 * 3.0,
+            wet1: Ramp::default(),
+            wet2: Ramp::default(),
+            width: 1.0,
+            gain: 0.015,
+            sample_rate,
+            ramp_time: EFFECT_RAMP_MS,
+            comb,
+            allpass,
+        };
+        rev.set_params(&Default::default());
+        rev.wet1.finish();
+        rev.wet2.finish();
+        return rev;
+    }
+
+    /**
+    Comb and allpass filters, with the delay line lengths scaled to the sample rate.
+     */
+    fn delay_lines(sample_rate: f32) -> ([LRPair<Comb>; 8], [LRPair<AllPass>; 4]) {
+        let len = |tuning: usize| {
+            let len = tuning as f64 * sample_rate as f64 / TUNING_SAMPLE_RATE as f64;
+            (len.round() as usize).max(1)
+        };
+        let spread = len(STEREO_SPREAD);
+
+        let comb = COMB_TUNINGS.map(|tuning| LRPair {
+            l: Comb::new(len(tuning)),
+            r: Comb::new(len(tuning) + spread),
+        });
+        let allpass = ALLPASS_TUNINGS.map(|tuning| LRPair {
+            l: AllPass::new(len(tuning), 0.5f32),
+            r: AllPass::new(len(tuning) + spread, 0.5f32),
+        });
+
+        (comb, allpass)
+    }
+
+    fn update(&mut self) {
+        let len = Ramp::samples(self.ramp_time, self.sample_rate);
+        self.wet1.set(self.wet * (self.width / 2f32 + 0.5f32), len);
+        self.wet2.set(self.wet * ((1f32 - self.width) / 2f32), len);
+        for comb in self.comb.iter_mut() {
+            comb.l.set_feedback(self.roomsize);
+            comb.r.set_feedback(self.roomsize);
+            comb.l.set_damp(self.damp);
+            comb.r.set_damp(self.damp);
+        }
+    }
+}
+
+impl ReverbEngine for Freeverb {
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        let (comb, allpass) = Self::delay_lines(self.sample_rate);
+        self.comb = comb;
+        self.allpass = allpass;
+        self.update();
+        self.wet1.finish();
+        self.wet2.finish();
+    }
+
+    fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.ramp_time = ramp_time;
+    }
+
+    fn set_params(&mut self, params: &ReverbParams) {
+        self.roomsize = params.roomsize * 0.28 + 0.7;
+        self.damp = params.damp * 1.0;
+        self.width = params.width;
+        self.wet = params.level * 3.0;
+        self.update();
+    }
+
+    fn process_mix(&mut self, input: &[f32], left_out: &mut [f32], right_out: &mut [f32]) {
+        for k in 0..input.len() {
+            let mut out_r = 0f32;
+            let mut out_l = out_r;
+            let input = (2.0 * input[k] + DC_OFFSET) * self.gain;
+
+            for comb in self.comb.iter_mut() {
+                out_l += comb.l.process(input);
+                out_r += comb.r.process(input);
+            }
+
+            for allpass in self.allpass.iter_mut() {
+                out_l = allpass.l.process(out_l);
+                out_r = allpass.r.process(out_r);
+            }
+
+            out_l -= DC_OFFSET;
+            out_r -= DC_OFFSET;
+
+            let wet1 = self.wet1.step();
+            let wet2 = self.wet2.step();
+            left_out[k] += out_l * wet1 + out_r * wet2;
+            right_out[k] += out_r * wet1 + out_l * wet2;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.save(state);
+    }
+
+    fn restore_state(&mut self, state: &mut StateReader) {
+        self.restore(state);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
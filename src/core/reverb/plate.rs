@@ -0,0 +1,430 @@
+/*!
+Plate reverb, after Jon Dattorro, "Effect Design Part 1: Reverberator
+and Other Filters" (J. Audio Eng. Soc., 1997).
+
+The input goes through a pre-delay, a bandwidth filter and four allpass
+diffusers, into a "figure eight" tank of two cross-coupled halves, each made
+of a modulated allpass, a delay, a damping lowpass and a second allpass.
+The output is a sum of taps from the tank delay lines.
+ */
+
+use std::any::Any;
+
+use super::{ReverbEngine, ReverbParams};
+use crate::core::snapshot::{snapshot, Snapshot, StateReader, StateWriter};
+use crate::core::utils::{Ramp, EFFECT_RAMP_MS};
+
+/// Sample rate the delay line lengths of the paper are given for
+const TUNING_SAMPLE_RATE: f64 = 29761.0;
+
+const INPUT_DIFFUSERS: [usize; 4] = [142, 107, 379, 277];
+const INPUT_DIFFUSION: [f32; 4] = [0.75, 0.75, 0.625, 0.625];
+const DECAY_DIFFUSION_1: f32 = 0.7;
+
+/// Modulated allpass, delay, allpass and delay lengths of both tank halves
+const TANK: [[usize; 4]; 2] = [[672, 4453, 1800, 3720], [908, 4217, 2656, 3163]];
+/// Peak excursion of the modulated allpass delays
+const EXCURSION: usize = 16;
+const LFO_HZ: f32 = 1.0;
+
+const BANDWIDTH: f32 = 0.9995;
+const MAX_PRE_DELAY_MS: f32 = 500.0;
+
+/// Output tap: tank half, line (1: first delay, 2: second allpass, 3: second delay),
+/// position, sign
+type Tap = (usize, usize, usize, f32);
+const LEFT_TAPS: [Tap; 7] = [
+    (1, 1, 266, 1.0),
+    (1, 1, 2974, 1.0),
+    (1, 2, 1913, -1.0),
+    (1, 3, 1996, 1.0),
+    (0, 1, 1990, -1.0),
+    (0, 2, 187, -1.0),
+    (0, 3, 1066, -1.0),
+];
+const RIGHT_TAPS: [Tap; 7] = [
+    (0, 1, 353, 1.0),
+    (0, 1, 3627, 1.0),
+    (0, 2, 1228, -1.0),
+    (0, 3, 2673, 1.0),
+    (1, 1, 2111, -1.0),
+    (1, 2, 335, -1.0),
+    (1, 3, 121, -1.0),
+];
+const TAP_GAIN: f32 = 0.6;
+
+/// Output gain, matching the loudness of Freeverb at the same level
+const GAIN: f32 = 2.25;
+
+/// Mean comb filter length of Freeverb, used to map the room size onto a decay time
+const FREEVERB_LOOP_SECONDS: f32 = 1378.0 / 44100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlateParams {
+    /// Delay before the reverb onset, in ms (0-500)
+    pub pre_delay: f32,
+    /// Time for the tail to decay by 60dB, in seconds
+    pub decay: f32,
+    /// High frequency damping of the tail (0-1)
+    pub damping: f32,
+    /// Density of the echoes (0-1)
+    pub diffusion: f32,
+    pub width: f32,
+    pub level: f32,
+}
+
+snapshot!(PlateParams {
+    pre_delay,
+    decay,
+    damping,
+    diffusion,
+    width,
+    level,
+});
+
+impl Default for PlateParams {
+    fn default() -> Self {
+        Self {
+            pre_delay: 10.0,
+            decay: 2.0,
+            damping: 0.0005,
+            diffusion: 1.0,
+            width: 0.5,
+            level: 0.9,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct DelayLine {
+    buffer: Vec<f32>,
+    pos: usize,
+}
+
+snapshot!(DelayLine { buffer, pos });
+
+impl DelayLine {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; len.max(1)],
+            pos: 0,
+        }
+    }
+
+    /// Sample pushed `delay` samples ago, `delay` in the 1..=len range
+    fn tap(&self, delay: usize) -> f32 {
+        let len = self.buffer.len();
+        self.buffer[(self.pos + len - delay.min(len)) % len]
+    }
+
+    /// Linearly interpolated tap, `delay` in the 1..len range
+    fn tap_frac(&self, delay: f32) -> f32 {
+        let id = delay as usize;
+        let frac = delay - id as f32;
+        self.tap(id) * (1.0 - frac) + self.tap(id + 1) * frac
+    }
+
+    fn push(&mut self, value: f32) {
+        self.buffer[self.pos] = value;
+        self.pos = (self.pos + 1) % self.buffer.len();
+    }
+
+    fn clear(&mut self) {
+        self.buffer.fill(0.0);
+        self.pos = 0;
+    }
+}
+
+#[derive(Clone)]
+struct AllPass {
+    line: DelayLine,
+    delay: f32,
+}
+
+snapshot!(AllPass { line });
+
+impl AllPass {
+    fn new(delay: usize, excursion: usize) -> Self {
+        Self {
+            line: DelayLine::new(delay + excursion + 2),
+            delay: delay as f32,
+        }
+    }
+
+    fn process(&mut self, input: f32, coef: f32, modulation: f32) -> f32 {
+        let delayed = self.line.tap_frac(self.delay + modulation);
+        let v = input + coef * delayed;
+        self.line.push(v);
+        delayed - coef * v
+    }
+}
+
+#[derive(Clone)]
+struct TankHalf {
+    mod_allpass: AllPass,
+    delay1: DelayLine,
+    allpass: AllPass,
+    delay2: DelayLine,
+    damp_state: f32,
+}
+
+snapshot!(TankHalf {
+    mod_allpass,
+    delay1,
+    allpass,
+    delay2,
+    damp_state,
+});
+
+impl TankHalf {
+    /// Output of the half, fed into the other half
+    fn output(&self) -> f32 {
+        self.delay2.tap(self.delay2.buffer.len())
+    }
+
+    fn line(&self, id: usize) -> &DelayLine {
+        match id {
+            1 => &self.delay1,
+            2 => &self.allpass.line,
+            _ => &self.delay2,
+        }
+    }
+}
+
+/**
+Dattorro plate reverb
+ */
+#[derive(Clone)]
+pub struct PlateReverb {
+    params: PlateParams,
+    sample_rate: f32,
+    ramp_time: f32,
+
+    pre_delay: DelayLine,
+    pre_delay_len: usize,
+    bandwidth_state: f32,
+    diffusers: [AllPass; 4],
+    tank: [TankHalf; 2],
+
+    left_taps: [Tap; 7],
+    right_taps: [Tap; 7],
+    excursion: f32,
+    lfo_phase: f32,
+    lfo_incr: f32,
+
+    decay: f32,
+    wet1: Ramp,
+    wet2: Ramp,
+}
+
+// The plate parameters, which the generic ones don't cover, are saved along
+snapshot!(PlateReverb {
+    params,
+    pre_delay,
+    pre_delay_len,
+    bandwidth_state,
+    diffusers,
+    tank,
+    lfo_phase,
+    decay,
+    wet1,
+    wet2,
+});
+
+impl PlateReverb {
+    pub fn new(sample_rate: f32) -> Self {
+        let scale = |len: usize| {
+            ((len as f64 * sample_rate as f64 / TUNING_SAMPLE_RATE).round() as usize).max(1)
+        };
+        let excursion = scale(EXCURSION);
+
+        let tank = TANK.map(|[mod_allpass, delay1, allpass, delay2]| TankHalf {
+            mod_allpass: AllPass::new(scale(mod_allpass), excursion),
+            delay1: DelayLine::new(scale(delay1)),
+            allpass: AllPass::new(scale(allpass), 0),
+            delay2: DelayLine::new(scale(delay2)),
+            damp_state: 0.0,
+        });
+        let taps =
+            |taps: [Tap; 7]| taps.map(|(half, line, pos, sign)| (half, line, scale(pos), sign));
+
+        let mut rev = Self {
+            params: PlateParams::default(),
+            sample_rate,
+            ramp_time: EFFECT_RAMP_MS,
+
+            pre_delay: DelayLine::new((MAX_PRE_DELAY_MS / 1000.0 * sample_rate) as usize + 1),
+            pre_delay_len: 1,
+            bandwidth_state: 0.0,
+            diffusers: INPUT_DIFFUSERS.map(|len| AllPass::new(scale(len), 0)),
+            tank,
+
+            left_taps: taps(LEFT_TAPS),
+            right_taps: taps(RIGHT_TAPS),
+            excursion: excursion as f32,
+            lfo_phase: 0.0,
+            lfo_incr: LFO_HZ / sample_rate,
+
+            decay: 0.0,
+            wet1: Ramp::default(),
+            wet2: Ramp::default(),
+        };
+        rev.set_plate(&PlateParams::default());
+        rev.wet1.finish();
+        rev.wet2.finish();
+        rev
+    }
+
+    /// Set the plate reverb parameters
+    pub fn set_plate(&mut self, params: &PlateParams) {
+        let params = PlateParams {
+            pre_delay: params.pre_delay.clamp(0.0, MAX_PRE_DELAY_MS),
+            decay: params.decay.max(0.1),
+            damping: params.damping.clamp(0.0, 1.0),
+            diffusion: params.diffusion.clamp(0.0, 1.0),
+            width: params.width,
+            level: params.level.clamp(0.0, 1.0),
+        };
+
+        self.pre_delay_len = ((params.pre_delay / 1000.0 * self.sample_rate) as usize).max(1);
+
+        // Both tank halves are traversed per loop, with the decay applied twice in each
+        let loop_samples: usize = TANK.iter().flatten().sum();
+        let loop_seconds = loop_samples as f32 / TUNING_SAMPLE_RATE as f32;
+        self.decay = 10f32.powf(-3.0 * loop_seconds / (4.0 * params.decay));
+
+        let wet = params.level * GAIN;
+        let len = Ramp::samples(self.ramp_time, self.sample_rate);
+        self.wet1.set(wet * (params.width / 2.0 + 0.5), len);
+        self.wet2.set(wet * ((1.0 - params.width) / 2.0), len);
+
+        self.params = params;
+    }
+
+    /// Query the plate reverb parameters
+    pub fn plate(&self) -> PlateParams {
+        self.params
+    }
+
+    fn tap(&self, taps: &[Tap; 7]) -> f32 {
+        taps.iter()
+            .map(|(half, line, pos, sign)| sign * self.tank[*half].line(*line).tap(pos + 1))
+            .sum::<f32>()
+            * TAP_GAIN
+    }
+}
+
+impl ReverbEngine for PlateReverb {
+    fn set_sample_rate(&mut self, sample_rate: f32) {
+        let (params, ramp_time) = (self.params, self.ramp_time);
+        *self = Self::new(sample_rate);
+        self.ramp_time = ramp_time;
+        self.set_plate(&params);
+        self.wet1.finish();
+        self.wet2.finish();
+    }
+
+    fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.ramp_time = ramp_time;
+    }
+
+    fn reset(&mut self) {
+        self.pre_delay.clear();
+        self.bandwidth_state = 0.0;
+        for diffuser in self.diffusers.iter_mut() {
+            diffuser.line.clear();
+        }
+        for half in self.tank.iter_mut() {
+            half.mod_allpass.line.clear();
+            half.delay1.clear();
+            half.allpass.line.clear();
+            half.delay2.clear();
+            half.damp_state = 0.0;
+        }
+        self.lfo_phase = 0.0;
+        self.wet1.finish();
+        self.wet2.finish();
+    }
+
+    /**
+    Map the generic parameters: the room size onto the decay time of a
+    Freeverb of the same room size, the damping onto the HF damping.
+     */
+    fn set_params(&mut self, params: &ReverbParams) {
+        let feedback = params.roomsize * 0.28 + 0.7;
+        let decay = if feedback > 0.0 && feedback < 1.0 {
+            3.0 * FREEVERB_LOOP_SECONDS / -feedback.log10()
+        } else {
+            f32::MAX
+        };
+
+        self.set_plate(&PlateParams {
+            decay,
+            damping: params.damp,
+            width: params.width,
+            level: params.level,
+            ..self.params
+        });
+    }
+
+    fn process_mix(&mut self, input: &[f32], left_out: &mut [f32], right_out: &mut [f32]) {
+        let diffusion = self.params.diffusion;
+        let damping = self.params.damping;
+        let decay_diffusion_2 = (self.decay + 0.15).clamp(0.25, 0.5);
+
+        for k in 0..input.len() {
+            let delayed = self.pre_delay.tap(self.pre_delay_len);
+            self.pre_delay.push(input[k]);
+
+            self.bandwidth_state = BANDWIDTH * delayed + (1.0 - BANDWIDTH) * self.bandwidth_state;
+            let mut x = self.bandwidth_state;
+            for (diffuser, coef) in self.diffusers.iter_mut().zip(INPUT_DIFFUSION.iter()) {
+                x = diffuser.process(x, coef * diffusion, 0.0);
+            }
+
+            let lfo = (self.lfo_phase * 2.0 * std::f32::consts::PI).sin();
+            let lfo = [lfo, (self.lfo_phase * 2.0 * std::f32::consts::PI).cos()];
+            self.lfo_phase = (self.lfo_phase + self.lfo_incr).fract();
+
+            let feedback = [self.tank[1].output(), self.tank[0].output()];
+            for ((half, feedback), lfo) in self.tank.iter_mut().zip(feedback.iter()).zip(lfo.iter())
+            {
+                let v = x + self.decay * feedback;
+                let v = half.mod_allpass.process(
+                    v,
+                    -DECAY_DIFFUSION_1 * diffusion,
+                    lfo * self.excursion,
+                );
+
+                let delayed = half.delay1.tap(half.delay1.buffer.len());
+                half.delay1.push(v);
+
+                half.damp_state = delayed * (1.0 - damping) + half.damp_state * damping;
+                let v = half
+                    .allpass
+                    .process(half.damp_state * self.decay, decay_diffusion_2, 0.0);
+                half.delay2.push(v);
+            }
+
+            let out_l = self.tap(&self.left_taps);
+            let out_r = self.tap(&self.right_taps);
+
+            let wet1 = self.wet1.step();
+            let wet2 = self.wet2.step();
+            left_out[k] += out_l * wet1 + out_r * wet2;
+            right_out[k] += out_r * wet1 + out_l * wet2;
+        }
+    }
+
+    fn save_state(&self, state: &mut StateWriter) {
+        self.save(state);
+    }
+
+    fn restore_state(&mut self, state: &mut StateReader) {
+        self.restore(state);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -1,6 +1,7 @@
-use super::Reverb;
+use super::{Reverb, ReverbEngine, ReverbType};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ReverbParams {
     pub roomsize: f32,
     pub damp: f32,
@@ -30,51 +31,57 @@ impl Reverb {
         self.active
     }
 
-    /// Set the current reverb room size
-    fn set_room_size(&mut self, value: f32) {
-        self.roomsize = value * 0.28 + 0.7;
-    }
-
     /// Query the current reverb room size
     pub fn room_size(&self) -> f32 {
-        (self.roomsize - 0.7) / 0.28
-    }
-
-    /// Set the current reverb dumping
-    fn set_damp(&mut self, value: f32) {
-        self.damp = value * 1.0;
+        self.params.roomsize
     }
 
     /// Query the current reverb dumping
     pub fn damp(&self) -> f32 {
-        self.damp / 1.0
-    }
-
-    /// Set the current reverb level
-    fn set_level(&mut self, value: f32) {
-        let value = if value < 0.0 {
-            0.0
-        } else if value > 1.0 {
-            1.0
-        } else {
-            value
-        };
-        self.wet = value * 3.0;
+        self.params.damp
     }
 
     /// Query the current reverb level
     pub fn level(&self) -> f32 {
-        self.wet / 3.0
-    }
-
-    /// Set the current reverb width
-    fn set_width(&mut self, value: f32) {
-        self.width = value;
+        self.params.level
     }
 
     /// Query the current reverb width
     pub fn width(&self) -> f32 {
-        self.width
+        self.params.width
+    }
+
+    /**
+    Replace the reverb algorithm.
+
+    The engine is set to the current sample rate, block size, ramp time and reverb parameters.
+     */
+    pub fn set_engine(&mut self, mut engine: Box<dyn ReverbEngine>) {
+        engine.set_sample_rate(self.sample_rate);
+        engine.set_block_size(self.block_size);
+        engine.set_ramp_time(self.ramp_time);
+        engine.set_params(&self.params);
+        self.ty = Self::builtin_type(engine.as_mut());
+        self.engine = engine;
+    }
+
+    /// Built-in algorithm of the reverb, `None` for the other engines set with `set_engine()`
+    pub fn ty(&self) -> Option<ReverbType> {
+        self.ty
+    }
+
+    /// Get the current reverb algorithm
+    pub fn engine(&self) -> &dyn ReverbEngine {
+        self.engine.as_ref()
+    }
+
+    /**
+    Get the current reverb algorithm, if it is of type `T`.
+
+    Used to access engine specific parameters, e.g. `engine_mut::<PlateReverb>()`.
+     */
+    pub fn engine_mut<T: ReverbEngine + 'static>(&mut self) -> Option<&mut T> {
+        self.engine.as_any_mut().downcast_mut()
     }
 }
 
@@ -86,11 +93,15 @@ impl Reverb {
 
     /// Set the parameters for the built-in reverb unit
     pub fn set_reverb_params(&mut self, roomsize: f32, damping: f32, width: f32, level: f32) {
-        self.set_room_size(roomsize);
-        self.set_damp(damping);
-        self.set_width(width);
-        self.set_level(level);
-        self.update();
+        let level = level.clamp(0.0, 1.0);
+
+        self.params = ReverbParams {
+            roomsize,
+            damp: damping,
+            width,
+            level,
+        };
+        self.engine.set_params(&self.params);
     }
 
     /// Query the current reverb params
@@ -1,8 +1,17 @@
+use std::cmp::Ordering;
 use std::convert::TryFrom;
 
+use super::reverb::ReverbType;
+
 pub struct SynthDescriptor {
     pub reverb_active: bool,
+    /// Def: Freeverb
+    pub reverb_type: ReverbType,
     pub chorus_active: bool,
+    /// Add a CC94 send bus with a `Delay`, as the first send bus
+    ///
+    /// Def: false
+    pub delay_active: bool,
     pub drums_channel_active: bool,
 
     /// Def: 256
@@ -26,20 +35,50 @@ pub struct SynthDescriptor {
     /// Max: 256
     pub audio_groups: u8,
     /// Def: 44100.0
-    /// Min: 22050.0
-    /// Max: 96000.0
+    /// Min: 8000.0
+    /// Max: 192000.0
     pub sample_rate: f32,
+    /// Sample rate the synth renders at, the output is then resampled to `sample_rate`.
+    ///
+    /// Def: None (render at `sample_rate`)
+    /// Min: 8000.0
+    /// Max: 192000.0
+    pub internal_sample_rate: Option<f32>,
     /// Def: 10
     /// Min: 0
     /// Max: 65535
     pub min_note_length: u16,
+    /// Time for the voice gains (master gain, volume, pan, sends) and filter
+    /// to reach a new value, in ms. The filter ramp is at most one block long.
+    ///
+    /// Def: 5.0
+    /// Min: 0.0
+    /// Max: 100.0
+    pub ramp_time: f32,
+    /// Time for the reverb, chorus and built-in effect parameters to reach a new value, in ms.
+    ///
+    /// Def: 20.0
+    /// Min: 0.0
+    /// Max: 100.0
+    pub effect_ramp_time: f32,
+    /// Number of frames rendered at once, a power of two.
+    ///
+    /// The voice envelopes, modulators and the events take effect once per block:
+    /// smaller blocks lower the latency, larger ones render faster.
+    ///
+    /// Def: 64
+    /// Min: 16
+    /// Max: 1024
+    pub block_size: usize,
 }
 
 impl Default for SynthDescriptor {
     fn default() -> Self {
         Self {
             reverb_active: true,
+            reverb_type: ReverbType::Freeverb,
             chorus_active: true,
+            delay_active: false,
             drums_channel_active: true,
 
             polyphony: 256,
@@ -48,7 +87,11 @@ impl Default for SynthDescriptor {
             audio_channels: 1,
             audio_groups: 1,
             sample_rate: 44100.0,
+            internal_sample_rate: None,
             min_note_length: 10,
+            ramp_time: 5.0,
+            effect_ramp_time: 20.0,
+            block_size: 64,
         }
     }
 }
@@ -78,12 +121,28 @@ pub struct Settings {
     pub(crate) audio_groups: u8,
     /// Def: 44100.0
     /// Min: 8000.0
-    /// Max: 96000.0
+    /// Max: 192000.0
     pub(crate) sample_rate: f32,
+    /// Def: None
+    /// Min: 8000.0
+    /// Max: 192000.0
+    pub(crate) internal_sample_rate: Option<f32>,
     /// Def: 10
     /// Min: 0
     /// Max: 65535
     pub(crate) min_note_length: u16,
+    /// Def: 5.0
+    /// Min: 0.0
+    /// Max: 100.0
+    pub(crate) ramp_time: f32,
+    /// Def: 20.0
+    /// Min: 0.0
+    /// Max: 100.0
+    pub(crate) effect_ramp_time: f32,
+    /// Def: 64
+    /// Min: 16
+    /// Max: 1024
+    pub(crate) block_size: usize,
 }
 
 impl Settings {
@@ -97,6 +156,27 @@ impl Settings {
     pub fn audio_groups_len(&self) -> u8 {
         self.audio_groups
     }
+
+    /// Returns the output sample rate
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Returns the sample rate the synthesizer renders at internally,
+    /// `None` if it renders at the output sample rate.
+    pub fn internal_sample_rate(&self) -> Option<f32> {
+        self.internal_sample_rate
+    }
+
+    /// Returns the number of frames rendered at once
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Sample rate of the voices and effects
+    pub(crate) fn render_sample_rate(&self) -> f32 {
+        self.internal_sample_rate.unwrap_or(self.sample_rate)
+    }
 }
 
 struct Range<T> {
@@ -112,7 +192,11 @@ pub enum RangeError<T> {
 
 impl<T: PartialOrd + Copy> Range<T> {
     fn check(&self, v: T) -> Result<T, RangeError<T>> {
-        if v < self.min {
+        // NaN is not in any range
+        if !matches!(
+            v.partial_cmp(&self.min),
+            Some(Ordering::Greater | Ordering::Equal)
+        ) {
             Err(RangeError::ToSmall {
                 got: v,
                 min: self.min,
@@ -138,10 +222,27 @@ static AUDIO_CHANNELS_RANGE: Range<u8> = Range { min: 1, max: 128 };
 static AUDIO_GROUPS_RANGE: Range<u8> = Range { min: 1, max: 128 };
 static SAMPLE_RATE_RANGE: Range<f32> = Range {
     min: 8000.0,
-    max: 96000.0,
+    max: 192000.0,
+};
+static RAMP_TIME_RANGE: Range<f32> = Range {
+    min: 0.0,
+    max: 100.0,
+};
+/// Largest block size, for the buffers allocated on the stack
+pub(crate) const MAX_BLOCK_SIZE: usize = 1024;
+static BLOCK_SIZE_RANGE: Range<usize> = Range {
+    min: 16,
+    max: MAX_BLOCK_SIZE,
 };
 // static MIN_NOTE_LENGTH_RANGE: Range<u16> = Range { min: 0, max: 65535 };
 
+/// Check an output or internal sample rate
+pub(crate) fn check_sample_rate(sample_rate: f32) -> Result<f32, SettingsError> {
+    SAMPLE_RATE_RANGE
+        .check(sample_rate)
+        .map_err(SettingsError::SammpleRateRange)
+}
+
 #[derive(Debug)]
 pub enum SettingsError {
     PolyphonyRange(RangeError<u16>),
@@ -149,6 +250,12 @@ pub enum SettingsError {
     AudioChannelRange(RangeError<u8>),
     AudioGroupsRange(RangeError<u8>),
     SammpleRateRange(RangeError<f32>),
+    RampTimeRange(RangeError<f32>),
+    EffectRampTimeRange(RangeError<f32>),
+    BlockSizeRange(RangeError<usize>),
+
+    /// Requested block size is not a power of two.
+    BlockSizeIsNotPowerOfTwo,
 
     /// Requested number of MIDI channels is not a multiple of 16. Increase the number of channels to the next multiple.
     MidiChannelsIsNotMultipleOf16,
@@ -181,9 +288,28 @@ impl TryFrom<SynthDescriptor> for Settings {
             .check(desc.audio_groups)
             .map_err(|e| SettingsError::AudioGroupsRange(e))?;
 
-        let sample_rate = SAMPLE_RATE_RANGE
-            .check(desc.sample_rate)
-            .map_err(|e| SettingsError::SammpleRateRange(e))?;
+        let sample_rate = check_sample_rate(desc.sample_rate)?;
+
+        let internal_sample_rate = desc
+            .internal_sample_rate
+            .map(check_sample_rate)
+            .transpose()?;
+
+        let ramp_time = RAMP_TIME_RANGE
+            .check(desc.ramp_time)
+            .map_err(SettingsError::RampTimeRange)?;
+
+        let effect_ramp_time = RAMP_TIME_RANGE
+            .check(desc.effect_ramp_time)
+            .map_err(SettingsError::EffectRampTimeRange)?;
+
+        let block_size = BLOCK_SIZE_RANGE
+            .check(desc.block_size)
+            .map_err(SettingsError::BlockSizeRange)?;
+        if !block_size.is_power_of_two() {
+            log::warn!("Requested block size is not a power of two.");
+            return Err(SettingsError::BlockSizeIsNotPowerOfTwo);
+        }
 
         // Guarded by type system
         let min_note_length = desc.min_note_length;
@@ -197,7 +323,11 @@ impl TryFrom<SynthDescriptor> for Settings {
             audio_channels,
             audio_groups,
             sample_rate,
+            internal_sample_rate,
             min_note_length,
+            ramp_time,
+            effect_ramp_time,
+            block_size,
         })
     }
 }
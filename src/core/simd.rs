@@ -0,0 +1,320 @@
+//! Vector kernels of the voice rendering.
+//!
+//! With the `simd` feature they process 8 samples at once with the `wide` crate
+//! (SSE2/AVX on x86, NEON on ARM, plain loops elsewhere), otherwise the scalar
+//! versions are used. The mixing and interpolation kernels perform the same
+//! operations in the same order in both, so their output is the same.
+//!
+//! Every output of the biquad depends on the previous ones, so the vector version
+//! computes each block of outputs at once from its inputs and from the filter
+//! state before it, with the impulse responses of the filter. It differs from the
+//! scalar version by the rounding errors only.
+
+/// Number of samples processed at once by [`interpolate`] and [`biquad`]
+pub(crate) const LANES: usize = 8;
+
+/// Biquad coefficients, normalized by a0, with `b0 = b02` and `b2 = b2_sign * b02`
+#[derive(Clone, Copy)]
+pub(crate) struct Biquad {
+    pub a1: f32,
+    pub a2: f32,
+    pub b02: f32,
+    pub b1: f32,
+    pub b2_sign: f32,
+}
+
+mod scalar {
+    use super::{Biquad, LANES};
+
+    /// `out += gain * input`
+    pub fn mix(out: &mut [f32], input: &[f32], gain: f32) {
+        for (out, v) in out.iter_mut().zip(input.iter()) {
+            *out += gain * v;
+        }
+    }
+
+    /// `left += gain * input` and `right += gain * input`
+    pub fn mix2(left: &mut [f32], right: &mut [f32], input: &[f32], gain: f32) {
+        for ((l, r), v) in left.iter_mut().zip(right.iter_mut()).zip(input.iter()) {
+            let v = gain * v;
+            *l += v;
+            *r += v;
+        }
+    }
+
+    /// Weighted sum of the sample points of each output, scaled by its amplitude
+    #[cfg_attr(feature = "simd", allow(dead_code))]
+    pub fn interpolate<const TAPS: usize>(
+        coeffs: &[[f32; TAPS]; LANES],
+        points: &[[f32; TAPS]; LANES],
+        amps: &[f32; LANES],
+        out: &mut [f32],
+    ) {
+        for (lane, out) in out.iter_mut().enumerate().take(LANES) {
+            let mut acc = coeffs[lane][0] * points[lane][0];
+            for tap in 1..TAPS {
+                acc += coeffs[lane][tap] * points[lane][tap];
+            }
+            *out = amps[lane] * acc;
+        }
+    }
+
+    /// Direct-II biquad with constant coefficients, `hist` holds the last two center nodes
+    pub fn biquad(buf: &mut [f32], filter: &Biquad, hist: &mut [f32; 2]) {
+        let Biquad {
+            a1,
+            a2,
+            b02,
+            b1,
+            b2_sign,
+        } = *filter;
+        let [mut hist1, mut hist2] = *hist;
+        for v in buf.iter_mut() {
+            let centernode = *v - a1 * hist1 - a2 * hist2;
+            *v = b02 * (centernode + b2_sign * hist2) + b1 * hist1;
+            hist2 = hist1;
+            hist1 = centernode;
+        }
+        *hist = [hist1, hist2];
+    }
+}
+
+#[cfg(feature = "simd")]
+mod vector {
+    use super::{Biquad, LANES};
+    use wide::f32x8;
+
+    fn load(s: &[f32]) -> f32x8 {
+        let mut a = [0.0; LANES];
+        a.copy_from_slice(s);
+        f32x8::from(a)
+    }
+
+    fn store(v: f32x8, s: &mut [f32]) {
+        s.copy_from_slice(&v.to_array());
+    }
+
+    pub fn mix(out: &mut [f32], input: &[f32], gain: f32) {
+        let len = out.len().min(input.len());
+        let (out, out_tail) = out[..len].split_at_mut(len - len % LANES);
+        let (input, input_tail) = input[..len].split_at(len - len % LANES);
+
+        let g = f32x8::splat(gain);
+        for (out, v) in out.chunks_exact_mut(LANES).zip(input.chunks_exact(LANES)) {
+            store(load(out) + g * load(v), out);
+        }
+        super::scalar::mix(out_tail, input_tail, gain);
+    }
+
+    pub fn mix2(left: &mut [f32], right: &mut [f32], input: &[f32], gain: f32) {
+        let len = left.len().min(right.len()).min(input.len());
+        let split = len - len % LANES;
+        let (left, left_tail) = left[..len].split_at_mut(split);
+        let (right, right_tail) = right[..len].split_at_mut(split);
+        let (input, input_tail) = input[..len].split_at(split);
+
+        let g = f32x8::splat(gain);
+        for ((l, r), v) in left
+            .chunks_exact_mut(LANES)
+            .zip(right.chunks_exact_mut(LANES))
+            .zip(input.chunks_exact(LANES))
+        {
+            let v = g * load(v);
+            store(load(l) + v, l);
+            store(load(r) + v, r);
+        }
+        super::scalar::mix2(left_tail, right_tail, input_tail, gain);
+    }
+
+    pub fn interpolate<const TAPS: usize>(
+        coeffs: &[[f32; TAPS]; LANES],
+        points: &[[f32; TAPS]; LANES],
+        amps: &[f32; LANES],
+        out: &mut [f32],
+    ) {
+        let column = |rows: &[[f32; TAPS]; LANES], tap: usize| {
+            let mut a = [0.0; LANES];
+            for (a, row) in a.iter_mut().zip(rows.iter()) {
+                *a = row[tap];
+            }
+            f32x8::from(a)
+        };
+
+        let mut acc = column(coeffs, 0) * column(points, 0);
+        for tap in 1..TAPS {
+            acc += column(coeffs, tap) * column(points, tap);
+        }
+        store(f32x8::from(*amps) * acc, &mut out[..LANES]);
+    }
+
+    /**
+    Responses of the outputs and of the center nodes of a block to one of its
+    inputs, or to one of the two center nodes before it.
+     */
+    struct Responses {
+        inputs: [(f32x8, f32x8); LANES],
+        hist1: (f32x8, f32x8),
+        hist2: (f32x8, f32x8),
+    }
+
+    impl Responses {
+        fn new(filter: &Biquad) -> Self {
+            let response = |input: [f32; LANES], hist: [f32; 2]| {
+                let Biquad {
+                    a1,
+                    a2,
+                    b02,
+                    b1,
+                    b2_sign,
+                } = *filter;
+                let [mut hist1, mut hist2] = hist;
+                let mut out = [0.0; LANES];
+                let mut centernodes = [0.0; LANES];
+                for id in 0..LANES {
+                    let centernode = input[id] - a1 * hist1 - a2 * hist2;
+                    out[id] = b02 * (centernode + b2_sign * hist2) + b1 * hist1;
+                    centernodes[id] = centernode;
+                    hist2 = hist1;
+                    hist1 = centernode;
+                }
+                (f32x8::from(out), f32x8::from(centernodes))
+            };
+
+            let mut inputs = [(f32x8::ZERO, f32x8::ZERO); LANES];
+            for (id, input) in inputs.iter_mut().enumerate() {
+                let mut impulse = [0.0; LANES];
+                impulse[id] = 1.0;
+                *input = response(impulse, [0.0; 2]);
+            }
+            Self {
+                inputs,
+                hist1: response([0.0; LANES], [1.0, 0.0]),
+                hist2: response([0.0; LANES], [0.0, 1.0]),
+            }
+        }
+    }
+
+    pub fn biquad(buf: &mut [f32], filter: &Biquad, hist: &mut [f32; 2]) {
+        let (body, tail) = buf.split_at_mut(buf.len() - buf.len() % LANES);
+        if !body.is_empty() {
+            let responses = Responses::new(filter);
+            let [mut hist1, mut hist2] = *hist;
+            for block in body.chunks_exact_mut(LANES) {
+                let (h1, h2) = (f32x8::splat(hist1), f32x8::splat(hist2));
+                let mut out = responses.hist1.0 * h1 + responses.hist2.0 * h2;
+                let mut centernodes = responses.hist1.1 * h1 + responses.hist2.1 * h2;
+                for (input, response) in block.iter().zip(responses.inputs.iter()) {
+                    let input = f32x8::splat(*input);
+                    out += response.0 * input;
+                    centernodes += response.1 * input;
+                }
+                store(out, block);
+                let centernodes = centernodes.to_array();
+                hist1 = centernodes[LANES - 1];
+                hist2 = centernodes[LANES - 2];
+            }
+            *hist = [hist1, hist2];
+        }
+        super::scalar::biquad(tail, filter, hist);
+    }
+}
+
+#[cfg(not(feature = "simd"))]
+pub(crate) use scalar::*;
+#[cfg(feature = "simd")]
+pub(crate) use vector::*;
+
+#[cfg(all(test, feature = "simd"))]
+mod test {
+    use super::*;
+
+    fn signal(len: usize, seed: f32) -> Vec<f32> {
+        (0..len).map(|i| (i as f32 * seed).sin()).collect()
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert!((a - b).abs() <= 1e-6 * a.abs().max(1.0), "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn mix_matches_scalar() {
+        // Lengths which are not a multiple of the lanes go through the scalar tail
+        for &len in &[64, 61, 5] {
+            let input = signal(len, 0.37);
+            let mut left = signal(len, 0.11);
+            let mut right = signal(len, 0.23);
+            let (mut left_ref, mut right_ref) = (left.clone(), right.clone());
+
+            vector::mix2(&mut left, &mut right, &input, 0.7);
+            scalar::mix2(&mut left_ref, &mut right_ref, &input, 0.7);
+            vector::mix(&mut left, &input, -0.3);
+            scalar::mix(&mut left_ref, &input, -0.3);
+
+            assert_close(&left, &left_ref);
+            assert_close(&right, &right_ref);
+        }
+    }
+
+    #[test]
+    fn interpolate_matches_scalar() {
+        let mut coeffs = [[0.0; 7]; LANES];
+        let mut points = [[0.0; 7]; LANES];
+        for lane in 0..LANES {
+            coeffs[lane].copy_from_slice(&signal(7, 0.5 + lane as f32));
+            points[lane].copy_from_slice(&signal(7, 1.5 + lane as f32));
+            points[lane].iter_mut().for_each(|p| *p *= 32767.0);
+        }
+        let mut amps = [0.0; LANES];
+        amps.copy_from_slice(&signal(LANES, 0.1));
+
+        let mut out = [0.0; LANES];
+        let mut out_ref = [0.0; LANES];
+        vector::interpolate(&coeffs, &points, &amps, &mut out);
+        scalar::interpolate(&coeffs, &points, &amps, &mut out_ref);
+        assert_close(&out, &out_ref);
+    }
+
+    #[test]
+    fn biquad_matches_scalar() {
+        // Resonant low-pass at 1 kHz, and band-pass at 5 kHz (b2 = -b0), at 44.1 kHz
+        let filter = |fres: f32, q: f32, band_pass: bool| {
+            let omega = 2.0 * std::f32::consts::PI * fres / 44100.0;
+            let alpha = omega.sin() / (2.0 * q);
+            let a0_inv = 1.0 / (1.0 + alpha);
+            let (b02, b1, b2_sign) = if band_pass {
+                (alpha * a0_inv, 0.0, -1.0)
+            } else {
+                let b1 = (1.0 - omega.cos()) * a0_inv;
+                (b1 * 0.5, b1, 1.0)
+            };
+            Biquad {
+                a1: -2.0 * omega.cos() * a0_inv,
+                a2: (1.0 - alpha) * a0_inv,
+                b02,
+                b1,
+                b2_sign,
+            }
+        };
+
+        for filter in [filter(1000.0, 4.0, false), filter(5000.0, 0.7, true)] {
+            // Lengths which are not a multiple of the lanes go through the scalar tail
+            let input = signal(4096, 0.37);
+            let (mut hist, mut hist_ref) = ([0.0; 2], [0.0; 2]);
+            let (mut out, mut out_ref) = (input.clone(), input);
+            let mut pos = 0;
+            for len in [64, 61, 3, 968, 3000] {
+                let end = (pos + len).min(out.len());
+                vector::biquad(&mut out[pos..end], &filter, &mut hist);
+                scalar::biquad(&mut out_ref[pos..end], &filter, &mut hist_ref);
+                pos = end;
+            }
+
+            for (a, b) in out.iter().zip(out_ref.iter()) {
+                assert!((a - b).abs() <= 1e-4, "{} != {}", a, b);
+            }
+            assert!((hist[0] - hist_ref[0]).abs() <= 1e-3 * hist_ref[0].abs().max(1.0));
+        }
+    }
+}
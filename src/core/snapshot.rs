@@ -0,0 +1,344 @@
+/*!
+Running state of the DSP units (voices, delay lines, filters...), saved as 32 bit words
+by [`Synth::save_dsp_state`](crate::Synth::save_dsp_state).
+ */
+
+use std::collections::VecDeque;
+
+/**
+Writer of the running state of a DSP unit.
+
+Custom reverb engines and effects use it in `save_state()`, and read the
+values back in the same order with a [`StateReader`] in `restore_state()`.
+ */
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    words: Vec<u32>,
+}
+
+impl StateWriter {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn into_words(self) -> Vec<u32> {
+        self.words
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.words.push(value);
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.words.push(value as u32);
+        self.words.push((value >> 32) as u32);
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_u32(value.to_bits());
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.write_u64(value.to_bits());
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u32(value as u32);
+    }
+
+    /// Write a buffer, along with its length
+    pub fn write_f32s(&mut self, values: &[f32]) {
+        self.write_u64(values.len() as u64);
+        self.words.extend(values.iter().map(|v| v.to_bits()));
+    }
+
+    /// Write the state of a unit in a block of its own, which can be skipped when read
+    pub(crate) fn section<F: FnOnce(&mut Self)>(&mut self, write: F) {
+        let start = self.words.len();
+        self.words.push(0);
+        write(self);
+        self.words[start] = (self.words.len() - start - 1) as u32;
+    }
+}
+
+/**
+Reader of the running state written by a [`StateWriter`].
+
+Reading past the end of the state, or a buffer of another length,
+marks the state as invalid and returns zeros.
+ */
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    words: &'a [u32],
+    pos: usize,
+    valid: bool,
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(words: &'a [u32]) -> Self {
+        Self {
+            words,
+            pos: 0,
+            valid: true,
+        }
+    }
+
+    /// False once a value could not be read
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    /// Mark the state as invalid, e.g. when it doesn't match the unit
+    pub fn invalidate(&mut self) {
+        self.valid = false;
+    }
+
+    /// Words left to read
+    fn remaining(&self) -> usize {
+        self.words.len() - self.pos
+    }
+
+    pub fn read_u32(&mut self) -> u32 {
+        match self.words.get(self.pos) {
+            Some(word) if self.valid => {
+                self.pos += 1;
+                *word
+            }
+            _ => {
+                self.valid = false;
+                0
+            }
+        }
+    }
+
+    pub fn read_u64(&mut self) -> u64 {
+        let low = self.read_u32() as u64;
+        let high = self.read_u32() as u64;
+        low | high << 32
+    }
+
+    pub fn read_f32(&mut self) -> f32 {
+        f32::from_bits(self.read_u32())
+    }
+
+    pub fn read_f64(&mut self) -> f64 {
+        f64::from_bits(self.read_u64())
+    }
+
+    pub fn read_bool(&mut self) -> bool {
+        self.read_u32() != 0
+    }
+
+    /// Read a buffer written by `write_f32s()`, which must have the length of `values`
+    pub fn read_f32s(&mut self, values: &mut [f32]) {
+        if self.read_u64() != values.len() as u64 || self.remaining() < values.len() {
+            self.valid = false;
+        }
+        if self.valid {
+            for (value, word) in values.iter_mut().zip(self.words[self.pos..].iter()) {
+                *value = f32::from_bits(*word);
+            }
+            self.pos += values.len();
+        }
+    }
+
+    /// Read a length, which can't be more than the words left
+    fn read_len(&mut self) -> usize {
+        let len = self.read_u64();
+        if len > self.remaining() as u64 {
+            self.valid = false;
+            0
+        } else {
+            len as usize
+        }
+    }
+
+    /// Read a block written by `StateWriter::section()`, skipping what `read` leaves
+    pub(crate) fn section<F: FnOnce(&mut StateReader)>(&mut self, read: F) {
+        let len = self.read_u32() as usize;
+        if len > self.remaining() {
+            self.valid = false;
+        }
+        if self.valid {
+            let mut section = StateReader::new(&self.words[self.pos..self.pos + len]);
+            read(&mut section);
+            self.valid = section.valid;
+            self.pos += len;
+        }
+    }
+}
+
+/**
+Running state of a DSP unit, saved and restored field by field
+ */
+pub(crate) trait Snapshot {
+    fn save(&self, w: &mut StateWriter);
+    fn restore(&mut self, r: &mut StateReader);
+}
+
+/// Implement `Snapshot` for a struct, over the listed fields
+macro_rules! snapshot {
+    ($ty:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::core::snapshot::Snapshot for $ty {
+            fn save(&self, w: &mut $crate::core::snapshot::StateWriter) {
+                $($crate::core::snapshot::Snapshot::save(&self.$field, w);)*
+            }
+
+            fn restore(&mut self, r: &mut $crate::core::snapshot::StateReader) {
+                $($crate::core::snapshot::Snapshot::restore(&mut self.$field, r);)*
+            }
+        }
+    };
+}
+pub(crate) use snapshot;
+
+macro_rules! snapshot_int {
+    ($($ty:ty),*) => {
+        $(impl Snapshot for $ty {
+            fn save(&self, w: &mut StateWriter) {
+                w.write_u64(*self as u64);
+            }
+
+            fn restore(&mut self, r: &mut StateReader) {
+                *self = r.read_u64() as $ty;
+            }
+        })*
+    };
+}
+snapshot_int!(u8, i8, u16, i16, u32, i32, u64, usize);
+
+impl Snapshot for bool {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_bool(*self);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        *self = r.read_bool();
+    }
+}
+
+impl Snapshot for f32 {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_f32(*self);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        *self = r.read_f32();
+    }
+}
+
+impl Snapshot for f64 {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_f64(*self);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        *self = r.read_f64();
+    }
+}
+
+impl<A: Snapshot, B: Snapshot> Snapshot for (A, B) {
+    fn save(&self, w: &mut StateWriter) {
+        self.0.save(w);
+        self.1.save(w);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        self.0.restore(r);
+        self.1.restore(r);
+    }
+}
+
+impl<T: Snapshot, const N: usize> Snapshot for [T; N] {
+    fn save(&self, w: &mut StateWriter) {
+        self.iter().for_each(|item| item.save(w));
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        self.iter_mut().for_each(|item| item.restore(r));
+    }
+}
+
+/// Buffers keep their length, which must be the saved one
+impl<T: Snapshot> Snapshot for Vec<T> {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_u64(self.len() as u64);
+        self.iter().for_each(|item| item.save(w));
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        if r.read_u64() != self.len() as u64 {
+            r.invalidate();
+        }
+        if r.is_valid() {
+            self.iter_mut().for_each(|item| item.restore(r));
+        }
+    }
+}
+
+/// Queues are restored to their saved length
+impl<T: Snapshot + Default> Snapshot for VecDeque<T> {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_u64(self.len() as u64);
+        self.iter().for_each(|item| item.save(w));
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        let len = r.read_len();
+        self.clear();
+        self.resize_with(len, T::default);
+        self.iter_mut().for_each(|item| item.restore(r));
+    }
+}
+
+impl<T: Snapshot + Default> Snapshot for Option<T> {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_bool(self.is_some());
+        if let Some(value) = self {
+            value.save(w);
+        }
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        if r.read_bool() {
+            self.get_or_insert_with(T::default).restore(r);
+        } else {
+            *self = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn state_words() {
+        let mut w = StateWriter::new();
+        (1.5f32, u64::MAX - 1).save(&mut w);
+        w.section(|w| w.write_f32s(&[1.0, 2.0]));
+        VecDeque::from(vec![-3i32, 4]).save(&mut w);
+        let words = w.into_words();
+
+        let mut r = StateReader::new(&words);
+        let mut pair = (0f32, 0u64);
+        pair.restore(&mut r);
+        assert_eq!(pair, (1.5, u64::MAX - 1));
+        // The section is skipped past what is read
+        r.section(|r| assert_eq!(r.read_u64(), 2));
+        let mut ints = VecDeque::<i32>::new();
+        ints.restore(&mut r);
+        assert_eq!(ints, [-3, 4]);
+        assert!(r.is_valid());
+
+        // A buffer of another length invalidates the state
+        let mut r = StateReader::new(&words[3..]);
+        r.section(|r| r.read_f32s(&mut [0.0; 3]));
+        assert!(!r.is_valid());
+        assert_eq!(r.read_f32(), 0.0);
+
+        let mut r = StateReader::new(&words[3..]);
+        r.section(|r| vec![0f32; 1].restore(r));
+        assert!(!r.is_valid());
+    }
+}
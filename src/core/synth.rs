@@ -6,30 +6,65 @@ pub(crate) use channel_pool::Channel;
 pub(crate) mod voice_pool;
 
 mod conv;
-pub use channel_pool::InterpolationMethod;
+mod events;
+mod meter;
+mod state;
+pub use channel_pool::{FilterType, InterpolationMethod, MpeZone, MpeZoneLayout};
+pub use events::SynthEvent;
+pub use meter::ChannelLevel;
+pub use state::{DspState, SynthState};
+#[cfg(feature = "parallel")]
+pub use voice_pool::RenderThreads;
+pub use voice_pool::{EnvelopeStage, StealPolicy, StolenVoice, VoiceInfo};
 
 pub mod font_bank;
 
 use super::chorus::Chorus;
+use super::effects::Effects;
+use super::midi2_event::Midi2Event;
 use super::midi_event::MidiEvent;
+use super::resampler::Resampler;
 use super::reverb::Reverb;
+use super::ump::{UmpDecoder, UmpMessage};
 use super::OxiError;
 
 pub mod soundfont;
 use self::soundfont::Preset;
 
+use meter::ChannelMeters;
 use voice_pool::VoicePool;
 
 use self::channel_pool::ChannelPool;
 use self::font_bank::FontBank;
 
 use super::settings::{Settings, SettingsError, SynthDescriptor};
+use super::tuning::TuningManager;
 use std::convert::TryInto;
 
+#[derive(Clone)]
+struct FxSend {
+    /// Voices only write into the sends of active effect units
+    pub active: bool,
+    pub buf: Vec<f32>,
+}
+
+impl FxSend {
+    fn new(block_size: usize) -> Self {
+        Self {
+            active: false,
+            buf: vec![0.0; block_size],
+        }
+    }
+}
+
 #[derive(Clone)]
 struct FxBuf {
-    pub reverb: [f32; 64],
-    pub chorus: [f32; 64],
+    /// One send per reverb instance
+    pub reverb: Vec<FxSend>,
+    /// One send per chorus instance
+    pub chorus: Vec<FxSend>,
+    /// Voices of each MIDI channel, empty when the meters are off
+    pub meters: Vec<Vec<f32>>,
 }
 
 pub struct Synth {
@@ -39,18 +74,26 @@ pub struct Synth {
     pub channels: ChannelPool,
     pub voices: VoicePool,
 
+    pub tunings: TuningManager,
+
     nbuf: u8,
 
-    left_buf: Vec<[f32; 64]>,
-    right_buf: Vec<[f32; 64]>,
+    left_buf: Vec<Vec<f32>>,
+    right_buf: Vec<Vec<f32>>,
 
     fx_left_buf: FxBuf,
     fx_right_buf: FxBuf,
 
-    pub reverb: Reverb,
-    pub chorus: Chorus,
+    /// Reverb instances, the first one is the main reverb
+    pub reverbs: Vec<Reverb>,
+    /// Chorus instances, the first one is the main chorus
+    pub choruses: Vec<Chorus>,
+    pub effects: Effects,
+
+    meters: ChannelMeters,
 
     cur: usize,
+    resampler: Option<Resampler>,
 
     min_note_length_ticks: usize,
 
@@ -60,6 +103,11 @@ pub struct Synth {
     dither_index: i32,
 }
 
+/// Minimal note length in ticks (samples) for a note length in milliseconds
+fn min_note_length_ticks(min_note_length: u16, sample_rate: f32) -> usize {
+    (min_note_length as f32 * sample_rate / 1000.0) as usize
+}
+
 impl Default for Synth {
     fn default() -> Self {
         Self::new(Default::default()).unwrap()
@@ -70,11 +118,16 @@ impl Synth {
     pub fn new(desc: SynthDescriptor) -> Result<Self, SettingsError> {
         let chorus_active = desc.chorus_active;
         let reverb_active = desc.reverb_active;
+        let delay_active = desc.delay_active;
+        let reverb_type = desc.reverb_type;
 
         let settings: Settings = desc.try_into()?;
 
-        let min_note_length_ticks =
-            (settings.min_note_length as f32 * settings.sample_rate / 1000.0) as usize;
+        let sample_rate = settings.render_sample_rate();
+        let min_note_length_ticks = min_note_length_ticks(settings.min_note_length, sample_rate);
+        let resampler = settings
+            .internal_sample_rate
+            .map(|rate| Resampler::new(rate, settings.sample_rate));
 
         let nbuf = {
             let nbuf = settings.audio_channels;
@@ -86,30 +139,49 @@ impl Synth {
         };
 
         let midi_channels = settings.midi_channels;
+        let block_size = settings.block_size;
         let mut synth = Self {
             ticks: 0,
 
             font_bank: FontBank::new(),
 
             channels: ChannelPool::new(midi_channels as usize, None),
-            voices: VoicePool::new(settings.polyphony as usize, settings.sample_rate),
+            voices: VoicePool::new(
+                settings.polyphony as usize,
+                midi_channels as usize,
+                sample_rate,
+                block_size,
+                settings.ramp_time,
+            ),
+            tunings: TuningManager::new(),
             nbuf,
-            left_buf: vec![[0.0; 64]; nbuf as usize],
-            right_buf: vec![[0.0; 64]; nbuf as usize],
+            left_buf: vec![vec![0.0; block_size]; nbuf as usize],
+            right_buf: vec![vec![0.0; block_size]; nbuf as usize],
 
             fx_left_buf: FxBuf {
-                reverb: [0.0; 64],
-                chorus: [0.0; 64],
+                reverb: vec![FxSend::new(block_size)],
+                chorus: vec![FxSend::new(block_size)],
+                meters: Vec::new(),
             },
             fx_right_buf: FxBuf {
-                reverb: [0.0; 64],
-                chorus: [0.0; 64],
+                reverb: vec![FxSend::new(block_size)],
+                chorus: vec![FxSend::new(block_size)],
+                meters: Vec::new(),
             },
 
-            reverb: Reverb::new(reverb_active),
-            chorus: Chorus::new(settings.sample_rate, chorus_active),
+            reverbs: vec![Reverb::new(
+                sample_rate,
+                block_size,
+                reverb_active,
+                reverb_type,
+            )],
+            choruses: vec![Chorus::new(sample_rate, chorus_active)],
+            effects: Effects::new(midi_channels as usize, sample_rate, block_size),
+
+            meters: ChannelMeters::new(midi_channels as usize, sample_rate, block_size),
 
-            cur: 64,
+            cur: block_size,
+            resampler,
             min_note_length_ticks,
 
             settings,
@@ -118,6 +190,11 @@ impl Synth {
             dither_index: 0,
         };
 
+        synth.set_effect_ramp_time(synth.settings.effect_ramp_time);
+        if delay_active {
+            synth.effects.add_delay_bus(94);
+        }
+
         if synth.settings.drums_channel_active {
             synth.bank_select(9, 128).ok();
         }
@@ -136,6 +213,7 @@ impl Synth {
                     self.settings.gain,
                     key,
                     vel,
+                    None,
                 )?;
             }
             MidiEvent::NoteOff { channel, key } => {
@@ -154,11 +232,21 @@ impl Synth {
                 internal::midi::cc(
                     self.channels.get_mut(channel as usize)?,
                     &mut self.voices,
+                    &self.tunings,
                     self.min_note_length_ticks,
                     self.settings.drums_channel_active,
                     ctrl,
                     value,
                 );
+                internal::mpe::cc(
+                    &mut self.channels,
+                    &mut self.voices,
+                    &self.tunings,
+                    self.min_note_length_ticks,
+                    self.settings.drums_channel_active,
+                    channel as usize,
+                    ctrl,
+                );
             }
             MidiEvent::AllNotesOff { channel } => {
                 internal::midi::all_notes_off(
@@ -176,6 +264,7 @@ impl Synth {
                     &mut self.voices,
                     value,
                 );
+                internal::mpe::pitch_bend(&mut self.channels, &mut self.voices, channel as usize);
             }
             MidiEvent::ProgramChange {
                 channel,
@@ -187,6 +276,7 @@ impl Synth {
                     program_id,
                     self.settings.drums_channel_active,
                 );
+                internal::mpe::program_change(&mut self.channels, channel as usize);
             }
             MidiEvent::ChannelPressure { channel, value } => {
                 internal::midi::channel_pressure(
@@ -212,12 +302,279 @@ impl Synth {
                     &mut self.voices,
                     &mut self.channels,
                     &self.font_bank,
-                    &mut self.chorus,
-                    &mut self.reverb,
+                    &mut self.choruses,
+                    &mut self.reverbs,
+                );
+                self.effects.reset();
+                self.channels.reset_mpe();
+            }
+        };
+
+        Ok(())
+    }
+
+    pub fn send_midi2_event(&mut self, event: Midi2Event) -> Result<(), OxiError> {
+        match event.check()? {
+            Midi2Event::NoteOn {
+                channel,
+                key,
+                vel,
+                attribute,
+            } => {
+                internal::midi2::noteon(
+                    self.channels.get(channel as usize)?,
+                    &mut self.voices,
+                    self.ticks,
+                    self.min_note_length_ticks,
+                    self.settings.gain,
+                    key,
+                    vel,
+                    attribute,
+                )?;
+            }
+            Midi2Event::NoteOff { channel, key, .. } => {
+                self.send_event(MidiEvent::NoteOff { channel, key })?;
+            }
+            Midi2Event::PolyphonicKeyPressure {
+                channel,
+                key,
+                value,
+            } => {
+                internal::midi2::key_pressure(
+                    self.channels.get_mut(channel as usize)?,
+                    &mut self.voices,
+                    key,
+                    value,
+                );
+            }
+            Midi2Event::ControlChange {
+                channel,
+                ctrl,
+                value,
+            } => {
+                internal::midi2::cc(
+                    self.channels.get_mut(channel as usize)?,
+                    &mut self.voices,
+                    &self.tunings,
+                    self.min_note_length_ticks,
+                    self.settings.drums_channel_active,
+                    ctrl,
+                    value,
+                );
+                internal::mpe::cc(
+                    &mut self.channels,
+                    &mut self.voices,
+                    &self.tunings,
+                    self.min_note_length_ticks,
+                    self.settings.drums_channel_active,
+                    channel as usize,
+                    ctrl,
+                );
+            }
+            Midi2Event::RegisteredController {
+                channel,
+                bank,
+                index,
+                value,
+            } => {
+                self.parameter_change(channel, [101, 100], bank, index, value)?;
+            }
+            Midi2Event::AssignableController {
+                channel,
+                bank,
+                index,
+                value,
+            } => {
+                self.parameter_change(channel, [99, 98], bank, index, value)?;
+            }
+            Midi2Event::RegisteredPerNoteController {
+                channel,
+                key,
+                index,
+                value,
+            } => {
+                internal::midi2::per_note_cc(
+                    self.channels.get_mut(channel as usize)?,
+                    &mut self.voices,
+                    key,
+                    true,
+                    index,
+                    value,
+                );
+            }
+            Midi2Event::AssignablePerNoteController {
+                channel,
+                key,
+                index,
+                value,
+            } => {
+                internal::midi2::per_note_cc(
+                    self.channels.get_mut(channel as usize)?,
+                    &mut self.voices,
+                    key,
+                    false,
+                    index,
+                    value,
+                );
+            }
+            Midi2Event::PerNotePitchBend {
+                channel,
+                key,
+                value,
+            } => {
+                internal::midi2::per_note_pitch_bend(
+                    self.channels.get_mut(channel as usize)?,
+                    &mut self.voices,
+                    key,
+                    value,
+                );
+            }
+            Midi2Event::PerNoteManagement {
+                channel,
+                key,
+                detach,
+                reset,
+            } => {
+                internal::midi2::per_note_management(
+                    self.channels.get_mut(channel as usize)?,
+                    &mut self.voices,
+                    key,
+                    detach,
+                    reset,
+                );
+            }
+            Midi2Event::ProgramChange {
+                channel,
+                program_id,
+                bank,
+            } => {
+                if let Some(bank) = bank {
+                    let chan = self.channels.get_mut(channel as usize)?;
+                    chan.set_bank_msb((bank >> 7) as u8);
+                    internal::midi::bank_select(chan, bank as u32);
+                }
+                self.send_event(MidiEvent::ProgramChange {
+                    channel,
+                    program_id,
+                })?;
+            }
+            Midi2Event::ChannelPressure { channel, value } => {
+                internal::midi2::channel_pressure(
+                    self.channels.get_mut(channel as usize)?,
+                    &mut self.voices,
+                    value,
                 );
             }
+            Midi2Event::PitchBend { channel, value } => {
+                internal::midi2::pitch_bend(
+                    self.channels.get_mut(channel as usize)?,
+                    &mut self.voices,
+                    value,
+                );
+                internal::mpe::pitch_bend(&mut self.channels, &mut self.voices, channel as usize);
+            }
         };
 
         Ok(())
     }
+
+    /**
+    Apply a MIDI 2.0 Registered / Assignable Controller through the MIDI 1.0
+    (N)RPN data entry, with the 14 most significant bits of the value.
+     */
+    fn parameter_change(
+        &mut self,
+        channel: u8,
+        select: [u8; 2],
+        bank: u8,
+        index: u8,
+        value: u32,
+    ) -> Result<(), OxiError> {
+        const DATA_ENTRY_MSB: u8 = 6;
+        const DATA_ENTRY_LSB: u8 = 38;
+
+        let saved = internal::midi2::ParameterSelection::save(self.channels.get(channel as usize)?);
+
+        for (ctrl, value) in [
+            (select[0], bank),
+            (select[1], index),
+            (DATA_ENTRY_LSB, ((value >> 18) & 0x7f) as u8),
+            (DATA_ENTRY_MSB, (value >> 25) as u8),
+        ]
+        .iter()
+        {
+            self.send_event(MidiEvent::ControlChange {
+                channel,
+                ctrl: *ctrl,
+                value: *value,
+            })?;
+        }
+
+        saved.restore(self.channels.get_mut(channel as usize)?);
+        Ok(())
+    }
+
+    /**
+    Send a stream of Universal MIDI Packets.
+
+    Unsupported packets are skipped.
+     */
+    pub fn send_ump(&mut self, words: &[u32]) -> Result<(), OxiError> {
+        for msg in UmpDecoder::new(words) {
+            match msg {
+                UmpMessage::Midi1(event) => self.send_event(event)?,
+                UmpMessage::Midi2(event) => self.send_midi2_event(event)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::Synth;
+    use crate::core::{MidiEvent, SoundFont, SynthDescriptor};
+
+    /// The sine wave test font
+    pub(crate) fn sin_font() -> SoundFont {
+        let mut file = std::fs::File::open("./testdata/sin.sf2").unwrap();
+        SoundFont::load(&mut file).unwrap()
+    }
+
+    /// A synth with the sine wave font loaded
+    pub(crate) fn sin_synth(desc: SynthDescriptor) -> Synth {
+        let mut synth = Synth::new(desc).unwrap();
+        synth.add_font(sin_font(), true);
+        synth
+    }
+
+    /// The synth of `render_note()`, with the effects off
+    pub(crate) fn note_synth() -> Synth {
+        let mut synth = sin_synth(Default::default());
+        synth.reverbs[0].set_active(false);
+        synth.choruses[0].set_active(false);
+        synth
+    }
+
+    /// Render a note on channel 0 with the effects off, after `setup`
+    pub(crate) fn render_note(setup: &dyn Fn(&mut Synth)) -> [f32; 4096] {
+        let mut synth = note_synth();
+        setup(&mut synth);
+        play_note(&mut synth)
+    }
+
+    /// Play the note of `render_note()`
+    pub(crate) fn play_note(synth: &mut Synth) -> [f32; 4096] {
+        synth
+            .send_event(MidiEvent::NoteOn {
+                channel: 0,
+                key: 69,
+                vel: 127,
+            })
+            .unwrap();
+
+        let mut samples = [0f32; 4096];
+        synth.write_interleaved(samples.as_mut());
+        samples
+    }
 }
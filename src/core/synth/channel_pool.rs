@@ -1,26 +1,63 @@
 use std::sync::Arc;
 
 mod channel;
-pub use channel::{Channel, InterpolationMethod};
+pub use channel::{Channel, FilterType, InterpolationMethod};
+
+mod mpe;
+pub use mpe::{MpeConfig, MpeZone, MpeZoneLayout};
 
 use crate::core::{soundfont::Preset, OxiError};
 
-pub struct ChannelPool(Vec<Channel>);
+pub struct ChannelPool {
+    channels: Vec<Channel>,
+    mpe: MpeConfig,
+}
 
 impl ChannelPool {
     pub fn new(len: usize, preset: Option<Arc<Preset>>) -> Self {
         let channels = (0..len)
             .map(|id| Channel::new(id, preset.clone()))
             .collect();
-        Self(channels)
+        Self {
+            channels,
+            mpe: MpeConfig::default(),
+        }
     }
 
     pub fn get(&self, id: usize) -> Result<&Channel, OxiError> {
-        self.0.get(id).ok_or(OxiError::ChannelOutOfRange)
+        self.channels.get(id).ok_or(OxiError::ChannelOutOfRange)
     }
 
     pub fn get_mut(&mut self, id: usize) -> Result<&mut Channel, OxiError> {
-        self.0.get_mut(id).ok_or(OxiError::ChannelOutOfRange)
+        self.channels.get_mut(id).ok_or(OxiError::ChannelOutOfRange)
+    }
+
+    pub fn mpe(&self) -> &MpeConfig {
+        &self.mpe
+    }
+
+    /**
+    Configure an MPE zone, marking its member channels as such.
+     */
+    pub fn set_mpe_zone(&mut self, zone: MpeZone, member_count: u8) {
+        self.mpe.set_zone(zone, member_count);
+
+        let mpe = self.mpe;
+        for channel in self.channels.iter_mut() {
+            let member = mpe.member_zone(channel.id()).is_some();
+            channel.set_mpe_member(member);
+            if !member {
+                channel.set_zone_pitch_bend(0.0);
+            }
+        }
+    }
+
+    /**
+    Disable both MPE zones.
+     */
+    pub fn reset_mpe(&mut self) {
+        self.set_mpe_zone(MpeZone::Lower, 0);
+        self.set_mpe_zone(MpeZone::Upper, 0);
     }
 }
 
@@ -28,12 +65,12 @@ impl std::ops::Deref for ChannelPool {
     type Target = Vec<Channel>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.channels
     }
 }
 
 impl std::ops::DerefMut for ChannelPool {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.channels
     }
 }
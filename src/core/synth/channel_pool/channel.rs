@@ -1,7 +1,11 @@
 use std::sync::Arc;
 
 use super::super::soundfont::{Preset, SoundFont};
+use super::super::state::ChannelState;
 
+use crate::core::midi2_event::{to_u14_range, to_u7_range};
+use crate::core::snapshot::{Snapshot, StateReader, StateWriter};
+use crate::core::soundfont::generator::GeneratorType;
 use crate::core::tuning::Tuning;
 use crate::core::utils::TypedIndex;
 
@@ -24,17 +28,44 @@ const PAN_MSB: MidiControlChange = 10;
 const VOLUME_MSB: MidiControlChange = 7;
 const BANK_SELECT_MSB: MidiControlChange = 0;
 
+/// Per-note controllers kept for each key, further controllers are ignored
+const NOTE_CC_SLOTS: usize = 16;
+
+/**
+MIDI 2.0 per-note controllers of a key
+ */
+#[derive(Debug, Clone, Copy, Default)]
+struct NoteControllers {
+    /// Controller overrides (controller, value), the first `cc_count` ones are set
+    cc: [(u8, u32); NOTE_CC_SLOTS],
+    cc_count: u8,
+    pitch_bend: Option<u32>,
+    /// Absolute pitch (Pitch 7.25)
+    pitch: Option<u32>,
+}
+
+impl NoteControllers {
+    fn cc(&self) -> &[(u8, u32)] {
+        &self.cc[..self.cc_count as usize]
+    }
+}
+
 /* Flags to choose the interpolation method */
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InterpolationMethod {
     /// No interpolation: Fastest, but questionable audio quality
-    None = 0,
+    None,
     /// Straight-line interpolation: A bit slower, reasonable audio quality
-    Linear = 1,
+    Linear,
     /// Fourth-order interpolation: Requires 50% of the whole DSP processing time, good quality (default)
-    FourthOrder = 4,
+    FourthOrder,
     /// Seventh-order interpolation
-    SeventhOrder = 7,
+    SeventhOrder,
+    /// Windowed-sinc interpolation over `taps` sample points, rounded up to an even number
+    /// in `8..=64`. When a sample is pitched up, the kernel widens to filter out what would
+    /// alias, with fewer taps past 512 points per output sample: the slowest, best quality.
+    WindowedSinc { taps: u8 },
 }
 
 impl Default for InterpolationMethod {
@@ -43,6 +74,78 @@ impl Default for InterpolationMethod {
     }
 }
 
+/**
+Filter of the voices, with the cutoff and resonance of the SoundFont filter generators
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FilterType {
+    /// 2-pole resonant low-pass of the SoundFont specification
+    #[default]
+    LowPass,
+    /// 4-pole resonant low-pass, two 2-pole stages in series
+    LowPass4,
+    /// 2-pole resonant high-pass
+    HighPass,
+    /// 2-pole band-pass, the resonance narrows the band
+    BandPass,
+    /// 2-pole band-reject, the resonance narrows the band
+    Notch,
+    /// Moog style 4-pole ladder low-pass, self-oscillating at full resonance
+    Ladder,
+}
+
+impl Snapshot for InterpolationMethod {
+    fn save(&self, w: &mut StateWriter) {
+        let (method, taps) = match self {
+            Self::None => (0, 0),
+            Self::Linear => (1, 0),
+            Self::FourthOrder => (2, 0),
+            Self::SeventhOrder => (3, 0),
+            Self::WindowedSinc { taps } => (4, *taps),
+        };
+        w.write_u32(method);
+        w.write_u32(taps as u32);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        let method = r.read_u32();
+        let taps = r.read_u32() as u8;
+        *self = match method {
+            0 => Self::None,
+            1 => Self::Linear,
+            2 => Self::FourthOrder,
+            3 => Self::SeventhOrder,
+            4 => Self::WindowedSinc { taps },
+            _ => {
+                r.invalidate();
+                Self::default()
+            }
+        };
+    }
+}
+
+impl Snapshot for FilterType {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_u32(*self as u32);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        *self = match r.read_u32() {
+            0 => Self::LowPass,
+            1 => Self::LowPass4,
+            2 => Self::HighPass,
+            3 => Self::BandPass,
+            4 => Self::Notch,
+            5 => Self::Ladder,
+            _ => {
+                r.invalidate();
+                Self::default()
+            }
+        };
+    }
+}
+
 #[derive(Clone)]
 pub struct Channel {
     id: usize,
@@ -65,12 +168,42 @@ pub struct Channel {
 
     interp_method: InterpolationMethod,
     tuning: Option<Tuning>,
+    /// Reverb and chorus instances the channel sends to
+    reverb_id: usize,
+    chorus_id: usize,
+    /// Filter of the new voices, overriding the one of the instrument zones
+    filter_type: Option<FilterType>,
+    tuning_bank: u8,
+    tuning_prog: u8,
+
+    /// Modulation wheel vibrato depth in cents (RPN 5)
+    mod_depth_range: f32,
 
     nrpn_select: i16,
     nrpn_active: i16,
 
     gen: [f32; 60],
     gen_abs: [i8; 60],
+
+    /// Per-key generator offsets, by key
+    key_gen: Vec<[f32; 60]>,
+    /// Per-key scales of the voice reverb and chorus sends (drum instrument NRPNs)
+    key_reverb_scale: [f32; 128],
+    key_chorus_scale: [f32; 128],
+
+    /// Member channel of an MPE zone
+    mpe_member: bool,
+    /// Pitch bend of the manager channel of the MPE zone, in cents
+    zone_pitch_bend: f32,
+
+    /// MIDI 2.0 32 bit values, `None` when last set by a MIDI 1.0 message
+    cc32: [Option<u32>; 128],
+    key_pressure32: [Option<u32>; 128],
+    channel_pressure32: Option<u32>,
+    pitch_bend32: Option<u32>,
+
+    /// MIDI 2.0 per-note controllers, by key
+    notes: Vec<NoteControllers>,
 }
 
 impl Channel {
@@ -94,12 +227,33 @@ impl Channel {
 
             interp_method: Default::default(),
             tuning: None,
+            reverb_id: 0,
+            chorus_id: 0,
+            filter_type: None,
+            tuning_bank: 0,
+            tuning_prog: 0,
+
+            mod_depth_range: 50.0,
 
             nrpn_select: 0,
             nrpn_active: 0,
 
             gen: [0f32; 60],
             gen_abs: [0; 60],
+
+            key_gen: vec![[0.0; 60]; 128],
+            key_reverb_scale: [1.0; 128],
+            key_chorus_scale: [1.0; 128],
+
+            mpe_member: false,
+            zone_pitch_bend: 0.0,
+
+            cc32: [None; 128],
+            key_pressure32: [None; 128],
+            channel_pressure32: None,
+            pitch_bend32: None,
+
+            notes: vec![NoteControllers::default(); 128],
         };
         chan.init_ctrl(0);
         chan
@@ -113,18 +267,29 @@ impl Channel {
         self.preset = preset;
         self.interp_method = Default::default();
         self.tuning = None;
+        self.tuning_bank = 0;
+        self.tuning_prog = 0;
         self.nrpn_select = 0;
         self.nrpn_active = 0;
     }
 
     pub fn init_ctrl(&mut self, is_all_ctrl_off: i32) {
         self.channel_pressure = 0;
+        self.channel_pressure32 = None;
         self.pitch_bend = 0x2000;
+        self.pitch_bend32 = None;
+
+        self.notes
+            .iter_mut()
+            .for_each(|note| *note = NoteControllers::default());
 
         for i in 0..60 {
             self.gen[i as usize] = 0.0;
             self.gen_abs[i as usize] = 0;
         }
+        self.key_gen.iter_mut().for_each(|gen| *gen = [0.0; 60]);
+        self.key_reverb_scale = [1.0; 128];
+        self.key_chorus_scale = [1.0; 128];
 
         if is_all_ctrl_off != 0 {
             for i in 0..ALL_SOUND_OFF {
@@ -137,41 +302,43 @@ impl Channel {
                             || i == PAN_MSB
                             || i == PAN_LSB)
                         {
-                            self.cc[i as usize] = 0;
+                            self.set_cc(i as usize, 0);
                         }
                     }
                 }
             }
         } else {
             for i in 0..128 {
-                self.cc[i] = 0;
+                self.set_cc(i, 0);
             }
         }
 
         for i in 0..128 {
             self.key_pressure[i] = 0;
+            self.key_pressure32[i] = None;
         }
 
-        self.cc[RPN_LSB as usize] = 127;
-        self.cc[RPN_MSB as usize] = 127;
-        self.cc[NRPN_LSB as usize] = 127;
-        self.cc[NRPN_MSB as usize] = 127;
-        self.cc[EXPRESSION_MSB as usize] = 127;
-        self.cc[EXPRESSION_LSB as usize] = 127;
+        self.set_cc(RPN_LSB as usize, 127);
+        self.set_cc(RPN_MSB as usize, 127);
+        self.set_cc(NRPN_LSB as usize, 127);
+        self.set_cc(NRPN_MSB as usize, 127);
+        self.set_cc(EXPRESSION_MSB as usize, 127);
+        self.set_cc(EXPRESSION_LSB as usize, 127);
 
         if is_all_ctrl_off == 0 {
             self.pitch_wheel_sensitivity = 2;
+            self.mod_depth_range = 50.0;
 
             let mut i = SOUND_CTRL1;
             while i <= SOUND_CTRL10 {
-                self.cc[i as usize] = 64;
+                self.set_cc(i as usize, 64);
                 i += 1
             }
 
-            self.cc[VOLUME_MSB as usize] = 100;
-            self.cc[VOLUME_LSB as usize] = 0;
-            self.cc[PAN_MSB as usize] = 64;
-            self.cc[PAN_LSB as usize] = 0;
+            self.set_cc(VOLUME_MSB as usize, 100);
+            self.set_cc(VOLUME_LSB as usize, 0);
+            self.set_cc(PAN_MSB as usize, 64);
+            self.set_cc(PAN_LSB as usize, 0);
         };
     }
 }
@@ -223,22 +390,42 @@ impl Channel {
 
     //
 
-    pub fn key_pressure(&self, id: usize) -> i8 {
-        self.key_pressure[id]
-    }
-
     pub fn set_key_pressure(&mut self, id: usize, val: i8) {
         self.key_pressure[id] = val;
+        self.key_pressure32[id] = None;
     }
 
-    //
+    pub fn set_key_pressure32(&mut self, id: usize, val: u32) {
+        self.key_pressure[id] = (val >> 25) as i8;
+        self.key_pressure32[id] = Some(val);
+    }
 
-    pub fn channel_pressure(&self) -> u8 {
-        self.channel_pressure
+    /// Key pressure in the 0-127 range, with the fraction of a MIDI 2.0 value
+    pub fn key_pressure_value(&self, id: usize) -> f32 {
+        match self.key_pressure32[id] {
+            Some(val) => to_u7_range(val, 32),
+            None => self.key_pressure[id] as f32,
+        }
     }
 
+    //
+
     pub fn set_channel_pressure(&mut self, val: u8) {
         self.channel_pressure = val;
+        self.channel_pressure32 = None;
+    }
+
+    pub fn set_channel_pressure32(&mut self, val: u32) {
+        self.channel_pressure = (val >> 25) as u8;
+        self.channel_pressure32 = Some(val);
+    }
+
+    /// Channel pressure in the 0-127 range, with the fraction of a MIDI 2.0 value
+    pub fn channel_pressure_value(&self) -> f32 {
+        match self.channel_pressure32 {
+            Some(val) => to_u7_range(val, 32),
+            None => self.channel_pressure as f32,
+        }
     }
 
     //
@@ -249,6 +436,20 @@ impl Channel {
 
     pub fn set_pitch_bend(&mut self, val: u16) {
         self.pitch_bend = val;
+        self.pitch_bend32 = None;
+    }
+
+    pub fn set_pitch_bend32(&mut self, val: u32) {
+        self.pitch_bend = (val >> 18) as u16;
+        self.pitch_bend32 = Some(val);
+    }
+
+    /// Pitch bend in the 0-16383 range, with the fraction of a MIDI 2.0 value
+    pub fn pitch_bend_value(&self) -> f32 {
+        match self.pitch_bend32 {
+            Some(val) => to_u14_range(val),
+            None => self.pitch_bend as f32,
+        }
     }
 
     //
@@ -271,8 +472,27 @@ impl Channel {
         }
     }
 
-    pub fn cc_mut(&mut self, id: usize) -> &mut u8 {
-        &mut self.cc[id]
+    pub fn set_cc(&mut self, id: usize, val: u8) {
+        self.cc[id] = val;
+        self.cc32[id] = None;
+    }
+
+    /// MIDI 2.0 value of a controller, `None` when last set by a MIDI 1.0 message
+    pub fn cc32(&self, id: usize) -> Option<u32> {
+        self.cc32[id]
+    }
+
+    pub fn set_cc32(&mut self, id: usize, val: u32) {
+        self.cc[id] = (val >> 25) as u8;
+        self.cc32[id] = Some(val);
+    }
+
+    /// Controller value in the 0-127 range, with the fraction of a MIDI 2.0 value
+    pub fn cc_value(&self, id: usize) -> f32 {
+        match self.cc32.get(id) {
+            Some(Some(val)) => to_u7_range(*val, 32),
+            _ => self.cc(id) as f32,
+        }
     }
 
     //
@@ -295,6 +515,30 @@ impl Channel {
         self.interp_method = new_method;
     }
 
+    pub fn filter_type(&self) -> Option<FilterType> {
+        self.filter_type
+    }
+
+    pub fn set_filter_type(&mut self, filter_type: Option<FilterType>) {
+        self.filter_type = filter_type;
+    }
+
+    pub fn reverb_id(&self) -> usize {
+        self.reverb_id
+    }
+
+    pub fn set_reverb_id(&mut self, id: usize) {
+        self.reverb_id = id;
+    }
+
+    pub fn chorus_id(&self) -> usize {
+        self.chorus_id
+    }
+
+    pub fn set_chorus_id(&mut self, id: usize) {
+        self.chorus_id = id;
+    }
+
     //
 
     pub fn tuning(&self) -> Option<&Tuning> {
@@ -305,6 +549,32 @@ impl Channel {
         self.tuning = val;
     }
 
+    pub fn tuning_bank(&self) -> u8 {
+        self.tuning_bank
+    }
+
+    pub fn set_tuning_bank(&mut self, val: u8) {
+        self.tuning_bank = val;
+    }
+
+    pub fn tuning_prog(&self) -> u8 {
+        self.tuning_prog
+    }
+
+    pub fn set_tuning_prog(&mut self, val: u8) {
+        self.tuning_prog = val;
+    }
+
+    //
+
+    pub fn mod_depth_range(&self) -> f32 {
+        self.mod_depth_range
+    }
+
+    pub fn set_mod_depth_range(&mut self, cents: f32) {
+        self.mod_depth_range = cents;
+    }
+
     //
 
     pub fn nrpn_select(&self) -> i16 {
@@ -344,4 +614,245 @@ impl Channel {
     pub fn set_gen_abs(&mut self, id: usize, val: i8) {
         self.gen_abs[id] = val;
     }
+
+    //
+
+    pub fn key_gen(&self, key: usize, id: usize) -> f32 {
+        self.key_gen.get(key).map(|gen| gen[id]).unwrap_or(0.0)
+    }
+
+    pub fn set_key_gen(&mut self, key: usize, id: usize, val: f32) {
+        self.key_gen[key][id] = val;
+    }
+
+    /**
+    NRPN offset of a generator for the voices of a key: the channel-wide and
+    per-key offsets, plus the MPE zone pitch bend and the MIDI 2.0 per-note
+    pitch for the Pitch generator.
+     */
+    pub fn voice_gen(&self, key: u8, id: usize) -> f32 {
+        let offset = self.gen[id] + self.key_gen(key as usize, id);
+        if id == GeneratorType::Pitch as usize {
+            offset + self.zone_pitch_bend + self.note_pitch_offset(key)
+        } else {
+            offset
+        }
+    }
+
+    /// Scales of the reverb and chorus sends of the voices of a key
+    pub fn key_send_scale(&self, key: u8) -> (f32, f32) {
+        (
+            self.key_reverb_scale[key as usize],
+            self.key_chorus_scale[key as usize],
+        )
+    }
+
+    pub fn set_key_reverb_scale(&mut self, key: u8, val: f32) {
+        self.key_reverb_scale[key as usize] = val;
+    }
+
+    pub fn set_key_chorus_scale(&mut self, key: u8, val: f32) {
+        self.key_chorus_scale[key as usize] = val;
+    }
+
+    //
+
+    pub fn is_mpe_member(&self) -> bool {
+        self.mpe_member
+    }
+
+    pub fn set_mpe_member(&mut self, val: bool) {
+        self.mpe_member = val;
+    }
+
+    pub fn set_zone_pitch_bend(&mut self, cents: f32) {
+        self.zone_pitch_bend = cents;
+    }
+
+    //
+
+    /// Per-note controller value of a key, falls back to the channel value
+    pub fn note_cc_value(&self, key: u8, id: usize) -> f32 {
+        let note = &self.notes[key as usize];
+        match note.cc().iter().find(|(num, _)| *num as usize == id) {
+            Some((_, val)) => to_u7_range(*val, 32),
+            None => self.cc_value(id),
+        }
+    }
+
+    pub fn set_note_cc(&mut self, key: u8, id: u8, val: u32) {
+        let note = &mut self.notes[key as usize];
+        let count = note.cc_count as usize;
+        if let Some(slot) = note.cc[..count].iter_mut().find(|(num, _)| *num == id) {
+            slot.1 = val;
+        } else if count < NOTE_CC_SLOTS {
+            note.cc[count] = (id, val);
+            note.cc_count += 1;
+        } else {
+            log::warn!(
+                "Too many per-note controllers on channel {} [key={} ctrl={}]",
+                self.id,
+                key,
+                id
+            );
+        }
+    }
+
+    pub fn set_note_pitch_bend(&mut self, key: u8, val: u32) {
+        self.notes[key as usize].pitch_bend = Some(val);
+    }
+
+    pub fn set_note_pitch(&mut self, key: u8, val: u32) {
+        self.notes[key as usize].pitch = Some(val);
+    }
+
+    /**
+    Pitch offset (in cents) of a key, set by the per-note pitch bend and the
+    absolute Pitch 7.25 per-note controller.
+     */
+    pub fn note_pitch_offset(&self, key: u8) -> f32 {
+        let note = &self.notes[key as usize];
+        let bend = note.pitch_bend.map_or(0.0, |val| {
+            (val as f64 - 0x8000_0000u32 as f64) / 0x8000_0000u32 as f64
+                * self.pitch_wheel_sensitivity as f64
+                * 100.0
+        });
+        let pitch = note.pitch.map_or(0.0, |val| {
+            (val as f64 / (1u32 << 25) as f64 - key as f64) * 100.0
+        });
+        (bend + pitch) as f32
+    }
+
+    /// Reset the per-note controllers of a key to their default values
+    pub fn reset_note_controllers(&mut self, key: u8) {
+        self.notes[key as usize] = NoteControllers::default();
+    }
+}
+
+impl Channel {
+    /**
+    MIDI state and settings of the channel, the preset being in the font `font`.
+    The voice allocation settings are left to the voice pool.
+     */
+    pub fn state(&self, font: Option<u64>) -> ChannelState {
+        let notes = || (0..).zip(self.notes.iter());
+        let mut note_cc: Vec<_> = notes()
+            .flat_map(|(key, note)| note.cc().iter().map(move |(id, val)| (key, *id, *val)))
+            .collect();
+        note_cc.sort_unstable();
+        let note_pitch_bend = notes()
+            .filter_map(|(key, note)| Some((key, note.pitch_bend?)))
+            .collect();
+        let note_pitch = notes()
+            .filter_map(|(key, note)| Some((key, note.pitch?)))
+            .collect();
+
+        ChannelState {
+            font,
+            bank: self.banknum,
+            program: self.prognum,
+            bank_msb: self.bank_msb,
+
+            cc: self.cc.to_vec(),
+            cc32: self.cc32.to_vec(),
+            key_pressure: self.key_pressure.to_vec(),
+            key_pressure32: self.key_pressure32.to_vec(),
+            channel_pressure: self.channel_pressure,
+            channel_pressure32: self.channel_pressure32,
+            pitch_bend: self.pitch_bend,
+            pitch_bend32: self.pitch_bend32,
+            pitch_wheel_sensitivity: self.pitch_wheel_sensitivity,
+            mod_depth_range: self.mod_depth_range,
+            nrpn_select: self.nrpn_select,
+            nrpn_active: self.nrpn_active,
+
+            gen: self.gen.to_vec(),
+            gen_abs: self.gen_abs.to_vec(),
+            key_gen: self.key_gen.iter().map(|gen| gen.to_vec()).collect(),
+            key_reverb_scale: self.key_reverb_scale.to_vec(),
+            key_chorus_scale: self.key_chorus_scale.to_vec(),
+            zone_pitch_bend: self.zone_pitch_bend,
+
+            note_cc,
+            note_pitch_bend,
+            note_pitch,
+
+            interp_method: self.interp_method,
+            filter_type: self.filter_type,
+            reverb_id: self.reverb_id,
+            chorus_id: self.chorus_id,
+            tuning: self.tuning.as_ref().map(Into::into),
+            tuning_bank: self.tuning_bank,
+            tuning_prog: self.tuning_prog,
+
+            voice_priority: 0,
+            voice_limit: None,
+        }
+    }
+
+    /**
+    Restore the state saved by `state()`, with the preset it selects.
+    Arrays of the wrong length are restored up to the shortest one.
+     */
+    pub fn restore_state(
+        &mut self,
+        state: &ChannelState,
+        sfontnum: Option<TypedIndex<SoundFont>>,
+        preset: Option<Arc<Preset>>,
+    ) {
+        fn copy<T: Copy>(dst: &mut [T], src: &[T]) {
+            dst.iter_mut().zip(src.iter()).for_each(|(d, s)| *d = *s);
+        }
+
+        self.sfontnum = sfontnum;
+        self.banknum = state.bank;
+        self.prognum = state.program;
+        self.preset = preset;
+        self.bank_msb = state.bank_msb;
+
+        copy(&mut self.cc, &state.cc);
+        copy(&mut self.cc32, &state.cc32);
+        copy(&mut self.key_pressure, &state.key_pressure);
+        copy(&mut self.key_pressure32, &state.key_pressure32);
+        self.channel_pressure = state.channel_pressure;
+        self.channel_pressure32 = state.channel_pressure32;
+        self.pitch_bend = state.pitch_bend;
+        self.pitch_bend32 = state.pitch_bend32;
+        self.pitch_wheel_sensitivity = state.pitch_wheel_sensitivity;
+        self.mod_depth_range = state.mod_depth_range;
+        self.nrpn_select = state.nrpn_select;
+        self.nrpn_active = state.nrpn_active;
+
+        copy(&mut self.gen, &state.gen);
+        copy(&mut self.gen_abs, &state.gen_abs);
+        self.key_gen.iter_mut().for_each(|gen| *gen = [0.0; 60]);
+        for (gen, state) in self.key_gen.iter_mut().zip(state.key_gen.iter()) {
+            copy(gen, state);
+        }
+        copy(&mut self.key_reverb_scale, &state.key_reverb_scale);
+        copy(&mut self.key_chorus_scale, &state.key_chorus_scale);
+        self.zone_pitch_bend = state.zone_pitch_bend;
+
+        self.notes
+            .iter_mut()
+            .for_each(|note| *note = NoteControllers::default());
+        let keys = 0..self.notes.len() as u8;
+        for (key, id, val) in state.note_cc.iter().filter(|n| keys.contains(&n.0)) {
+            self.set_note_cc(*key, *id, *val);
+        }
+        for (key, val) in state.note_pitch_bend.iter().filter(|n| keys.contains(&n.0)) {
+            self.set_note_pitch_bend(*key, *val);
+        }
+        for (key, val) in state.note_pitch.iter().filter(|n| keys.contains(&n.0)) {
+            self.set_note_pitch(*key, *val);
+        }
+
+        self.interp_method = state.interp_method;
+        self.filter_type = state.filter_type;
+        self.reverb_id = state.reverb_id;
+        self.chorus_id = state.chorus_id;
+        self.tuning = state.tuning.as_ref().map(Into::into);
+        self.tuning_bank = state.tuning_bank;
+        self.tuning_prog = state.tuning_prog;
+    }
 }
@@ -0,0 +1,168 @@
+use std::ops::RangeInclusive;
+
+/// Number of channels that can take part in MPE zones (one MIDI port)
+pub const MPE_CHANNELS: usize = 16;
+
+/**
+An MPE zone
+
+The lower zone is managed by channel 0 and its member channels follow it upwards,
+the upper zone is managed by channel 15 and its member channels follow it downwards.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MpeZone {
+    Lower,
+    Upper,
+}
+
+impl MpeZone {
+    /// The manager channel of the zone
+    pub fn manager_channel(&self) -> usize {
+        match self {
+            MpeZone::Lower => 0,
+            MpeZone::Upper => MPE_CHANNELS - 1,
+        }
+    }
+
+    /// Returns the zone managed by the given channel, if any
+    pub fn from_manager_channel(channel: usize) -> Option<Self> {
+        match channel {
+            0 => Some(MpeZone::Lower),
+            ch if ch == MPE_CHANNELS - 1 => Some(MpeZone::Upper),
+            _ => None,
+        }
+    }
+}
+
+/**
+The channel layout of an active MPE zone
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MpeZoneLayout {
+    pub zone: MpeZone,
+    /// Number of member channels (1-15)
+    pub member_count: u8,
+}
+
+impl MpeZoneLayout {
+    pub fn manager_channel(&self) -> usize {
+        self.zone.manager_channel()
+    }
+
+    pub fn member_channels(&self) -> RangeInclusive<usize> {
+        let count = self.member_count as usize;
+        match self.zone {
+            MpeZone::Lower => 1..=count,
+            MpeZone::Upper => (MPE_CHANNELS - 1 - count)..=(MPE_CHANNELS - 2),
+        }
+    }
+
+    pub fn is_member(&self, channel: usize) -> bool {
+        self.member_channels().contains(&channel)
+    }
+}
+
+/**
+MPE zone configuration, as set by the MPE Configuration Message (RPN 6)
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MpeConfig {
+    lower: Option<MpeZoneLayout>,
+    upper: Option<MpeZoneLayout>,
+}
+
+impl MpeConfig {
+    pub fn zone(&self, zone: MpeZone) -> Option<MpeZoneLayout> {
+        match zone {
+            MpeZone::Lower => self.lower,
+            MpeZone::Upper => self.upper,
+        }
+    }
+
+    /**
+    Set the number of member channels of a zone, 0 disables the zone.
+
+    As required by the MPE specification a zone that overlaps the other one
+    shrinks the other zone, disabling it if no member channel is left.
+     */
+    pub fn set_zone(&mut self, zone: MpeZone, member_count: u8) {
+        let member_count = member_count.min(MPE_CHANNELS as u8 - 1);
+        let layout = if member_count == 0 {
+            None
+        } else {
+            Some(MpeZoneLayout { zone, member_count })
+        };
+
+        // Channels left for the other zone (its manager channel included)
+        let left = MPE_CHANNELS as u8 - 1 - member_count;
+        let shrink = |other: Option<MpeZoneLayout>| {
+            other.and_then(|other| {
+                let member_count = other.member_count.min(left.saturating_sub(1));
+                if member_count == 0 {
+                    None
+                } else {
+                    Some(MpeZoneLayout {
+                        member_count,
+                        ..other
+                    })
+                }
+            })
+        };
+
+        match zone {
+            MpeZone::Lower => {
+                self.lower = layout;
+                self.upper = shrink(self.upper);
+            }
+            MpeZone::Upper => {
+                self.upper = layout;
+                self.lower = shrink(self.lower);
+            }
+        }
+    }
+
+    pub fn zones(&self) -> impl Iterator<Item = MpeZoneLayout> {
+        self.lower.into_iter().chain(self.upper)
+    }
+
+    /// The zone the channel is a member channel of
+    pub fn member_zone(&self, channel: usize) -> Option<MpeZoneLayout> {
+        self.zones().find(|zone| zone.is_member(channel))
+    }
+
+    /// The zone the channel is the manager channel of
+    pub fn manager_zone(&self, channel: usize) -> Option<MpeZoneLayout> {
+        self.zones().find(|zone| zone.manager_channel() == channel)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn zone_overlap() {
+        let mut mpe = MpeConfig::default();
+
+        mpe.set_zone(MpeZone::Lower, 7);
+        mpe.set_zone(MpeZone::Upper, 7);
+        assert_eq!(mpe.zone(MpeZone::Lower).unwrap().member_channels(), 1..=7);
+        assert_eq!(mpe.zone(MpeZone::Upper).unwrap().member_channels(), 8..=14);
+
+        // Growing the upper zone shrinks the lower one
+        mpe.set_zone(MpeZone::Upper, 10);
+        assert_eq!(mpe.zone(MpeZone::Lower).unwrap().member_channels(), 1..=4);
+        assert_eq!(mpe.member_zone(5).unwrap().zone, MpeZone::Upper);
+        assert_eq!(mpe.manager_zone(0).unwrap().zone, MpeZone::Lower);
+
+        // A full zone disables the other one
+        mpe.set_zone(MpeZone::Lower, 15);
+        assert_eq!(mpe.zone(MpeZone::Upper), None);
+        assert_eq!(mpe.zone(MpeZone::Lower).unwrap().member_channels(), 1..=15);
+
+        mpe.set_zone(MpeZone::Lower, 0);
+        assert_eq!(mpe.zones().count(), 0);
+    }
+}
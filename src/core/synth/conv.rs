@@ -180,19 +180,33 @@ pub fn pan(mut c: f32, left: i32) -> f32 {
 pub fn concave(val: f32) -> f32 {
     if val < 0.0 {
         0.0
-    } else if val > 127.0 {
+    } else if val >= 127.0 {
         1.0
     } else {
-        CONCAVE_TAB[val as usize]
+        // Interpolate between the table entries for high resolution (MIDI 2.0) values
+        let i = val as usize;
+        let frac = val - i as f32;
+        if frac == 0.0 {
+            CONCAVE_TAB[i]
+        } else {
+            CONCAVE_TAB[i] + frac * (CONCAVE_TAB[i + 1] - CONCAVE_TAB[i])
+        }
     }
 }
 
 pub fn convex(val: f32) -> f32 {
     if val < 0.0 {
         0.0
-    } else if val > 127.0 {
+    } else if val >= 127.0 {
         1.0
     } else {
-        CONVEX_TAB[val as usize]
+        // Interpolate between the table entries for high resolution (MIDI 2.0) values
+        let i = val as usize;
+        let frac = val - i as f32;
+        if frac == 0.0 {
+            CONVEX_TAB[i]
+        } else {
+            CONVEX_TAB[i] + frac * (CONVEX_TAB[i + 1] - CONVEX_TAB[i])
+        }
     }
 }
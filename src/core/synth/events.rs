@@ -0,0 +1,70 @@
+use std::collections::vec_deque::{Drain, VecDeque};
+
+use super::voice_pool::StolenVoice;
+
+/**
+Notification of something the synth did, see
+[`Synth::set_event_capacity`](crate::Synth::set_event_capacity)
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SynthEvent {
+    /// A voice started playing a note
+    VoiceStarted { channel: usize, key: u8, vel: u8 },
+    /// A voice stopped, at the end of its release or of its sample, or by a sound off
+    VoiceFinished { channel: usize, key: u8 },
+    /// A voice was killed to make room for a new one
+    VoiceStolen(StolenVoice),
+    /// A note on was dropped, the channel having no preset
+    NoPreset { channel: usize, key: u8, vel: u8 },
+    /// A voice of a note on was dropped, no voice could be freed under the polyphony
+    /// or the voice limit of the channel
+    PolyphonyExhausted { channel: usize, key: u8, vel: u8 },
+    /// A voice was released by a new voice of the same exclusive class
+    ExclusiveClassKill { channel: usize, key: u8 },
+}
+
+/// Events waiting to be drained, the memory being allocated up front
+pub(crate) struct EventQueue {
+    events: VecDeque<SynthEvent>,
+    capacity: usize,
+    /// Events dropped since the last drain, the queue being full
+    lost: usize,
+}
+
+impl EventQueue {
+    pub fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+            capacity: 0,
+            lost: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Hold up to `capacity` events, 0 to stop recording them
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.events = VecDeque::with_capacity(capacity);
+        self.capacity = capacity;
+        self.lost = 0;
+    }
+
+    pub fn push(&mut self, event: SynthEvent) {
+        if self.events.len() < self.capacity {
+            self.events.push_back(event);
+        } else if self.capacity != 0 {
+            self.lost += 1;
+        }
+    }
+
+    pub fn drain(&mut self) -> Drain<'_, SynthEvent> {
+        self.lost = 0;
+        self.events.drain(..)
+    }
+
+    pub fn lost(&self) -> usize {
+        self.lost
+    }
+}
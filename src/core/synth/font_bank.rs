@@ -62,6 +62,21 @@ impl FontBank {
         }
     }
 
+    /**
+    Get the ID of the SoundFont with the given fingerprint, the highest on the stack.
+     */
+    pub fn find_fingerprint(&self, fingerprint: u64) -> Option<TypedIndex<SoundFont>> {
+        self.stack
+            .iter()
+            .copied()
+            .find(|id| self.fonts.get(*id).map(|f| f.fingerprint()) == Some(fingerprint))
+    }
+
+    /// IDs of the SoundFonts, top of the stack first
+    pub fn stack(&self) -> &[TypedIndex<SoundFont>] {
+        &self.stack
+    }
+
     pub fn iter_stack(&self) -> impl Iterator<Item = &SoundFont> {
         self.stack.iter().filter_map(move |f| self.fonts.get(*f))
     }
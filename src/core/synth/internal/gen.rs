@@ -16,7 +16,24 @@ pub fn set_gen(channel: &mut Channel, voices: &mut VoicePool, param: GeneratorTy
     channel.set_gen(param as usize, value);
     channel.set_gen_abs(param as usize, 0);
 
-    voices.set_gen(channel.id(), param, value);
+    voices.set_gen(channel, param);
+}
+
+/**
+Change the value of a generator for a single key of a channel. The
+value is added on top of the channel-wide value set by `set_gen()`,
+and applies to voices started on that key.
+ */
+pub fn set_key_gen(
+    channel: &mut Channel,
+    voices: &mut VoicePool,
+    key: u8,
+    param: GeneratorType,
+    value: f32,
+) {
+    channel.set_key_gen(key as usize, param as usize, value);
+
+    voices.set_key_gen(channel, key, param);
 }
 
 /**
@@ -28,3 +45,11 @@ Returns the value of the generator.
 pub fn gen(channel: &Channel, param: GeneratorType) -> f32 {
     channel.gen(param as usize)
 }
+
+/**
+Retreive the per-key value of a generator, set by a previous call to
+`set_key_gen()` or by a drum instrument NRPN message.
+ */
+pub fn key_gen(channel: &Channel, key: u8, param: GeneratorType) -> f32 {
+    channel.key_gen(key as usize, param as usize)
+}
@@ -1,4 +1,5 @@
 use std::convert::TryInto;
+use std::sync::Arc;
 
 use crate::core::chorus::Chorus;
 use crate::core::error::OxiError;
@@ -9,8 +10,10 @@ use crate::core::soundfont::{
     InstrumentZone, PresetZone, SoundFont,
 };
 use crate::core::synth::channel_pool::Channel;
+use crate::core::synth::events::SynthEvent;
 use crate::core::synth::font_bank::FontBank;
-use crate::core::synth::voice_pool::{Voice, VoiceAddMode, VoiceDescriptor, VoicePool};
+use crate::core::synth::voice_pool::{Midi2Note, Voice, VoiceAddMode, VoiceDescriptor, VoicePool};
+use crate::core::tuning::TuningManager;
 use crate::core::utils::TypedIndex;
 
 type GenType = u32;
@@ -21,11 +24,14 @@ const RPN_MSB: MidiControlChange = 101;
 const RPN_LSB: MidiControlChange = 100;
 const NRPN_MSB: MidiControlChange = 99;
 const NRPN_LSB: MidiControlChange = 98;
+const DATA_ENTRY_MSB: MidiControlChange = 6;
 const DATA_ENTRY_LSB: MidiControlChange = 38;
+const DATA_ENTRY_INCR: MidiControlChange = 96;
 
 /**
 Send a noteon message.
  */
+#[allow(clippy::too_many_arguments)]
 pub fn noteon(
     channel: &Channel,
     voices: &mut VoicePool,
@@ -34,17 +40,23 @@ pub fn noteon(
     gain: f32,
     key: u8,
     vel: u8,
+    midi2: Option<Midi2Note>,
 ) -> Result<(), OxiError> {
     if vel == 0 {
         noteoff(channel, voices, min_note_length_ticks, key);
         Ok(())
     } else if channel.preset().is_none() {
+        voices.push_event(SynthEvent::NoPreset {
+            channel: channel.id(),
+            key,
+            vel,
+        });
         Err(OxiError::ChannelHasNoPreset)
     } else {
         voices.release_voice_on_same_note(channel, key, min_note_length_ticks);
         voices.noteid_add();
 
-        inner_noteon(channel, voices, start_time, gain, key, vel);
+        inner_noteon(channel, voices, start_time, gain, key, vel, midi2);
         Ok(())
     }
 }
@@ -56,6 +68,7 @@ fn inner_noteon(
     gain: f32,
     key: u8,
     vel: u8,
+    midi2: Option<Midi2Note>,
 ) {
     fn preset_zone_inside_range(zone: &PresetZone, key: u8, vel: u8) -> bool {
         zone.key_low <= key && zone.key_high >= key && zone.vel_low <= vel && zone.vel_high >= vel
@@ -93,6 +106,17 @@ fn inner_noteon(
                         // Initialize Voice
                         let init = |voice: &mut Voice| {
                             voice.add_default_mods();
+                            if channel.is_mpe_member() {
+                                voice.add_mpe_mods();
+                            }
+
+                            // The channel filter overrides the one of the zone
+                            voice.set_filter_type(
+                                channel
+                                    .filter_type()
+                                    .or(inst_zone.filter_type)
+                                    .unwrap_or_default(),
+                            );
 
                             // Instrument level, generators
                             for i in 0..GEN_LAST {
@@ -275,9 +299,11 @@ fn inner_noteon(
 
                         let desc = VoiceDescriptor {
                             sample: sample.as_ref().unwrap().clone(),
+                            preset: Arc::clone(preset),
                             channel,
                             key,
                             vel,
+                            midi2,
                             start_time,
                             gain,
                         };
@@ -290,7 +316,7 @@ fn inner_noteon(
                                 channel.id(),
                                 key,
                                 vel,
-                                start_time as f32 / 44100.0,
+                                start_time as f32 / voices.sample_rate(),
                             );
                         } else {
                             log::warn!(
@@ -319,13 +345,37 @@ Send a control change message.
 pub fn cc(
     channel: &mut Channel,
     voices: &mut VoicePool,
+    tunings: &TuningManager,
     min_note_length_ticks: usize,
     drums_channel_active: bool,
     num: u8,
     value: u8,
 ) {
-    *channel.cc_mut(num as usize) = value;
+    channel.set_cc(num as usize, value);
+
+    control_change(
+        channel,
+        voices,
+        tunings,
+        min_note_length_ticks,
+        drums_channel_active,
+        num,
+        value,
+    );
+}
 
+/**
+Act on a control change, once the new value is stored in the channel.
+ */
+pub(super) fn control_change(
+    channel: &mut Channel,
+    voices: &mut VoicePool,
+    tunings: &TuningManager,
+    min_note_length_ticks: usize,
+    drums_channel_active: bool,
+    num: u8,
+    value: u8,
+) {
     match num {
         // SUSTAIN_SWITCH
         64 => {
@@ -388,63 +438,14 @@ pub fn cc(
         }
 
         // DATA_ENTRY_MSB
-        6 => {
-            let data: i32 =
-                ((value as i32) << 7 as i32) + channel.cc(DATA_ENTRY_LSB as usize) as i32;
-
-            if channel.nrpn_active() != 0 {
-                let (nrpn_select, nrpn_msb, nrpn_lsb) = (
-                    channel.nrpn_select(),
-                    channel.cc(NRPN_MSB as usize),
-                    channel.cc(NRPN_LSB as usize),
-                );
-
-                // SontFont 2.01 NRPN Message (Sect. 9.6, p. 74)
-                if nrpn_msb == 120 && nrpn_lsb < 100 {
-                    if (nrpn_select as i32) < GEN_LAST as i32 {
-                        use num_traits::FromPrimitive;
+        6 => data_entry(channel, voices, tunings),
 
-                        let scale_nrpn: f32 = gen_scale_nrpn(nrpn_select, data);
-
-                        let param = FromPrimitive::from_u8(nrpn_select as u8).unwrap();
-                        super::gen::set_gen(channel, voices, param, scale_nrpn)
-                    }
-
-                    channel.set_nrpn_select(0); // Reset to 0
-                }
-            }
-            /* RPN is active: MSB = 0? */
-            else if channel.cc(RPN_MSB as usize) == 0 {
-                match channel.cc(RPN_LSB as usize) {
-                    // RPN_PITCH_BEND_RANGE
-                    0 => pitch_wheel_sens(channel, voices, value),
-                    // RPN_CHANNEL_FINE_TUNE
-                    1 => {
-                        super::gen::set_gen(
-                            channel,
-                            voices,
-                            GeneratorType::FineTune,
-                            ((data - 8192) as f64 / 8192.0f64 * 100.0f64) as f32,
-                        );
-                    }
-                    // RPN_CHANNEL_COARSE_TUNE
-                    2 => {
-                        super::gen::set_gen(
-                            channel,
-                            voices,
-                            GeneratorType::CoarseTune,
-                            (value - 64) as f32,
-                        );
-                    }
-                    // RPN_TUNING_PROGRAM_CHANGE | RPN_TUNING_BANK_SELECT | RPN_MODULATION_DEPTH_RANGE
-                    3 | 4 | 5 | _ => {}
-                }
-            }
-        }
+        // DATA_ENTRY_INCR | DATA_ENTRY_DECR
+        96 | 97 => data_entry_step(channel, voices, tunings, num == DATA_ENTRY_INCR as u8),
 
         // NRPN_MSB
         99 => {
-            *channel.cc_mut(NRPN_LSB as usize) = 0;
+            channel.set_cc(NRPN_LSB as usize, 0);
             channel.set_nrpn_select(0);
             channel.set_nrpn_active(1);
         }
@@ -472,6 +473,277 @@ pub fn cc(
     }
 }
 
+/// RPN 127/127 (RPN null) deselects the current parameter, so that further
+/// data entry messages are ignored.
+fn is_rpn_null(channel: &Channel) -> bool {
+    channel.cc(RPN_MSB as usize) == 127 && channel.cc(RPN_LSB as usize) == 127
+}
+
+/**
+Apply the current Data Entry value (CC6 / CC38) to the selected RPN or NRPN.
+ */
+fn data_entry(channel: &mut Channel, voices: &mut VoicePool, tunings: &TuningManager) {
+    let value = channel.cc(DATA_ENTRY_MSB as usize);
+    let data: i32 = ((value as i32) << 7) + channel.cc(DATA_ENTRY_LSB as usize) as i32;
+
+    if channel.nrpn_active() != 0 {
+        let (nrpn_select, nrpn_msb, nrpn_lsb) = (
+            channel.nrpn_select(),
+            channel.cc(NRPN_MSB as usize),
+            channel.cc(NRPN_LSB as usize),
+        );
+
+        // SontFont 2.01 NRPN Message (Sect. 9.6, p. 74)
+        if nrpn_msb == 120 && nrpn_lsb < 100 {
+            if (nrpn_select as i32) < GEN_LAST as i32 {
+                use num_traits::FromPrimitive;
+
+                let scale_nrpn: f32 = gen_scale_nrpn(nrpn_select, data);
+
+                let param = FromPrimitive::from_u8(nrpn_select as u8).unwrap();
+                super::gen::set_gen(channel, voices, param, scale_nrpn)
+            }
+
+            channel.set_nrpn_select(0); // Reset to 0
+        } else {
+            gs_xg_nrpn(channel, voices, nrpn_msb, nrpn_lsb, value);
+        }
+    } else if is_rpn_null(channel) {
+        // No parameter selected
+    }
+    /* RPN is active: MSB = 0? */
+    else if channel.cc(RPN_MSB as usize) == 0 {
+        match channel.cc(RPN_LSB as usize) {
+            // RPN_PITCH_BEND_RANGE
+            0 => pitch_wheel_sens(channel, voices, value),
+            // RPN_CHANNEL_FINE_TUNE
+            1 => {
+                super::gen::set_gen(
+                    channel,
+                    voices,
+                    GeneratorType::FineTune,
+                    ((data - 8192) as f64 / 8192.0f64 * 100.0f64) as f32,
+                );
+            }
+            // RPN_CHANNEL_COARSE_TUNE
+            2 => {
+                super::gen::set_gen(
+                    channel,
+                    voices,
+                    GeneratorType::CoarseTune,
+                    (value as i32 - 64) as f32,
+                );
+            }
+            // RPN_TUNING_PROGRAM_CHANGE
+            3 => {
+                channel.set_tuning_prog(value);
+                activate_tuning(channel, tunings);
+            }
+            // RPN_TUNING_BANK_SELECT
+            4 => channel.set_tuning_bank(value),
+            // RPN_MODULATION_DEPTH_RANGE
+            5 => {
+                // MSB is in semitones, LSB in 100/128 cents
+                let cents = value as f32 * 100.0
+                    + channel.cc(DATA_ENTRY_LSB as usize) as f32 * 100.0 / 128.0;
+                mod_depth_range(channel, voices, cents);
+            }
+            _ => {}
+        }
+    }
+}
+
+/**
+GS/XG NRPN parameters.
+
+The relative parameters (value 64 = no change) are mapped onto the
+channel-wide generator offsets, the drum instrument parameters
+(MSB 0x14-0x1F, LSB = drum note) onto per-key generator offsets.
+ */
+fn gs_xg_nrpn(channel: &mut Channel, voices: &mut VoicePool, msb: u8, lsb: u8, value: u8) {
+    /// Maps a relative -64..+63 NRPN value onto -range..+range
+    fn relative(value: u8, range: f32) -> f32 {
+        (value as f32 - 64.0) / 64.0 * range
+    }
+
+    use super::gen::{set_gen, set_key_gen};
+
+    match (msb, lsb) {
+        // Vibrato rate, in cents
+        (0x01, 0x08) => set_gen(
+            channel,
+            voices,
+            GeneratorType::VibLfoFreq,
+            relative(value, 2400.0),
+        ),
+        // Vibrato depth, in cents
+        (0x01, 0x09) => set_gen(
+            channel,
+            voices,
+            GeneratorType::VibLfoToPitch,
+            relative(value, 100.0),
+        ),
+        // Vibrato delay, in timecents
+        (0x01, 0x0a) => set_gen(
+            channel,
+            voices,
+            GeneratorType::VibLfoDelay,
+            relative(value, 4800.0),
+        ),
+        // Filter cutoff frequency, in cents
+        (0x01, 0x20) => set_gen(
+            channel,
+            voices,
+            GeneratorType::FilterFc,
+            relative(value, 4800.0),
+        ),
+        // Filter resonance, in cB
+        (0x01, 0x21) => set_gen(
+            channel,
+            voices,
+            GeneratorType::FilterQ,
+            relative(value, 240.0),
+        ),
+        // Envelope attack time, in timecents
+        (0x01, 0x63) => set_gen(
+            channel,
+            voices,
+            GeneratorType::VolEnvAttack,
+            relative(value, 4800.0),
+        ),
+        // Envelope decay time, in timecents
+        (0x01, 0x64) => set_gen(
+            channel,
+            voices,
+            GeneratorType::VolEnvDecay,
+            relative(value, 4800.0),
+        ),
+        // Envelope release time, in timecents
+        (0x01, 0x66) => set_gen(
+            channel,
+            voices,
+            GeneratorType::VolEnvRelease,
+            relative(value, 4800.0),
+        ),
+
+        // Drum instrument filter cutoff (XG), in cents
+        (0x14, key) => set_key_gen(
+            channel,
+            voices,
+            key,
+            GeneratorType::FilterFc,
+            relative(value, 4800.0),
+        ),
+        // Drum instrument filter resonance (XG), in centibels
+        (0x15, key) => set_key_gen(
+            channel,
+            voices,
+            key,
+            GeneratorType::FilterQ,
+            relative(value, 240.0),
+        ),
+        // Drum instrument pitch coarse, in semitones
+        (0x18, key) => set_key_gen(
+            channel,
+            voices,
+            key,
+            GeneratorType::CoarseTune,
+            value as f32 - 64.0,
+        ),
+        // Drum instrument pitch fine (XG), in cents
+        (0x19, key) => set_key_gen(
+            channel,
+            voices,
+            key,
+            GeneratorType::FineTune,
+            value as f32 - 64.0,
+        ),
+        // Drum instrument level, 127 = unchanged
+        (0x1a, key) => {
+            let atten = if value == 0 {
+                1440.0
+            } else {
+                (400.0 * f32::log10(127.0 / value as f32)).min(1440.0)
+            };
+            set_key_gen(channel, voices, key, GeneratorType::Attenuation, atten)
+        }
+        // Drum instrument pan, 64 = unchanged (random pan is not supported)
+        (0x1c, key) => set_key_gen(
+            channel,
+            voices,
+            key,
+            GeneratorType::Pan,
+            relative(value, 500.0),
+        ),
+        // Drum instrument reverb send, scaling the voice reverb send (127 = unchanged)
+        (0x1d, key) => {
+            channel.set_key_reverb_scale(key, value as f32 / 127.0);
+            voices.set_key_send_scale(channel, key);
+        }
+        // Drum instrument chorus send, scaling the voice chorus send (127 = unchanged)
+        (0x1e, key) => {
+            channel.set_key_chorus_scale(key, value as f32 / 127.0);
+            voices.set_key_send_scale(channel, key);
+        }
+        _ => {}
+    }
+}
+
+/**
+Handle Data Increment (CC96) and Data Decrement (CC97).
+
+Parameters that only use the MSB of the data entry value (pitch bend range,
+coarse tune, tuning program/bank and the GS/XG NRPNs) are stepped by one
+MSB unit, all other parameters are stepped by one LSB unit.
+ */
+fn data_entry_step(
+    channel: &mut Channel,
+    voices: &mut VoicePool,
+    tunings: &TuningManager,
+    incr: bool,
+) {
+    if channel.nrpn_active() == 0 && is_rpn_null(channel) {
+        return;
+    }
+
+    let msb_only = if channel.nrpn_active() != 0 {
+        // Only the SoundFont NRPNs use the LSB
+        channel.cc(NRPN_MSB as usize) != 120
+    } else {
+        matches!(channel.cc(RPN_LSB as usize), 0 | 2 | 3 | 4)
+    };
+    let step: i32 = if msb_only { 1 << 7 } else { 1 };
+
+    let data: i32 = ((channel.cc(DATA_ENTRY_MSB as usize) as i32) << 7)
+        + channel.cc(DATA_ENTRY_LSB as usize) as i32;
+    let data = if incr { data + step } else { data - step };
+    let data = data.clamp(0, 0x3fff);
+
+    channel.set_cc(DATA_ENTRY_MSB as usize, (data >> 7) as u8);
+    channel.set_cc(DATA_ENTRY_LSB as usize, (data & 0x7f) as u8);
+
+    data_entry(channel, voices, tunings);
+}
+
+/**
+Select the tuning stored in the tuning manager under the channel's
+current tuning bank and program.
+ */
+fn activate_tuning(channel: &mut Channel, tunings: &TuningManager) {
+    let (bank, prog) = (channel.tuning_bank() as u32, channel.tuning_prog() as u32);
+
+    if let Some(tuning) = tunings.tuning(bank, prog) {
+        channel.set_tuning(Some(*tuning));
+    } else {
+        log::warn!(
+            "No tuning found on channel {} [bank={} prog={}]",
+            channel.id(),
+            bank,
+            prog
+        );
+    }
+}
+
 /**
 Get a control value.
  */
@@ -514,6 +786,16 @@ pub fn pitch_wheel_sens(channel: &mut Channel, voices: &mut VoicePool, val: u8)
     voices.modulate_voices(&channel, false, MOD_PITCHWHEELSENS);
 }
 
+/**
+Set the modulation wheel vibrato depth (in cents).
+ */
+pub fn mod_depth_range(channel: &mut Channel, voices: &mut VoicePool, cents: f32) {
+    const MODULATION_MSB: u8 = 1;
+
+    channel.set_mod_depth_range(cents);
+    voices.modulate_voices(channel, true, MODULATION_MSB);
+}
+
 // /**
 // Get the pitch wheel sensitivity.
 //  */
@@ -667,8 +949,8 @@ pub fn system_reset(
     voices: &mut VoicePool,
     channels: &mut [Channel],
     font_bank: &FontBank,
-    chorus: &mut Chorus,
-    reverb: &mut Reverb,
+    choruses: &mut [Chorus],
+    reverbs: &mut [Reverb],
 ) {
     voices.system_reset();
 
@@ -678,6 +960,6 @@ pub fn system_reset(
         channel.init_ctrl(0);
     }
 
-    chorus.reset();
-    reverb.reset();
+    choruses.iter_mut().for_each(|chorus| chorus.reset());
+    reverbs.iter_mut().for_each(|reverb| reverb.reset());
 }
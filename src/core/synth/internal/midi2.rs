@@ -0,0 +1,194 @@
+use crate::core::error::OxiError;
+use crate::core::midi2_event::NoteAttribute;
+use crate::core::soundfont::generator::GeneratorType;
+use crate::core::synth::channel_pool::Channel;
+use crate::core::synth::voice_pool::{Midi2Note, VoicePool};
+use crate::core::tuning::TuningManager;
+
+const MOD_KEYPRESSURE: u8 = 10;
+const MOD_CHANNELPRESSURE: u8 = 13;
+const MOD_PITCHWHEEL: u8 = 14;
+
+/// Registered Per-Note Controller #3: absolute Pitch 7.25 of the note
+const PER_NOTE_PITCH: u8 = 3;
+
+/**
+Send a MIDI 2.0 noteon message.
+
+Unlike MIDI 1.0, a velocity of 0 does not turn the note off.
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn noteon(
+    channel: &Channel,
+    voices: &mut VoicePool,
+    start_time: usize,
+    min_note_length_ticks: usize,
+    gain: f32,
+    key: u8,
+    vel: u16,
+    attribute: NoteAttribute,
+) -> Result<(), OxiError> {
+    let pitch = match attribute {
+        NoteAttribute::Pitch7_9(pitch) => Some(pitch),
+        _ => None,
+    };
+
+    super::midi::noteon(
+        channel,
+        voices,
+        start_time,
+        min_note_length_ticks,
+        gain,
+        key,
+        ((vel >> 9) as u8).max(1),
+        Some(Midi2Note { vel, pitch }),
+    )
+}
+
+/**
+Send a control change message with 32 bit value.
+ */
+pub fn cc(
+    channel: &mut Channel,
+    voices: &mut VoicePool,
+    tunings: &TuningManager,
+    min_note_length_ticks: usize,
+    drums_channel_active: bool,
+    num: u8,
+    value: u32,
+) {
+    channel.set_cc32(num as usize, value);
+
+    super::midi::control_change(
+        channel,
+        voices,
+        tunings,
+        min_note_length_ticks,
+        drums_channel_active,
+        num,
+        channel.cc(num as usize),
+    );
+}
+
+/**
+Send a pitch bend message with 32 bit value.
+ */
+pub fn pitch_bend(channel: &mut Channel, voices: &mut VoicePool, val: u32) {
+    channel.set_pitch_bend32(val);
+    voices.modulate_voices(channel, false, MOD_PITCHWHEEL);
+}
+
+/**
+Set channel pressure with 32 bit value.
+ */
+pub fn channel_pressure(channel: &mut Channel, voices: &mut VoicePool, val: u32) {
+    channel.set_channel_pressure32(val);
+    voices.modulate_voices(channel, false, MOD_CHANNELPRESSURE);
+}
+
+/**
+Set key pressure (aftertouch) with 32 bit value.
+ */
+pub fn key_pressure(channel: &mut Channel, voices: &mut VoicePool, key: u8, val: u32) {
+    channel.set_key_pressure32(key as usize, val);
+    voices.modulate_note_voices(channel, key, MOD_KEYPRESSURE);
+}
+
+/**
+Set a per-note controller.
+
+Registered controller 3 sets the absolute pitch of the note, all other
+controllers (registered or assignable) in the 0-127 range override the
+value of the corresponding control change for the voices of that key.
+ */
+pub fn per_note_cc(
+    channel: &mut Channel,
+    voices: &mut VoicePool,
+    key: u8,
+    registered: bool,
+    index: u8,
+    value: u32,
+) {
+    if registered && index == PER_NOTE_PITCH {
+        channel.set_note_pitch(key, value);
+        update_note_pitch(channel, voices, key);
+    } else if index < 128 {
+        channel.set_note_cc(key, index, value);
+        voices.modulate_note_voices(channel, key, index);
+    }
+}
+
+/**
+Send a per-note pitch bend message, using the pitch bend range of the channel.
+ */
+pub fn per_note_pitch_bend(channel: &mut Channel, voices: &mut VoicePool, key: u8, value: u32) {
+    channel.set_note_pitch_bend(key, value);
+    update_note_pitch(channel, voices, key);
+}
+
+/**
+Per-Note Management: detach the sounding voices of a key from its per-note
+controllers and/or reset the per-note controllers of that key.
+ */
+pub fn per_note_management(
+    channel: &mut Channel,
+    voices: &mut VoicePool,
+    key: u8,
+    detach: bool,
+    reset: bool,
+) {
+    if detach {
+        voices.detach_note(channel, key);
+    }
+
+    if reset {
+        channel.reset_note_controllers(key);
+        update_note_pitch(channel, voices, key);
+        voices.modulate_note_voices_all(channel, key);
+    }
+}
+
+/// The per-note pitch is added to the Pitch generator offsets of the voices of the key
+fn update_note_pitch(channel: &Channel, voices: &mut VoicePool, key: u8) {
+    voices.set_key_gen(channel, key, GeneratorType::Pitch);
+}
+
+/**
+(N)RPN selection and data entry state of a channel, restored after a MIDI 2.0
+Registered or Assignable Controller message, which neither selects the parameter
+nor sets the data entry value for subsequent MIDI 1.0 data entry messages.
+ */
+pub struct ParameterSelection {
+    cc: [(u8, Option<u32>); 6],
+    nrpn_select: i16,
+    nrpn_active: i16,
+}
+
+impl ParameterSelection {
+    /// NRPN_LSB, NRPN_MSB, RPN_LSB, RPN_MSB, DATA_ENTRY_MSB, DATA_ENTRY_LSB
+    const CONTROLLERS: [usize; 6] = [98, 99, 100, 101, 6, 38];
+
+    pub fn save(channel: &Channel) -> Self {
+        let mut cc = [(0, None); 6];
+        for (cc, num) in cc.iter_mut().zip(Self::CONTROLLERS.iter()) {
+            *cc = (channel.cc(*num), channel.cc32(*num));
+        }
+
+        Self {
+            cc,
+            nrpn_select: channel.nrpn_select(),
+            nrpn_active: channel.nrpn_active(),
+        }
+    }
+
+    pub fn restore(&self, channel: &mut Channel) {
+        for ((cc, cc32), num) in self.cc.iter().zip(Self::CONTROLLERS.iter()) {
+            match cc32 {
+                Some(val) => channel.set_cc32(*num, *val),
+                None => channel.set_cc(*num, *cc),
+            }
+        }
+        channel.set_nrpn_select(self.nrpn_select);
+        channel.set_nrpn_active(self.nrpn_active);
+    }
+}
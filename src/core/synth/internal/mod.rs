@@ -1,5 +1,7 @@
 pub mod gen;
 pub mod midi;
+pub mod midi2;
+pub mod mpe;
 
 pub use gen::*;
 pub use midi::*;
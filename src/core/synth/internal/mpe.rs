@@ -0,0 +1,277 @@
+use crate::core::soundfont::generator::GeneratorType;
+use crate::core::synth::channel_pool::{Channel, ChannelPool, MpeZone, MpeZoneLayout};
+use crate::core::synth::voice_pool::VoicePool;
+use crate::core::tuning::TuningManager;
+
+type MidiControlChange = u32;
+const RPN_MSB: MidiControlChange = 101;
+const RPN_LSB: MidiControlChange = 100;
+const DATA_ENTRY_MSB: MidiControlChange = 6;
+const ALL_SOUND_OFF: MidiControlChange = 120;
+const ALL_CTRL_OFF: MidiControlChange = 121;
+
+const RPN_PITCH_BEND_RANGE: u8 = 0;
+const RPN_MPE_CONFIGURATION: u8 = 6;
+
+/// Pitch bend range of member channels set by the MPE Configuration Message
+const MEMBER_PITCH_BEND_RANGE: u8 = 48;
+/// Pitch bend range of manager channels set by the MPE Configuration Message
+const MANAGER_PITCH_BEND_RANGE: u8 = 2;
+
+/**
+Controllers of a manager channel that are forwarded to the member channels of its zone.
+
+Pitch bend, channel pressure and CC74 (timbre) are per-note dimensions of the member
+channels, (N)RPN selection and data entry stay local to the channel they were sent on.
+ */
+fn is_zone_controller(num: u8) -> bool {
+    !matches!(num, 6 | 38 | 74 | 96..=101)
+}
+
+/**
+Configure an MPE zone (MPE Configuration Message, RPN 6).
+
+The member channels take over the preset and the controllers of the manager
+channel, and the pitch bend ranges are reset to the MPE defaults:
+48 semitones on member channels, 2 semitones on the manager channel.
+A member count of 0 disables the zone.
+ */
+pub fn configure_zone(
+    channels: &mut ChannelPool,
+    voices: &mut VoicePool,
+    zone: MpeZone,
+    member_count: u8,
+) {
+    let old = *channels.mpe();
+    channels.set_mpe_zone(zone, member_count);
+    let new = *channels.mpe();
+
+    // Channels that left their zone lose the zone-wide pitch bend
+    for id in old.zones().flat_map(|z| z.member_channels()) {
+        if new.member_zone(id).is_none() {
+            if let Ok(channel) = channels.get_mut(id) {
+                set_zone_pitch_bend(channel, voices, 0.0);
+            }
+        }
+    }
+
+    if let Some(layout) = new.zone(zone) {
+        if let Ok(manager) = channels.get_mut(layout.manager_channel()) {
+            super::midi::pitch_wheel_sens(manager, voices, MANAGER_PITCH_BEND_RANGE);
+        }
+        member_pitch_wheel_sens(channels, voices, layout, MEMBER_PITCH_BEND_RANGE);
+
+        sync_members(channels, voices, layout);
+    }
+
+    for layout in new.zones() {
+        update_zone_pitch_bend(channels, voices, layout);
+    }
+}
+
+/**
+Set the pitch bend range (in semitones) of every member channel of a zone.
+ */
+pub fn member_pitch_wheel_sens(
+    channels: &mut ChannelPool,
+    voices: &mut VoicePool,
+    layout: MpeZoneLayout,
+    val: u8,
+) {
+    for id in layout.member_channels() {
+        if let Ok(channel) = channels.get_mut(id) {
+            super::midi::pitch_wheel_sens(channel, voices, val);
+        }
+    }
+}
+
+/**
+Copy the preset and the zone-wide controllers of the manager channel to the member channels.
+ */
+fn sync_members(channels: &mut ChannelPool, voices: &mut VoicePool, layout: MpeZoneLayout) {
+    sync_program(channels, layout);
+
+    let mut cc = [0; 128];
+    let bank_msb = match channels.get(layout.manager_channel()) {
+        Ok(manager) => {
+            for (num, value) in cc.iter_mut().enumerate() {
+                *value = manager.cc(num);
+            }
+            manager.bank_msb()
+        }
+        Err(_) => return,
+    };
+
+    for id in layout.member_channels() {
+        if let Ok(channel) = channels.get_mut(id) {
+            for num in (0..ALL_SOUND_OFF as u8).filter(|num| is_zone_controller(*num)) {
+                channel.set_cc(num as usize, cc[num as usize]);
+            }
+            channel.set_bank_msb(bank_msb);
+
+            voices.modulate_voices_all(channel);
+        }
+    }
+}
+
+/**
+Copy the preset of the manager channel to the member channels.
+ */
+fn sync_program(channels: &mut ChannelPool, layout: MpeZoneLayout) {
+    let (sfontnum, banknum, prognum, preset) = match channels.get(layout.manager_channel()) {
+        Ok(manager) => (
+            manager.sfontnum(),
+            manager.banknum(),
+            manager.prognum(),
+            manager.preset().cloned(),
+        ),
+        Err(_) => return,
+    };
+
+    for id in layout.member_channels() {
+        if let Ok(channel) = channels.get_mut(id) {
+            channel.set_sfontnum(sfontnum);
+            channel.set_banknum(banknum);
+            channel.set_prognum(prognum);
+            channel.set_preset(preset.clone());
+        }
+    }
+}
+
+/**
+Apply the pitch bend of the manager channel to all notes of the zone.
+
+The zone-wide bend is added on top of the per-note bend and the Pitch
+generator offset of each member channel.
+ */
+fn update_zone_pitch_bend(
+    channels: &mut ChannelPool,
+    voices: &mut VoicePool,
+    layout: MpeZoneLayout,
+) {
+    let cents = match channels.get(layout.manager_channel()) {
+        Ok(manager) => {
+            (manager.pitch_bend() as f32 - 8192.0) / 8192.0
+                * manager.pitch_wheel_sensitivity() as f32
+                * 100.0
+        }
+        Err(_) => return,
+    };
+
+    for id in layout.member_channels() {
+        if let Ok(channel) = channels.get_mut(id) {
+            set_zone_pitch_bend(channel, voices, cents);
+        }
+    }
+}
+
+fn set_zone_pitch_bend(channel: &mut Channel, voices: &mut VoicePool, cents: f32) {
+    channel.set_zone_pitch_bend(cents);
+    voices.set_gen(channel, GeneratorType::Pitch);
+}
+
+/**
+MPE handling of a control change message, called once the message was applied to its channel.
+
+Zone-wide controllers are forwarded to the member channels with the value stored
+in the manager channel, keeping the resolution of MIDI 2.0 values.
+ */
+pub fn cc(
+    channels: &mut ChannelPool,
+    voices: &mut VoicePool,
+    tunings: &TuningManager,
+    min_note_length_ticks: usize,
+    drums_channel_active: bool,
+    channel_id: usize,
+    num: u8,
+) {
+    let channel = match channels.get(channel_id) {
+        Ok(channel) => channel,
+        Err(_) => return,
+    };
+    let mpe = *channels.mpe();
+
+    // DATA_ENTRY_MSB | DATA_ENTRY_INCR | DATA_ENTRY_DECR
+    if matches!(num, 6 | 96 | 97) {
+        if channel.nrpn_active() != 0 || channel.cc(RPN_MSB as usize) != 0 {
+            return;
+        }
+
+        match channel.cc(RPN_LSB as usize) {
+            RPN_MPE_CONFIGURATION => {
+                if let Some(zone) = MpeZone::from_manager_channel(channel_id) {
+                    let member_count = channel.cc(DATA_ENTRY_MSB as usize);
+                    configure_zone(channels, voices, zone, member_count);
+                }
+            }
+            RPN_PITCH_BEND_RANGE => {
+                if let Some(layout) = mpe.member_zone(channel_id) {
+                    // The per-note pitch bend range is shared by all member channels
+                    let val = channel.pitch_wheel_sensitivity();
+                    member_pitch_wheel_sens(channels, voices, layout, val);
+                } else if let Some(layout) = mpe.manager_zone(channel_id) {
+                    update_zone_pitch_bend(channels, voices, layout);
+                }
+            }
+            _ => {}
+        }
+    } else {
+        if let Some(layout) = mpe.manager_zone(channel_id) {
+            if is_zone_controller(num) {
+                let (value, value32) = (channel.cc(num as usize), channel.cc32(num as usize));
+                for id in layout.member_channels() {
+                    if let Ok(member) = channels.get_mut(id) {
+                        if let Some(value) = value32 {
+                            super::midi2::cc(
+                                member,
+                                voices,
+                                tunings,
+                                min_note_length_ticks,
+                                drums_channel_active,
+                                num,
+                                value,
+                            );
+                        } else {
+                            super::midi::cc(
+                                member,
+                                voices,
+                                tunings,
+                                min_note_length_ticks,
+                                drums_channel_active,
+                                num,
+                                value,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        // Resetting the controllers also clears the zone-wide pitch bend
+        if num == ALL_CTRL_OFF as u8 {
+            for layout in mpe.zones() {
+                if layout.manager_channel() == channel_id || layout.is_member(channel_id) {
+                    update_zone_pitch_bend(channels, voices, layout);
+                }
+            }
+        }
+    }
+}
+
+/**
+MPE handling of a pitch bend message, called once the message was applied to its channel.
+ */
+pub fn pitch_bend(channels: &mut ChannelPool, voices: &mut VoicePool, channel_id: usize) {
+    if let Some(layout) = channels.mpe().manager_zone(channel_id) {
+        update_zone_pitch_bend(channels, voices, layout);
+    }
+}
+
+/**
+MPE handling of a program change message, called once the message was applied to its channel.
+ */
+pub fn program_change(channels: &mut ChannelPool, channel_id: usize) {
+    if let Some(layout) = channels.mpe().manager_zone(channel_id) {
+        sync_program(channels, layout);
+    }
+}
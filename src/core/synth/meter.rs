@@ -0,0 +1,95 @@
+use crate::core::snapshot::snapshot;
+
+/// Time constant of the meters, in seconds
+const METER_TIME: f32 = 0.3;
+
+/**
+Level of the voices of a MIDI channel, before the channel effects and the panning,
+relative to the full scale of the output.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ChannelLevel {
+    /// Peak amplitude, falling back with a 300 ms time constant
+    pub peak: f32,
+    /// Root mean square amplitude, averaged over 300 ms
+    pub rms: f32,
+}
+
+/// Levels of the MIDI channels, from the voices mixed in each block
+pub(crate) struct ChannelMeters {
+    enabled: bool,
+    levels: Vec<ChannelLevel>,
+    mean_square: Vec<f32>,
+    /// Fall back of the levels for one block
+    decay: f32,
+}
+
+snapshot!(ChannelLevel { peak, rms });
+snapshot!(ChannelMeters {
+    levels,
+    mean_square
+});
+
+impl ChannelMeters {
+    pub fn new(midi_channels: usize, sample_rate: f32, block_size: usize) -> Self {
+        let mut meters = Self {
+            enabled: false,
+            levels: vec![ChannelLevel::default(); midi_channels],
+            mean_square: vec![0.0; midi_channels],
+            decay: 0.0,
+        };
+        meters.set_timing(sample_rate, block_size);
+        meters
+    }
+
+    pub fn set_timing(&mut self, sample_rate: f32, block_size: usize) {
+        self.decay = (-(block_size as f32) / (METER_TIME * sample_rate)).exp();
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enable the meters, returning the number of channel buffers the voices mix into
+    pub fn set_enabled(&mut self, enabled: bool) -> usize {
+        self.enabled = enabled;
+        self.reset();
+        if enabled {
+            self.levels.len()
+        } else {
+            0
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.levels.fill(ChannelLevel::default());
+        self.mean_square.fill(0.0);
+    }
+
+    pub fn level(&self, chan: usize) -> ChannelLevel {
+        self.levels[chan]
+    }
+
+    pub fn levels(&self) -> &[ChannelLevel] {
+        &self.levels
+    }
+
+    /// Update the levels with the block of each channel
+    pub fn update(&mut self, bufs: &[Vec<f32>]) {
+        for ((level, mean_square), buf) in self
+            .levels
+            .iter_mut()
+            .zip(self.mean_square.iter_mut())
+            .zip(bufs.iter())
+        {
+            let (peak, sum) = buf.iter().fold((0f32, 0f32), |(peak, sum), v| {
+                (peak.max(v.abs()), sum + v * v)
+            });
+
+            let block = sum / buf.len() as f32;
+            *mean_square = block + (*mean_square - block) * self.decay;
+            level.rms = mean_square.sqrt();
+            level.peak = peak.max(level.peak * self.decay);
+        }
+    }
+}
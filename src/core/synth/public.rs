@@ -1,5 +1,7 @@
 mod font;
 mod midi;
+mod monitor;
 mod params;
+mod state;
 mod tuning;
 mod write;
@@ -1,6 +1,6 @@
 use crate::core::error::OxiError;
 use crate::core::soundfont::SoundFont;
-use crate::core::synth::{internal, Synth};
+use crate::core::synth::{internal, MpeZone, MpeZoneLayout, Synth};
 use crate::core::utils::{RangeCheck, TypedIndex};
 
 impl Synth {
@@ -108,4 +108,34 @@ impl Synth {
             self.settings.drums_channel_active,
         )
     }
+
+    /**
+    Configure an MPE zone, as the MPE Configuration Message (RPN 6) does.
+
+    A member count of 0 disables the zone.
+     */
+    pub fn set_mpe_zone(&mut self, zone: MpeZone, member_channels: u8) {
+        internal::mpe::configure_zone(&mut self.channels, &mut self.voices, zone, member_channels)
+    }
+
+    /**
+    Returns the channel layout of an MPE zone, if the zone is active.
+     */
+    pub fn get_mpe_zone(&self, zone: MpeZone) -> Option<MpeZoneLayout> {
+        self.channels.mpe().zone(zone)
+    }
+
+    /**
+    Set the per-note pitch bend range (in semitones) of the member channels of an MPE zone.
+     */
+    pub fn set_mpe_pitch_bend_range(&mut self, zone: MpeZone, val: u8) -> Result<(), OxiError> {
+        let layout = self
+            .channels
+            .mpe()
+            .zone(zone)
+            .ok_or(OxiError::MpeZoneInactive)?;
+
+        internal::mpe::member_pitch_wheel_sens(&mut self.channels, &mut self.voices, layout, val);
+        Ok(())
+    }
 }
@@ -0,0 +1,70 @@
+use std::collections::vec_deque::Drain;
+
+use crate::core::synth::{ChannelLevel, Synth, SynthEvent, VoiceInfo};
+use crate::core::OxiError;
+
+impl Synth {
+    /**
+    State of the playing voices, as of the last rendered block
+     */
+    pub fn playing_voices(&self) -> impl Iterator<Item = VoiceInfo<'_>> {
+        self.voices.playing()
+    }
+
+    /**
+    Measure the level of each MIDI channel while rendering
+     */
+    pub fn set_channel_meters(&mut self, enabled: bool) {
+        let len = self.meters.set_enabled(enabled);
+        self.fx_left_buf.meters = vec![vec![0.0; self.settings.block_size]; len];
+        self.voices.set_fx_layout(&self.fx_left_buf);
+    }
+
+    pub fn channel_meters(&self) -> bool {
+        self.meters.enabled()
+    }
+
+    /**
+    Level of a MIDI channel, zero while the meters are off
+     */
+    pub fn channel_level(&self, chan: usize) -> Result<ChannelLevel, OxiError> {
+        self.channels.get(chan)?;
+        Ok(self.meters.level(chan))
+    }
+
+    /**
+    Levels of all the MIDI channels
+     */
+    pub fn channel_levels(&self) -> &[ChannelLevel] {
+        self.meters.levels()
+    }
+
+    /**
+    Record up to `capacity` events until they are drained, 0 to stop recording them.
+    The queue is allocated here, recording doesn't allocate.
+     */
+    pub fn set_event_capacity(&mut self, capacity: usize) {
+        self.voices.events_mut().set_capacity(capacity);
+    }
+
+    /**
+    Number of events recorded until they are drained
+     */
+    pub fn event_capacity(&self) -> usize {
+        self.voices.events().capacity()
+    }
+
+    /**
+    Take the recorded events, oldest first
+     */
+    pub fn drain_events(&mut self) -> Drain<'_, SynthEvent> {
+        self.voices.events_mut().drain()
+    }
+
+    /**
+    Number of events dropped since the last drain, the queue being full
+     */
+    pub fn lost_events(&self) -> usize {
+        self.voices.events().lost()
+    }
+}
@@ -1,14 +1,69 @@
 use std::sync::Arc;
 
-use crate::core::settings::Settings;
-use crate::core::synth::{Chorus, InterpolationMethod, Preset, Synth};
+use crate::core::chorus::Chorus;
+use crate::core::resampler::Resampler;
+use crate::core::reverb::{Reverb, ReverbType};
+use crate::core::settings::{check_sample_rate, Settings, SettingsError};
+#[cfg(feature = "parallel")]
+use crate::core::synth::RenderThreads;
+use crate::core::synth::{FilterType, FxSend, InterpolationMethod, Preset, StealPolicy, Synth};
+use crate::core::OxiError;
 
 impl Synth {
-    pub fn set_sample_rate(&mut self, sample_rate: f32) {
-        self.settings.sample_rate = sample_rate;
-        self.voices.set_sample_rate(sample_rate);
+    /**
+    Set the output sample rate.
+
+    When rendering at a fixed internal sample rate, only the output resampler
+    is updated, otherwise the voices and effects are rebuilt for the new rate.
+
+    The rate has the range of `SynthDescriptor::sample_rate`, nothing changes if it is out of range.
+     */
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), SettingsError> {
+        self.settings.sample_rate = check_sample_rate(sample_rate)?;
+        self.update_sample_rate();
+        Ok(())
+    }
+
+    /**
+    Render at a fixed internal sample rate, resampling the output to the
+    output sample rate, or at the output sample rate (`None`).
+
+    The rate has the range of `SynthDescriptor::sample_rate`, nothing changes if it is out of range.
+     */
+    pub fn set_internal_sample_rate(
+        &mut self,
+        sample_rate: Option<f32>,
+    ) -> Result<(), SettingsError> {
+        self.settings.internal_sample_rate = sample_rate.map(check_sample_rate).transpose()?;
+        self.update_sample_rate();
+        Ok(())
+    }
+
+    fn update_sample_rate(&mut self) {
+        let sample_rate = self.settings.render_sample_rate();
 
-        self.chorus = Chorus::new(sample_rate, self.chorus.active());
+        if self.voices.sample_rate() != sample_rate {
+            self.voices.set_sample_rate(sample_rate);
+            for reverb in self.reverbs.iter_mut() {
+                reverb.set_sample_rate(sample_rate);
+            }
+            for chorus in self.choruses.iter_mut() {
+                chorus.set_sample_rate(sample_rate);
+            }
+            self.effects.set_sample_rate(sample_rate);
+            self.meters
+                .set_timing(sample_rate, self.settings.block_size);
+
+            self.min_note_length_ticks = crate::core::synth::min_note_length_ticks(
+                self.settings.min_note_length,
+                sample_rate,
+            );
+        }
+
+        self.resampler = self
+            .settings
+            .internal_sample_rate
+            .map(|rate| Resampler::new(rate, self.settings.sample_rate));
     }
 
     pub fn settings(&self) -> &Settings {
@@ -37,6 +92,70 @@ impl Synth {
         self.settings.gain
     }
 
+    /**
+    Set the time for the voice gains (master gain, volume, pan, sends) and
+    filter to reach a new value, in ms (0-100).
+
+    The changes are ramped over this time to avoid zipper noise,
+    0 applies them at the next block.
+     */
+    pub fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.settings.ramp_time = ramp_time.clamp(0.0, 100.0);
+        self.voices.set_ramp_time(self.settings.ramp_time);
+    }
+
+    /**
+    Get the ramp time of the voice parameters, in ms
+     */
+    pub fn ramp_time(&self) -> f32 {
+        self.settings.ramp_time
+    }
+
+    /**
+    Set the time for the reverb, chorus and built-in effect parameters to
+    reach a new value, in ms (0-100).
+     */
+    pub fn set_effect_ramp_time(&mut self, ramp_time: f32) {
+        let ramp_time = ramp_time.clamp(0.0, 100.0);
+        self.settings.effect_ramp_time = ramp_time;
+        for reverb in self.reverbs.iter_mut() {
+            reverb.set_ramp_time(ramp_time);
+        }
+        for chorus in self.choruses.iter_mut() {
+            chorus.set_ramp_time(ramp_time);
+        }
+        self.effects.set_ramp_time(ramp_time);
+    }
+
+    /**
+    Get the ramp time of the effect parameters, in ms
+     */
+    pub fn effect_ramp_time(&self) -> f32 {
+        self.settings.effect_ramp_time
+    }
+
+    /**
+    Select the threads rendering the voices.
+    Stopping a pool waits for its threads to finish their work.
+     */
+    #[cfg(feature = "parallel")]
+    pub fn set_render_threads(&mut self, threads: RenderThreads) -> Result<(), OxiError> {
+        self.voices.set_render_threads(
+            threads,
+            &self.left_buf,
+            &self.fx_left_buf,
+            self.effects.strips(),
+        )
+    }
+
+    /**
+    Get the threads rendering the voices
+     */
+    #[cfg(feature = "parallel")]
+    pub fn render_threads(&self) -> RenderThreads {
+        self.voices.render_threads()
+    }
+
     /**
     Set the polyphony limit
      */
@@ -58,18 +177,68 @@ impl Synth {
         self.settings.polyphony as u32
     }
 
+    /**
+    Select the voice to kill when a new one is needed past the polyphony limit,
+    or past the voice limit of its channel
+     */
+    pub fn set_steal_policy(&mut self, policy: StealPolicy) {
+        self.voices.set_steal_policy(policy);
+    }
+
+    pub fn steal_policy(&self) -> StealPolicy {
+        self.voices.steal_policy()
+    }
+
+    /**
+    Set the voice priority of a channel (64 by default), the voices of the channels
+    with the lowest priority being stolen first by [`StealPolicy::LowestPriorityChannel`]
+     */
+    pub fn set_channel_voice_priority(
+        &mut self,
+        chan: usize,
+        priority: u8,
+    ) -> Result<(), OxiError> {
+        self.channels.get(chan)?;
+        self.voices.set_channel_priority(chan, priority);
+        Ok(())
+    }
+
+    pub fn channel_voice_priority(&self, chan: usize) -> Result<u8, OxiError> {
+        self.channels.get(chan)?;
+        Ok(self.voices.channel_priority(chan))
+    }
+
+    /**
+    Limit the number of voices playing at once on a channel, `None` for no limit.
+    Past the limit, a new voice replaces one of the channel chosen by the steal policy.
+     */
+    pub fn set_channel_voice_limit(
+        &mut self,
+        chan: usize,
+        limit: Option<usize>,
+    ) -> Result<(), OxiError> {
+        self.channels.get(chan)?;
+        self.voices.set_channel_voice_limit(chan, limit);
+        Ok(())
+    }
+
+    pub fn channel_voice_limit(&self, chan: usize) -> Result<Option<usize>, OxiError> {
+        self.channels.get(chan)?;
+        Ok(self.voices.channel_voice_limit(chan))
+    }
+
     /**
     Get the internal buffer size. The internal buffer size if not the
     same thing as the buffer size specified in the
     settings. Internally, the synth *always* uses a specific buffer
     size independent of the buffer size used by the audio driver. The
-    internal buffer size is normally 64 samples. The reason why it
-    uses an internal buffer size is to allow audio drivers to call the
+    internal buffer size is `SynthDescriptor::block_size`, 64 samples by default.
+    The reason why it uses an internal buffer size is to allow audio drivers to call the
     synthesizer with a variable buffer length. The internal buffer
     size is useful for client who want to optimize their buffer sizes.
      */
     pub fn internal_bufsize(&self) -> usize {
-        64
+        self.settings.block_size
     }
 
     /**
@@ -97,4 +266,85 @@ impl Synth {
             None
         }
     }
+
+    /**
+    Add a reverb instance with default parameters, returning its id.
+
+    Channels are routed to it with `set_channel_reverb()`.
+     */
+    pub fn add_reverb(&mut self, ty: ReverbType) -> usize {
+        let sample_rate = self.settings.render_sample_rate();
+        let mut reverb = Reverb::new(sample_rate, self.settings.block_size, true, ty);
+        reverb.set_ramp_time(self.settings.effect_ramp_time);
+        self.reverbs.push(reverb);
+        self.fx_left_buf
+            .reverb
+            .push(FxSend::new(self.settings.block_size));
+        self.fx_right_buf
+            .reverb
+            .push(FxSend::new(self.settings.block_size));
+        self.voices.set_fx_layout(&self.fx_left_buf);
+        self.reverbs.len() - 1
+    }
+
+    /**
+    Add a chorus instance with default parameters, returning its id.
+
+    Channels are routed to it with `set_channel_chorus()`.
+     */
+    pub fn add_chorus(&mut self) -> usize {
+        let sample_rate = self.settings.render_sample_rate();
+        let mut chorus = Chorus::new(sample_rate, true);
+        chorus.set_ramp_time(self.settings.effect_ramp_time);
+        self.choruses.push(chorus);
+        self.fx_left_buf
+            .chorus
+            .push(FxSend::new(self.settings.block_size));
+        self.fx_right_buf
+            .chorus
+            .push(FxSend::new(self.settings.block_size));
+        self.voices.set_fx_layout(&self.fx_left_buf);
+        self.choruses.len() - 1
+    }
+
+    /**
+    Send the reverb of a channel to the reverb instance `id`, 0 being the main reverb.
+     */
+    pub fn set_channel_reverb(&mut self, chan: usize, id: usize) -> Result<(), OxiError> {
+        if id >= self.reverbs.len() {
+            return Err(OxiError::FxInstanceOutOfRange);
+        }
+        self.channels.get_mut(chan)?.set_reverb_id(id);
+        Ok(())
+    }
+
+    /**
+    Select the filter of the voices started on a channel, overriding the one of
+    the instrument zones, or follow the zones again (`None`).
+    The playing voices keep their filter.
+     */
+    pub fn set_channel_filter(
+        &mut self,
+        chan: usize,
+        filter: Option<FilterType>,
+    ) -> Result<(), OxiError> {
+        self.channels.get_mut(chan)?.set_filter_type(filter);
+        Ok(())
+    }
+
+    /// Filter selected for a channel, `None` when it follows the instrument zones
+    pub fn channel_filter(&self, chan: usize) -> Result<Option<FilterType>, OxiError> {
+        Ok(self.channels.get(chan)?.filter_type())
+    }
+
+    /**
+    Send the chorus of a channel to the chorus instance `id`, 0 being the main chorus.
+     */
+    pub fn set_channel_chorus(&mut self, chan: usize, id: usize) -> Result<(), OxiError> {
+        if id >= self.choruses.len() {
+            return Err(OxiError::FxInstanceOutOfRange);
+        }
+        self.channels.get_mut(chan)?.set_chorus_id(id);
+        Ok(())
+    }
 }
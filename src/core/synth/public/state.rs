@@ -0,0 +1,312 @@
+use crate::core::reverb::ReverbType;
+use crate::core::snapshot::{Snapshot, StateReader, StateWriter};
+use crate::core::synth::state::{DspState, FontState, FxState, ReverbState, SynthState};
+use crate::core::synth::Synth;
+use crate::core::tuning::TuningManager;
+use crate::core::OxiError;
+
+impl Synth {
+    /**
+    Save the settings and MIDI state of the synth
+     */
+    pub fn save_state(&self) -> SynthState {
+        let fingerprint = |id| self.font_bank.get_font(id).map(|font| font.fingerprint());
+
+        let fonts = self
+            .font_bank
+            .stack()
+            .iter()
+            .filter_map(|id| {
+                Some(FontState {
+                    fingerprint: fingerprint(*id)?,
+                    bank_offset: self
+                        .font_bank
+                        .bank_offsets
+                        .get(*id)
+                        .map_or(0, |offset| offset.offset),
+                })
+            })
+            .collect();
+
+        let channels = self
+            .channels
+            .iter()
+            .map(|channel| {
+                let mut state = channel.state(channel.sfontnum().and_then(fingerprint));
+                state.voice_priority = self.voices.channel_priority(channel.id());
+                state.voice_limit = self.voices.channel_voice_limit(channel.id());
+                state
+            })
+            .collect();
+
+        SynthState {
+            fonts,
+            channels,
+            mpe_zones: self.channels.mpe().zones().collect(),
+            tunings: self.tunings.tuning_iter().map(Into::into).collect(),
+            reverbs: self
+                .reverbs
+                .iter()
+                .map(|reverb| ReverbState {
+                    active: reverb.active(),
+                    ty: reverb.ty(),
+                    params: reverb.reverb(),
+                })
+                .collect(),
+            choruses: self
+                .choruses
+                .iter()
+                .map(|chorus| FxState {
+                    active: chorus.active(),
+                    params: chorus.get_chorus(),
+                })
+                .collect(),
+            gain: self.settings.gain,
+            polyphony: self.settings.polyphony,
+            steal_policy: self.voices.steal_policy(),
+        }
+    }
+
+    /**
+    Restore a state saved by `save_state()`, stopping the sounding voices and
+    clearing the effect tails.
+
+    The fonts of the state must be loaded, in any order, and the reverb instances
+    with an engine other than the built-in ones set up. The channels past the ones
+    of the synth are ignored, reverb and chorus instances are added or removed to
+    match the ones of the state.
+     */
+    pub fn restore_state(&mut self, state: &SynthState) -> Result<(), OxiError> {
+        let mut fonts = Vec::with_capacity(state.fonts.len());
+        for font in state.fonts.iter() {
+            let id = self
+                .font_bank
+                .find_fingerprint(font.fingerprint)
+                .ok_or(OxiError::FontNotLoaded(font.fingerprint))?;
+            fonts.push((font.fingerprint, id));
+        }
+        let font_id = |fingerprint: u64| {
+            fonts
+                .iter()
+                .find(|(f, _)| *f == fingerprint)
+                .map(|(_, id)| *id)
+                .ok_or(OxiError::FontNotLoaded(fingerprint))
+        };
+        // Check the fonts of the channels, the tunings and the reverb engines before changing anything
+        for channel in state.channels.iter() {
+            if let Some(fingerprint) = channel.font {
+                font_id(fingerprint)?;
+            }
+        }
+        let mut tunings = TuningManager::new();
+        for tuning in state.tunings.iter() {
+            tunings
+                .add_tuning(tuning.into())
+                .map_err(|_| OxiError::TuningOutOfRange)?;
+        }
+        for (id, fx) in state.reverbs.iter().enumerate() {
+            let custom = matches!(self.reverbs.get(id), Some(reverb) if reverb.ty().is_none());
+            if fx.ty.is_none() && !custom {
+                return Err(OxiError::ReverbEngineNotSet(id));
+            }
+        }
+        self.set_polyphony(state.polyphony)
+            .map_err(|_| OxiError::PolyphonyOutOfRange)?;
+
+        self.voices.system_reset();
+
+        for (font, (_, id)) in state.fonts.iter().zip(fonts.iter()) {
+            self.font_bank.bank_offsets.set(*id, font.bank_offset);
+        }
+
+        self.set_gain(state.gain);
+        self.voices.set_steal_policy(state.steal_policy);
+        self.tunings = tunings;
+
+        // The main instances are kept
+        let reverbs = state.reverbs.len().max(1);
+        self.reverbs.truncate(reverbs);
+        self.fx_left_buf.reverb.truncate(reverbs);
+        self.fx_right_buf.reverb.truncate(reverbs);
+        while self.reverbs.len() < state.reverbs.len() {
+            self.add_reverb(ReverbType::default());
+        }
+        for (reverb, fx) in self.reverbs.iter_mut().zip(state.reverbs.iter()) {
+            if let Some(ty) = fx.ty {
+                if reverb.ty() != Some(ty) {
+                    reverb.set_type(ty);
+                }
+            }
+            reverb.set_active(fx.active);
+            reverb.set_reverb(&fx.params);
+            reverb.reset();
+        }
+
+        let choruses = state.choruses.len().max(1);
+        self.choruses.truncate(choruses);
+        self.fx_left_buf.chorus.truncate(choruses);
+        self.fx_right_buf.chorus.truncate(choruses);
+        while self.choruses.len() < state.choruses.len() {
+            self.add_chorus();
+        }
+        for (chorus, fx) in self.choruses.iter_mut().zip(state.choruses.iter()) {
+            chorus.set_active(fx.active);
+            chorus.set_chorus(&fx.params);
+            chorus.reset();
+        }
+        self.voices.set_fx_layout(&self.fx_left_buf);
+
+        for (channel, state) in self.channels.iter_mut().zip(state.channels.iter()) {
+            let (sfontnum, preset) = match state.font {
+                Some(fingerprint) => {
+                    let id = font_id(fingerprint)?;
+                    (
+                        Some(id),
+                        self.font_bank.preset(id, state.bank, state.program),
+                    )
+                }
+                None => (
+                    None,
+                    self.font_bank
+                        .find_preset(state.bank, state.program)
+                        .map(|(_, preset)| preset),
+                ),
+            };
+            channel.restore_state(state, sfontnum, preset);
+
+            let id = channel.id();
+            self.voices.set_channel_priority(id, state.voice_priority);
+            self.voices.set_channel_voice_limit(id, state.voice_limit);
+        }
+        for channel in self.channels.iter_mut() {
+            if channel.reverb_id() >= self.reverbs.len() {
+                channel.set_reverb_id(0);
+            }
+            if channel.chorus_id() >= self.choruses.len() {
+                channel.set_chorus_id(0);
+            }
+        }
+
+        self.channels.reset_mpe();
+        for zone in state.mpe_zones.iter() {
+            self.channels.set_mpe_zone(zone.zone, zone.member_count);
+        }
+
+        Ok(())
+    }
+
+    /**
+    Save the settings, the MIDI state and the running state of the DSP units
+     */
+    pub fn save_dsp_state(&self) -> DspState {
+        let mut state = StateWriter::new();
+
+        self.ticks.save(&mut state);
+        self.cur.save(&mut state);
+        self.left_buf.save(&mut state);
+        self.right_buf.save(&mut state);
+        state.write_bool(self.resampler.is_some());
+        if let Some(resampler) = self.resampler.as_ref() {
+            state.write_f32(self.settings.sample_rate);
+            resampler.save(&mut state);
+        }
+        self.meters.save(&mut state);
+
+        let font_bank = &self.font_bank;
+        self.voices.save_state(&mut state, |voice| {
+            let font = font_bank
+                .iter_stack()
+                .find(|font| font.has_preset(&voice.preset))?;
+            Some((font.fingerprint(), font.sample_index(&voice.sample)?))
+        });
+
+        state.write_u32(self.reverbs.len() as u32);
+        for reverb in self.reverbs.iter() {
+            reverb.save_state(&mut state);
+        }
+        state.write_u32(self.choruses.len() as u32);
+        for chorus in self.choruses.iter() {
+            chorus.save(&mut state);
+        }
+        self.effects.save_state(&mut state);
+
+        DspState {
+            synth: self.save_state(),
+            sample_rate: self.settings.render_sample_rate(),
+            block_size: self.settings.block_size,
+            words: state.into_words(),
+        }
+    }
+
+    /**
+    Restore a state saved by `save_dsp_state()`, resuming the rendering where it was saved.
+
+    The fonts of the state must be loaded, the sample rate and block size must
+    be the saved ones, and the reverb engines and user effects set up like when saved.
+    When they are not, the synth state is restored, the voices are stopped and the
+    effects cleared.
+     */
+    pub fn restore_dsp_state(&mut self, state: &DspState) -> Result<(), OxiError> {
+        if state.sample_rate != self.settings.render_sample_rate()
+            || state.block_size != self.settings.block_size
+        {
+            return Err(OxiError::DspStateMismatch);
+        }
+        self.restore_state(&state.synth)?;
+
+        let mut reader = StateReader::new(&state.words);
+        let r = &mut reader;
+
+        self.ticks.restore(r);
+        self.cur.restore(r);
+        self.left_buf.restore(r);
+        self.right_buf.restore(r);
+        if r.read_bool() != self.resampler.is_some() {
+            r.invalidate();
+        }
+        if let Some(resampler) = self.resampler.as_mut() {
+            if r.read_f32() != self.settings.sample_rate {
+                r.invalidate();
+            }
+            resampler.restore(r);
+        }
+        self.meters.restore(r);
+
+        let font_bank = &self.font_bank;
+        match self.channels.first() {
+            Some(channel) => {
+                self.voices
+                    .restore_state(r, channel, |fingerprint, sample, bank, program| {
+                        let font = font_bank.get_font(font_bank.find_fingerprint(fingerprint)?)?;
+                        Some((font.sample(sample)?, font.preset(bank, program)?))
+                    })
+            }
+            None => r.invalidate(),
+        }
+
+        if r.read_u32() != self.reverbs.len() as u32 {
+            r.invalidate();
+        }
+        for reverb in self.reverbs.iter_mut() {
+            reverb.restore_state(r);
+        }
+        if r.read_u32() != self.choruses.len() as u32 {
+            r.invalidate();
+        }
+        for chorus in self.choruses.iter_mut() {
+            chorus.restore(r);
+        }
+        self.effects.restore_state(r);
+
+        if reader.is_valid() && self.cur <= self.settings.block_size {
+            Ok(())
+        } else {
+            self.voices.system_reset();
+            self.reverbs.iter_mut().for_each(|reverb| reverb.reset());
+            self.choruses.iter_mut().for_each(|chorus| chorus.reset());
+            self.effects.reset();
+            self.cur = self.settings.block_size;
+            Err(OxiError::DspStateMismatch)
+        }
+    }
+}
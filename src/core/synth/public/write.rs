@@ -1,4 +1,28 @@
-use crate::core::synth::Synth;
+use std::ops::Range;
+
+use crate::core::synth::{FxSend, Synth};
+
+/**
+Add the output of the active effect instances after the first one to the output of the first.
+ */
+fn mix_instances(left: &mut [FxSend], right: &mut [FxSend]) {
+    if let (Some((left_out, left)), Some((right_out, right))) =
+        (left.split_first_mut(), right.split_first_mut())
+    {
+        for (left, right) in left
+            .iter()
+            .zip(right.iter())
+            .filter(|(send, _)| send.active)
+        {
+            for (out, v) in left_out.buf.iter_mut().zip(left.buf.iter()) {
+                *out += v;
+            }
+            for (out, v) in right_out.buf.iter_mut().zip(right.buf.iter()) {
+                *out += v;
+            }
+        }
+    }
+}
 
 impl Synth {
     fn one_block(&mut self, do_not_mix_fx_to_out: i32) {
@@ -10,11 +34,24 @@ impl Synth {
             }
 
             {
-                self.fx_left_buf.reverb.iter_mut().for_each(|v| *v = 0.0);
-                self.fx_left_buf.chorus.iter_mut().for_each(|v| *v = 0.0);
-
-                self.fx_right_buf.reverb.iter_mut().for_each(|v| *v = 0.0);
-                self.fx_right_buf.chorus.iter_mut().for_each(|v| *v = 0.0);
+                for (send, reverb) in self.fx_left_buf.reverb.iter_mut().zip(self.reverbs.iter()) {
+                    send.active = reverb.active();
+                    send.buf.iter_mut().for_each(|v| *v = 0.0);
+                }
+                for (send, chorus) in self.fx_left_buf.chorus.iter_mut().zip(self.choruses.iter()) {
+                    send.active = chorus.active();
+                    send.buf.iter_mut().for_each(|v| *v = 0.0);
+                }
+                self.fx_left_buf
+                    .meters
+                    .iter_mut()
+                    .for_each(|buf| buf.fill(0.0));
+
+                self.fx_right_buf
+                    .reverb
+                    .iter_mut()
+                    .chain(self.fx_right_buf.chorus.iter_mut())
+                    .for_each(|send| send.buf.iter_mut().for_each(|v| *v = 0.0));
             }
         }
 
@@ -31,89 +68,237 @@ impl Synth {
             &mut self.left_buf,
             &mut self.right_buf,
             &mut self.fx_left_buf,
-            self.reverb.active(),
-            self.chorus.active(),
+            self.effects.prepare(&self.channels),
+        );
+
+        self.voices.report_finished();
+
+        if self.meters.enabled() {
+            self.meters.update(&self.fx_left_buf.meters);
+        }
+
+        /* channel inserts and send buses */
+        self.effects.mix_channels(
+            &self.channels,
+            self.settings.audio_groups,
+            &mut self.left_buf,
+            &mut self.right_buf,
         );
 
+        let reverbs = self
+            .reverbs
+            .iter_mut()
+            .zip(self.fx_left_buf.reverb.iter_mut())
+            .zip(self.fx_right_buf.reverb.iter_mut())
+            .filter(|((reverb, _), _)| reverb.active());
+        let choruses = self
+            .choruses
+            .iter_mut()
+            .zip(self.fx_left_buf.chorus.iter_mut())
+            .zip(self.fx_right_buf.chorus.iter_mut())
+            .filter(|((chorus, _), _)| chorus.active());
+
         /* if multi channel output, don't mix the output of the chorus and
         reverb in the final output. The effects outputs are send
         separately. */
         if do_not_mix_fx_to_out != 0 {
             /* send to reverb */
-            if self.reverb.active() {
-                self.reverb
-                    .process_replace(&mut self.fx_left_buf.reverb, &mut self.fx_right_buf.reverb);
+            for ((reverb, left), right) in reverbs {
+                reverb.process_replace(&mut left.buf, &mut right.buf);
             }
             /* send to chorus */
-            if self.chorus.active() {
-                self.chorus
-                    .process_replace(&mut self.fx_left_buf.chorus, &mut self.fx_right_buf.chorus);
+            for ((chorus, left), right) in choruses {
+                chorus.process_replace(&mut left.buf, &mut right.buf);
             }
+            /* the reverb and chorus outputs carry all the instances */
+            mix_instances(&mut self.fx_left_buf.reverb, &mut self.fx_right_buf.reverb);
+            mix_instances(&mut self.fx_left_buf.chorus, &mut self.fx_right_buf.chorus);
         } else {
             /* send to reverb */
-            if self.reverb.active() {
-                self.reverb.process_mix(
-                    &mut self.fx_left_buf.reverb,
-                    &mut self.left_buf[0],
-                    &mut self.right_buf[0],
-                );
+            for ((reverb, send), _) in reverbs {
+                reverb.process_mix(&mut send.buf, &mut self.left_buf[0], &mut self.right_buf[0]);
             }
             /* send to chorus */
-            if self.chorus.active() {
-                self.chorus.process_mix(
-                    &mut self.fx_left_buf.chorus,
-                    &mut self.left_buf[0],
-                    &mut self.right_buf[0],
-                );
+            for ((chorus, send), _) in choruses {
+                chorus.process_mix(&mut send.buf, &mut self.left_buf[0], &mut self.right_buf[0]);
             }
         }
-        self.ticks = self.ticks.wrapping_add(64);
+
+        /* master bus effects */
+        self.effects
+            .process_master(&mut self.left_buf[0], &mut self.right_buf[0]);
+
+        self.ticks = self.ticks.wrapping_add(self.settings.block_size);
     }
 
-    pub fn read_next(&mut self) -> (f32, f32) {
-        let mut l = self.cur;
-        let mut i: usize = 0;
-        let len = 1;
+    /**
+    Next frame of the rendered blocks, at the internal sample rate.
+     */
+    fn render_frame(&mut self) -> (f32, f32) {
+        /* fill up the buffers as needed */
+        if self.cur == self.settings.block_size {
+            self.one_block(0);
+            self.cur = 0;
+        }
 
-        let mut out = (0.0, 0.0);
+        let out = (self.left_buf[0][self.cur], self.right_buf[0][self.cur]);
+        self.cur += 1;
 
-        while i < len {
-            /* fill up the buffers as needed */
-            if l == 64 {
+        out
+    }
+
+    /**
+    Next `frames` frames at the output sample rate, handed to `out` along with their index.
+     */
+    fn read_frames<F: FnMut(usize, f32, f32)>(&mut self, frames: usize, mut out: F) {
+        match self.resampler.take() {
+            Some(mut resampler) => {
+                for id in 0..frames {
+                    let (l, r) = resampler.next(|| self.render_frame());
+                    out(id, l, r);
+                }
+                self.resampler = Some(resampler);
+            }
+            None => {
+                for id in 0..frames {
+                    let (l, r) = self.render_frame();
+                    out(id, l, r);
+                }
+            }
+        }
+    }
+
+    /**
+    Next frame at the output sample rate.
+     */
+    pub fn read_next(&mut self) -> (f32, f32) {
+        let mut frame = (0.0, 0.0);
+        self.read_frames(1, |_, l, r| frame = (l, r));
+        frame
+    }
+
+    /**
+    Render the blocks needed for the next `frames` frames at the internal sample rate,
+    handing each span of the rendered block to `copy` along with its offset in the output.
+     */
+    fn write_spans<F>(&mut self, frames: usize, mut copy: F)
+    where
+        F: FnMut(usize, &[Vec<f32>], &[Vec<f32>], Range<usize>),
+    {
+        let mut pos = 0;
+        while pos < frames {
+            if self.cur == self.settings.block_size {
                 self.one_block(0);
-                l = 0;
+                self.cur = 0;
             }
 
-            out = (self.left_buf[0][l], self.right_buf[0][l]);
+            let len = (self.settings.block_size - self.cur).min(frames - pos);
+            copy(
+                pos,
+                &self.left_buf,
+                &self.right_buf,
+                self.cur..self.cur + len,
+            );
 
-            i += 1;
-            l += 1;
+            self.cur += len;
+            pos += len;
         }
-        self.cur = l;
+    }
 
-        out
+    /**
+    Number of interleaved channels written by [`Synth::write_channels`]:
+    a left and a right channel for each output.
+     */
+    pub fn output_channels(&self) -> usize {
+        self.nbuf as usize * 2
     }
 
-    pub fn write<F: FnMut(usize, f32, f32)>(&mut self, len: usize, incr: usize, mut cb: F) {
-        let mut l = self.cur;
-        let mut i: usize = 0;
+    /**
+    Write the main output into separate left and right buffers.
 
-        let mut out_id = 0;
-        while i < len {
-            /* fill up the buffers as needed */
-            if l == 64 {
-                self.one_block(0);
-                l = 0;
-            }
+    The number of frames is the length of the shorter buffer.
+     */
+    pub fn write_planar(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let frames = left.len().min(right.len());
 
-            cb(out_id, self.left_buf[0][l], self.right_buf[0][l]);
+        if self.resampler.is_some() {
+            self.read_frames(frames, |id, l, r| {
+                left[id] = l;
+                right[id] = r;
+            });
+            return;
+        }
 
-            out_id += incr;
+        self.write_spans(frames, |pos, left_buf, right_buf, span| {
+            let out = pos..pos + span.len();
+            left[out.clone()].copy_from_slice(&left_buf[0][span.clone()]);
+            right[out].copy_from_slice(&right_buf[0][span]);
+        });
+    }
 
-            i += 1;
-            l += 1;
+    /**
+    Write the main output as interleaved stereo frames.
+     */
+    pub fn write_interleaved(&mut self, out: &mut [f32]) {
+        let frames = out.len() / 2;
+
+        if self.resampler.is_some() {
+            self.read_frames(frames, |id, l, r| {
+                out[id * 2] = l;
+                out[id * 2 + 1] = r;
+            });
+            return;
         }
-        self.cur = l;
+
+        self.write_spans(frames, |pos, left_buf, right_buf, span| {
+            let out = out[pos * 2..(pos + span.len()) * 2].chunks_exact_mut(2);
+            let input = left_buf[0][span.clone()]
+                .iter()
+                .zip(right_buf[0][span].iter());
+            for (frame, (l, r)) in out.zip(input) {
+                frame[0] = *l;
+                frame[1] = *r;
+            }
+        });
+    }
+
+    /**
+    Write all the outputs as interleaved frames of [`Synth::output_channels`] channels,
+    the left and right channels of the first output coming first.
+
+    The main output is the only one going through the output resampler: with a fixed
+    internal sample rate, the other outputs are silent.
+     */
+    pub fn write_channels(&mut self, out: &mut [f32]) {
+        let channels = self.output_channels();
+        let frames = out.len() / channels;
+
+        if self.resampler.is_some() {
+            self.read_frames(frames, |id, l, r| {
+                let frame = &mut out[id * channels..(id + 1) * channels];
+                frame[0] = l;
+                frame[1] = r;
+                frame[2..].fill(0.0);
+            });
+            return;
+        }
+
+        self.write_spans(frames, |pos, left_buf, right_buf, span| {
+            let out = out[pos * channels..(pos + span.len()) * channels].chunks_exact_mut(channels);
+            for (frame, id) in out.zip(span) {
+                for (pair, (left, right)) in frame
+                    .chunks_exact_mut(2)
+                    .zip(left_buf.iter().zip(right_buf.iter()))
+                {
+                    pair[0] = left[id];
+                    pair[1] = right[id];
+                }
+            }
+        });
+    }
+
+    pub fn write<F: FnMut(usize, f32, f32)>(&mut self, len: usize, incr: usize, mut cb: F) {
+        self.read_frames(len, |id, l, r| cb(id * incr, l, r));
     }
 
     pub fn write_f32(
@@ -126,27 +311,10 @@ impl Synth {
         roff: usize,
         rincr: usize,
     ) {
-        let mut l = self.cur;
-        let mut i: usize = 0;
-        let mut j = loff;
-        let mut k = roff;
-
-        while i < len {
-            /* fill up the buffers as needed */
-            if l == 64 {
-                self.one_block(0);
-                l = 0;
-            }
-
-            left_out[j] = self.left_buf[0][l];
-            right_out[k] = self.right_buf[0][l];
-
-            i += 1;
-            l += 1;
-            j += lincr;
-            k += rincr
-        }
-        self.cur = l;
+        self.read_frames(len, |id, l, r| {
+            left_out[loff + id * lincr] = l;
+            right_out[roff + id * rincr] = r;
+        });
     }
 
     pub fn write_f64(
@@ -159,27 +327,10 @@ impl Synth {
         roff: usize,
         rincr: usize,
     ) {
-        let mut l = self.cur;
-        let mut i: usize = 0;
-        let mut j = loff;
-        let mut k = roff;
-
-        while i < len {
-            /* fill up the buffers as needed */
-            if l == 64 {
-                self.one_block(0 as i32);
-                l = 0;
-            }
-
-            left_out[j] = self.left_buf[0][l] as f64;
-            right_out[k] = self.right_buf[0][l] as f64;
-
-            i += 1;
-            l += 1;
-            j += lincr;
-            k += rincr
-        }
-        self.cur = l;
+        self.read_frames(len, |id, l, r| {
+            left_out[loff + id * lincr] = l as f64;
+            right_out[roff + id * rincr] = r as f64;
+        });
     }
 
     #[cfg(feature = "i16-out")]
@@ -195,29 +346,17 @@ impl Synth {
     ) {
         let mut di: i32 = self.dither_index;
 
-        let mut cur = self.cur;
-        let mut i: usize = 0;
-        let mut j = loff;
-        let mut k = roff;
-        while i < len {
-            /* fill up the buffers as needed */
-            if cur == 64 {
-                self.one_block(0 as i32);
-                cur = 0;
-            }
+        self.read_frames(len, |i, left, right| {
+            let (j, k) = (loff + i * lincr, roff + i * rincr);
             /*
              * Converts stereo floating point sample data to signed 16 bit data with
              * dithering.
              */
 
-            let mut left_sample = f32::round(
-                self.left_buf[0][cur as usize] * 32766.0f32
-                    + RAND_TABLE[0 as i32 as usize][di as usize],
-            );
-            let mut right_sample = f32::round(
-                self.right_buf[0][cur as usize] * 32766.0f32
-                    + RAND_TABLE[1 as i32 as usize][di as usize],
-            );
+            let mut left_sample =
+                f32::round(left * 32766.0f32 + RAND_TABLE[0 as i32 as usize][di as usize]);
+            let mut right_sample =
+                f32::round(right * 32766.0f32 + RAND_TABLE[1 as i32 as usize][di as usize]);
 
             di += 1;
             if di >= 48000 as i32 {
@@ -240,13 +379,7 @@ impl Synth {
 
             left_out[j as usize] = left_sample as i16;
             right_out[k as usize] = right_sample as i16;
-
-            i += 1;
-            cur += 1;
-            j += lincr;
-            k += rincr
-        }
-        self.cur = cur;
+        });
         /* keep dither buffer continous */
         self.dither_index = di;
     }
@@ -270,3 +403,51 @@ lazy_static! {
         rand
     };
 }
+
+#[cfg(test)]
+mod test {
+    use crate::core::reverb::ReverbType;
+    use crate::core::synth::test::sin_font;
+    use crate::core::synth::Synth;
+    use crate::core::MidiEvent;
+
+    #[test]
+    fn fx_outputs() {
+        let mut synth = Synth::default();
+        synth.add_font(sin_font(), true);
+
+        // Channel 0 sends to a second reverb only
+        let id = synth.add_reverb(ReverbType::Freeverb);
+        synth.set_channel_reverb(0, id).unwrap();
+        synth.reverbs[0].set_active(false);
+        synth.choruses[0].set_active(false);
+        for (ctrl, value) in [(91, 127), (93, 0)] {
+            synth
+                .send_event(MidiEvent::ControlChange {
+                    channel: 0,
+                    ctrl,
+                    value,
+                })
+                .unwrap();
+        }
+        synth
+            .send_event(MidiEvent::NoteOn {
+                channel: 0,
+                key: 69,
+                vel: 100,
+            })
+            .unwrap();
+
+        let mut wet = 0f32;
+        for _ in 0..100 {
+            synth.one_block(1);
+            wet = synth.fx_left_buf.reverb[0]
+                .buf
+                .iter()
+                .chain(synth.fx_right_buf.reverb[0].buf.iter())
+                .fold(wet, |wet, v| wet.max(v.abs()));
+        }
+        assert!(wet > 0.0);
+        assert!(synth.fx_left_buf.chorus[0].buf.iter().all(|v| *v == 0.0));
+    }
+}
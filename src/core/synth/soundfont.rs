@@ -16,8 +16,14 @@ pub(crate) use {
 
 pub use preset::Preset;
 
+use super::FilterType;
+
 pub struct SoundFont {
     presets: Vec<Arc<Preset>>,
+    /// Samples in file order, shared with the zones of the presets
+    samples: Vec<Arc<Sample>>,
+    /// Hash of the file headers, identifying the font across sessions
+    fingerprint: u64,
 }
 
 impl SoundFont {
@@ -43,6 +49,7 @@ impl SoundFont {
         }
 
         let sf2 = sf2.sort_presets();
+        let fingerprint = fingerprint(&sf2);
 
         let smpl = sf2.sample_data.smpl.as_ref().unwrap();
 
@@ -64,7 +71,56 @@ impl SoundFont {
             presets.push(Arc::new(preset));
         }
 
-        Ok(Self { presets })
+        Ok(Self {
+            presets,
+            samples,
+            fingerprint,
+        })
+    }
+
+    /**
+    Identifier of the font, the same each time the file is loaded: a hash of its
+    name, presets and sample headers. Used to refer to the font in a
+    [`SynthState`](crate::SynthState).
+     */
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /**
+    Select the filter of the voices started by a zone of an instrument
+    (`Some(id)`, the zones being numbered in file order without the global zone),
+    or by all its zones (`None`), in place of the SoundFont low-pass filter.
+
+    Must be called before the presets are shared (e.g. before the font is added to the synth).
+    Returns the number of changed zones, over all the presets using the instrument.
+     */
+    pub fn set_zone_filter(
+        &mut self,
+        instrument: &str,
+        zone: Option<usize>,
+        filter: FilterType,
+    ) -> usize {
+        let mut count = 0;
+        for preset in self.presets.iter_mut() {
+            let preset = match Arc::get_mut(preset) {
+                Some(preset) => preset,
+                None => {
+                    log::warn!(
+                        "Preset {} is shared, its zones are left unchanged",
+                        preset.name()
+                    );
+                    continue;
+                }
+            };
+            for inst in preset
+                .instruments_mut()
+                .filter(|inst| inst.name() == instrument)
+            {
+                count += inst.set_zone_filter(zone, filter);
+            }
+        }
+        count
     }
 
     pub fn preset(&self, bank: u32, prenum: u8) -> Option<Arc<Preset>> {
@@ -73,4 +129,50 @@ impl SoundFont {
             .find(|p| p.banknum() == bank && p.num() == prenum as u32)
             .cloned()
     }
+
+    /// Index of a sample of the font
+    pub(crate) fn sample_index(&self, sample: &Arc<Sample>) -> Option<usize> {
+        self.samples.iter().position(|s| Arc::ptr_eq(s, sample))
+    }
+
+    pub(crate) fn sample(&self, index: usize) -> Option<Arc<Sample>> {
+        self.samples.get(index).cloned()
+    }
+
+    /// Whether the preset belongs to the font
+    pub(crate) fn has_preset(&self, preset: &Arc<Preset>) -> bool {
+        self.presets.iter().any(|p| Arc::ptr_eq(p, preset))
+    }
+}
+
+/// FNV-1a hash of the font name, the preset headers and the sample headers
+fn fingerprint(sf2: &soundfont::SoundFont2) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let mut write = |bytes: &[u8]| {
+        for byte in bytes {
+            hash = (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+
+    write(sf2.info.bank_name.as_bytes());
+    for preset in sf2.presets.iter() {
+        let header = &preset.header;
+        write(header.name.as_bytes());
+        write(&header.bank.to_le_bytes());
+        write(&header.preset.to_le_bytes());
+    }
+    for sample in sf2.sample_headers.iter() {
+        write(sample.name.as_bytes());
+        for value in [
+            sample.start,
+            sample.end,
+            sample.loop_start,
+            sample.loop_end,
+            sample.sample_rate,
+        ] {
+            write(&value.to_le_bytes());
+        }
+        write(&[sample.origpitch, sample.pitchadj as u8]);
+    }
+    hash
 }
@@ -2,6 +2,8 @@ use super::super::Channel;
 
 use num_derive::FromPrimitive;
 
+use crate::core::snapshot::snapshot;
+
 /**
 Generator (effect) numbers
 
@@ -148,6 +150,13 @@ pub struct Generator {
     pub nrpn: f64,
 }
 
+snapshot!(Generator {
+    flags,
+    val,
+    mod_0,
+    nrpn
+});
+
 const GEN_ABS_NRPN: u8 = 2;
 const GEN_UNUSED: u8 = 0;
 
@@ -660,11 +669,11 @@ pub(crate) fn get_default_values() -> [Generator; 60] {
     out
 }
 
-pub(crate) fn gen_init(channel: &Channel) -> [Generator; 60] {
+pub(crate) fn gen_init(channel: &Channel, key: u8) -> [Generator; 60] {
     let mut out = get_default_values();
 
     for (id, gen) in out.iter_mut().enumerate() {
-        gen.nrpn = channel.gen(id) as f64;
+        gen.nrpn = channel.voice_gen(key, id) as f64;
         if channel.gen_abs(id) != 0 {
             gen.flags = GEN_ABS_NRPN;
         }
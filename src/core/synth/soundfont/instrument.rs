@@ -3,12 +3,13 @@ use std::sync::Arc;
 use super::generator::{self, Generator};
 use super::modulator::Mod;
 use super::Sample;
+use crate::core::synth::FilterType;
 
 const GEN_SET: u32 = 1;
 
 #[derive(Clone, Debug)]
 pub struct Instrument {
-    _name: String,
+    name: String,
     global_zone: Option<InstrumentZone>,
     zones: Vec<InstrumentZone>,
 }
@@ -39,12 +40,35 @@ impl Instrument {
         }
 
         Ok(Self {
-            _name: name,
+            name,
             global_zone,
             zones,
         })
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /**
+    Select the filter of a zone (`Some(id)`, in file order without the global zone)
+    or of the whole instrument (`None`). Returns the number of changed zones.
+     */
+    pub(crate) fn set_zone_filter(&mut self, zone: Option<usize>, filter: FilterType) -> usize {
+        match zone {
+            Some(id) => self
+                .zones
+                .get_mut(id)
+                .map(|zone| zone.filter_type = Some(filter))
+                .map_or(0, |_| 1),
+            None => self
+                .zones
+                .iter_mut()
+                .map(|zone| zone.filter_type = Some(filter))
+                .count(),
+        }
+    }
+
     pub fn global_zone(&self) -> Option<&InstrumentZone> {
         self.global_zone.as_ref()
     }
@@ -65,6 +89,8 @@ pub struct InstrumentZone {
     pub vel_high: u8,
     pub gen: [Generator; 60],
     pub mods: Vec<Mod>,
+    /// Filter of the voices, overriding the SoundFont low-pass
+    pub filter_type: Option<FilterType>,
 }
 
 impl InstrumentZone {
@@ -142,6 +168,7 @@ impl InstrumentZone {
             vel_high,
             gen,
             mods,
+            filter_type: None,
         })
     }
 }
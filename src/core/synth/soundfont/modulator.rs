@@ -3,6 +3,7 @@ use super::super::conv::{concave, convex};
 use super::super::voice_pool::Voice;
 
 use super::generator::GeneratorType;
+use crate::core::snapshot::{Snapshot, StateReader, StateWriter};
 
 use soundfont::data::modulator::{
     ControllerPalette, GeneralPalette, Modulator as SFModulator, ModulatorSource,
@@ -65,12 +66,59 @@ impl Default for Mod {
     }
 }
 
+/// Sources as SoundFont source enumerators
+impl Snapshot for Mod {
+    fn save(&self, w: &mut StateWriter) {
+        let source = |src: &ModulatorSource| {
+            let ty = match src.ty {
+                SourceType::Linear => 0,
+                SourceType::Concave => 1,
+                SourceType::Convex => 2,
+                SourceType::Switch => 3,
+                SourceType::Unknown(ty) => ty as u32,
+            };
+            src.index as u32
+                | (matches!(src.controller_palette, ControllerPalette::Midi(_)) as u32) << 7
+                | ((src.direction == SourceDirection::Negative) as u32) << 8
+                | ((src.polarity == SourcePolarity::Bipolar) as u32) << 9
+                | ty << 10
+        };
+
+        w.write_u32(self.dest as u32);
+        w.write_f64(self.amount);
+        w.write_u32(source(&self.src));
+        w.write_u32(source(&self.src2));
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        use num_traits::FromPrimitive;
+
+        match FromPrimitive::from_u32(r.read_u32()) {
+            Some(dest) => self.dest = dest,
+            None => r.invalidate(),
+        }
+        self.amount = r.read_f64();
+        self.src = (r.read_u32() as u16).into();
+        self.src2 = (r.read_u32() as u16).into();
+    }
+}
+
 impl Mod {
     pub fn get_dest(&self) -> GeneratorType {
         self.dest
     }
 
     pub fn get_value(&self, chan: &Channel, voice: &Voice) -> f32 {
+        /// Controller value, MIDI 2.0 per-note controllers take precedence over the
+        /// channel value unless the voice was detached from them
+        fn cc_value(chan: &Channel, voice: &Voice, id: u8) -> f32 {
+            if voice.is_detached() {
+                chan.cc_value(id as usize)
+            } else {
+                chan.note_cc_value(voice.key, id as usize)
+            }
+        }
+
         /* 'special treatment' for default controller
          *
          *  Reference: SF2.01 section 8.4.2
@@ -111,16 +159,16 @@ impl Mod {
         let mut v1 = if self.src.index > 0 {
             use GeneralPalette::*;
             let v1 = match self.src.controller_palette {
-                ControllerPalette::Midi(id) => chan.cc(id as usize) as f32,
+                ControllerPalette::Midi(id) => cc_value(chan, voice, id),
                 ControllerPalette::General(g) => match g {
                     NoController => range1,
-                    NoteOnVelocity => voice.vel as f32,
+                    NoteOnVelocity => voice.vel_value(),
                     NoteOnKeyNumber => voice.key as f32,
-                    PolyPressure => chan.key_pressure(voice.key as usize) as f32,
-                    ChannelPressure => chan.channel_pressure() as f32,
+                    PolyPressure => chan.key_pressure_value(voice.key as usize),
+                    ChannelPressure => chan.channel_pressure_value(),
                     PitchWheel => {
                         range1 = 0x4000 as f32;
-                        chan.pitch_bend() as f32
+                        chan.pitch_bend_value()
                     }
                     PitchWheelSensitivity => chan.pitch_wheel_sensitivity() as f32,
                     _ => 0.0,
@@ -234,14 +282,14 @@ impl Mod {
         let v2 = if self.src2.index > 0 {
             use GeneralPalette::*;
             let v2 = match self.src2.controller_palette {
-                ControllerPalette::Midi(id) => chan.cc(id as usize) as f32,
+                ControllerPalette::Midi(id) => cc_value(chan, voice, id),
                 ControllerPalette::General(g) => match g {
                     NoController => range2,
-                    NoteOnVelocity => voice.vel as f32,
+                    NoteOnVelocity => voice.vel_value(),
                     NoteOnKeyNumber => voice.key as f32,
-                    PolyPressure => chan.key_pressure(voice.key as usize) as f32,
-                    ChannelPressure => chan.channel_pressure() as f32,
-                    PitchWheel => chan.pitch_bend() as f32,
+                    PolyPressure => chan.key_pressure_value(voice.key as usize),
+                    ChannelPressure => chan.channel_pressure_value(),
+                    PitchWheel => chan.pitch_bend_value(),
                     PitchWheelSensitivity => chan.pitch_wheel_sensitivity() as f32,
                     _ => {
                         // https://github.com/divideconcept/FluidLite/blob/fdd05bad03cdb24d1f78b5fe3453842890c1b0e8/src/fluid_mod.c#L282
@@ -350,7 +398,17 @@ impl Mod {
             1.0
         };
 
-        return self.amount as f32 * v1 * v2;
+        /* RPN 5 (modulation depth range) scales the vibrato depth of the
+         * modulation wheel, relative to the GM default of 50 cents. */
+        let amount = if self.dest == GeneratorType::VibLfoToPitch
+            && self.src.controller_palette == ControllerPalette::Midi(1)
+        {
+            self.amount as f32 * chan.mod_depth_range() / 50.0
+        } else {
+            self.amount as f32
+        };
+
+        amount * v1 * v2
     }
 
     pub fn test_identity(&self, mod2: &Mod) -> bool {
@@ -369,7 +427,10 @@ impl Mod {
 pub mod default {
     use super::Mod;
     use soundfont::data::generator::GeneratorType;
-    use soundfont::data::modulator::default_modulators;
+    use soundfont::data::modulator::{
+        default_modulators, ControllerPalette, GeneralPalette, Modulator, ModulatorSource,
+        ModulatorTransform, SourceDirection, SourcePolarity, SourceType,
+    };
 
     lazy_static! {
         /// 8.4.1  MIDI Note-On Velocity to Initial Attenuation
@@ -404,5 +465,55 @@ pub mod default {
         /// GeneratorType::Unused5 (59) coresponds to gen::GenParam::Pitch (59)
         pub static ref DEFAULT_PITCH_BEND_MOD: Mod = (&default_modulators::default_pitch_bend_mod(GeneratorType::Unused5)).into();
 
+        /// MPE Timbre (CC74) to Initial Filter Cutoff
+        ///
+        /// Not a SF2 default modulator, it is only added to voices of MPE member channels.
+        pub static ref MPE_TIMBRE_MOD: Mod = (&Modulator {
+            dest: GeneratorType::InitialFilterFc,
+            amount: 2400,
+
+            src: ModulatorSource {
+                index: 74,
+                controller_palette: ControllerPalette::Midi(74),
+                direction: SourceDirection::Positive,
+                polarity: SourcePolarity::Bipolar,
+                ty: SourceType::Linear,
+            },
+
+            amt_src: ModulatorSource {
+                index: 0,
+                controller_palette: ControllerPalette::General(GeneralPalette::NoController),
+                direction: SourceDirection::Positive,
+                polarity: SourcePolarity::Unipolar,
+                ty: SourceType::Linear,
+            },
+            transform: ModulatorTransform::Linear,
+        }).into();
+
+        /// MPE Pressure (channel pressure) to Initial Attenuation, up to 24 dB softer without pressure
+        ///
+        /// Not a SF2 default modulator, it is only added to voices of MPE member channels.
+        pub static ref MPE_PRESSURE_MOD: Mod = (&Modulator {
+            dest: GeneratorType::InitialAttenuation,
+            amount: 240,
+
+            src: ModulatorSource {
+                index: 13,
+                controller_palette: ControllerPalette::General(GeneralPalette::ChannelPressure),
+                direction: SourceDirection::Negative,
+                polarity: SourcePolarity::Unipolar,
+                ty: SourceType::Linear,
+            },
+
+            amt_src: ModulatorSource {
+                index: 0,
+                controller_palette: ControllerPalette::General(GeneralPalette::NoController),
+                direction: SourceDirection::Positive,
+                polarity: SourcePolarity::Unipolar,
+                ty: SourceType::Linear,
+            },
+            transform: ModulatorTransform::Linear,
+        }).into();
+
     }
 }
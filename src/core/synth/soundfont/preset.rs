@@ -57,6 +57,10 @@ impl Preset {
         &self.zones
     }
 
+    pub(crate) fn instruments_mut(&mut self) -> impl Iterator<Item = &mut Instrument> {
+        self.zones.iter_mut().filter_map(|zone| zone.inst.as_mut())
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
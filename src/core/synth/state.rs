@@ -0,0 +1,167 @@
+use crate::core::chorus::ChorusParams;
+use crate::core::reverb::{ReverbParams, ReverbType};
+use crate::core::tuning::Tuning;
+
+use super::{FilterType, InterpolationMethod, MpeZoneLayout, StealPolicy};
+
+/**
+Settings and MIDI state of the synth, saved by
+[`Synth::save_state`](crate::Synth::save_state): the preset, controllers, pitch bend,
+RPN/NRPN selection and generator offsets of each channel, the MPE zones, the tunings,
+the reverb algorithms and parameters, the chorus parameters, the gain and the polyphony.
+
+The SoundFonts are not part of the state, it refers to them by their
+[`fingerprint`](crate::SoundFont::fingerprint). Neither are the sounding voices,
+nor the reverb engines other than the built-in ones.
+ */
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SynthState {
+    /// The font stack, top first
+    pub(crate) fonts: Vec<FontState>,
+    pub(crate) channels: Vec<ChannelState>,
+    pub(crate) mpe_zones: Vec<MpeZoneLayout>,
+    pub(crate) tunings: Vec<TuningState>,
+    pub(crate) reverbs: Vec<ReverbState>,
+    pub(crate) choruses: Vec<FxState<ChorusParams>>,
+    pub(crate) gain: f32,
+    pub(crate) polyphony: u16,
+    pub(crate) steal_policy: StealPolicy,
+}
+
+impl SynthState {
+    /**
+    Fingerprints of the fonts to load before restoring the state, top of the stack first
+     */
+    pub fn fonts(&self) -> impl Iterator<Item = u64> + '_ {
+        self.fonts.iter().map(|font| font.fingerprint)
+    }
+}
+
+/**
+Complete state of the synth, saved by
+[`Synth::save_dsp_state`](crate::Synth::save_dsp_state): the [`SynthState`], along
+with the running state of the sounding voices (sample position, envelopes, LFOs,
+filter memories...), of the reverb, chorus and delay lines, and of the user effects.
+
+Rendering can be resumed from it with a bit-identical output.
+ */
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DspState {
+    pub(crate) synth: SynthState,
+    pub(crate) sample_rate: f32,
+    pub(crate) block_size: usize,
+    /// Running state of the DSP units, see `core::snapshot`
+    pub(crate) words: Vec<u32>,
+}
+
+impl DspState {
+    /**
+    Settings and MIDI state part of the state
+     */
+    pub fn synth_state(&self) -> &SynthState {
+        &self.synth
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct FontState {
+    pub fingerprint: u64,
+    pub bank_offset: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct ReverbState {
+    pub active: bool,
+    /// Built-in algorithm, `None` for the other engines
+    pub ty: Option<ReverbType>,
+    pub params: ReverbParams,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct FxState<P> {
+    pub active: bool,
+    pub params: P,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct TuningState {
+    pub bank: u32,
+    pub program: u32,
+    /// Pitch of each key, in cents
+    pub pitch: Vec<f64>,
+}
+
+impl From<&Tuning> for TuningState {
+    fn from(tuning: &Tuning) -> Self {
+        Self {
+            bank: tuning.bank,
+            program: tuning.program,
+            pitch: tuning.pitch.to_vec(),
+        }
+    }
+}
+
+impl From<&TuningState> for Tuning {
+    fn from(state: &TuningState) -> Self {
+        let mut tuning = Tuning::new(state.bank, state.program);
+        for (pitch, state) in tuning.pitch.iter_mut().zip(state.pitch.iter()) {
+            *pitch = *state;
+        }
+        tuning
+    }
+}
+
+/// MIDI state and settings of a channel, the arrays being indexed like the ones of `Channel`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct ChannelState {
+    /// Fingerprint of the font of the preset, `None` for the first font with the preset
+    pub font: Option<u64>,
+    pub bank: u32,
+    pub program: u8,
+    pub bank_msb: u8,
+
+    pub cc: Vec<u8>,
+    pub cc32: Vec<Option<u32>>,
+    pub key_pressure: Vec<i8>,
+    pub key_pressure32: Vec<Option<u32>>,
+    pub channel_pressure: u8,
+    pub channel_pressure32: Option<u32>,
+    pub pitch_bend: u16,
+    pub pitch_bend32: Option<u32>,
+    pub pitch_wheel_sensitivity: u8,
+    pub mod_depth_range: f32,
+    pub nrpn_select: i16,
+    pub nrpn_active: i16,
+
+    pub gen: Vec<f32>,
+    pub gen_abs: Vec<i8>,
+    pub key_gen: Vec<Vec<f32>>,
+    pub key_reverb_scale: Vec<f32>,
+    pub key_chorus_scale: Vec<f32>,
+    /// Pitch bend of the MPE zone manager channel, in cents
+    pub zone_pitch_bend: f32,
+
+    /// MIDI 2.0 per-note controllers, (key, controller, value)
+    pub note_cc: Vec<(u8, u8, u32)>,
+    /// MIDI 2.0 per-note pitch bend and absolute pitch, (key, value)
+    pub note_pitch_bend: Vec<(u8, u32)>,
+    pub note_pitch: Vec<(u8, u32)>,
+
+    pub interp_method: InterpolationMethod,
+    pub filter_type: Option<FilterType>,
+    pub reverb_id: usize,
+    pub chorus_id: usize,
+    pub tuning: Option<TuningState>,
+    pub tuning_bank: u8,
+    pub tuning_prog: u8,
+
+    pub voice_priority: u8,
+    pub voice_limit: Option<usize>,
+}
@@ -1,10 +1,33 @@
+mod info;
+mod steal;
 mod voice;
+#[cfg(feature = "parallel")]
+mod workers;
 
-pub(crate) use voice::{Voice, VoiceAddMode, VoiceDescriptor, VoiceEnvelope, VoiceStatus};
+pub use info::{EnvelopeStage, VoiceInfo};
+pub use steal::{StealPolicy, StolenVoice};
+
+#[cfg(feature = "parallel")]
+pub use workers::RenderThreads;
+
+use voice::VoiceOutput;
+pub(crate) use voice::{
+    Midi2Note, Voice, VoiceAddMode, VoiceDescriptor, VoiceEnvelope, VoiceStatus,
+};
 
 use super::channel_pool::Channel;
+use super::events::{EventQueue, SynthEvent};
 use super::soundfont::generator::GeneratorType;
+use super::soundfont::{Preset, Sample};
 use super::FxBuf;
+use crate::core::effects::ChannelStrip;
+use crate::core::snapshot::{Snapshot, StateReader, StateWriter};
+use crate::core::utils::Ramp;
+#[cfg(feature = "parallel")]
+use crate::core::OxiError;
+use steal::{ChannelVoices, NewNote};
+
+use std::sync::Arc;
 
 #[derive(Copy, Clone)]
 struct VoiceId(pub(crate) usize);
@@ -12,21 +35,49 @@ struct VoiceId(pub(crate) usize);
 pub struct VoicePool {
     voices: Vec<Voice>,
     sample_rate: f32,
+    /// Frames rendered at once
+    block_size: usize,
     polyphony_limit: usize,
+    /// Length of the voice gain and filter ramps, in ms
+    ramp_time: f32,
 
     noteid: usize,
     storeid: usize,
+
+    steal_policy: StealPolicy,
+    /// Priority and voice limit of each MIDI channel
+    channel_voices: Vec<ChannelVoices>,
+    events: EventQueue,
+
+    /// Threads rendering the voices, `None` renders them on the calling thread
+    #[cfg(feature = "parallel")]
+    workers: Option<workers::Workers>,
 }
 
 impl VoicePool {
-    pub fn new(len: usize, sample_rate: f32) -> Self {
+    pub fn new(
+        len: usize,
+        midi_channels: usize,
+        sample_rate: f32,
+        block_size: usize,
+        ramp_time: f32,
+    ) -> Self {
         Self {
             voices: Vec::new(),
             sample_rate,
+            block_size,
             polyphony_limit: len,
+            ramp_time,
 
             noteid: 0,
             storeid: 0,
+
+            steal_policy: StealPolicy::default(),
+            channel_voices: vec![ChannelVoices::default(); midi_channels],
+            events: EventQueue::new(),
+
+            #[cfg(feature = "parallel")]
+            workers: None,
         }
     }
 
@@ -35,11 +86,54 @@ impl VoicePool {
         self.noteid += 1;
     }
 
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
     pub fn set_sample_rate(&mut self, sample_rate: f32) {
         self.voices.clear();
         self.sample_rate = sample_rate;
     }
 
+    /// Set the length of the voice gain and filter ramps, in ms
+    pub fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.ramp_time = ramp_time;
+        let len = Ramp::samples(ramp_time, self.sample_rate);
+        for voice in self.voices.iter_mut() {
+            voice.set_ramp_len(len);
+        }
+    }
+
+    /// Select the threads rendering the voices, into buffers laid out like the ones of the synth
+    #[cfg(feature = "parallel")]
+    pub(super) fn set_render_threads(
+        &mut self,
+        threads: RenderThreads,
+        left: &[Vec<f32>],
+        fx: &FxBuf,
+        strips: &[ChannelStrip],
+    ) -> Result<(), OxiError> {
+        self.workers = workers::Workers::new(threads, left, fx, strips)?;
+        Ok(())
+    }
+
+    /// Follow a change of the effect sends or meter buffers of the synth
+    #[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+    pub(super) fn set_fx_layout(&mut self, fx: &FxBuf) {
+        #[cfg(feature = "parallel")]
+        if let Some(workers) = self.workers.as_mut() {
+            workers.set_fx_layout(fx);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    pub fn render_threads(&self) -> RenderThreads {
+        self.workers
+            .as_ref()
+            .map(|workers| workers.threads())
+            .unwrap_or_default()
+    }
+
     /// Set the polyphony limit
     pub fn set_polyphony_limit(&mut self, polyphony: usize) {
         /* remove any voices above the new limit */
@@ -47,16 +141,99 @@ impl VoicePool {
         self.polyphony_limit = polyphony;
     }
 
-    pub fn set_gen(&mut self, chan: usize, param: GeneratorType, value: f32) {
+    pub fn set_steal_policy(&mut self, policy: StealPolicy) {
+        self.steal_policy = policy;
+    }
+
+    pub fn steal_policy(&self) -> StealPolicy {
+        self.steal_policy
+    }
+
+    pub fn set_channel_priority(&mut self, chan: usize, priority: u8) {
+        self.channel_voices[chan].priority = priority;
+    }
+
+    pub fn channel_priority(&self, chan: usize) -> u8 {
+        self.channel_voices[chan].priority
+    }
+
+    pub fn set_channel_voice_limit(&mut self, chan: usize, limit: Option<usize>) {
+        self.channel_voices[chan].limit = limit;
+    }
+
+    pub fn channel_voice_limit(&self, chan: usize) -> Option<usize> {
+        self.channel_voices[chan].limit
+    }
+
+    pub fn events(&self) -> &EventQueue {
+        &self.events
+    }
+
+    pub fn events_mut(&mut self) -> &mut EventQueue {
+        &mut self.events
+    }
+
+    pub fn push_event(&mut self, event: SynthEvent) {
+        self.events.push(event);
+    }
+
+    /// Report the voice turned off since the last call
+    fn report_stopped(&mut self, id: usize) {
+        if let Some((channel, key)) = self.voices[id].stopped.take() {
+            self.events.push(SynthEvent::VoiceFinished { channel, key });
+        }
+    }
+
+    /// Report the voices turned off since the last block
+    pub fn report_finished(&mut self) {
+        for id in 0..self.voices.len() {
+            self.report_stopped(id);
+        }
+    }
+
+    /// State of the playing voices
+    pub fn playing(&self) -> impl Iterator<Item = VoiceInfo<'_>> {
+        self.voices
+            .iter()
+            .filter(|v| v.is_playing())
+            .map(|v| v.info())
+    }
+
+    pub fn set_gen(&mut self, channel: &Channel, param: GeneratorType) {
         for voice in self
             .voices
             .iter_mut()
-            .filter(|v| v.get_channel_id() == chan)
+            .filter(|v| v.get_channel_id() == channel.id())
         {
+            let value = channel.voice_gen(voice.key, param as usize);
             voice.set_param(param, value, 0);
         }
     }
 
+    pub fn set_key_gen(&mut self, channel: &Channel, key: u8, param: GeneratorType) {
+        for voice in self
+            .voices
+            .iter_mut()
+            .filter(|v| v.get_channel_id() == channel.id())
+            .filter(|v| v.key == key && !v.is_detached())
+        {
+            let value = channel.voice_gen(key, param as usize);
+            voice.set_param(param, value, 0);
+        }
+    }
+
+    pub fn set_key_send_scale(&mut self, channel: &Channel, key: u8) {
+        let (reverb, chorus) = channel.key_send_scale(key);
+        for voice in self
+            .voices
+            .iter_mut()
+            .filter(|v| v.get_channel_id() == channel.id())
+            .filter(|v| v.key == key && !v.is_detached())
+        {
+            voice.set_send_scale(reverb, chorus);
+        }
+    }
+
     pub fn set_gain(&mut self, gain: f32) {
         for voice in self.voices.iter_mut().filter(|v| v.is_playing()) {
             voice.set_gain(gain);
@@ -77,8 +254,8 @@ impl VoicePool {
                 voice.key,
                 0,
                 voice.get_note_id(),
-                voice.start_time.wrapping_add(voice.ticks) as f32 / 44100.0,
-                voice.ticks as f32 / 44100.0,
+                voice.start_time.wrapping_add(voice.ticks) as f32 / self.sample_rate,
+                voice.ticks as f32 / self.sample_rate,
             );
             voice.noteoff(channel, min_note_length_ticks);
         }
@@ -111,6 +288,121 @@ impl VoicePool {
         self.voices.iter_mut().for_each(|v| v.off())
     }
 
+    /**
+    Save the voices up to the last playing one, keeping their slots.
+
+    `link` gives the font fingerprint and the sample index of a playing voice,
+    the voices it doesn't find are saved as free slots.
+     */
+    pub(crate) fn save_state<F>(&self, state: &mut StateWriter, mut link: F)
+    where
+        F: FnMut(&Voice) -> Option<(u64, usize)>,
+    {
+        state.write_u64(self.noteid as u64);
+        state.write_u64(self.storeid as u64);
+
+        let links: Vec<_> = self
+            .voices
+            .iter()
+            .map(|voice| {
+                if voice.is_playing() {
+                    link(voice)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let len = links
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(0, |id| id + 1);
+
+        state.write_u64(len as u64);
+        for (voice, link) in self.voices.iter().zip(links.iter()).take(len) {
+            state.write_bool(link.is_some());
+            if let Some((font, sample)) = link {
+                state.write_u64(*font);
+                state.write_u64(*sample as u64);
+                state.write_u32(voice.preset.banknum());
+                state.write_u32(voice.preset.num());
+                voice.save(state);
+            }
+        }
+    }
+
+    /**
+    Replace the voices by the saved ones, `find` giving the sample and the
+    preset of a voice from its font fingerprint, sample index, bank and program.
+
+    The pool is left empty when the state is invalid.
+     */
+    pub(crate) fn restore_state<F>(
+        &mut self,
+        state: &mut StateReader,
+        channel: &Channel,
+        mut find: F,
+    ) where
+        F: FnMut(u64, usize, u32, u8) -> Option<(Arc<Sample>, Arc<Preset>)>,
+    {
+        self.noteid.restore(state);
+        self.storeid.restore(state);
+
+        let len = state.read_u64();
+        if len > self.polyphony_limit as u64 {
+            state.invalidate();
+        }
+
+        let mut slots: Vec<Option<Voice>> = Vec::new();
+        while state.is_valid() && slots.len() < len as usize {
+            if !state.read_bool() {
+                slots.push(None);
+                continue;
+            }
+
+            let font = state.read_u64();
+            let sample = state.read_u64() as usize;
+            let bank = state.read_u32();
+            let program = state.read_u32() as u8;
+            match find(font, sample, bank, program) {
+                Some((sample, preset)) => {
+                    let desc = VoiceDescriptor {
+                        sample,
+                        preset,
+                        channel,
+                        key: 0,
+                        vel: 0,
+                        midi2: None,
+                        start_time: 0,
+                        gain: 1.0,
+                    };
+                    let mut voice = Voice::new(self.sample_rate, self.block_size, desc, 0);
+                    voice.restore(state);
+                    slots.push(Some(voice));
+                }
+                None => state.invalidate(),
+            }
+        }
+
+        self.voices.clear();
+        if !state.is_valid() {
+            return;
+        }
+
+        // The free slots hold a turned off copy of a playing voice
+        let free = slots.iter().flatten().last().map(|voice| {
+            let mut voice = voice.clone();
+            voice.off();
+            voice.stopped = None;
+            voice
+        });
+        if let Some(free) = free {
+            self.voices = slots
+                .into_iter()
+                .map(|voice| voice.unwrap_or_else(|| free.clone()))
+                .collect();
+        }
+    }
+
     pub fn key_pressure(&mut self, channel: &Channel, key: u8) {
         const MOD_KEYPRESSURE: u8 = 10;
 
@@ -124,6 +416,39 @@ impl VoicePool {
         }
     }
 
+    pub fn modulate_note_voices(&mut self, channel: &Channel, key: u8, ctrl: u8) {
+        for voice in self
+            .voices
+            .iter_mut()
+            .filter(|v| v.get_channel_id() == channel.id())
+            .filter(|v| v.key == key && !v.is_detached())
+        {
+            voice.modulate(channel, true, ctrl);
+        }
+    }
+
+    pub fn modulate_note_voices_all(&mut self, channel: &Channel, key: u8) {
+        for voice in self
+            .voices
+            .iter_mut()
+            .filter(|v| v.get_channel_id() == channel.id())
+            .filter(|v| v.key == key && !v.is_detached())
+        {
+            voice.modulate_all(channel);
+        }
+    }
+
+    pub fn detach_note(&mut self, channel: &Channel, key: u8) {
+        for voice in self
+            .voices
+            .iter_mut()
+            .filter(|v| v.get_channel_id() == channel.id())
+            .filter(|v| v.key == key)
+        {
+            voice.detach();
+        }
+    }
+
     pub fn damp_voices(&mut self, channel: &Channel, min_note_length_ticks: usize) {
         for voice in self
             .voices
@@ -155,35 +480,39 @@ impl VoicePool {
         }
     }
 
-    fn free_voice_by_kill(&mut self, noteid: usize) -> Option<VoiceId> {
-        let mut best_prio: f32 = 999999.0f32;
-        let mut best_voice_index: Option<usize> = None;
+    /// Kill a voice to make room for `note`, among the ones of its channel if `same_channel`
+    fn free_voice_by_kill(&mut self, note: &NewNote, same_channel: bool) -> Option<VoiceId> {
+        let mut best: Option<((f32, f32), usize)> = None;
 
-        for (id, voice) in self.voices.iter_mut().enumerate() {
-            if voice.is_available() {
+        for (id, voice) in self.voices.iter().enumerate() {
+            if same_channel {
+                if !voice.is_playing() || voice.get_channel_id() != note.channel {
+                    continue;
+                }
+            } else if voice.is_available() {
+                self.report_stopped(id);
                 return Some(VoiceId(id));
             }
-            let mut this_voice_prio = 10000.0;
-            if voice.get_channel_id() == 0xff {
-                this_voice_prio -= 2000.0;
-            }
-            if voice.status == VoiceStatus::Sustained {
-                this_voice_prio -= 1000.0;
-            }
-            this_voice_prio -= noteid.wrapping_sub(voice.get_note_id()) as f32;
-            if voice.volenv_section != VoiceEnvelope::Attack as i32 {
-                this_voice_prio =
-                    (this_voice_prio as f64 + voice.volenv_val as f64 * 1000.0f64) as f32
-            }
-            if this_voice_prio < best_prio {
-                best_voice_index = Some(id);
-                best_prio = this_voice_prio
+
+            let rank = self.steal_policy.rank(voice, note, &self.channel_voices);
+            // `Option::is_none_or` needs Rust 1.82
+            #[allow(clippy::unnecessary_map_or)]
+            let better = best.map_or(true, |(best, _)| rank < best);
+            if better {
+                best = Some((rank, id));
             }
         }
 
-        if let Some(id) = best_voice_index {
+        if let Some((_, id)) = best {
             let voice = &mut self.voices[id];
+            let stolen = StolenVoice {
+                channel: voice.get_channel_id(),
+                key: voice.key,
+                vel: voice.vel,
+            };
             voice.off();
+            voice.stopped = None;
+            self.events.push(SynthEvent::VoiceStolen(stolen));
             Some(VoiceId(id))
         } else {
             None
@@ -214,6 +543,10 @@ impl VoicePool {
                             == excl_class
                         {
                             if existing_voice.get_note_id() != new_voice.get_note_id() {
+                                self.events.push(SynthEvent::ExclusiveClassKill {
+                                    channel: existing_voice.get_channel_id(),
+                                    key: existing_voice.key,
+                                });
                                 self.voices[i as usize].kill_excl();
                             }
                         }
@@ -247,38 +580,88 @@ impl VoicePool {
         channels: &[Channel],
         min_note_length_ticks: usize,
         audio_groups: u8,
-        dsp_left_buf: &mut [[f32; 64]],
-        dsp_right_buf: &mut [[f32; 64]],
+        dsp_left_buf: &mut [Vec<f32>],
+        dsp_right_buf: &mut [Vec<f32>],
         fx_left_buf: &mut FxBuf,
-        reverb_active: bool,
-        chorus_active: bool,
+        channel_strips: &mut [ChannelStrip],
     ) {
-        for voice in self.voices.iter_mut().filter(|v| v.is_playing()) {
-            /* The output associated with a MIDI channel is wrapped around
-             * using the number of audio groups as modulo divider.  This is
-             * typically the number of output channels on the 'sound card',
-             * as long as the LADSPA Fx unit is not used. In case of LADSPA
-             * unit, think of it as subgroups on a mixer.
-             *
-             * For example: Assume that the number of groups is set to 2.
-             * Then MIDI channel 1, 3, 5, 7 etc. go to output 1, channels 2,
-             * 4, 6, 8 etc to output 2.  Or assume 3 groups: Then MIDI
-             * channels 1, 4, 7, 10 etc go to output 1; 2, 5, 8, 11 etc to
-             * output 2, 3, 6, 9, 12 etc to output 3.
-             */
-            let mut auchan = voice.get_channel_id();
-            auchan %= audio_groups as usize;
-
-            voice.write(
-                &channels[voice.get_channel_id()],
-                min_note_length_ticks,
-                &mut dsp_left_buf[auchan as usize],
-                &mut dsp_right_buf[auchan as usize],
+        let ctx = RenderContext {
+            channels,
+            min_note_length_ticks,
+            audio_groups,
+        };
+
+        #[cfg(feature = "parallel")]
+        if let Some(workers) = self.workers.as_mut() {
+            workers.write_voices(
+                &mut self.voices,
+                &ctx,
+                dsp_left_buf,
+                dsp_right_buf,
                 fx_left_buf,
-                reverb_active,
-                chorus_active,
+                channel_strips,
             );
+            return;
         }
+
+        write_voices(
+            &mut self.voices,
+            &ctx,
+            dsp_left_buf,
+            dsp_right_buf,
+            fx_left_buf,
+            channel_strips,
+        );
+    }
+}
+
+/// State shared by all the voices rendered in a block
+#[derive(Clone, Copy)]
+struct RenderContext<'a> {
+    channels: &'a [Channel],
+    min_note_length_ticks: usize,
+    audio_groups: u8,
+}
+
+fn write_voices(
+    voices: &mut [Voice],
+    ctx: &RenderContext,
+    dsp_left_buf: &mut [Vec<f32>],
+    dsp_right_buf: &mut [Vec<f32>],
+    fx_left_buf: &mut FxBuf,
+    channel_strips: &mut [ChannelStrip],
+) {
+    for voice in voices.iter_mut().filter(|v| v.is_playing()) {
+        /* The output associated with a MIDI channel is wrapped around
+         * using the number of audio groups as modulo divider.  This is
+         * typically the number of output channels on the 'sound card',
+         * as long as the LADSPA Fx unit is not used. In case of LADSPA
+         * unit, think of it as subgroups on a mixer.
+         *
+         * For example: Assume that the number of groups is set to 2.
+         * Then MIDI channel 1, 3, 5, 7 etc. go to output 1, channels 2,
+         * 4, 6, 8 etc to output 2.  Or assume 3 groups: Then MIDI
+         * channels 1, 4, 7, 10 etc go to output 1; 2, 5, 8, 11 etc to
+         * output 2, 3, 6, 9, 12 etc to output 3.
+         */
+        let mut auchan = voice.get_channel_id();
+        auchan %= ctx.audio_groups as usize;
+
+        /* Channels going through effects are rendered separately */
+        let (left, right) = match channel_strips.get_mut(voice.get_channel_id()) {
+            Some(strip) if strip.active => (&mut strip.left, &mut strip.right),
+            _ => (&mut dsp_left_buf[auchan], &mut dsp_right_buf[auchan]),
+        };
+
+        voice.write(
+            &ctx.channels[voice.get_channel_id()],
+            ctx.min_note_length_ticks,
+            VoiceOutput {
+                left,
+                right,
+                fx: fx_left_buf,
+            },
+        );
     }
 }
 
@@ -295,34 +678,63 @@ impl VoicePool {
         desc: VoiceDescriptor,
         after: A,
     ) -> Result<(), ()> {
-        // find free synthesis process
-        let voice_id = self
-            .voices
-            .iter()
-            .enumerate()
-            .find(|(_, v)| v.is_available())
-            .map(|(id, _)| VoiceId(id));
-
         let channel = desc.channel;
+        let vel = desc.vel;
+        let note = NewNote {
+            noteid: self.noteid,
+            channel: channel.id(),
+            key: desc.key,
+        };
+
+        // a channel at its voice limit replaces one of its own voices
+        let at_limit = self.channel_voices[note.channel]
+            .limit
+            .is_some_and(|limit| {
+                self.voices
+                    .iter()
+                    .filter(|v| v.is_playing() && v.get_channel_id() == note.channel)
+                    .count()
+                    >= limit
+            });
+
+        // find free synthesis process
+        let voice_id = if at_limit {
+            // the channel can only replace one of its own voices
+            self.free_voice_by_kill(&note, true)
+        } else {
+            self.voices
+                .iter()
+                .enumerate()
+                .find(|(_, v)| v.is_available())
+                .map(|(id, _)| VoiceId(id))
+        };
 
         let voice_id = match voice_id {
             Some(id) => {
-                self.voices[id.0] = Voice::new(self.sample_rate, desc, self.storeid);
+                self.report_stopped(id.0);
+                self.voices[id.0] =
+                    Voice::new(self.sample_rate, self.block_size, desc, self.storeid);
                 Some(id)
             }
+            None if at_limit => None,
             // If none free voice was found:
             None => {
                 // Check if we can add a new voice
                 if self.voices.len() < self.polyphony_limit {
                     // If we can we do...
-                    self.voices
-                        .push(Voice::new(self.sample_rate, desc, self.storeid));
+                    self.voices.push(Voice::new(
+                        self.sample_rate,
+                        self.block_size,
+                        desc,
+                        self.storeid,
+                    ));
                     Some(VoiceId(self.voices.len() - 1))
                 } else {
                     // If we can't we free already existing one...
-                    let id = self.free_voice_by_kill(self.noteid);
+                    let id = self.free_voice_by_kill(&note, false);
                     if let Some(id) = id {
-                        self.voices[id.0] = Voice::new(self.sample_rate, desc, self.storeid);
+                        self.voices[id.0] =
+                            Voice::new(self.sample_rate, self.block_size, desc, self.storeid);
                     }
                     id
                 }
@@ -330,12 +742,23 @@ impl VoicePool {
         };
 
         if let Some(id) = voice_id {
+            self.voices[id.0].set_ramp_len(Ramp::samples(self.ramp_time, self.sample_rate));
             after(&mut self.voices[id.0]);
 
             // add the synthesis process to the synthesis loop.
             self.start_voice(channel, id);
+            self.events.push(SynthEvent::VoiceStarted {
+                channel: note.channel,
+                key: note.key,
+                vel: self.voices[id.0].vel,
+            });
             Ok(())
         } else {
+            self.events.push(SynthEvent::PolyphonyExhausted {
+                channel: note.channel,
+                key: note.key,
+                vel,
+            });
             Err(())
         }
     }
@@ -0,0 +1,66 @@
+use super::{Voice, VoiceEnvelope, VoiceStatus};
+use crate::core::soundfont::Preset;
+
+/**
+Section of the volume envelope of a voice
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EnvelopeStage {
+    Delay,
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+    Finished,
+}
+
+impl EnvelopeStage {
+    fn from_section(section: i32) -> Self {
+        match section {
+            s if s == VoiceEnvelope::Delay as i32 => Self::Delay,
+            s if s == VoiceEnvelope::Attack as i32 => Self::Attack,
+            s if s == VoiceEnvelope::Hold as i32 => Self::Hold,
+            s if s == VoiceEnvelope::Decay as i32 => Self::Decay,
+            s if s == VoiceEnvelope::Sustain as i32 => Self::Sustain,
+            s if s == VoiceEnvelope::Release as i32 => Self::Release,
+            _ => Self::Finished,
+        }
+    }
+}
+
+/**
+State of a playing voice, at the end of the last rendered block
+ */
+#[derive(Clone, Copy)]
+pub struct VoiceInfo<'a> {
+    pub channel: usize,
+    pub key: u8,
+    pub vel: u8,
+    pub sample_name: &'a str,
+    /// Preset of the channel when the note started
+    pub preset: &'a Preset,
+    pub envelope: EnvelopeStage,
+    /// Released by its note off, but held by the sustain pedal
+    pub sustained: bool,
+    /// Amplitude of the voice, the volume envelope, attenuation and tremolo applied
+    pub amplitude: f32,
+    /// Samples rendered since the voice started, at the internal sample rate
+    pub age: usize,
+}
+
+impl Voice {
+    pub(super) fn info(&self) -> VoiceInfo<'_> {
+        VoiceInfo {
+            channel: self.get_channel_id(),
+            key: self.key,
+            vel: self.vel,
+            sample_name: &self.sample.name,
+            preset: &self.preset,
+            envelope: EnvelopeStage::from_section(self.volenv_section),
+            sustained: self.status == VoiceStatus::Sustained,
+            amplitude: self.amp,
+            age: self.ticks,
+        }
+    }
+}
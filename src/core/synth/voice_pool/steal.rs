@@ -0,0 +1,101 @@
+use super::{Voice, VoiceEnvelope, VoiceStatus};
+
+/**
+Choice of the voice to kill when the polyphony, or the voice limit of a channel, is reached
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StealPolicy {
+    /// FluidSynth priorities: released voices first, then sustained ones,
+    /// favouring old and quiet voices past their attack
+    #[default]
+    Default,
+    /// The voice of the oldest note
+    Oldest,
+    /// The voice with the lowest amplitude
+    Quietest,
+    /// A voice of the same key on the channel of the new note, else the oldest one
+    SameNoteFirst,
+    /// A voice of the channel with the lowest voice priority, see
+    /// [`Synth::set_channel_voice_priority`](crate::Synth::set_channel_voice_priority),
+    /// choosing within the channel like [`StealPolicy::Default`]
+    LowestPriorityChannel,
+}
+
+/**
+Voice killed to make room for a new one, reported by `SynthEvent::VoiceStolen`
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StolenVoice {
+    pub channel: usize,
+    pub key: u8,
+    pub vel: u8,
+}
+
+/// Voice allocation settings of a MIDI channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct ChannelVoices {
+    /// Voices of the channels with the lowest priority are stolen first
+    pub priority: u8,
+    /// Most voices playing at once on the channel
+    pub limit: Option<usize>,
+}
+
+impl Default for ChannelVoices {
+    fn default() -> Self {
+        Self {
+            priority: 64,
+            limit: None,
+        }
+    }
+}
+
+/// Note requesting a voice
+pub(super) struct NewNote {
+    pub noteid: usize,
+    pub channel: usize,
+    pub key: u8,
+}
+
+impl StealPolicy {
+    /// Rank of a voice to steal, the lowest one being killed
+    pub(super) fn rank(
+        &self,
+        voice: &Voice,
+        note: &NewNote,
+        channels: &[ChannelVoices],
+    ) -> (f32, f32) {
+        let age = note.noteid.wrapping_sub(voice.get_note_id()) as f32;
+
+        match self {
+            StealPolicy::Default => (0.0, default_prio(voice, age)),
+            StealPolicy::Oldest => (0.0, -age),
+            StealPolicy::Quietest => (0.0, voice.amp.abs()),
+            StealPolicy::SameNoteFirst => {
+                let same = voice.get_channel_id() == note.channel && voice.key == note.key;
+                (if same { 0.0 } else { 1.0 }, -age)
+            }
+            StealPolicy::LowestPriorityChannel => {
+                let priority = channels
+                    .get(voice.get_channel_id())
+                    .map_or(ChannelVoices::default().priority, |c| c.priority);
+                (priority as f32, default_prio(voice, age))
+            }
+        }
+    }
+}
+
+fn default_prio(voice: &Voice, age: f32) -> f32 {
+    let mut prio = 10000.0;
+    if voice.get_channel_id() == 0xff {
+        prio -= 2000.0;
+    }
+    if voice.status == VoiceStatus::Sustained {
+        prio -= 1000.0;
+    }
+    prio -= age;
+    if voice.volenv_section != VoiceEnvelope::Attack as i32 {
+        prio = (prio as f64 + voice.volenv_val as f64 * 1000.0f64) as f32
+    }
+    prio
+}
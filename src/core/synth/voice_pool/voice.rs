@@ -1,16 +1,22 @@
 mod dsp_float;
 
 use super::super::{
-    channel_pool::{Channel, InterpolationMethod},
+    channel_pool::{Channel, FilterType, InterpolationMethod},
     FxBuf,
 };
 
 use super::super::soundfont::{
     generator::{self, Generator, GeneratorType},
     modulator::Mod,
-    Sample,
+    Preset, Sample,
 };
 
+use crate::core::midi2_event::to_u7_range;
+use crate::core::settings::MAX_BLOCK_SIZE;
+use crate::core::simd;
+use crate::core::snapshot::{snapshot, Snapshot, StateReader, StateWriter};
+use crate::core::utils::Ramp;
+
 use super::super::conv::{
     act2hz, atten2amp, cb2amp, ct2hz, ct2hz_real, pan, tc2sec, tc2sec_attack, tc2sec_delay,
     tc2sec_release,
@@ -53,21 +59,63 @@ pub enum VoiceStatus {
     Off,
 }
 
+impl Snapshot for VoiceStatus {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_u32(match self {
+            Self::Clean => 0,
+            Self::On => 1,
+            Self::Sustained => 2,
+            Self::Off => 3,
+        });
+    }
+
+    fn restore(&mut self, r: &mut StateReader) {
+        *self = match r.read_u32() {
+            0 => Self::Clean,
+            1 => Self::On,
+            2 => Self::Sustained,
+            3 => Self::Off,
+            _ => {
+                r.invalidate();
+                Self::Off
+            }
+        };
+    }
+}
+
 pub enum LoopMode {
     UnLooped = 0,
     DuringRelease = 1,
     UntilRelease = 3,
 }
 
+/// MIDI 2.0 note on data beyond the 7 bit key and velocity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Midi2Note {
+    /// 16 bit velocity
+    pub vel: u16,
+    /// Pitch 7.9 attribute
+    pub pitch: Option<u16>,
+}
+
 pub struct VoiceDescriptor<'a> {
     pub sample: Arc<Sample>,
+    pub preset: Arc<Preset>,
     pub channel: &'a Channel,
     pub key: u8,
     pub vel: u8,
+    pub midi2: Option<Midi2Note>,
     pub start_time: usize,
     pub gain: f32,
 }
 
+/// Buffers a voice mixes its output into
+pub struct VoiceOutput<'a> {
+    pub left: &'a mut [f32],
+    pub right: &'a mut [f32],
+    pub fx: &'a mut FxBuf,
+}
+
 #[derive(Clone)]
 pub struct Voice {
     pub note_id: usize,
@@ -77,10 +125,19 @@ pub struct Voice {
     pub key: u8,
     pub vel: u8,
 
+    /// Velocity in the 0-127 range, with the fraction of a MIDI 2.0 velocity
+    vel_value: f32,
+    /// Pitch of the note in semitones, when set by a MIDI 2.0 Pitch 7.9 attribute
+    note_pitch: Option<f32>,
+    /// Detached from the per-note controllers of its key (MIDI 2.0 Per-Note Management)
+    detached: bool,
+
     interp_method: InterpolationMethod,
     mod_count: usize,
 
     pub sample: Arc<Sample>,
+    /// Preset of the channel at the note on
+    pub preset: Arc<Preset>,
     pub start_time: usize,
 
     pub ticks: usize,
@@ -88,7 +145,12 @@ pub struct Voice {
 
     pub has_looped: bool,
 
+    /// Channel and key of the voice turned off, until reported
+    pub stopped: Option<(usize, u8)>,
+
     filter_startup: bool,
+    /// The output gains jump to their first value, instead of ramping to it
+    amp_startup: bool,
 
     volenv_count: u32,
     pub volenv_section: i32,
@@ -104,6 +166,9 @@ pub struct Voice {
 
     hist1: f32,
     hist2: f32,
+    /// Sample history of the second stage of the 4-pole low-pass
+    hist3: f32,
+    hist4: f32,
 
     pub(crate) gen: [Generator; 60],
     synth_gain: f32,
@@ -129,6 +194,16 @@ pub struct Voice {
     amp_reverb: f32,
     chorus_send: f32,
     amp_chorus: f32,
+    /// Scales of the reverb and chorus sends, set per key by the drum instrument NRPNs
+    reverb_scale: f32,
+    chorus_scale: f32,
+
+    /// Length of the gain and filter ramps, in samples
+    ramp_len: u32,
+    ramp_left: Ramp,
+    ramp_right: Ramp,
+    ramp_reverb: Ramp,
+    ramp_chorus: Ramp,
 
     root_pitch: f32,
     fres: f32,
@@ -136,6 +211,16 @@ pub struct Voice {
     q_lin: f32,
     filter_gain: f32,
 
+    filter_type: FilterType,
+    /// The biquad b2 coefficient is b02 times this sign: -1 for the band-pass, 1 otherwise
+    b2_sign: f32,
+    /// Stage states of the ladder filter
+    ladder: [f32; 4],
+    /// Ladder stage gain, from the cutoff frequency
+    ladder_g: Ramp,
+    /// Ladder feedback, from the resonance
+    ladder_k: Ramp,
+
     modlfo_to_pitch: f32,
     modlfo_to_vol: f32,
     modlfo_to_fc: f32,
@@ -159,6 +244,8 @@ pub struct Voice {
     mod_0: [Mod; 64],
 
     output_rate: f32,
+    /// Frames rendered by each `write`
+    block_size: usize,
 
     pub phase: Phase,
 
@@ -184,8 +271,115 @@ pub struct EnvData {
     pub max: f32,
 }
 
+snapshot!(EnvData {
+    count,
+    coeff,
+    incr,
+    min,
+    max
+});
+
+// Everything but the sample and preset, which the voice pool links to the fonts
+snapshot!(Voice {
+    note_id,
+    channel_id,
+    key,
+    vel,
+    vel_value,
+    note_pitch,
+    detached,
+    interp_method,
+    mod_count,
+    start_time,
+    ticks,
+    noteoff_ticks,
+    has_looped,
+    filter_startup,
+    amp_startup,
+    volenv_count,
+    volenv_section,
+    volenv_val,
+    amp,
+    modenv_count,
+    modenv_section,
+    modenv_val,
+    modlfo_val,
+    viblfo_val,
+    hist1,
+    hist2,
+    hist3,
+    hist4,
+    gen,
+    synth_gain,
+    amplitude_that_reaches_noise_floor_nonloop,
+    amplitude_that_reaches_noise_floor_loop,
+    status,
+    check_sample_sanity_flag,
+    min_attenuation_c_b,
+    last_fres,
+    pan,
+    amp_left,
+    amp_right,
+    attenuation,
+    pitch,
+    reverb_send,
+    amp_reverb,
+    chorus_send,
+    amp_chorus,
+    reverb_scale,
+    chorus_scale,
+    ramp_len,
+    ramp_left,
+    ramp_right,
+    ramp_reverb,
+    ramp_chorus,
+    root_pitch,
+    fres,
+    q_lin,
+    filter_gain,
+    filter_type,
+    b2_sign,
+    ladder,
+    ladder_g,
+    ladder_k,
+    modlfo_to_pitch,
+    modlfo_to_vol,
+    modlfo_to_fc,
+    modlfo_delay,
+    modlfo_incr,
+    viblfo_incr,
+    viblfo_delay,
+    viblfo_to_pitch,
+    modenv_to_pitch,
+    modenv_to_fc,
+    start,
+    end,
+    loopstart,
+    loopend,
+    volenv_data,
+    modenv_data,
+    mod_0,
+    output_rate,
+    block_size,
+    phase,
+    filter_coeff_incr_count,
+    a1,
+    a2,
+    b02,
+    b1,
+    a1_incr,
+    a2_incr,
+    b02_incr,
+    b1_incr,
+});
+
 impl Voice {
-    pub fn new(output_rate: f32, desc: VoiceDescriptor, note_id: usize) -> Voice {
+    pub fn new(
+        output_rate: f32,
+        block_size: usize,
+        desc: VoiceDescriptor,
+        note_id: usize,
+    ) -> Voice {
         let mut volenv_data = [EnvData::default(); 7];
         {
             let sustain = &mut volenv_data[VoiceEnvelope::Sustain as usize];
@@ -226,6 +420,8 @@ impl Voice {
             desc.gain
         };
 
+        let (reverb_scale, chorus_scale) = desc.channel.key_send_scale(desc.key);
+
         Voice {
             note_id,
             channel_id: desc.channel.id(),
@@ -233,10 +429,20 @@ impl Voice {
             key: desc.key,
             vel: desc.vel,
 
+            vel_value: desc
+                .midi2
+                .map_or(desc.vel as f32, |note| to_u7_range(note.vel as u32, 16)),
+            note_pitch: desc
+                .midi2
+                .and_then(|note| note.pitch)
+                .map(|pitch| pitch as f32 / 512.0),
+            detached: false,
+
             interp_method: desc.channel.interp_method(),
             mod_count: 0,
 
             sample: desc.sample,
+            preset: desc.preset,
             start_time: desc.start_time,
 
             ticks: 0,
@@ -244,8 +450,11 @@ impl Voice {
 
             has_looped: false,
 
+            stopped: None,
+
             last_fres: -1.0,
             filter_startup: true,
+            amp_startup: true,
 
             volenv_count: 0,
             volenv_section: 0,
@@ -261,8 +470,10 @@ impl Voice {
 
             hist1: 0.0,
             hist2: 0.0,
+            hist3: 0.0,
+            hist4: 0.0,
 
-            gen: generator::gen_init(&desc.channel),
+            gen: generator::gen_init(desc.channel, desc.key),
             synth_gain,
 
             amplitude_that_reaches_noise_floor_nonloop: 0.00003 / synth_gain,
@@ -272,6 +483,7 @@ impl Voice {
             mod_0: [Mod::default(); 64],
             check_sample_sanity_flag: 0,
             output_rate,
+            block_size,
             phase: 0,
             pitch: 0.0,
             attenuation: 0.0,
@@ -296,6 +508,11 @@ impl Voice {
             fres: 0.0,
             q_lin: 0.0,
             filter_gain: 0.0,
+            filter_type: FilterType::LowPass,
+            b2_sign: 1.0,
+            ladder: [0.0; 4],
+            ladder_g: Ramp::default(),
+            ladder_k: Ramp::default(),
             b02: 0.0,
             b1: 0.0,
             a1: 0.0,
@@ -312,6 +529,13 @@ impl Voice {
             amp_reverb: 0.0,
             chorus_send: 0.0,
             amp_chorus: 0.0,
+            reverb_scale,
+            chorus_scale,
+            ramp_len: 0,
+            ramp_left: Ramp::default(),
+            ramp_right: Ramp::default(),
+            ramp_reverb: Ramp::default(),
+            ramp_chorus: Ramp::default(),
         }
     }
 
@@ -391,6 +615,13 @@ impl Voice {
         self.add_mod(&DEFAULT_PITCH_BEND_MOD, VoiceAddMode::Default);
     }
 
+    /// Modulators for the per-note controllers of MPE member channels
+    pub fn add_mpe_mods(&mut self) {
+        use crate::core::soundfont::modulator::default::*;
+        self.add_mod(&MPE_TIMBRE_MOD, VoiceAddMode::Default);
+        self.add_mod(&MPE_PRESSURE_MOD, VoiceAddMode::Default);
+    }
+
     pub fn gen_incr(&mut self, i: u32, val: f64) {
         self.gen[i as usize].val += val;
         self.gen[i as usize].flags = GEN_SET as u8;
@@ -549,6 +780,9 @@ impl Voice {
     /// Turns off a voice, meaning that it is not processed
     /// anymore by the DSP loop.
     pub fn off(&mut self) {
+        if self.is_playing() {
+            self.stopped = Some((self.channel_id, self.key));
+        }
         self.channel_id = 0xff;
         self.volenv_section = VoiceEnvelope::Finished as i32;
         self.volenv_count = 0;
@@ -565,6 +799,20 @@ impl Voice {
         self.note_id
     }
 
+    /// Velocity in the 0-127 range, with the fraction of a MIDI 2.0 velocity
+    pub fn vel_value(&self) -> f32 {
+        self.vel_value
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    /// Detach the voice from further per-note controllers of its key
+    pub fn detach(&mut self) {
+        self.detached = true;
+    }
+
     /// A lower boundary for the attenuation (as in 'the minimum
     /// attenuation of this voice, with volume pedals, modulators
     /// etc. resulting in minimum attenuation, cannot fall below x cB) is
@@ -665,13 +913,22 @@ impl Voice {
         }
         let tuning = channel.tuning();
         if let Some(tuning) = tuning {
+            let key_pitch = match self.note_pitch {
+                Some(pitch) => {
+                    // Interpolate the tuning between the two surrounding keys
+                    let key = (pitch as usize).min(126);
+                    let frac = (pitch - key as f32) as f64;
+                    tuning.pitch[key] + frac * (tuning.pitch[key + 1] - tuning.pitch[key])
+                }
+                None => tuning.pitch[self.key as usize],
+            };
             self.gen[GeneratorType::Pitch as usize].val = tuning.pitch[60]
                 + self.gen[GeneratorType::ScaleTune as usize].val / 100.0f32 as f64
-                    * (tuning.pitch[self.key as usize] - tuning.pitch[60])
+                    * (key_pitch - tuning.pitch[60])
         } else {
+            let key = self.note_pitch.unwrap_or(self.key as f32);
             self.gen[GeneratorType::Pitch as usize].val =
-                self.gen[GeneratorType::ScaleTune as usize].val
-                    * (self.key as i32 as f32 - 60.0f32) as f64
+                self.gen[GeneratorType::ScaleTune as usize].val * (key - 60.0f32) as f64
                     + (100.0f32 * 60.0f32) as f64
         }
 
@@ -830,6 +1087,14 @@ impl Voice {
         self.update_param(gen);
     }
 
+    /// Scale the reverb and chorus sends
+    pub fn set_send_scale(&mut self, reverb: f32, chorus: f32) {
+        self.reverb_scale = reverb;
+        self.chorus_scale = chorus;
+        self.update_param(GeneratorType::ReverbSend);
+        self.update_param(GeneratorType::ChorusSend);
+    }
+
     pub fn set_gain(&mut self, mut gain: f32) {
         /* avoid division by zero*/
         if gain < 0.0000001 {
@@ -843,20 +1108,35 @@ impl Voice {
         self.amp_chorus = self.chorus_send * gain / 32768.0;
     }
 
+    /**
+    Set the length of the ramps smoothing the changes of the output gains
+    and filter coefficients, in samples.
+     */
+    pub fn set_ramp_len(&mut self, len: u32) {
+        self.ramp_len = len;
+    }
+
+    /// Select the filter of the voice, before it is started
+    pub fn set_filter_type(&mut self, filter_type: FilterType) {
+        self.filter_type = filter_type;
+        self.b2_sign = if filter_type == FilterType::BandPass {
+            -1.0
+        } else {
+            1.0
+        };
+    }
+
     pub(super) fn write(
         &mut self,
         channel: &Channel,
         min_note_length_ticks: usize,
-        dsp_left_buf: &mut [f32; 64],
-        dsp_right_buf: &mut [f32; 64],
-        fx_left_buf: &mut FxBuf,
-        reverb_active: bool,
-        chorus_active: bool,
+        mut out: VoiceOutput,
     ) {
         let current_block: u64;
         let target_amp; /* target amplitude */
 
-        let mut dsp_buf: [f32; 64] = [0.; 64];
+        let mut dsp_buf = [0f32; MAX_BLOCK_SIZE];
+        let dsp_buf = &mut dsp_buf[..self.block_size];
 
         /* make sure we're playing and that we have sample data */
         if !self.is_playing() {
@@ -1024,7 +1304,7 @@ impl Voice {
                 3632332525568699835 => {}
                 _ => {
                     /* Volume increment to go from voice->amp to target_amp in FLUID_BUFSIZE steps */
-                    let amp_incr = (target_amp - self.amp) / 64.0;
+                    let amp_incr = (target_amp - self.amp) / self.block_size as f32;
                     /* no volume and not changing? - No need to process */
                     if !(self.amp == 0.0 && amp_incr == 0.0) {
                         /* Calculate the number of samples, that the DSP loop advances
@@ -1107,9 +1387,46 @@ impl Voice {
                              *  voice->b2=(1.-cos_coeff)*a0_inv*0.5*voice->filter_gain; */
                             let a1_temp: f32 = -2.0f32 * cos_coeff * a0_inv;
                             let a2_temp: f32 = (1.0f32 - alpha_coeff) * a0_inv;
-                            let b1_temp: f32 = (1.0f32 - cos_coeff) * a0_inv * (self).filter_gain;
-                            /* both b0 -and- b2 */
-                            let b02_temp: f32 = b1_temp * 0.5f32;
+                            let (b02_temp, b1_temp) = match self.filter_type {
+                                FilterType::HighPass => {
+                                    let b1_temp = -(1.0 + cos_coeff) * a0_inv * self.filter_gain;
+                                    (b1_temp * -0.5, b1_temp)
+                                }
+                                /* The peak gain is 0 dB (before filter_gain), b2 is -b0 */
+                                FilterType::BandPass => {
+                                    (alpha_coeff * a0_inv * self.filter_gain, 0.0)
+                                }
+                                FilterType::Notch => {
+                                    let b02_temp = a0_inv * self.filter_gain;
+                                    (b02_temp, -2.0 * cos_coeff * b02_temp)
+                                }
+                                /* Both stages share the gain reduction */
+                                FilterType::LowPass4 => {
+                                    let b1_temp =
+                                        (1.0 - cos_coeff) * a0_inv * f32::sqrt(self.filter_gain);
+                                    (b1_temp * 0.5, b1_temp)
+                                }
+                                _ => {
+                                    let b1_temp: f32 =
+                                        (1.0f32 - cos_coeff) * a0_inv * (self).filter_gain;
+                                    /* both b0 -and- b2 */
+                                    (b1_temp * 0.5f32, b1_temp)
+                                }
+                            };
+
+                            if self.filter_type == FilterType::Ladder {
+                                /* Zero-delay feedback one-pole gain, and a feedback
+                                 * reaching self-oscillation (4) at full resonance. */
+                                let g = f32::tan(std::f32::consts::PI * fres / self.output_rate);
+                                let len = if self.filter_startup {
+                                    0
+                                } else {
+                                    self.ramp_len.clamp(1, self.block_size as u32)
+                                };
+                                self.ladder_g.set(g / (1.0 + g), len);
+                                self.ladder_k
+                                    .set((4.0 * (1.0 - 1.0 / self.q_lin)).clamp(0.0, 3.99), len);
+                            }
 
                             if self.filter_startup != false {
                                 /* The filter is calculated, because the voice was started up.
@@ -1123,82 +1440,79 @@ impl Voice {
                                 self.filter_startup = false;
                             } else {
                                 /* The filter frequency is changed.  Calculate an increment
-                                 * factor, so that the new setting is reached after one buffer
-                                 * length. x_incr is added to the current value FLUID_BUFSIZE
-                                 * times. The length is arbitrarily chosen. Longer than one
-                                 * buffer will sacrifice some performance, though.  Note: If
-                                 * the filter is still too 'grainy', then increase this number
-                                 * at will.
+                                 * factor, so that the new setting is reached after the ramp
+                                 * length. x_incr is added to the current value ramp_len
+                                 * times. The coefficients are recalculated every buffer, so
+                                 * the ramp is at most one buffer long.
                                  */
-                                self.a1_incr = (a1_temp - self.a1) / 64.0;
-                                self.a2_incr = (a2_temp - self.a2) / 64.0;
-                                self.b02_incr = (b02_temp - self.b02) / 64.0;
-                                self.b1_incr = (b1_temp - self.b1) / 64.0;
+                                let len = self.ramp_len.clamp(1, self.block_size as u32) as f32;
+                                self.a1_incr = (a1_temp - self.a1) / len;
+                                self.a2_incr = (a2_temp - self.a2) / len;
+                                self.b02_incr = (b02_temp - self.b02) / len;
+                                self.b1_incr = (b1_temp - self.b1) / len;
                                 /* Have to add the increments filter_coeff_incr_count times. */
-                                self.filter_coeff_incr_count = 64;
+                                self.filter_coeff_incr_count = len as i32;
                             }
                             self.last_fres = fres
                         }
 
-                        let count = match self.interp_method {
-                            InterpolationMethod::None => {
-                                self.dsp_float_interpolate_none(&mut dsp_buf, amp_incr, phase_incr)
-                            }
-                            InterpolationMethod::Linear => self.dsp_float_interpolate_linear(
-                                &mut dsp_buf,
-                                amp_incr,
-                                phase_incr,
-                            ),
-                            InterpolationMethod::FourthOrder => self
-                                .dsp_float_interpolate_4th_order(
-                                    &mut dsp_buf,
-                                    amp_incr,
-                                    phase_incr,
-                                ),
-                            InterpolationMethod::SeventhOrder => self
-                                .dsp_float_interpolate_7th_order(
-                                    &mut dsp_buf,
-                                    amp_incr,
-                                    phase_incr,
-                                ),
-                        };
+                        let count =
+                            match self.interp_method {
+                                InterpolationMethod::None => {
+                                    self.dsp_float_interpolate_none(dsp_buf, amp_incr, phase_incr)
+                                }
+                                InterpolationMethod::Linear => {
+                                    self.dsp_float_interpolate_linear(dsp_buf, amp_incr, phase_incr)
+                                }
+                                InterpolationMethod::FourthOrder => self
+                                    .dsp_float_interpolate_4th_order(dsp_buf, amp_incr, phase_incr),
+                                InterpolationMethod::SeventhOrder => self
+                                    .dsp_float_interpolate_7th_order(dsp_buf, amp_incr, phase_incr),
+                                InterpolationMethod::WindowedSinc { taps } => self
+                                    .dsp_float_interpolate_sinc(
+                                        dsp_buf,
+                                        amp_incr,
+                                        phase_incr,
+                                        taps as usize,
+                                    ),
+                            };
 
                         if count > 0 {
                             self.effects(
-                                &mut dsp_buf,
+                                dsp_buf,
                                 count,
-                                dsp_left_buf,
-                                dsp_right_buf,
-                                fx_left_buf,
-                                reverb_active,
-                                chorus_active,
+                                &mut out,
+                                channel.reverb_id(),
+                                channel.chorus_id(),
                             );
                         }
                         /* turn off voice if short count (sample ended and not looping) */
-                        if count < 64 {
+                        if count < self.block_size {
                             self.off();
                         }
                     }
                 }
             }
         }
-        self.ticks += self.ticks.wrapping_add(64);
+        self.ticks = self.ticks.wrapping_add(self.block_size);
     }
 
     #[inline]
     fn effects(
         &mut self,
-        dsp_buf: &mut [f32; 64],
+        dsp_buf: &mut [f32],
         count: usize,
-        dsp_left_buf: &mut [f32],
-        dsp_right_buf: &mut [f32],
-        fx_left_buf: &mut FxBuf,
-        reverb_active: bool,
-        chorus_active: bool,
+        out: &mut VoiceOutput,
+        reverb_id: usize,
+        chorus_id: usize,
     ) {
         /* IIR filter sample history */
         let mut dsp_hist1: f32 = self.hist1;
         let mut dsp_hist2: f32 = self.hist2;
+        let mut dsp_hist3: f32 = self.hist3;
+        let mut dsp_hist4: f32 = self.hist4;
+        let b2_sign = self.b2_sign;
+        let cascade = self.filter_type == FilterType::LowPass4;
 
         /* IIR filter coefficients */
         let mut dsp_a1: f32 = self.a1;
@@ -1208,7 +1522,6 @@ impl Voice {
         let mut dsp_filter_coeff_incr_count: i32 = self.filter_coeff_incr_count;
 
         let mut dsp_centernode;
-        let mut v;
 
         /* filter (implement the voice filter according to SoundFont standard) */
 
@@ -1221,14 +1534,24 @@ impl Voice {
          * changing towards its new setting. The other, if the filter
          * doesn't change.
          */
-        if dsp_filter_coeff_incr_count > 0 {
+        if self.filter_type == FilterType::Ladder {
+            self.ladder(&mut dsp_buf[..count]);
+        } else if dsp_filter_coeff_incr_count > 0 {
             /* Increment is added to each filter coefficient filter_coeff_incr_count times. */
             for dsp_i in 0..count {
                 /* The filter is implemented in Direct-II form. */
                 dsp_centernode = dsp_buf[dsp_i] - dsp_a1 * dsp_hist1 - dsp_a2 * dsp_hist2;
-                dsp_buf[dsp_i] = dsp_b02 * (dsp_centernode + dsp_hist2) + dsp_b1 * dsp_hist1;
+                dsp_buf[dsp_i] =
+                    dsp_b02 * (dsp_centernode + b2_sign * dsp_hist2) + dsp_b1 * dsp_hist1;
                 dsp_hist2 = dsp_hist1;
                 dsp_hist1 = dsp_centernode;
+                if cascade {
+                    /* Second stage of the 4-pole low-pass, with the same coefficients */
+                    dsp_centernode = dsp_buf[dsp_i] - dsp_a1 * dsp_hist3 - dsp_a2 * dsp_hist4;
+                    dsp_buf[dsp_i] = dsp_b02 * (dsp_centernode + dsp_hist4) + dsp_b1 * dsp_hist3;
+                    dsp_hist4 = dsp_hist3;
+                    dsp_hist3 = dsp_centernode;
+                }
                 let fresh0 = dsp_filter_coeff_incr_count;
                 dsp_filter_coeff_incr_count -= 1;
 
@@ -1240,17 +1563,38 @@ impl Voice {
                 }
             }
         }
-        /* The filter parameters are constant.  This is duplicated to save time. */
+        /* The filter parameters are constant, the stages run one after the other on the whole buffer. */
         else {
-            /* The filter is implemented in Direct-II form. */
-            for dsp_i in 0..count {
-                dsp_centernode = dsp_buf[dsp_i] - dsp_a1 * dsp_hist1 - dsp_a2 * dsp_hist2;
-                dsp_buf[dsp_i] = dsp_b02 * (dsp_centernode + dsp_hist2) + dsp_b1 * dsp_hist1;
-                dsp_hist2 = dsp_hist1;
-                dsp_hist1 = dsp_centernode;
+            let mut filter = simd::Biquad {
+                a1: dsp_a1,
+                a2: dsp_a2,
+                b02: dsp_b02,
+                b1: dsp_b1,
+                b2_sign,
+            };
+            let mut hist = [dsp_hist1, dsp_hist2];
+            simd::biquad(&mut dsp_buf[..count], &filter, &mut hist);
+            dsp_hist1 = hist[0];
+            dsp_hist2 = hist[1];
+            if cascade {
+                filter.b2_sign = 1.0;
+                let mut hist = [dsp_hist3, dsp_hist4];
+                simd::biquad(&mut dsp_buf[..count], &filter, &mut hist);
+                dsp_hist3 = hist[0];
+                dsp_hist4 = hist[1];
             }
         }
 
+        /* The output gains ramp to their new value when they change. */
+        let ramp_len = if self.amp_startup { 0 } else { self.ramp_len };
+        self.amp_startup = false;
+        self.ramp_left.set(self.amp_left, ramp_len);
+        self.ramp_right.set(self.amp_right, ramp_len);
+        self.ramp_reverb.set(self.amp_reverb, ramp_len);
+        self.ramp_chorus.set(self.amp_chorus, ramp_len);
+
+        let dsp_buf = &dsp_buf[..count];
+
         /* pan (Copy the signal to the left and right output buffer) The voice
          * panning generator has a range of -500 .. 500.  If it is centered,
          * it's close to 0.  voice->amp_left and voice->amp_right are then the
@@ -1258,46 +1602,48 @@ impl Voice {
          */
         if -0.5f64 < (self).pan as f64 && ((self).pan as f64) < 0.5f64 {
             /* The voice is centered. Use voice->amp_left twice. */
-            for dsp_i in 0..count {
-                v = self.amp_left * dsp_buf[dsp_i];
-                dsp_left_buf[dsp_i as usize] += v;
-                dsp_right_buf[dsp_i as usize] += v;
+            if self.ramp_left.is_steady() {
+                let amp = self.ramp_left.value();
+                simd::mix2(out.left, out.right, dsp_buf, amp);
+            } else {
+                let outs = out.left.iter_mut().zip(out.right.iter_mut());
+                for ((left, right), v) in outs.zip(dsp_buf.iter()) {
+                    let v = self.ramp_left.step() * v;
+                    *left += v;
+                    *right += v;
+                }
             }
+            self.ramp_right.skip(count as u32);
         }
         /* The voice is not centered. Stereo samples have one side zero. */
         else {
-            if self.amp_left as f64 != 0.0f64 {
-                for dsp_i in 0..count {
-                    dsp_left_buf[dsp_i] += (self).amp_left * dsp_buf[dsp_i];
-                }
-            }
-            if self.amp_right as f64 != 0.0f64 {
-                for dsp_i in 0..count {
-                    dsp_right_buf[dsp_i] += self.amp_right * dsp_buf[dsp_i];
-                }
-            }
+            self.ramp_left.mix(out.left, dsp_buf);
+            self.ramp_right.mix(out.right, dsp_buf);
         }
 
-        if reverb_active {
-            if self.amp_reverb != 0.0 {
-                for dsp_i in 0..count {
-                    // dsp_reverb_buf
-                    fx_left_buf.reverb[dsp_i] += self.amp_reverb * dsp_buf[dsp_i];
-                }
-            }
+        /* Sends to the reverb and chorus instances of the channel */
+        match out.fx.reverb.get_mut(reverb_id).filter(|s| s.active) {
+            Some(reverb) => self.ramp_reverb.mix(&mut reverb.buf, dsp_buf),
+            None => self.ramp_reverb.skip(count as u32),
         }
 
-        if chorus_active {
-            if self.amp_chorus != 0.0 {
-                for dsp_i in 0..count {
-                    // dsp_chorus_buf
-                    fx_left_buf.chorus[dsp_i] += self.amp_chorus * dsp_buf[dsp_i];
-                }
+        match out.fx.chorus.get_mut(chorus_id).filter(|s| s.active) {
+            Some(chorus) => self.ramp_chorus.mix(&mut chorus.buf, dsp_buf),
+            None => self.ramp_chorus.skip(count as u32),
+        }
+
+        /* Channel meters, in the scale of the output */
+        if let Some(meter) = out.fx.meters.get_mut(self.channel_id) {
+            let gain = self.synth_gain / 32768.0;
+            for (out, v) in meter.iter_mut().zip(dsp_buf.iter()) {
+                *out += v * gain;
             }
         }
 
         self.hist1 = dsp_hist1;
         self.hist2 = dsp_hist2;
+        self.hist3 = dsp_hist3;
+        self.hist4 = dsp_hist4;
         self.a1 = dsp_a1;
         self.a2 = dsp_a2;
         self.b02 = dsp_b02;
@@ -1305,6 +1651,37 @@ impl Voice {
         self.filter_coeff_incr_count = dsp_filter_coeff_incr_count;
     }
 
+    /**
+    Moog style ladder: four one-pole low-passes in series, with the output fed
+    back to the input. The feedback loop is solved per sample (zero-delay feedback),
+    which keeps the filter stable at any cutoff frequency.
+     */
+    fn ladder(&mut self, buf: &mut [f32]) {
+        for v in buf.iter_mut() {
+            let g = self.ladder_g.step();
+            let k = self.ladder_k.step();
+
+            // Output of the last stage, as a function of the ladder input
+            let mut gain = 1.0;
+            let mut state = 0.0;
+            for s in self.ladder.iter().rev() {
+                state += gain * (1.0 - g) * s;
+                gain *= g;
+            }
+            let out = (gain * *v + state) / (1.0 + k * gain);
+
+            let mut x = *v - k * out;
+            for s in self.ladder.iter_mut() {
+                let y = g * (x - *s) + *s;
+                *s = 2.0 * y - *s;
+                x = y;
+            }
+            /* Make up for the passband loss of the feedback, and apply the
+             * SoundFont gain reduction as the other filters. */
+            *v = x * (1.0 + k) * self.filter_gain;
+        }
+    }
+
     pub fn calculate_hold_decay_buffers(
         &mut self,
         gen_base: GeneratorType,
@@ -1335,7 +1712,7 @@ impl Voice {
         }
         let seconds = tc2sec(timecents);
         // buffers
-        ((self.output_rate as f64 * seconds / 64.0) + 0.5) as i32
+        ((self.output_rate as f64 * seconds / self.block_size as f64) + 0.5) as i32
     }
 
     /// The value of a generator (gen) has changed.  (The different
@@ -1413,7 +1790,7 @@ impl Voice {
                     1.0
                 } else {
                     self.reverb_send
-                };
+                } * self.reverb_scale;
                 self.amp_reverb = self.reverb_send * self.synth_gain / 32768.0;
             }
 
@@ -1427,7 +1804,7 @@ impl Voice {
                     1.0
                 } else {
                     self.chorus_send
-                };
+                } * self.chorus_scale;
                 self.amp_chorus = self.chorus_send * self.synth_gain / 32768.0;
             }
 
@@ -1575,7 +1952,7 @@ impl Voice {
                 } else {
                     val
                 };
-                self.modlfo_incr = 4.0 * 64.0 * act2hz(val) / self.output_rate;
+                self.modlfo_incr = 4.0 * self.block_size as f32 * act2hz(val) / self.output_rate;
             }
 
             GeneratorType::VibLfoFreq => {
@@ -1593,7 +1970,7 @@ impl Voice {
                 } else {
                     freq
                 };
-                self.viblfo_incr = 4.0 * 64.0 * act2hz(freq) / self.output_rate;
+                self.viblfo_incr = 4.0 * self.block_size as f32 * act2hz(freq) / self.output_rate;
             }
 
             GeneratorType::VibLfoDelay => {
@@ -1634,6 +2011,7 @@ impl Voice {
 
                 if val >= 0.0 {
                     self.key = val as u8;
+                    self.note_pitch = None;
                 }
             }
 
@@ -1648,6 +2026,7 @@ impl Voice {
                 let val = gen_sum!(GeneratorType::Velocity);
                 if val > 0.0 {
                     self.vel = val as u8;
+                    self.vel_value = self.vel as f32;
                 }
             }
 
@@ -1747,7 +2126,7 @@ impl Voice {
                     val
                 };
 
-                let count = (self.output_rate * tc2sec_delay(val) / 64.0) as u32;
+                let count = (self.output_rate * tc2sec_delay(val) / self.block_size as f32) as u32;
                 self.volenv_data[VoiceEnvelope::Delay as usize].count = count;
                 self.volenv_data[VoiceEnvelope::Delay as usize].coeff = 0.0;
                 self.volenv_data[VoiceEnvelope::Delay as usize].incr = 0.0;
@@ -1766,8 +2145,9 @@ impl Voice {
                     val
                 };
 
-                let count =
-                    1u32.wrapping_add((self.output_rate * tc2sec_attack(val) / 64.0) as u32);
+                let count = 1u32.wrapping_add(
+                    (self.output_rate * tc2sec_attack(val) / self.block_size as f32) as u32,
+                );
                 self.volenv_data[VoiceEnvelope::Attack as usize].count = count;
                 self.volenv_data[VoiceEnvelope::Attack as usize].coeff = 1.0;
                 self.volenv_data[VoiceEnvelope::Attack as usize].incr =
@@ -1827,8 +2207,9 @@ impl Voice {
                     val
                 };
 
-                let count =
-                    1u32.wrapping_add((self.output_rate * tc2sec_release(val) / 64.0) as u32);
+                let count = 1u32.wrapping_add(
+                    (self.output_rate * tc2sec_release(val) / self.block_size as f32) as u32,
+                );
                 self.volenv_data[VoiceEnvelope::Release as usize].count = count;
                 self.volenv_data[VoiceEnvelope::Release as usize].coeff = 1.0;
                 self.volenv_data[VoiceEnvelope::Release as usize].incr =
@@ -1849,7 +2230,7 @@ impl Voice {
                 };
 
                 self.modenv_data[VoiceEnvelope::Delay as usize].count =
-                    (self.output_rate * tc2sec_delay(val) / 64.0) as u32;
+                    (self.output_rate * tc2sec_delay(val) / self.block_size as f32) as u32;
                 self.modenv_data[VoiceEnvelope::Delay as usize].coeff = 0.0;
                 self.modenv_data[VoiceEnvelope::Delay as usize].incr = 0.0;
                 self.modenv_data[VoiceEnvelope::Delay as usize].min = -1.0;
@@ -1867,8 +2248,9 @@ impl Voice {
                     val
                 };
 
-                let count =
-                    1u32.wrapping_add((self.output_rate * tc2sec_attack(val) / 64.0) as u32);
+                let count = 1u32.wrapping_add(
+                    (self.output_rate * tc2sec_attack(val) / self.block_size as f32) as u32,
+                );
                 self.modenv_data[VoiceEnvelope::Attack as usize].count = count;
                 self.modenv_data[VoiceEnvelope::Attack as usize].coeff = 1.0;
                 self.modenv_data[VoiceEnvelope::Attack as usize].incr =
@@ -1928,8 +2310,9 @@ impl Voice {
                     val
                 };
 
-                let count =
-                    1u32.wrapping_add((self.output_rate * tc2sec_release(val) / 64.0) as u32);
+                let count = 1u32.wrapping_add(
+                    (self.output_rate * tc2sec_release(val) / self.block_size as f32) as u32,
+                );
                 self.modenv_data[VoiceEnvelope::Release as usize].count = count;
                 self.modenv_data[VoiceEnvelope::Release as usize].coeff = 1.0;
                 self.modenv_data[VoiceEnvelope::Release as usize].incr =
@@ -1941,3 +2324,50 @@ impl Voice {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::core::synth::test::{render_note, sin_font};
+    use crate::core::synth::FilterType;
+
+    #[test]
+    fn filters() {
+        let energy = |samples: [f32; 4096]| samples[2048..].iter().map(|v| v * v).sum::<f32>();
+        let dry = energy(render_note(&|_| {}));
+
+        // The 440 Hz note is far below the cutoff of the SoundFont filter (~20 kHz)
+        for (filter, passes) in [
+            (FilterType::LowPass, true),
+            (FilterType::LowPass4, true),
+            (FilterType::HighPass, false),
+            (FilterType::BandPass, false),
+            (FilterType::Notch, true),
+            (FilterType::Ladder, true),
+        ] {
+            let out = energy(render_note(&|synth| {
+                synth.set_channel_filter(0, Some(filter)).unwrap();
+                assert_eq!(synth.channel_filter(0).unwrap(), Some(filter));
+            }));
+            if passes {
+                assert!((out / dry - 1.0).abs() < 0.1, "{:?}", filter);
+            } else {
+                assert!(out < dry * 0.01, "{:?}", filter);
+            }
+        }
+
+        // Filter of an instrument zone, the second one covers the A4 key
+        let out = energy(render_note(&|synth| {
+            let mut font = sin_font();
+            assert_eq!(
+                font.set_zone_filter("Sine Wave", Some(1), FilterType::HighPass),
+                1
+            );
+            assert_eq!(
+                font.set_zone_filter("Sine Wave", Some(3), FilterType::HighPass),
+                0
+            );
+            synth.add_font(font, true);
+        }));
+        assert!(out < dry * 0.01);
+    }
+}
@@ -1,4 +1,5 @@
 use super::Voice;
+use crate::core::simd;
 pub type Phase = u64;
 pub type GenType = u32;
 pub const GEN_SAMPLEMODE: GenType = 54;
@@ -12,6 +13,10 @@ pub struct DspFloatGlobal {
     interp_coeff_linear: [[f32; 2]; 256],
     interp_coeff: [[f32; 4]; 256],
     sinc_table7: [[f32; 7]; 256],
+    /// sin(pi x) / (pi x), `SINC_RES` entries per zero crossing
+    sinc: Vec<f32>,
+    /// Blackman window from its center to its edge, `SINC_WINDOW_RES` entries
+    sinc_window: Vec<f32>,
 }
 impl DspFloatGlobal {
     fn new() -> Self {
@@ -19,6 +24,8 @@ impl DspFloatGlobal {
             interp_coeff_linear: [[0.; 2]; 256],
             interp_coeff: [[0.; 4]; 256],
             sinc_table7: [[0.; 7]; 256],
+            sinc: Vec::new(),
+            sinc_window: Vec::new(),
         };
 
         let mut i: usize;
@@ -59,10 +66,43 @@ impl DspFloatGlobal {
             i += 1
         }
 
+        // One more entry past the end for the linear interpolation of the lookups
+        global.sinc = (0..=SINC_MAX_TAPS / 2 * SINC_RES + 1)
+            .map(|i| {
+                let x = std::f64::consts::PI * i as f64 / SINC_RES as f64;
+                if i == 0 {
+                    1.0
+                } else {
+                    (x.sin() / x) as f32
+                }
+            })
+            .collect();
+        global.sinc_window = (0..=SINC_WINDOW_RES + 1)
+            .map(|i| {
+                let x = std::f64::consts::PI * (i as f64 / SINC_WINDOW_RES as f64).min(1.0);
+                (0.42 + 0.5 * x.cos() + 0.08 * (2.0 * x).cos()) as f32
+            })
+            .collect();
+
         global
     }
 }
 
+const SINC_RES: usize = 256;
+const SINC_WINDOW_RES: usize = 1024;
+const SINC_MIN_TAPS: usize = 8;
+const SINC_MAX_TAPS: usize = 64;
+/// Bound of the widened kernel, in sample points
+const SINC_MAX_POINTS: usize = 512;
+
+/// Linear interpolation of `table` at `x`, in entries
+#[inline(always)]
+fn table_lookup(table: &[f32], x: f32) -> f32 {
+    let id = x as usize;
+    let fract = x - id as f32;
+    table[id] + fract * (table[id + 1] - table[id])
+}
+
 lazy_static! {
     static ref DSP_FLOAT_GLOBAL: DspFloatGlobal = DspFloatGlobal::new();
 }
@@ -105,16 +145,63 @@ fn phase_set_float(b: f32) -> u64 {
     left | right
 }
 
+/**
+Interpolate `simd::LANES` output samples at once, all the points of which are
+inside of the sample data. `first` is the number of points before the phase index.
+ */
+#[allow(clippy::too_many_arguments)]
+fn interpolate_block<const TAPS: usize>(
+    table: &[[f32; TAPS]; 256],
+    data: &[i16],
+    first: usize,
+    out: &mut [f32],
+    phase: &mut Phase,
+    phase_incr: Phase,
+    amp: &mut f32,
+    amp_incr: f32,
+) {
+    let mut coeffs = [[0.0; TAPS]; simd::LANES];
+    let mut points = [[0.0; TAPS]; simd::LANES];
+    let mut amps = [0.0; simd::LANES];
+
+    for lane in 0..simd::LANES {
+        let index = (*phase >> 32) as usize;
+        coeffs[lane] = table[phase_fract_to_tablerow(*phase as usize)];
+        for (tap, point) in points[lane].iter_mut().enumerate() {
+            *point = data[index.wrapping_sub(first).wrapping_add(tap)] as f32;
+        }
+        amps[lane] = *amp;
+
+        *phase = phase.wrapping_add(phase_incr);
+        *amp += amp_incr;
+    }
+
+    simd::interpolate(&coeffs, &points, &amps, out);
+}
+
+/// Whether the next `simd::LANES` samples can be interpolated without reaching past `end_index`
+fn block_fits(
+    dsp_i: usize,
+    block: usize,
+    phase: Phase,
+    phase_incr: Phase,
+    end_index: usize,
+) -> bool {
+    let last = phase.saturating_add(phase_incr.saturating_mul(simd::LANES as u64 - 1));
+    dsp_i + simd::LANES <= block && (last >> 32) as usize <= end_index
+}
+
 impl Voice {
     /// No interpolation. Just take the sample, which is closest to
     /// the playback pointer.  Questionable quality, but very
     /// efficient.
     pub fn dsp_float_interpolate_none(
         &mut self,
-        dsp_buf: &mut [f32; 64],
+        dsp_buf: &mut [f32],
         dsp_amp_incr: f32,
         phase_incr: f32,
     ) -> usize {
+        let block = dsp_buf.len();
         let mut dsp_phase: Phase = self.phase;
         let dsp_data: &[i16] = &self.sample.data;
         let mut dsp_amp: f32 = self.amp;
@@ -143,7 +230,7 @@ impl Voice {
                 (dsp_phase.wrapping_add(0x80000000 as u32 as u64) >> 32 as i32) as usize;
 
             /* interpolate sequence of sample points */
-            while dsp_i < 64 && dsp_phase_index <= end_index {
+            while dsp_i < block && dsp_phase_index <= end_index {
                 dsp_buf[dsp_i] = dsp_amp * dsp_data[dsp_phase_index] as f32;
 
                 /* increment phase and amplitude */
@@ -167,7 +254,7 @@ impl Voice {
             }
 
             /* break out if filled buffer */
-            if dsp_i >= 64 {
+            if dsp_i >= block {
                 break;
             }
         }
@@ -179,14 +266,15 @@ impl Voice {
     }
 
     /// Straight line interpolation.
-    /// Returns number of samples processed (usually FLUID_BUFSIZE but could be
+    /// Returns number of samples processed (usually the block size but could be
     /// smaller if end of sample occurs).
     pub fn dsp_float_interpolate_linear(
         &mut self,
-        dsp_buf: &mut [f32; 64],
+        dsp_buf: &mut [f32],
         dsp_amp_incr: f32,
         phase_incr: f32,
     ) -> usize {
+        let block = dsp_buf.len();
         let mut dsp_phase: Phase = self.phase;
         let dsp_data: &[i16] = &self.sample.data;
         let mut dsp_amp: f32 = self.amp;
@@ -223,7 +311,7 @@ impl Voice {
             let mut dsp_phase_index = (dsp_phase >> 32 as i32) as usize;
 
             /* interpolate the sequence of sample points */
-            while dsp_i < 64 && dsp_phase_index <= end_index {
+            while dsp_i < block && dsp_phase_index <= end_index {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.interp_coeff_linear[id];
 
@@ -238,14 +326,14 @@ impl Voice {
             }
 
             /* break out if buffer filled */
-            if dsp_i >= 64 {
+            if dsp_i >= block {
                 break;
             }
             /* we're now interpolating the last point */
             end_index = end_index.wrapping_add(1);
 
             /* interpolate within last point */
-            while dsp_phase_index <= end_index && dsp_i < 64 {
+            while dsp_phase_index <= end_index && dsp_i < block {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.interp_coeff_linear[id];
 
@@ -272,7 +360,7 @@ impl Voice {
             }
 
             /* break out if filled buffer */
-            if dsp_i >= 64 {
+            if dsp_i >= block {
                 break;
             }
 
@@ -286,14 +374,15 @@ impl Voice {
     }
 
     /// 4th order (cubic) interpolation.
-    /// Returns number of samples processed (usually FLUID_BUFSIZE but could be
+    /// Returns number of samples processed (usually the block size but could be
     /// smaller if end of sample occurs).
     pub fn dsp_float_interpolate_4th_order(
         &mut self,
-        dsp_buf: &mut [f32; 64],
+        dsp_buf: &mut [f32],
         dsp_amp_incr: f32,
         phase_incr: f32,
     ) -> usize {
+        let block = dsp_buf.len();
         let mut dsp_phase: Phase = self.phase;
         let dsp_data: &[i16] = &self.sample.data;
         let mut dsp_amp: f32 = self.amp;
@@ -344,7 +433,7 @@ impl Voice {
         loop {
             let mut dsp_phase_index = (dsp_phase >> 32 as i32) as usize;
             /* interpolate first sample point (start or loop start) if needed */
-            while dsp_phase_index == start_index && dsp_i < 64 {
+            while dsp_phase_index == start_index && dsp_i < block {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.interp_coeff[id];
 
@@ -361,8 +450,24 @@ impl Voice {
                 dsp_i = dsp_i.wrapping_add(1)
             }
 
-            /* interpolate the sequence of sample points */
-            while dsp_i < 64 && dsp_phase_index <= end_index {
+            /* interpolate the sequence of sample points, a block at a time */
+            while block_fits(dsp_i, block, dsp_phase, dsp_phase_incr, end_index) {
+                interpolate_block(
+                    &DSP_FLOAT_GLOBAL.interp_coeff,
+                    dsp_data,
+                    1,
+                    &mut dsp_buf[dsp_i..dsp_i + simd::LANES],
+                    &mut dsp_phase,
+                    dsp_phase_incr,
+                    &mut dsp_amp,
+                    dsp_amp_incr,
+                );
+                dsp_i += simd::LANES;
+            }
+            dsp_phase_index = (dsp_phase >> 32) as usize;
+
+            /* and the remaining points one by one */
+            while dsp_i < block && dsp_phase_index <= end_index {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.interp_coeff[id];
 
@@ -380,7 +485,7 @@ impl Voice {
             }
 
             /* break out if buffer filled */
-            if dsp_i >= 64 {
+            if dsp_i >= block {
                 break;
             }
 
@@ -388,7 +493,7 @@ impl Voice {
             end_index = end_index.wrapping_add(1);
 
             /* interpolate within 2nd to last point */
-            while dsp_phase_index <= end_index && dsp_i < 64 {
+            while dsp_phase_index <= end_index && dsp_i < block {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.interp_coeff[id];
 
@@ -408,7 +513,7 @@ impl Voice {
             end_index = end_index.wrapping_add(1);
 
             /* interpolate within the last point */
-            while dsp_phase_index <= end_index && dsp_i < 64 {
+            while dsp_phase_index <= end_index && dsp_i < block {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.interp_coeff[id];
 
@@ -443,7 +548,7 @@ impl Voice {
             }
 
             /* break out if filled buffer */
-            if dsp_i >= 64 {
+            if dsp_i >= block {
                 break;
             }
 
@@ -458,10 +563,11 @@ impl Voice {
 
     pub fn dsp_float_interpolate_7th_order(
         &mut self,
-        dsp_buf: &mut [f32; 64],
+        dsp_buf: &mut [f32],
         dsp_amp_incr: f32,
         phase_incr: f32,
     ) -> usize {
+        let block = dsp_buf.len();
         let dsp_data: &[i16] = &self.sample.data;
         let mut dsp_amp: f32 = self.amp;
 
@@ -522,7 +628,7 @@ impl Voice {
             dsp_phase_index = (dsp_phase >> 32 as i32) as usize;
 
             /* interpolate first sample point (start or loop start) if needed */
-            while dsp_phase_index == start_index && dsp_i < 64 {
+            while dsp_phase_index == start_index && dsp_i < block {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.sinc_table7[id];
                 dsp_buf[dsp_i] = dsp_amp
@@ -543,7 +649,7 @@ impl Voice {
             start_index = start_index.wrapping_add(1);
 
             /* interpolate 2nd to first sample point (start or loop start) if needed */
-            while dsp_phase_index == start_index && dsp_i < 64 {
+            while dsp_phase_index == start_index && dsp_i < block {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.sinc_table7[id];
                 dsp_buf[dsp_i] = dsp_amp
@@ -565,7 +671,7 @@ impl Voice {
             start_index = start_index.wrapping_add(1);
 
             /* interpolate 3rd to first sample point (start or loop start) if needed */
-            while dsp_phase_index == start_index && dsp_i < 64 {
+            while dsp_phase_index == start_index && dsp_i < block {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.sinc_table7[id];
                 dsp_buf[dsp_i] = dsp_amp
@@ -587,8 +693,24 @@ impl Voice {
             /* set back to original start index */
             start_index = start_index.wrapping_sub(2);
 
-            /* interpolate the sequence of sample points */
-            while dsp_i < 64 && dsp_phase_index <= end_index {
+            /* interpolate the sequence of sample points, a block at a time */
+            while block_fits(dsp_i, block, dsp_phase, dsp_phase_incr, end_index) {
+                interpolate_block(
+                    &DSP_FLOAT_GLOBAL.sinc_table7,
+                    dsp_data,
+                    3,
+                    &mut dsp_buf[dsp_i..dsp_i + simd::LANES],
+                    &mut dsp_phase,
+                    dsp_phase_incr,
+                    &mut dsp_amp,
+                    dsp_amp_incr,
+                );
+                dsp_i += simd::LANES;
+            }
+            dsp_phase_index = (dsp_phase >> 32) as usize;
+
+            /* and the remaining points one by one */
+            while dsp_i < block && dsp_phase_index <= end_index {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.sinc_table7[id];
                 dsp_buf[dsp_i] = dsp_amp
@@ -608,7 +730,7 @@ impl Voice {
             }
 
             /* break out if buffer filled */
-            if dsp_i >= 64 {
+            if dsp_i >= block {
                 break;
             }
 
@@ -616,7 +738,7 @@ impl Voice {
             end_index = end_index.wrapping_add(1);
 
             /* interpolate within 3rd to last point */
-            while dsp_phase_index <= end_index && dsp_i < 64 {
+            while dsp_phase_index <= end_index && dsp_i < block {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.sinc_table7[id];
                 dsp_buf[dsp_i] = dsp_amp
@@ -639,7 +761,7 @@ impl Voice {
             end_index = end_index.wrapping_add(1);
 
             /* interpolate within 2nd to last point */
-            while dsp_phase_index <= end_index && dsp_i < 64 {
+            while dsp_phase_index <= end_index && dsp_i < block {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.sinc_table7[id];
                 dsp_buf[dsp_i] = dsp_amp
@@ -662,7 +784,7 @@ impl Voice {
             end_index = end_index.wrapping_add(1);
 
             /* interpolate within last point */
-            while dsp_phase_index <= end_index && dsp_i < 64 {
+            while dsp_phase_index <= end_index && dsp_i < block {
                 let id = phase_fract_to_tablerow(dsp_phase as usize);
                 let coeffs = &DSP_FLOAT_GLOBAL.sinc_table7[id];
                 dsp_buf[dsp_i] = dsp_amp
@@ -702,7 +824,7 @@ impl Voice {
             }
 
             /* break out if filled buffer */
-            if dsp_i >= 64 {
+            if dsp_i >= block {
                 break;
             }
 
@@ -719,4 +841,145 @@ impl Voice {
 
         dsp_i
     }
+
+    /// Windowed-sinc interpolation over `taps` points, the kernel being widened by the
+    /// phase increment when above 1 to band-limit the pitched up sample.
+    /// Returns number of samples processed (usually the block size but could be
+    /// smaller if end of sample occurs).
+    pub fn dsp_float_interpolate_sinc(
+        &mut self,
+        dsp_buf: &mut [f32],
+        dsp_amp_incr: f32,
+        phase_incr: f32,
+        taps: usize,
+    ) -> usize {
+        let block = dsp_buf.len();
+        let mut dsp_phase: Phase = self.phase;
+        let dsp_data: &[i16] = &self.sample.data;
+        let mut dsp_amp: f32 = self.amp;
+
+        /* Convert playback "speed" floating point value to phase index/fract */
+        let dsp_phase_incr = phase_set_float(phase_incr);
+
+        /* voice is currently looping? */
+        let looping = self.gen[GEN_SAMPLEMODE as usize].val as i32
+            == FLUID_LOOP_DURING_RELEASE as i32
+            || self.gen[GEN_SAMPLEMODE as usize].val as i32 == FLUID_LOOP_UNTIL_RELEASE as i32
+                && self.volenv_section < FLUID_VOICE_ENVRELEASE as i32;
+
+        let end_index = if looping { self.loopend - 1 } else { self.end } as usize;
+
+        /* zero crossings on each side of the kernel, and its widening */
+        let stretch = phase_incr.clamp(1.0, (SINC_MAX_POINTS / SINC_MIN_TAPS) as f32);
+        let half = taps
+            .clamp(SINC_MIN_TAPS, SINC_MAX_TAPS)
+            .div_ceil(2)
+            .min((SINC_MAX_POINTS as f32 / (2.0 * stretch)) as usize)
+            .max(SINC_MIN_TAPS / 2);
+        let reach = (half as f32 * stretch).ceil() as isize;
+        let sinc_scale = SINC_RES as f32 / stretch;
+        let window_scale = SINC_WINDOW_RES as f32 / (half as f32 * stretch);
+
+        let (start, end) = (self.start as isize, self.end as isize);
+        let (loopstart, loopend) = (self.loopstart as isize, self.loopend as isize);
+        let loop_len = (loopend - loopstart).max(1);
+
+        let mut dsp_i: usize = 0;
+        loop {
+            let mut dsp_phase_index = (dsp_phase >> 32) as usize;
+
+            /* interpolate the sequence of sample points */
+            while dsp_i < block && dsp_phase_index <= end_index {
+                let index = dsp_phase_index as isize;
+                let fract = phase_fract(dsp_phase as usize) as f32 / 4294967296.0;
+
+                let mut sum = 0.0;
+                let mut norm = 0.0;
+                for point in index - reach + 1..=index + reach {
+                    let distance = ((point - index) as f32 - fract).abs();
+                    let window_pos = distance * window_scale;
+                    if window_pos >= SINC_WINDOW_RES as f32 {
+                        continue;
+                    }
+                    let weight = table_lookup(&DSP_FLOAT_GLOBAL.sinc, distance * sinc_scale)
+                        * table_lookup(&DSP_FLOAT_GLOBAL.sinc_window, window_pos);
+
+                    /* points past the loop end come from its start once looping,
+                     * and the other way around, else the sample ends are held */
+                    let mut data_index = point;
+                    if looping && data_index >= loopend {
+                        data_index = loopstart + (data_index - loopstart) % loop_len;
+                    } else if self.has_looped && data_index < loopstart {
+                        data_index = loopstart + (data_index - loopstart).rem_euclid(loop_len);
+                    }
+                    let data_index = data_index.clamp(start, end) as usize;
+
+                    sum += weight * dsp_data[data_index] as f32;
+                    norm += weight;
+                }
+                dsp_buf[dsp_i] = dsp_amp * sum / norm;
+
+                /* increment phase and amplitude */
+                dsp_phase = dsp_phase.wrapping_add(dsp_phase_incr);
+                dsp_phase_index = (dsp_phase >> 32) as usize;
+                dsp_amp += dsp_amp_incr;
+                dsp_i += 1;
+            }
+
+            /* break out if not looping (end of sample) */
+            if !looping {
+                break;
+            }
+
+            /* go back to loop start */
+            if dsp_phase_index > end_index {
+                dsp_phase = dsp_phase.wrapping_sub(((self.loopend - self.loopstart) as u64) << 32);
+                self.has_looped = true;
+            }
+
+            /* break out if filled buffer */
+            if dsp_i >= block {
+                break;
+            }
+        }
+        self.phase = dsp_phase;
+        self.amp = dsp_amp;
+
+        dsp_i
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::core::synth::internal::set_gen;
+    use crate::core::synth::soundfont::generator::GeneratorType;
+    use crate::core::synth::test::render_note;
+    use crate::core::synth::InterpolationMethod;
+
+    #[test]
+    fn windowed_sinc() {
+        let energy = |samples: [f32; 4096]| samples[2048..].iter().map(|v| v * v).sum::<f32>();
+        let render = |interp_method: InterpolationMethod, coarse_tune: f32| {
+            energy(render_note(&|synth| {
+                synth.set_interp_method(Some(0), interp_method);
+                let channel = synth.channels.get_mut(0).unwrap();
+                set_gen(
+                    channel,
+                    &mut synth.voices,
+                    GeneratorType::CoarseTune,
+                    coarse_tune,
+                );
+            }))
+        };
+        let sinc = InterpolationMethod::WindowedSinc { taps: 16 };
+
+        let reference = render(InterpolationMethod::FourthOrder, 0.0);
+        let out = render(sinc, 0.0);
+        assert!((out / reference - 1.0).abs() < 0.01);
+
+        // 71 semitones up the sine is past the Nyquist frequency, and would fold back
+        let aliased = render(InterpolationMethod::SeventhOrder, 71.0);
+        assert!(aliased > reference * 0.1);
+        assert!(render(sinc, 71.0) < aliased * 0.01);
+    }
 }
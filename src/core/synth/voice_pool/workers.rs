@@ -0,0 +1,369 @@
+use std::thread;
+
+use super::super::{FxBuf, FxSend};
+use super::{write_voices, RenderContext, Voice};
+use crate::core::effects::ChannelStrip;
+use crate::core::OxiError;
+
+/**
+Threads rendering the voices.
+
+The voices are split into one group per thread, with the same number of playing voices
+in each group. Every group renders into its own buffers, which are summed in the group order:
+the output only depends on the number of threads, not on their scheduling.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RenderThreads {
+    /// All the voices are rendered by the calling thread
+    #[default]
+    Single,
+    /// Threads spawned for each block, the calling thread renders the first group.
+    /// There is no setup, but spawning threads is not real-time safe.
+    Scoped(usize),
+    /// Threads spawned once, when selected. The buffers of the groups are allocated then,
+    /// and when reverb or chorus instances are added or the meters enabled: rendering
+    /// neither spawns threads nor allocates memory.
+    Pool(usize),
+}
+
+impl RenderThreads {
+    fn threads(&self) -> usize {
+        match *self {
+            RenderThreads::Single => 1,
+            RenderThreads::Scoped(threads) | RenderThreads::Pool(threads) => threads.max(1),
+        }
+    }
+}
+
+/// Buffers of a group of voices, laid out like the ones of the synth
+struct Group {
+    left: Vec<Vec<f32>>,
+    right: Vec<Vec<f32>>,
+    fx: FxBuf,
+    strips: Vec<ChannelStrip>,
+}
+
+impl Group {
+    fn new(left: &[Vec<f32>], fx: &FxBuf, strips: &[ChannelStrip]) -> Self {
+        Self {
+            left: left.to_vec(),
+            right: left.to_vec(),
+            fx: fx.clone(),
+            strips: strips.to_vec(),
+        }
+    }
+
+    /// Add or remove effect sends and meter buffers to match the ones of the synth
+    fn set_fx_layout(&mut self, fx: &FxBuf) {
+        let block_size = self.left[0].len();
+        if self.fx.reverb.len() != fx.reverb.len() {
+            self.fx
+                .reverb
+                .resize_with(fx.reverb.len(), || FxSend::new(block_size));
+        }
+        if self.fx.chorus.len() != fx.chorus.len() {
+            self.fx
+                .chorus
+                .resize_with(fx.chorus.len(), || FxSend::new(block_size));
+        }
+        if self.fx.meters.len() != fx.meters.len() {
+            self.fx
+                .meters
+                .resize_with(fx.meters.len(), || vec![0.0; block_size]);
+        }
+    }
+
+    /// Take the active units of the synth buffers, and clear the buffers
+    fn prepare(&mut self, fx: &FxBuf, strips: &[ChannelStrip]) {
+        self.left
+            .iter_mut()
+            .chain(self.right.iter_mut())
+            .for_each(|buf| buf.fill(0.0));
+
+        self.set_fx_layout(fx);
+        let sends = self.fx.reverb.iter_mut().chain(self.fx.chorus.iter_mut());
+        for (send, main) in sends.zip(fx.reverb.iter().chain(fx.chorus.iter())) {
+            send.active = main.active;
+            send.buf.fill(0.0);
+        }
+
+        self.fx.meters.iter_mut().for_each(|buf| buf.fill(0.0));
+
+        for (strip, main) in self.strips.iter_mut().zip(strips.iter()) {
+            strip.active = main.active;
+            strip.left.fill(0.0);
+            strip.right.fill(0.0);
+        }
+    }
+
+    fn write_voices(&mut self, voices: &mut [Voice], ctx: &RenderContext) {
+        write_voices(
+            voices,
+            ctx,
+            &mut self.left,
+            &mut self.right,
+            &mut self.fx,
+            &mut self.strips,
+        );
+    }
+
+    fn add_to(
+        &self,
+        left: &mut [Vec<f32>],
+        right: &mut [Vec<f32>],
+        fx: &mut FxBuf,
+        strips: &mut [ChannelStrip],
+    ) {
+        fn add(out: &mut [f32], buf: &[f32]) {
+            for (out, v) in out.iter_mut().zip(buf.iter()) {
+                *out += v;
+            }
+        }
+
+        for (out, buf) in left.iter_mut().zip(self.left.iter()) {
+            add(out, buf);
+        }
+        for (out, buf) in right.iter_mut().zip(self.right.iter()) {
+            add(out, buf);
+        }
+
+        let sends = fx.reverb.iter_mut().chain(fx.chorus.iter_mut());
+        let group_sends = self.fx.reverb.iter().chain(self.fx.chorus.iter());
+        for (out, send) in sends.zip(group_sends).filter(|(out, _)| out.active) {
+            add(&mut out.buf, &send.buf);
+        }
+        for (out, buf) in fx.meters.iter_mut().zip(self.fx.meters.iter()) {
+            add(out, buf);
+        }
+
+        for (out, strip) in strips.iter_mut().zip(self.strips.iter()) {
+            if out.active {
+                add(&mut out.left, &strip.left);
+                add(&mut out.right, &strip.right);
+            }
+        }
+    }
+}
+
+pub(super) struct Workers {
+    threads: RenderThreads,
+    pool: Option<rayon::ThreadPool>,
+    /// Buffers of the groups after the first one, which renders into the synth buffers
+    groups: Vec<Group>,
+    /// End of the voices of each group
+    bounds: Vec<usize>,
+}
+
+impl Workers {
+    /// Start the threads, `None` when the voices are rendered by the calling thread.
+    /// The buffers of the groups are laid out like `left`, `fx` and `strips`.
+    pub fn new(
+        threads: RenderThreads,
+        left: &[Vec<f32>],
+        fx: &FxBuf,
+        strips: &[ChannelStrip],
+    ) -> Result<Option<Self>, OxiError> {
+        if threads.threads() == 1 {
+            return Ok(None);
+        }
+
+        let pool = match threads {
+            RenderThreads::Pool(count) => Some(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(count)
+                    .thread_name(|id| format!("oxisynth-voices-{}", id))
+                    .build()
+                    .map_err(|err| OxiError::RenderThreads(err.to_string()))?,
+            ),
+            _ => None,
+        };
+
+        Ok(Some(Self {
+            threads,
+            pool,
+            groups: (1..threads.threads())
+                .map(|_| Group::new(left, fx, strips))
+                .collect(),
+            bounds: Vec::with_capacity(threads.threads()),
+        }))
+    }
+
+    pub fn threads(&self) -> RenderThreads {
+        self.threads
+    }
+
+    /// Add or remove effect sends and meter buffers to match the ones of the synth
+    pub fn set_fx_layout(&mut self, fx: &FxBuf) {
+        for group in self.groups.iter_mut() {
+            group.set_fx_layout(fx);
+        }
+    }
+
+    /// Split the voices into groups with the same number of playing voices
+    fn split(&mut self, voices: &[Voice]) {
+        let threads = self.threads.threads();
+        let playing = voices.iter().filter(|v| v.is_playing()).count();
+        let per_group = playing.div_ceil(threads).max(1);
+
+        self.bounds.clear();
+        let mut count = 0;
+        for (id, voice) in voices.iter().enumerate() {
+            if self.bounds.len() + 1 == threads {
+                break;
+            }
+            if voice.is_playing() {
+                count += 1;
+                if count == per_group {
+                    self.bounds.push(id + 1);
+                    count = 0;
+                }
+            }
+        }
+        while self.bounds.len() < threads {
+            self.bounds.push(voices.len());
+        }
+    }
+
+    pub fn write_voices(
+        &mut self,
+        voices: &mut [Voice],
+        ctx: &RenderContext,
+        dsp_left_buf: &mut [Vec<f32>],
+        dsp_right_buf: &mut [Vec<f32>],
+        fx_left_buf: &mut FxBuf,
+        channel_strips: &mut [ChannelStrip],
+    ) {
+        self.split(voices);
+
+        for group in self.groups.iter_mut() {
+            group.prepare(fx_left_buf, channel_strips);
+        }
+
+        let (first, rest) = voices.split_at_mut(self.bounds[0]);
+        let bounds = &self.bounds;
+        let groups = &mut self.groups;
+        let mut write_first = || {
+            write_voices(
+                first,
+                ctx,
+                dsp_left_buf,
+                dsp_right_buf,
+                fx_left_buf,
+                channel_strips,
+            )
+        };
+
+        match self.pool.as_ref() {
+            Some(pool) => pool.install(|| {
+                rayon::join(write_first, || {
+                    write_groups(rest, &bounds[1..], bounds[0], groups, ctx)
+                });
+            }),
+            None => thread::scope(|scope| {
+                let mut rest = rest;
+                let mut offset = bounds[0];
+                for (group, &end) in groups.iter_mut().zip(bounds[1..].iter()) {
+                    let (voices, tail) = std::mem::take(&mut rest).split_at_mut(end - offset);
+                    rest = tail;
+                    offset = end;
+                    scope.spawn(move || group.write_voices(voices, ctx));
+                }
+                write_first();
+            }),
+        }
+
+        for group in self.groups.iter() {
+            group.add_to(dsp_left_buf, dsp_right_buf, fx_left_buf, channel_strips);
+        }
+    }
+}
+
+/// Render each group in the pool, `bounds` holding the end of their voices
+fn write_groups(
+    voices: &mut [Voice],
+    bounds: &[usize],
+    offset: usize,
+    groups: &mut [Group],
+    ctx: &RenderContext,
+) {
+    match groups.len() {
+        0 => {}
+        1 => groups[0].write_voices(voices, ctx),
+        len => {
+            let mid = len / 2;
+            let (left_groups, right_groups) = groups.split_at_mut(mid);
+            let (left_voices, right_voices) = voices.split_at_mut(bounds[mid - 1] - offset);
+            rayon::join(
+                || write_groups(left_voices, &bounds[..mid], offset, left_groups, ctx),
+                || {
+                    write_groups(
+                        right_voices,
+                        &bounds[mid..],
+                        bounds[mid - 1],
+                        right_groups,
+                        ctx,
+                    )
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RenderThreads;
+    use crate::core::reverb::ReverbType;
+    use crate::core::synth::test::sin_font;
+    use crate::core::{MidiEvent, Synth};
+
+    /// Length and capacity of every buffer of the groups
+    fn group_sizes(synth: &Synth) -> Vec<(usize, usize)> {
+        let mut sizes = Vec::new();
+        for group in synth.voices.workers.as_ref().unwrap().groups.iter() {
+            let bufs = group
+                .left
+                .iter()
+                .chain(group.right.iter())
+                .chain(group.fx.reverb.iter().map(|send| &send.buf))
+                .chain(group.fx.chorus.iter().map(|send| &send.buf))
+                .chain(group.fx.meters.iter())
+                .chain(group.strips.iter().map(|strip| &strip.left))
+                .chain(group.strips.iter().map(|strip| &strip.right));
+            sizes.push((group.fx.reverb.len(), group.fx.chorus.len()));
+            sizes.push((group.fx.meters.len(), group.strips.len()));
+            sizes.extend(bufs.map(|buf| (buf.len(), buf.capacity())));
+        }
+        sizes
+    }
+
+    #[test]
+    fn preallocated_groups() {
+        let mut synth = Synth::default();
+        synth.add_font(sin_font(), true);
+        synth.set_render_threads(RenderThreads::Pool(3)).unwrap();
+        synth.add_reverb(ReverbType::Plate);
+        synth.add_chorus();
+        synth.set_channel_meters(true);
+
+        let sizes = group_sizes(&synth);
+        assert_eq!(sizes[0], (2, 2));
+        assert_eq!(sizes[1], (16, 16));
+        assert_eq!(sizes.iter().filter(|&&size| size == (2, 2)).count(), 2);
+
+        for key in 60..72 {
+            synth
+                .send_event(MidiEvent::NoteOn {
+                    channel: 0,
+                    key,
+                    vel: 100,
+                })
+                .unwrap();
+        }
+        let mut samples = vec![0f32; 4096];
+        synth.write_interleaved(samples.as_mut_slice());
+        assert!(samples.iter().any(|v| *v != 0.0));
+
+        // The buffers were sized before rendering
+        assert_eq!(group_sizes(&synth), sizes);
+    }
+}
@@ -0,0 +1,263 @@
+/*!
+Universal MIDI Packet decoder
+
+Decodes the MIDI 1.0 channel voice messages (message type 0x2), the MIDI 2.0
+channel voice messages (message type 0x4) and the system reset message
+(message type 0x1). All other packets are skipped.
+ */
+
+use super::midi2_event::{Midi2Event, NoteAttribute};
+use super::midi_event::MidiEvent;
+
+/// A message decoded from a Universal MIDI Packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UmpMessage {
+    Midi1(MidiEvent),
+    Midi2(Midi2Event),
+}
+
+/**
+Number of 32 bit words of a packet, given its first word.
+ */
+pub fn packet_len(word0: u32) -> usize {
+    match word0 >> 28 {
+        0x0 | 0x1 | 0x2 | 0x6 | 0x7 => 1,
+        0x3 | 0x4 | 0x8 | 0x9 | 0xA => 2,
+        0xB | 0xC => 3,
+        _ => 4,
+    }
+}
+
+/**
+Decode a single packet.
+
+Returns `None` for unsupported or truncated packets.
+ */
+pub fn decode(words: &[u32]) -> Option<UmpMessage> {
+    let word0 = *words.first()?;
+    if words.len() < packet_len(word0) {
+        return None;
+    }
+
+    let ty = (word0 >> 28) as u8;
+    let group = ((word0 >> 24) & 0xF) as u8;
+    let status = ((word0 >> 20) & 0xF) as u8;
+    let channel = group * 16 + ((word0 >> 16) & 0xF) as u8;
+    let byte3 = ((word0 >> 8) & 0xFF) as u8;
+    let byte4 = (word0 & 0xFF) as u8;
+
+    match ty {
+        // System Real Time and System Common Messages
+        0x1 => match (word0 >> 16) & 0xFF {
+            0xFF => Some(UmpMessage::Midi1(MidiEvent::SystemReset)),
+            _ => None,
+        },
+        // MIDI 1.0 Channel Voice Messages
+        0x2 => {
+            let (byte3, byte4) = (byte3 & 0x7F, byte4 & 0x7F);
+            let event = match status {
+                0x8 => MidiEvent::NoteOff {
+                    channel,
+                    key: byte3,
+                },
+                0x9 => MidiEvent::NoteOn {
+                    channel,
+                    key: byte3,
+                    vel: byte4,
+                },
+                0xA => MidiEvent::PolyphonicKeyPressure {
+                    channel,
+                    key: byte3,
+                    value: byte4,
+                },
+                0xB => MidiEvent::ControlChange {
+                    channel,
+                    ctrl: byte3,
+                    value: byte4,
+                },
+                0xC => MidiEvent::ProgramChange {
+                    channel,
+                    program_id: byte3,
+                },
+                0xD => MidiEvent::ChannelPressure {
+                    channel,
+                    value: byte3,
+                },
+                0xE => MidiEvent::PitchBend {
+                    channel,
+                    value: byte3 as u16 | (byte4 as u16) << 7,
+                },
+                _ => return None,
+            };
+            Some(UmpMessage::Midi1(event))
+        }
+        // MIDI 2.0 Channel Voice Messages
+        0x4 => {
+            let data = words[1];
+            let (byte3, byte4) = (byte3 & 0x7F, byte4);
+            let event = match status {
+                0x0 => Midi2Event::RegisteredPerNoteController {
+                    channel,
+                    key: byte3,
+                    index: byte4,
+                    value: data,
+                },
+                0x1 => Midi2Event::AssignablePerNoteController {
+                    channel,
+                    key: byte3,
+                    index: byte4,
+                    value: data,
+                },
+                0x2 => Midi2Event::RegisteredController {
+                    channel,
+                    bank: byte3,
+                    index: byte4 & 0x7F,
+                    value: data,
+                },
+                0x3 => Midi2Event::AssignableController {
+                    channel,
+                    bank: byte3,
+                    index: byte4 & 0x7F,
+                    value: data,
+                },
+                0x6 => Midi2Event::PerNotePitchBend {
+                    channel,
+                    key: byte3,
+                    value: data,
+                },
+                0x8 => Midi2Event::NoteOff {
+                    channel,
+                    key: byte3,
+                    vel: (data >> 16) as u16,
+                    attribute: NoteAttribute::new(byte4, data as u16),
+                },
+                0x9 => Midi2Event::NoteOn {
+                    channel,
+                    key: byte3,
+                    vel: (data >> 16) as u16,
+                    attribute: NoteAttribute::new(byte4, data as u16),
+                },
+                0xA => Midi2Event::PolyphonicKeyPressure {
+                    channel,
+                    key: byte3,
+                    value: data,
+                },
+                0xB => Midi2Event::ControlChange {
+                    channel,
+                    ctrl: byte3,
+                    value: data,
+                },
+                0xC => Midi2Event::ProgramChange {
+                    channel,
+                    program_id: ((data >> 24) & 0x7F) as u8,
+                    bank: if byte4 & 0x1 != 0 {
+                        Some((((data >> 8) & 0x7F) << 7 | (data & 0x7F)) as u16)
+                    } else {
+                        None
+                    },
+                },
+                0xD => Midi2Event::ChannelPressure {
+                    channel,
+                    value: data,
+                },
+                0xE => Midi2Event::PitchBend {
+                    channel,
+                    value: data,
+                },
+                0xF => Midi2Event::PerNoteManagement {
+                    channel,
+                    key: byte3,
+                    detach: byte4 & 0x2 != 0,
+                    reset: byte4 & 0x1 != 0,
+                },
+                // Relative Registered / Assignable Controllers are not supported
+                _ => return None,
+            };
+            Some(UmpMessage::Midi2(event))
+        }
+        _ => None,
+    }
+}
+
+/**
+Iterator over the messages of a stream of Universal MIDI Packets.
+ */
+pub struct UmpDecoder<'a> {
+    words: &'a [u32],
+}
+
+impl<'a> UmpDecoder<'a> {
+    pub fn new(words: &'a [u32]) -> Self {
+        Self { words }
+    }
+}
+
+impl<'a> Iterator for UmpDecoder<'a> {
+    type Item = UmpMessage;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(word0) = self.words.first() {
+            let len = packet_len(*word0).min(self.words.len());
+            let (packet, rest) = self.words.split_at(len);
+            self.words = rest;
+
+            if let Some(msg) = decode(packet) {
+                return Some(msg);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_packets() {
+        let words = [
+            // MIDI 1.0 note on, group 1, channel 2
+            0x2192_3C64,
+            // Utility NOOP
+            0x0000_0000,
+            // MIDI 2.0 note on with Pitch 7.9 attribute
+            0x4090_3C03,
+            0xFFFF_7880,
+            // MIDI 2.0 program change with bank select
+            0x40C0_0001,
+            0x0500_0102,
+            // Per-note management, detach + reset
+            0x40F0_3C03,
+            0x0000_0000,
+        ];
+
+        let msgs: Vec<_> = UmpDecoder::new(&words).collect();
+        assert_eq!(
+            msgs,
+            vec![
+                UmpMessage::Midi1(MidiEvent::NoteOn {
+                    channel: 18,
+                    key: 60,
+                    vel: 100
+                }),
+                UmpMessage::Midi2(Midi2Event::NoteOn {
+                    channel: 0,
+                    key: 60,
+                    vel: 0xFFFF,
+                    attribute: NoteAttribute::Pitch7_9(0x7880)
+                }),
+                UmpMessage::Midi2(Midi2Event::ProgramChange {
+                    channel: 0,
+                    program_id: 5,
+                    bank: Some(130)
+                }),
+                UmpMessage::Midi2(Midi2Event::PerNoteManagement {
+                    channel: 0,
+                    key: 60,
+                    detach: true,
+                    reset: true
+                }),
+            ]
+        );
+    }
+}
@@ -2,6 +2,8 @@ use generational_arena::{Arena, Index};
 
 use std::{cmp::PartialEq, fmt, marker::PhantomData};
 
+use super::snapshot::snapshot;
+
 pub struct TypedIndex<T>(Index, PhantomData<T>);
 
 impl<T> From<Index> for TypedIndex<T> {
@@ -85,3 +87,138 @@ impl RangeCheck {
         }
     }
 }
+
+/// Default ramp time of the built-in effect parameters, in ms
+pub(crate) const EFFECT_RAMP_MS: f32 = 20.0;
+
+/**
+Parameter value ramping linearly to its target, to avoid zipper noise
+ */
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Ramp {
+    value: f32,
+    target: f32,
+    incr: f32,
+    /// Samples left until the target is reached
+    remaining: u32,
+}
+
+snapshot!(Ramp {
+    value,
+    target,
+    incr,
+    remaining
+});
+
+impl Ramp {
+    pub fn new(value: f32) -> Self {
+        Self {
+            value,
+            target: value,
+            incr: 0.0,
+            remaining: 0,
+        }
+    }
+
+    /// Length of a ramp of `ms` milliseconds, in samples
+    pub fn samples(ms: f32, sample_rate: f32) -> u32 {
+        (ms.max(0.0) / 1000.0 * sample_rate) as u32
+    }
+
+    /**
+    Ramp to `target` over `len` samples, or jump to it when `len` is 0.
+    Setting the current target again leaves a running ramp alone.
+     */
+    pub fn set(&mut self, target: f32, len: u32) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        if len == 0 {
+            self.finish();
+        } else {
+            self.incr = (target - self.value) / len as f32;
+            self.remaining = len;
+        }
+    }
+
+    /// Jump to the target
+    pub fn finish(&mut self) {
+        self.value = self.target;
+        self.remaining = 0;
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn is_steady(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Advance by one sample, returning the new value
+    #[inline]
+    pub fn step(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.value = self.target;
+            } else {
+                self.value += self.incr;
+            }
+        }
+        self.value
+    }
+
+    /// Advance by `count` samples
+    pub fn skip(&mut self, count: u32) {
+        if count >= self.remaining {
+            self.finish();
+        } else {
+            self.value += self.incr * count as f32;
+            self.remaining -= count;
+        }
+    }
+
+    /**
+    Add `input` scaled by the ramped gain to `out`.
+     */
+    pub fn mix(&mut self, out: &mut [f32], input: &[f32]) {
+        if self.is_steady() {
+            let gain = self.value;
+            if gain != 0.0 {
+                super::simd::mix(out, input, gain);
+            }
+        } else {
+            for (out, v) in out.iter_mut().zip(input.iter()) {
+                *out += self.step() * v;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ramp() {
+        let mut ramp = Ramp::new(0.0);
+        ramp.set(1.0, 4);
+        let values: Vec<f32> = (0..6).map(|_| ramp.step()).collect();
+        assert_eq!(values, [0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+        assert!(ramp.is_steady());
+
+        ramp.set(0.0, 4);
+        ramp.skip(2);
+        assert_eq!(ramp.value(), 0.5);
+        ramp.set(0.0, 100);
+        ramp.skip(2);
+        assert_eq!(ramp.value(), 0.0);
+
+        let mut out = [1.0; 4];
+        ramp.set(2.0, 0);
+        ramp.mix(&mut out, &[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(out, [3.0, 5.0, 7.0, 9.0]);
+    }
+}
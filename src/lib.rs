@@ -2,7 +2,7 @@ mod core;
 mod synth;
 
 pub use crate::core::soundfont::{Preset, SoundFont};
-pub use crate::core::{MidiEvent, OxiError};
+pub use crate::core::{Midi2Event, MidiEvent, NoteAttribute, OxiError};
 
 pub use crate::core::TypedIndex;
 pub type SoundFontId = TypedIndex<SoundFont>;
@@ -14,5 +14,37 @@ pub mod settings {
     pub use crate::core::{Settings, SettingsError, SynthDescriptor};
 }
 
+pub mod reverb {
+    pub use crate::core::reverb::{
+        Freeverb, PlateParams, PlateReverb, Reverb, ReverbEngine, ReverbParams, ReverbType,
+    };
+
+    #[cfg(feature = "convolution")]
+    pub use crate::core::reverb::{ConvolutionReverb, ImpulseResponse, Partitioning};
+}
+
+pub mod chorus {
+    pub use crate::core::chorus::{Chorus, ChorusMode, ChorusParams, ChorusType};
+}
+
+pub mod delay {
+    pub use crate::core::delay::{Delay, DelayParams, DelayTime};
+}
+
+pub mod effects {
+    pub use crate::core::effects::{
+        Compressor, CompressorParams, Effect, EffectChain, Effects, EqBand, EqBandType, Equalizer,
+        Limiter, LimiterParams, SendBus,
+    };
+}
+
+pub mod snapshot {
+    pub use crate::core::snapshot::{StateReader, StateWriter};
+}
+
+pub mod ump {
+    pub use crate::core::ump::{decode, packet_len, UmpDecoder, UmpMessage};
+}
+
 #[macro_use]
 extern crate lazy_static;
@@ -1,15 +1,29 @@
 mod font;
 mod midi;
+mod monitor;
 mod params;
+mod state;
 mod write;
 
 use crate::core::chorus::Chorus;
+use crate::core::delay::{Delay, DelayParams};
+use crate::core::effects::{
+    Compressor, CompressorParams, Effect, Effects, EqBand, Equalizer, Limiter, LimiterParams,
+};
 use crate::core::font_bank::FontBank;
-use crate::core::reverb::Reverb;
+use crate::core::midi2_event::from_u7;
+use crate::core::reverb::{Reverb, ReverbType};
 pub use crate::core::soundfont::generator::GeneratorType;
 pub use crate::core::tuning::{Tuning, TuningManager};
+use crate::core::utils::RangeCheck;
 use crate::core::OxiError;
-use crate::{MidiEvent, SettingsError, SynthDescriptor};
+#[cfg(feature = "parallel")]
+pub use crate::core::RenderThreads;
+pub use crate::core::{
+    ChannelLevel, DspState, EnvelopeStage, FilterType, InterpolationMethod, MpeZone, MpeZoneLayout,
+    StealPolicy, StolenVoice, SynthEvent, SynthState, VoiceInfo,
+};
+use crate::{Midi2Event, MidiEvent, SettingsError, SynthDescriptor};
 
 /**
 The synth object
@@ -52,15 +66,41 @@ impl Synth {
 
     /**
     Set synth sample rate
+
+    The rate has the range of `SynthDescriptor::sample_rate`, nothing changes if it is out of range.
      */
-    pub fn set_sample_rate(&mut self, sample_rate: f32) {
-        self.core.set_sample_rate(sample_rate);
+    pub fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), SettingsError> {
+        self.core.set_sample_rate(sample_rate)
+    }
+
+    /**
+    Render at a fixed internal sample rate (`Some(rate)`) and resample the
+    output to the synth sample rate, or render at the synth sample rate (`None`).
+
+    Useful for hosts running at unusual sample rates. The rate has the range of
+    [`SynthDescriptor::sample_rate`], an error is returned if it is out of range.
+     */
+    pub fn set_internal_sample_rate(
+        &mut self,
+        sample_rate: Option<f32>,
+    ) -> Result<(), SettingsError> {
+        self.core.set_internal_sample_rate(sample_rate)
     }
 
     pub fn send_event(&mut self, event: MidiEvent) -> Result<(), OxiError> {
         self.core.send_event(event)
     }
 
+    /// Send a MIDI 2.0 Protocol event, with high resolution values and per-note controllers
+    pub fn send_midi2_event(&mut self, event: Midi2Event) -> Result<(), OxiError> {
+        self.core.send_midi2_event(event)
+    }
+
+    /// Send a stream of Universal MIDI Packets (32 bit words), unsupported packets are skipped
+    pub fn send_ump(&mut self, words: &[u32]) -> Result<(), OxiError> {
+        self.core.send_ump(words)
+    }
+
     pub fn font_bank(&self) -> &FontBank {
         &self.core.font_bank
     }
@@ -72,23 +112,166 @@ impl Synth {
 
 // Rverb
 impl Synth {
+    /// The main reverb
     pub fn get_reverb(&self) -> &Reverb {
-        &self.core.reverb
+        &self.core.reverbs[0]
     }
 
+    /// The main reverb
     pub fn get_reverb_mut(&mut self) -> &mut Reverb {
-        &mut self.core.reverb
+        &mut self.core.reverbs[0]
+    }
+
+    /**
+    Add a reverb instance, e.g. a dry room for the drums next to a hall for the strings.
+    Returns the id of the instance.
+     */
+    pub fn add_reverb(&mut self, ty: ReverbType) -> usize {
+        self.core.add_reverb(ty)
+    }
+
+    /// Reverb instance `id`, 0 being the main reverb
+    pub fn reverb_instance(&self, id: usize) -> Option<&Reverb> {
+        self.core.reverbs.get(id)
+    }
+
+    /// Reverb instance `id`, 0 being the main reverb
+    pub fn reverb_instance_mut(&mut self, id: usize) -> Option<&mut Reverb> {
+        self.core.reverbs.get_mut(id)
+    }
+
+    pub fn count_reverbs(&self) -> usize {
+        self.core.reverbs.len()
+    }
+
+    /// Route the reverb send of a channel to the reverb instance `id`
+    pub fn set_channel_reverb(&mut self, chan: u8, id: usize) -> Result<(), OxiError> {
+        self.core.set_channel_reverb(chan as usize, id)
+    }
+
+    /// Reverb instance the channel is routed to
+    pub fn channel_reverb(&self, chan: u8) -> Result<usize, OxiError> {
+        Ok(self.core.channels.get(chan as usize)?.reverb_id())
     }
 }
 
 // Chorus
 impl Synth {
+    /// The main chorus
     pub fn chorus(&self) -> &Chorus {
-        &self.core.chorus
+        &self.core.choruses[0]
     }
 
+    /// The main chorus
     pub fn chorus_mut(&mut self) -> &mut Chorus {
-        &mut self.core.chorus
+        &mut self.core.choruses[0]
+    }
+
+    /// Add a chorus instance, returning its id
+    pub fn add_chorus(&mut self) -> usize {
+        self.core.add_chorus()
+    }
+
+    /// Chorus instance `id`, 0 being the main chorus
+    pub fn chorus_instance(&self, id: usize) -> Option<&Chorus> {
+        self.core.choruses.get(id)
+    }
+
+    /// Chorus instance `id`, 0 being the main chorus
+    pub fn chorus_instance_mut(&mut self, id: usize) -> Option<&mut Chorus> {
+        self.core.choruses.get_mut(id)
+    }
+
+    pub fn count_choruses(&self) -> usize {
+        self.core.choruses.len()
+    }
+
+    /// Route the chorus send of a channel to the chorus instance `id`
+    pub fn set_channel_chorus(&mut self, chan: u8, id: usize) -> Result<(), OxiError> {
+        self.core.set_channel_chorus(chan as usize, id)
+    }
+
+    /// Chorus instance the channel is routed to
+    pub fn channel_chorus(&self, chan: u8) -> Result<usize, OxiError> {
+        Ok(self.core.channels.get(chan as usize)?.chorus_id())
+    }
+}
+
+// Delay
+impl Synth {
+    /**
+    Add a send bus with a `Delay`, fed by the channels at the level of the `ctrl`
+    controller (CC94 for the XG variation send). Returns the bus id.
+     */
+    pub fn add_delay(&mut self, ctrl: u8) -> usize {
+        self.core.effects.add_delay_bus(ctrl)
+    }
+
+    /// Set the parameters of the `Delay` at position `id` of the send bus `bus`
+    pub fn set_send_delay(
+        &mut self,
+        bus: usize,
+        id: usize,
+        params: &DelayParams,
+    ) -> Result<(), OxiError> {
+        self.core
+            .effects
+            .send_bus_mut(bus)
+            .and_then(|bus| bus.chain_mut().get_mut::<Delay>(id))
+            .ok_or(OxiError::EffectNotFound)?
+            .set_delay(params);
+        Ok(())
+    }
+
+    /// Set the tempo the `DelayTime::Beats` delay times are synced to, in BPM
+    pub fn set_delay_tempo(&mut self, bpm: f32) {
+        self.core.effects.set_delay_tempo(bpm);
+    }
+}
+
+// Effects
+impl Synth {
+    /// Channel insert, send bus and master bus effects
+    pub fn effects(&self) -> &Effects {
+        &self.core.effects
+    }
+
+    pub fn effects_mut(&mut self) -> &mut Effects {
+        &mut self.core.effects
+    }
+
+    /// Set the parameters of the `Limiter` at position `id` of the master bus
+    pub fn set_master_limiter(
+        &mut self,
+        id: usize,
+        params: &LimiterParams,
+    ) -> Result<(), OxiError> {
+        self.master_effect::<Limiter>(id)?.set_limiter(params);
+        Ok(())
+    }
+
+    /// Set the parameters of the `Compressor` at position `id` of the master bus
+    pub fn set_master_compressor(
+        &mut self,
+        id: usize,
+        params: &CompressorParams,
+    ) -> Result<(), OxiError> {
+        self.master_effect::<Compressor>(id)?.set_compressor(params);
+        Ok(())
+    }
+
+    /// Set the bands of the `Equalizer` at position `id` of the master bus
+    pub fn set_master_eq_bands(&mut self, id: usize, bands: &[EqBand]) -> Result<(), OxiError> {
+        self.master_effect::<Equalizer>(id)?.set_bands(bands);
+        Ok(())
+    }
+
+    fn master_effect<T: Effect + 'static>(&mut self, id: usize) -> Result<&mut T, OxiError> {
+        self.core
+            .effects
+            .master_mut()
+            .get_mut(id)
+            .ok_or(OxiError::EffectNotFound)
     }
 }
 
@@ -136,6 +319,64 @@ impl Synth {
         let channel = self.core.channels.get(chan as usize)?;
         Ok(crate::core::synth::internal::gen(channel, param))
     }
+
+    /**
+    Change the value of a generator for a single key of a channel, e.g. to
+    adjust the pan, tuning or level of a drum kit instrument. The value is
+    added on top of the channel-wide value set by `set_gen()`, and applies
+    to the sounding and future voices of that key.
+     */
+    pub fn set_key_gen(
+        &mut self,
+        chan: usize,
+        key: u8,
+        param: GeneratorType,
+        value: f32,
+    ) -> Result<(), OxiError> {
+        RangeCheck::check(0..=127, &key, OxiError::KeyOutOfRange)?;
+        let channel = self.core.channels.get_mut(chan)?;
+
+        crate::core::synth::internal::set_key_gen(
+            channel,
+            &mut self.core.voices,
+            key,
+            param,
+            value,
+        );
+
+        Ok(())
+    }
+
+    /// Retreive the per-key value of a generator, set by a previous call to
+    /// 'set_key_gen()' or by a drum instrument NRPN message.
+    pub fn key_gen(&self, chan: u8, key: u8, param: GeneratorType) -> Result<f32, OxiError> {
+        RangeCheck::check(0..=127, &key, OxiError::KeyOutOfRange)?;
+        let channel = self.core.channels.get(chan as usize)?;
+        Ok(crate::core::synth::internal::key_gen(channel, key, param))
+    }
+
+    /**
+    Override the value of a controller for a single key of a channel.
+    Modulators of the voices of that key use this value instead of the
+    channel-wide controller value, until the channel controllers are reset.
+     */
+    pub fn set_key_cc(&mut self, chan: u8, key: u8, ctrl: u8, value: u8) -> Result<(), OxiError> {
+        RangeCheck::check(0..=127, &key, OxiError::KeyOutOfRange)?;
+        RangeCheck::check(0..=127, &ctrl, OxiError::CtrlOutOfRange)?;
+        RangeCheck::check(0..=127, &value, OxiError::CCValueOutOfRange)?;
+        let channel = self.core.channels.get_mut(chan as usize)?;
+
+        crate::core::synth::internal::midi2::per_note_cc(
+            channel,
+            &mut self.core.voices,
+            key,
+            false,
+            ctrl,
+            from_u7(value),
+        );
+
+        Ok(())
+    }
 }
 
 // Tuning
@@ -149,11 +390,22 @@ impl Synth {
     pub fn channel_reset_tuning(&mut self, chan: u8) -> Result<(), OxiError> {
         self.core.channel_reset_tuning(chan)
     }
+
+    /// Tunings that can be selected by MIDI Tuning Program Change (RPN 3)
+    /// and Tuning Bank Select (RPN 4) messages.
+    pub fn tuning_manager(&self) -> &TuningManager {
+        &self.core.tunings
+    }
+
+    pub fn tuning_manager_mut(&mut self) -> &mut TuningManager {
+        &mut self.core.tunings
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{MidiEvent, SoundFont, Synth, SynthDescriptor};
+    use crate::core::synth::test::{self as core_test, sin_font};
+    use crate::{Midi2Event, MidiEvent, SoundFont, Synth, SynthDescriptor};
     use std::{fs::File, io::Write, slice::from_raw_parts};
 
     #[test]
@@ -198,4 +450,953 @@ mod test {
 
         drop(synth);
     }
+
+    #[test]
+    fn sample_rate() {
+        assert!(Synth::new(SynthDescriptor {
+            sample_rate: 192000.0,
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(Synth::new(SynthDescriptor {
+            internal_sample_rate: Some(200000.0),
+            ..Default::default()
+        })
+        .is_err());
+
+        let mut synth = Synth::default();
+        let mut params = synth.chorus().get_chorus();
+        params.depth = 4.0;
+        synth.chorus_mut().set_chorus(&params);
+
+        // Effect parameters survive a sample rate change
+        synth.set_sample_rate(96000.0).unwrap();
+        assert_eq!(synth.chorus().get_chorus(), params);
+        assert_eq!(synth.settings().sample_rate(), 96000.0);
+
+        for rate in [0.0, -44100.0, f32::NAN, 400000.0] {
+            assert!(synth.set_sample_rate(rate).is_err());
+        }
+        assert_eq!(synth.settings().sample_rate(), 96000.0);
+
+        synth.add_font(sin_font(), true);
+
+        for rate in [0.0, -44100.0, f32::NAN, 400000.0] {
+            assert!(synth.set_internal_sample_rate(Some(rate)).is_err());
+        }
+        assert_eq!(synth.settings().internal_sample_rate(), None);
+        synth.set_internal_sample_rate(Some(44100.0)).unwrap();
+        assert_eq!(synth.settings().internal_sample_rate(), Some(44100.0));
+
+        synth
+            .send_event(MidiEvent::NoteOn {
+                channel: 0,
+                key: 69,
+                vel: 127,
+            })
+            .unwrap();
+
+        // One second at the output rate, one period of A4 at 96kHz is ~218 frames
+        let mut samples = [0f32; 96000 * 2];
+        synth.write(samples.as_mut());
+
+        let crossings = samples
+            .chunks(2)
+            .map(|frame| frame[0])
+            .collect::<Vec<_>>()
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count();
+        assert!((438..=442).contains(&crossings), "{}", crossings);
+    }
+
+    #[test]
+    fn block_size() {
+        for block_size in [8, 48, 2048] {
+            assert!(Synth::new(SynthDescriptor {
+                block_size,
+                ..Default::default()
+            })
+            .is_err());
+        }
+
+        let rms = |block_size: usize| {
+            let mut synth = sin_synth(SynthDescriptor {
+                block_size,
+                ..Default::default()
+            });
+            assert_eq!(synth.internal_buffer_size(), block_size);
+            synth
+                .send_event(MidiEvent::NoteOn {
+                    channel: 0,
+                    key: 69,
+                    vel: 127,
+                })
+                .unwrap();
+
+            let mut samples = vec![0f32; 8192];
+            synth.write(samples.as_mut_slice());
+            (samples[4096..].iter().map(|v| v * v).sum::<f32>() / 4096.0).sqrt()
+        };
+
+        // The envelopes and effects keep their timing with any block size
+        let reference = rms(64);
+        for block_size in [16, 32, 1024] {
+            let out = rms(block_size);
+            assert!(
+                (out / reference - 1.0).abs() < 0.02,
+                "{}: {}",
+                block_size,
+                out
+            );
+        }
+    }
+
+    #[test]
+    fn key_gen() {
+        use crate::GeneratorType;
+
+        let mut synth = Synth::default();
+
+        synth.set_key_gen(9, 36, GeneratorType::Pan, 250.0).unwrap();
+        assert_eq!(synth.key_gen(9, 36, GeneratorType::Pan).unwrap(), 250.0);
+        assert_eq!(synth.key_gen(9, 38, GeneratorType::Pan).unwrap(), 0.0);
+        assert_eq!(synth.gen(9, GeneratorType::Pan).unwrap(), 0.0);
+        assert!(synth.set_key_gen(9, 128, GeneratorType::Pan, 0.0).is_err());
+
+        // XG drum instrument filter cutoff of key 36
+        for (ctrl, value) in [(99, 0x14), (98, 36), (6, 0)].iter() {
+            synth
+                .send_event(MidiEvent::ControlChange {
+                    channel: 9,
+                    ctrl: *ctrl,
+                    value: *value,
+                })
+                .unwrap();
+        }
+        assert_eq!(
+            synth.key_gen(9, 36, GeneratorType::FilterFc).unwrap(),
+            -4800.0
+        );
+
+        assert!(synth.set_key_cc(9, 36, 74, 128).is_err());
+
+        // The MIDI 2.0 per-note pitch adds up with the per-key Pitch generator
+        let raised = render_note(&|synth| {
+            synth
+                .set_key_gen(0, 69, GeneratorType::Pitch, 100.0)
+                .unwrap();
+        });
+        let bent = render_note(&|synth| {
+            synth
+                .set_key_gen(0, 69, GeneratorType::Pitch, 50.0)
+                .unwrap();
+            synth
+                .send_midi2_event(Midi2Event::PerNotePitchBend {
+                    channel: 0,
+                    key: 69,
+                    value: 0xA000_0000,
+                })
+                .unwrap();
+            assert_eq!(synth.key_gen(0, 69, GeneratorType::Pitch).unwrap(), 50.0);
+        });
+        assert_eq!(raised, bent);
+
+        // A per-key controller changes the voices of that key only
+        let dry = render_note(&|_| {});
+        let key_volume = render_note(&|synth| synth.set_key_cc(0, 69, 7, 64).unwrap());
+        let other_key = render_note(&|synth| synth.set_key_cc(0, 70, 7, 64).unwrap());
+        let channel_volume = render_note(&|synth| {
+            synth
+                .send_event(MidiEvent::ControlChange {
+                    channel: 0,
+                    ctrl: 7,
+                    value: 64,
+                })
+                .unwrap()
+        });
+        assert_ne!(key_volume, dry);
+        assert_eq!(key_volume, channel_volume);
+        assert_eq!(other_key, dry);
+    }
+
+    /// A synth with the sine wave font loaded
+    pub(crate) fn sin_synth(desc: SynthDescriptor) -> Synth {
+        Synth {
+            core: core_test::sin_synth(desc),
+        }
+    }
+
+    /// `render_note()` of the core, with `setup` given the public synth
+    fn render_note(setup: &dyn Fn(&mut Synth)) -> [f32; 4096] {
+        let mut synth = Synth {
+            core: core_test::note_synth(),
+        };
+        setup(&mut synth);
+        core_test::play_note(&mut synth.core)
+    }
+
+    #[test]
+    fn effects() {
+        use crate::core::effects::test::Gain;
+
+        let dry = render_note(&|_| {});
+        assert!(dry.iter().any(|v| *v != 0.0));
+
+        // Muted channel insert
+        let out = render_note(&|synth| {
+            synth
+                .effects_mut()
+                .insert_mut(0)
+                .unwrap()
+                .push(Box::new(Gain(0.0)));
+        });
+        assert!(out.iter().all(|v| *v == 0.0));
+        assert!(Synth::default().effects().insert(16).is_err());
+
+        // CC94 send bus, added to the dry signal, then the master bus
+        let send_bus = |synth: &mut Synth| {
+            let bus = synth.effects_mut().add_send_bus(94);
+            synth
+                .effects_mut()
+                .send_bus_mut(bus)
+                .unwrap()
+                .chain_mut()
+                .push(Box::new(Gain(1.0)));
+            synth.effects_mut().master_mut().push(Box::new(Gain(0.5)));
+            synth
+                .send_event(MidiEvent::ControlChange {
+                    channel: 0,
+                    ctrl: 94,
+                    value: 127,
+                })
+                .unwrap();
+        };
+        let out = render_note(&|synth| {
+            synth.set_effect_ramp_time(0.0);
+            send_bus(synth);
+        });
+        for (out, dry) in out.iter().zip(dry.iter()) {
+            assert!((out - dry).abs() < 1e-6);
+        }
+
+        // The send level ramps up over the effect ramp time, 882 frames
+        let out = render_note(&|synth| send_bus(synth));
+        for frame in (0..1000).filter(|frame| dry[frame * 2].abs() > 0.01) {
+            let level = ((frame + 1) as f32 / 882.0).min(1.0);
+            let gain = out[frame * 2] / dry[frame * 2];
+            assert!((gain - 0.5 * (1.0 + level)).abs() < 1e-3);
+        }
+
+        // Master limiter set through the synth, at -30dB the note is limited
+        let ceiling = 10f32.powf(-30.0 / 20.0);
+        let out = render_note(&|synth| {
+            synth
+                .effects_mut()
+                .master_mut()
+                .push(Box::new(crate::effects::Limiter::new(44100.0)));
+            synth
+                .set_master_limiter(
+                    0,
+                    &crate::effects::LimiterParams {
+                        threshold: -30.0,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            assert!(matches!(
+                synth.set_master_compressor(0, &Default::default()),
+                Err(crate::OxiError::EffectNotFound)
+            ));
+            assert!(synth.set_master_eq_bands(1, &[]).is_err());
+        });
+        assert!(dry.iter().any(|v| v.abs() > ceiling));
+        assert!(out.iter().all(|v| v.abs() <= ceiling));
+
+        // CC94 delay send, the first echo comes after 10ms
+        let out = render_note(&|synth| {
+            let bus = synth.add_delay(94);
+            synth
+                .set_send_delay(
+                    bus,
+                    0,
+                    &crate::delay::DelayParams {
+                        time: crate::delay::DelayTime::Ms(10.0),
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            assert!(synth.set_send_delay(bus, 1, &Default::default()).is_err());
+            synth
+                .send_event(MidiEvent::ControlChange {
+                    channel: 0,
+                    ctrl: 94,
+                    value: 127,
+                })
+                .unwrap();
+        });
+        assert_eq!(out[..800], dry[..800]);
+        assert!(out[1000..] != dry[1000..]);
+
+        // The delay of the settings is the first send bus
+        let synth = Synth::new(crate::SynthDescriptor {
+            delay_active: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(synth.effects().count_send_buses(), 1);
+        assert_eq!(synth.effects().send_bus(0).unwrap().ctrl(), 94);
+    }
+
+    #[test]
+    fn fx_instances() {
+        use crate::reverb::ReverbType;
+
+        let dry = render_note(&|_| {});
+
+        // Channel 0 sends to a second reverb, the main one stays off
+        let out = render_note(&|synth| {
+            let id = synth.add_reverb(ReverbType::Freeverb);
+            synth.set_channel_reverb(0, id).unwrap();
+            synth
+                .send_event(MidiEvent::ControlChange {
+                    channel: 0,
+                    ctrl: 91,
+                    value: 127,
+                })
+                .unwrap();
+        });
+        assert!(out != dry);
+
+        // The main reverb doesn't get the channel 0 send anymore
+        let out = render_note(&|synth| {
+            synth.get_reverb_mut().set_active(true);
+            let id = synth.add_reverb(ReverbType::Plate);
+            synth.reverb_instance_mut(id).unwrap().set_active(false);
+            synth.set_channel_reverb(0, id).unwrap();
+            assert_eq!(synth.channel_reverb(0).unwrap(), id);
+
+            let id = synth.add_chorus();
+            synth.set_channel_chorus(1, id).unwrap();
+        });
+        // Up to the anti-denormal offset of Freeverb
+        for (out, dry) in out.iter().zip(dry.iter()) {
+            assert!((out - dry).abs() < 1e-6);
+        }
+
+        let mut synth = Synth::default();
+        assert!(synth.set_channel_reverb(0, 1).is_err());
+        assert!(synth.set_channel_chorus(16, 0).is_err());
+        assert_eq!(synth.count_choruses(), 1);
+    }
+
+    #[test]
+    fn ramp_time() {
+        // Output after the master gain is halved mid-note, relative to the unchanged output
+        let gain_ratio = |ramp_time: f32| {
+            let mut render = |gain: f32| {
+                let mut samples = vec![0f32; 2048];
+                let mut synth = sin_synth(Default::default());
+                synth.get_reverb_mut().set_active(false);
+                synth.chorus_mut().set_active(false);
+                synth.set_ramp_time(ramp_time);
+                assert_eq!(synth.ramp_time(), ramp_time);
+                synth
+                    .send_event(MidiEvent::NoteOn {
+                        channel: 0,
+                        key: 69,
+                        vel: 127,
+                    })
+                    .unwrap();
+                synth.write(samples.as_mut_slice());
+                synth.set_gain(gain);
+                synth.write(samples.as_mut_slice());
+                samples
+            };
+            let reference = render(0.2);
+            let out = render(0.1);
+            move |frame: usize| out[frame * 2] / reference[frame * 2]
+        };
+
+        let ratio = gain_ratio(0.0);
+        assert!((ratio(1) - 0.5).abs() < 1e-4);
+
+        // 5 ms at 44.1 kHz is 220 frames
+        let ratio = gain_ratio(5.0);
+        assert!((ratio(1) - 1.0).abs() < 0.01);
+        assert!((ratio(110) - 0.75).abs() < 0.01);
+        assert!((ratio(300) - 0.5).abs() < 1e-4);
+
+        // The effects have their own ramp time
+        let mut synth = Synth::default();
+        assert_eq!(synth.effect_ramp_time(), 20.0);
+        synth.set_effect_ramp_time(150.0);
+        assert_eq!(synth.effect_ramp_time(), 100.0);
+        assert!(Synth::new(SynthDescriptor {
+            effect_ramp_time: -1.0,
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn voice_stealing() {
+        use crate::{StealPolicy, SynthEvent};
+
+        // Keys stolen by the last note of `notes`, (channel, key, vel)
+        let steal = |setup: &dyn Fn(&mut Synth), notes: &[(u8, u8, u8)]| {
+            let mut synth = sin_synth(Default::default());
+            synth.set_polyphony(4).unwrap();
+            synth.set_event_capacity(64);
+            setup(&mut synth);
+
+            for &(channel, key, vel) in notes {
+                synth
+                    .send_event(MidiEvent::NoteOn { channel, key, vel })
+                    .unwrap();
+                let mut samples = [0f32; 256];
+                synth.write(samples.as_mut());
+            }
+            synth
+                .drain_events()
+                .filter_map(|event| match event {
+                    SynthEvent::VoiceStolen(voice) => Some((voice.channel, voice.key)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let policy = |policy: StealPolicy| move |synth: &mut Synth| synth.set_steal_policy(policy);
+        let four = [(0, 60, 100), (0, 61, 100), (0, 62, 100), (0, 63, 100)];
+
+        assert_eq!(steal(&policy(StealPolicy::Oldest), &four), vec![]);
+        assert_eq!(
+            steal(
+                &policy(StealPolicy::Oldest),
+                &[&four[..], &[(0, 64, 100)]].concat()
+            ),
+            vec![(0, 60)]
+        );
+        assert_eq!(
+            steal(
+                &policy(StealPolicy::SameNoteFirst),
+                &[&four[..], &[(0, 62, 100)]].concat()
+            ),
+            vec![(0, 62)]
+        );
+        assert_eq!(
+            steal(
+                &policy(StealPolicy::Quietest),
+                &[
+                    (0, 60, 100),
+                    (0, 61, 10),
+                    (0, 62, 100),
+                    (0, 63, 100),
+                    (0, 64, 100)
+                ]
+            ),
+            vec![(0, 61)]
+        );
+
+        // The pads of channel 2 go before the older drums (3) and melody (0)
+        let priorities = |synth: &mut Synth| {
+            synth.set_steal_policy(StealPolicy::LowestPriorityChannel);
+            synth.set_channel_voice_priority(3, 100).unwrap();
+            synth.set_channel_voice_priority(0, 90).unwrap();
+            synth.set_channel_voice_priority(2, 10).unwrap();
+            assert_eq!(synth.channel_voice_priority(2).unwrap(), 10);
+            assert_eq!(synth.channel_voice_priority(1).unwrap(), 64);
+        };
+        assert_eq!(
+            steal(
+                &priorities,
+                &[
+                    (3, 36, 100),
+                    (0, 60, 100),
+                    (2, 48, 100),
+                    (3, 38, 100),
+                    (0, 64, 100)
+                ]
+            ),
+            vec![(2, 48)]
+        );
+
+        // Past its voice limit, a channel replaces its own voices
+        let limit = |synth: &mut Synth| {
+            synth.set_steal_policy(StealPolicy::Oldest);
+            synth.set_channel_voice_limit(0, Some(2)).unwrap();
+            assert_eq!(synth.channel_voice_limit(0).unwrap(), Some(2));
+            assert!(synth.set_channel_voice_limit(16, Some(2)).is_err());
+        };
+        assert_eq!(
+            steal(
+                &limit,
+                &[(1, 50, 100), (0, 60, 100), (0, 61, 100), (0, 62, 100)]
+            ),
+            vec![(0, 60)]
+        );
+    }
+
+    #[test]
+    fn monitoring() {
+        use crate::EnvelopeStage;
+
+        let mut synth = sin_synth(Default::default());
+        synth.set_channel_meters(true);
+
+        for (channel, vel) in [(0, 127), (1, 40)] {
+            synth
+                .send_event(MidiEvent::NoteOn {
+                    channel,
+                    key: 69,
+                    vel,
+                })
+                .unwrap();
+        }
+        let mut samples = vec![0f32; 88200];
+        synth.write(samples.as_mut_slice());
+
+        let voices = synth.playing_voices().collect::<Vec<_>>();
+        assert_eq!(voices.len(), 2);
+        assert_eq!(
+            (voices[0].channel, voices[0].key, voices[0].vel),
+            (0, 69, 127)
+        );
+        assert_eq!(
+            voices[0].preset.name(),
+            synth.channel_preset(0).unwrap().name()
+        );
+        assert!(!voices[0].sample_name.is_empty());
+        assert_eq!(voices[0].envelope, EnvelopeStage::Sustain);
+        assert!(!voices[0].sustained);
+        assert_eq!(voices[0].age, 44160);
+        assert!(voices[0].amplitude > voices[1].amplitude);
+
+        // A sine has a crest factor of sqrt(2)
+        let loud = synth.channel_level(0).unwrap();
+        assert!((loud.peak / loud.rms - 2f32.sqrt()).abs() < 0.05);
+        assert!(synth.channel_level(1).unwrap().rms < loud.rms);
+        assert_eq!(synth.channel_level(2).unwrap().rms, 0.0);
+        assert_eq!(synth.channel_levels().len(), 16);
+
+        // The levels fall back after the notes off
+        synth
+            .send_event(MidiEvent::AllNotesOff { channel: 0 })
+            .unwrap();
+        synth.write(samples.as_mut_slice());
+        let level = synth.channel_level(0).unwrap();
+        assert!(level.peak < loud.peak * 0.1 && level.rms < loud.rms * 0.3);
+        assert_eq!(synth.playing_voices().count(), 1);
+
+        synth.set_channel_meters(false);
+        assert_eq!(synth.channel_level(1).unwrap().peak, 0.0);
+    }
+
+    #[test]
+    fn synth_events() {
+        use crate::{StolenVoice, SynthEvent};
+
+        let mut synth = sin_synth(Default::default());
+        synth.set_polyphony(1).unwrap();
+        let mut samples = [0f32; 256];
+
+        // Nothing is recorded by default
+        synth
+            .send_event(MidiEvent::NoteOn {
+                channel: 0,
+                key: 60,
+                vel: 127,
+            })
+            .unwrap();
+        assert_eq!(synth.drain_events().count(), 0);
+
+        synth.set_event_capacity(16);
+        assert_eq!(synth.event_capacity(), 16);
+        synth
+            .send_event(MidiEvent::NoteOn {
+                channel: 0,
+                key: 62,
+                vel: 127,
+            })
+            .unwrap();
+        assert!(synth
+            .send_event(MidiEvent::NoteOn {
+                channel: 9,
+                key: 36,
+                vel: 80,
+            })
+            .is_err());
+        synth.set_channel_voice_limit(1, Some(0)).unwrap();
+        synth
+            .send_event(MidiEvent::NoteOn {
+                channel: 1,
+                key: 64,
+                vel: 70,
+            })
+            .unwrap();
+        assert_eq!(
+            synth.drain_events().collect::<Vec<_>>(),
+            vec![
+                SynthEvent::VoiceStolen(StolenVoice {
+                    channel: 0,
+                    key: 60,
+                    vel: 127
+                }),
+                SynthEvent::VoiceStarted {
+                    channel: 0,
+                    key: 62,
+                    vel: 127
+                },
+                SynthEvent::NoPreset {
+                    channel: 9,
+                    key: 36,
+                    vel: 80
+                },
+                SynthEvent::PolyphonyExhausted {
+                    channel: 1,
+                    key: 64,
+                    vel: 70
+                },
+            ]
+        );
+
+        // Voices stopped by a sound off finish with the next block
+        synth
+            .send_event(MidiEvent::AllSoundOff { channel: 0 })
+            .unwrap();
+        synth.write(samples.as_mut());
+        assert_eq!(
+            synth.drain_events().collect::<Vec<_>>(),
+            vec![SynthEvent::VoiceFinished {
+                channel: 0,
+                key: 62
+            }]
+        );
+
+        // Events past the capacity are dropped
+        synth.set_event_capacity(1);
+        for key in [60, 61, 62] {
+            synth
+                .send_event(MidiEvent::NoteOn {
+                    channel: 0,
+                    key,
+                    vel: 100,
+                })
+                .unwrap();
+        }
+        assert_eq!(synth.lost_events(), 4);
+        assert_eq!(synth.drain_events().count(), 1);
+        assert_eq!(synth.lost_events(), 0);
+    }
+
+    #[test]
+    fn synth_state() {
+        use crate::reverb::ReverbParams;
+        use crate::{GeneratorType, OxiError, Tuning};
+
+        let load = || {
+            let font = sin_font();
+            let fingerprint = font.fingerprint();
+            let mut synth = Synth::default();
+            synth.add_font(font, true);
+            (synth, fingerprint)
+        };
+        let render = |synth: &mut Synth| {
+            synth
+                .send_event(MidiEvent::NoteOn {
+                    channel: 2,
+                    key: 69,
+                    vel: 100,
+                })
+                .unwrap();
+            let mut samples = vec![0f32; 8192];
+            synth.write(samples.as_mut_slice());
+            samples
+        };
+
+        let (mut synth, fingerprint) = load();
+        for (ctrl, value) in [(7, 90), (10, 20), (101, 0), (100, 0), (6, 12)] {
+            synth
+                .send_event(MidiEvent::ControlChange {
+                    channel: 2,
+                    ctrl,
+                    value,
+                })
+                .unwrap();
+        }
+        synth
+            .send_event(MidiEvent::PitchBend {
+                channel: 2,
+                value: 0x3000,
+            })
+            .unwrap();
+        synth.set_gen(2, GeneratorType::FilterFc, -1200.0).unwrap();
+        synth
+            .channel_set_tuning(2, Tuning::new_octave_tuning(0, 1, &[-20.0; 12]))
+            .unwrap();
+        synth.get_reverb_mut().set_reverb(&ReverbParams {
+            roomsize: 0.8,
+            ..Default::default()
+        });
+        synth.set_gain(0.5);
+        synth.set_polyphony(32).unwrap();
+        synth.set_channel_voice_limit(2, Some(3)).unwrap();
+
+        let state = synth.save_state();
+        assert_eq!(state.fonts().collect::<Vec<_>>(), vec![fingerprint]);
+        let expected = render(&mut synth);
+
+        // The fonts of the state must be loaded
+        assert!(matches!(
+            Synth::default().restore_state(&state),
+            Err(OxiError::FontNotLoaded(f)) if f == fingerprint
+        ));
+
+        let (mut restored, _) = load();
+        restored.restore_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.gain(), 0.5);
+        assert_eq!(restored.channel_voice_limit(2).unwrap(), Some(3));
+        assert_eq!(render(&mut restored), expected);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&state).unwrap();
+            let state: crate::SynthState = serde_json::from_str(&json).unwrap();
+            let (mut restored, _) = load();
+            restored.restore_state(&state).unwrap();
+            assert_eq!(render(&mut restored), expected);
+        }
+    }
+
+    #[test]
+    fn synth_state_effects() {
+        use crate::reverb::ReverbType;
+        use crate::{OxiError, Tuning};
+
+        let mut synth = Synth::default();
+        synth.add_reverb(ReverbType::Plate);
+        synth.add_chorus();
+        let state = synth.save_state();
+
+        // Instances are added with the saved engines, and the extra ones removed
+        let mut restored = Synth::default();
+        for _ in 0..3 {
+            restored.add_reverb(ReverbType::Freeverb);
+            restored.add_chorus();
+        }
+        restored.set_channel_reverb(0, 3).unwrap();
+        restored.restore_state(&state).unwrap();
+        assert_eq!(restored.count_reverbs(), 2);
+        assert_eq!(restored.count_choruses(), 2);
+        assert_eq!(
+            restored.reverb_instance(1).unwrap().ty(),
+            Some(ReverbType::Plate)
+        );
+        assert_eq!(restored.channel_reverb(0).unwrap(), 0);
+        assert_eq!(restored.save_state(), state);
+        let mut samples = vec![0f32; 1024];
+        restored.write(samples.as_mut_slice());
+
+        // Invalid states are rejected without changing the synth
+        let mut restored = Synth::default();
+        let mut invalid = state.clone();
+        invalid.polyphony = 0;
+        assert!(matches!(
+            restored.restore_state(&invalid),
+            Err(OxiError::PolyphonyOutOfRange)
+        ));
+        let mut tuned = Synth::default();
+        tuned
+            .tuning_manager_mut()
+            .add_tuning(Tuning::new(0, 0))
+            .unwrap();
+        let mut invalid = tuned.save_state();
+        invalid.tunings[0].bank = 200;
+        assert!(matches!(
+            restored.restore_state(&invalid),
+            Err(OxiError::TuningOutOfRange)
+        ));
+        let mut invalid = state.clone();
+        invalid.reverbs[1].ty = None;
+        assert!(matches!(
+            restored.restore_state(&invalid),
+            Err(OxiError::ReverbEngineNotSet(1))
+        ));
+        assert_eq!(restored.count_reverbs(), 1);
+        assert_eq!(restored.save_state(), Synth::default().save_state());
+    }
+
+    #[test]
+    fn synth_dsp_state() {
+        use crate::effects::Limiter;
+        use crate::OxiError;
+
+        let load = |effects: bool| {
+            let mut synth = sin_synth(Default::default());
+            if effects {
+                let limiter = Box::new(Limiter::new(44100.0));
+                synth.effects_mut().master_mut().push(limiter);
+                synth.add_delay(94);
+            }
+            synth
+        };
+        let render = |synth: &mut Synth, frames: usize| {
+            let mut samples = vec![0f32; frames * 2];
+            synth.write(samples.as_mut_slice());
+            samples
+        };
+
+        let mut synth = load(true);
+        for channel in 0..3 {
+            let key = 60 + channel * 7;
+            synth
+                .send_event(MidiEvent::NoteOn {
+                    channel,
+                    key,
+                    vel: 100,
+                })
+                .unwrap();
+        }
+        synth
+            .send_event(MidiEvent::ControlChange {
+                channel: 1,
+                ctrl: 94,
+                value: 100,
+            })
+            .unwrap();
+        render(&mut synth, 1000);
+        synth
+            .send_event(MidiEvent::NoteOff {
+                channel: 0,
+                key: 60,
+            })
+            .unwrap();
+        // Saved in the middle of a block and of the release
+        render(&mut synth, 301);
+
+        let state = synth.save_dsp_state();
+        let expected = render(&mut synth, 8192);
+        assert!(expected.iter().any(|v| *v != 0.0));
+
+        let mut restored = load(true);
+        restored.restore_dsp_state(&state).unwrap();
+        assert_eq!(restored.playing_voices().count(), 3);
+        assert_eq!(restored.effects().count_send_buses(), 1);
+        assert_eq!(render(&mut restored, 8192), expected);
+
+        // The effects must be set up like the saved ones
+        let mut other = load(false);
+        assert!(matches!(
+            other.restore_dsp_state(&state),
+            Err(OxiError::DspStateMismatch)
+        ));
+        assert_eq!(other.playing_voices().count(), 0);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&state).unwrap();
+            let state: crate::DspState = serde_json::from_str(&json).unwrap();
+            let mut restored = load(true);
+            restored.restore_dsp_state(&state).unwrap();
+            assert_eq!(render(&mut restored, 8192), expected);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn render_threads() {
+        use crate::RenderThreads;
+
+        let render = |threads: RenderThreads| {
+            let mut synth = Synth::default();
+            synth.set_render_threads(threads).unwrap();
+            assert_eq!(synth.render_threads(), threads);
+
+            let mut file = std::fs::File::open("./testdata/Boomwhacker.sf2").unwrap();
+            synth.add_font(SoundFont::load(&mut file).unwrap(), true);
+            for key in 40..80 {
+                synth
+                    .send_event(MidiEvent::NoteOn {
+                        channel: key % 4,
+                        key,
+                        vel: 100,
+                    })
+                    .unwrap();
+            }
+
+            let mut samples = vec![0f32; 8192];
+            synth.write(samples.as_mut_slice());
+            samples
+        };
+
+        let single = render(RenderThreads::Single);
+        let scoped = render(RenderThreads::Scoped(3));
+        let pool = render(RenderThreads::Pool(3));
+
+        // Only the summing order of the voices differs from a single thread
+        assert!(single.iter().any(|v| v.abs() > 0.01));
+        for (a, b) in single.iter().zip(scoped.iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+        // The groups don't depend on how the threads are started
+        assert_eq!(scoped, pool);
+        assert_eq!(pool, render(RenderThreads::Pool(3)));
+    }
+
+    #[test]
+    fn write_blocks() {
+        let synth = |audio_channels: u8| {
+            let mut synth = sin_synth(SynthDescriptor {
+                audio_channels,
+                audio_groups: audio_channels,
+                ..Default::default()
+            });
+            for channel in 0..2 {
+                synth
+                    .send_event(MidiEvent::NoteOn {
+                        channel,
+                        key: 60 + channel * 9,
+                        vel: 127,
+                    })
+                    .unwrap();
+            }
+            synth
+        };
+
+        let mut reference = synth(1);
+        let frames = (0..1000).map(|_| reference.read_next()).collect::<Vec<_>>();
+        assert!(frames.iter().any(|(l, _)| l.abs() > 0.01));
+
+        // Chunk lengths not aligned on the blocks
+        let mut planar = synth(1);
+        let mut interleaved = synth(1);
+        let mut pos = 0;
+        for len in [1, 63, 100, 64, 772] {
+            let (mut left, mut right) = (vec![0f32; len], vec![0f32; len]);
+            planar.write_planar(&mut left, &mut right);
+            let mut out = vec![0f32; len * 2];
+            interleaved.write_interleaved(&mut out);
+
+            for id in 0..len {
+                assert_eq!((left[id], right[id]), frames[pos + id]);
+                assert_eq!((out[id * 2], out[id * 2 + 1]), frames[pos + id]);
+            }
+            pos += len;
+        }
+
+        // Each channel of the first two is routed to its own output
+        let mut multi = synth(2);
+        assert_eq!(multi.output_channels(), 4);
+        let mut out = vec![0f32; 1000 * 4];
+        multi.write_channels(&mut out);
+        assert!(out.chunks(4).any(|frame| frame[0].abs() > 0.01));
+        assert!(out.chunks(4).any(|frame| frame[2].abs() > 0.01));
+
+        let mut single = synth(1);
+        let mut mono = vec![0f32; 1000 * 2];
+        single.write_channels(&mut mono);
+        for (frame, expected) in mono.chunks(2).zip(frames.iter()) {
+            assert_eq!((frame[0], frame[1]), *expected);
+        }
+    }
 }
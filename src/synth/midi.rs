@@ -1,6 +1,7 @@
 use crate::core::OxiError;
 use crate::SoundFontId;
 use crate::Synth;
+use crate::{MpeZone, MpeZoneLayout};
 
 /**
 MIDI channel messages
@@ -80,4 +81,458 @@ impl Synth {
     pub fn program_reset(&mut self) {
         self.core.program_reset()
     }
+
+    /**
+    Configure an MPE zone, as the MPE Configuration Message (RPN 6) does.
+
+    The member channels take over the preset and controllers of the manager channel,
+    their pitch bend range is set to 48 semitones and the one of the manager channel to 2.
+    A member count of 0 disables the zone.
+
+    On the member channels CC74 (timbre) moves the filter cutoff by up to ±2400 cents,
+    and the channel pressure sets the loudness, notes being 24 dB softer without pressure.
+     */
+    pub fn set_mpe_zone(&mut self, zone: MpeZone, member_channels: u8) {
+        self.core.set_mpe_zone(zone, member_channels)
+    }
+
+    /**
+    Returns the channel layout of an MPE zone, if the zone is active.
+     */
+    pub fn get_mpe_zone(&self, zone: MpeZone) -> Option<MpeZoneLayout> {
+        self.core.get_mpe_zone(zone)
+    }
+
+    /**
+    Set the per-note pitch bend range (in semitones) of the member channels of an MPE zone.
+     */
+    pub fn set_mpe_pitch_bend_range(&mut self, zone: MpeZone, val: u8) -> Result<(), OxiError> {
+        self.core.set_mpe_pitch_bend_range(zone, val)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::synth::test::sin_synth;
+    use crate::{
+        GeneratorType, Midi2Event, MidiEvent, MpeZone, NoteAttribute, Synth, SynthDescriptor,
+    };
+
+    fn cc(synth: &mut Synth, ctrl: u8, value: u8) {
+        cc_on(synth, 0, ctrl, value);
+    }
+
+    fn cc_on(synth: &mut Synth, channel: u8, ctrl: u8, value: u8) {
+        synth
+            .send_event(MidiEvent::ControlChange {
+                channel,
+                ctrl,
+                value,
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn rpn_data_entry() {
+        let mut synth = Synth::default();
+        assert_eq!(synth.get_pitch_wheel_sens(0).unwrap(), 2);
+
+        // Select RPN 0 (pitch bend range)
+        cc(&mut synth, 101, 0);
+        cc(&mut synth, 100, 0);
+        cc(&mut synth, 6, 12);
+        assert_eq!(synth.get_pitch_wheel_sens(0).unwrap(), 12);
+
+        // Data increment / decrement
+        cc(&mut synth, 96, 0);
+        assert_eq!(synth.get_pitch_wheel_sens(0).unwrap(), 13);
+        cc(&mut synth, 97, 0);
+        cc(&mut synth, 97, 0);
+        assert_eq!(synth.get_pitch_wheel_sens(0).unwrap(), 11);
+
+        // RPN null deselects the parameter
+        cc(&mut synth, 101, 127);
+        cc(&mut synth, 100, 127);
+        cc(&mut synth, 6, 24);
+        cc(&mut synth, 96, 0);
+        assert_eq!(synth.get_pitch_wheel_sens(0).unwrap(), 11);
+    }
+
+    #[test]
+    fn rpn_tuning_select() {
+        use crate::Tuning;
+
+        let mut synth = Synth::default();
+        for bank in [0, 2] {
+            let tuning = Tuning::new_octave_tuning(bank, 3, &[-20.0; 12]);
+            synth.tuning_manager_mut().add_tuning(tuning).unwrap();
+        }
+        let tuning = |synth: &Synth| {
+            let channel = synth.core.channels.get(0).unwrap();
+            channel.tuning().map(|tuning| (tuning.bank, tuning.program))
+        };
+        assert_eq!(tuning(&synth), None);
+
+        // RPN 3 (tuning program) activates the tuning of the selected bank
+        cc(&mut synth, 101, 0);
+        cc(&mut synth, 100, 3);
+        cc(&mut synth, 6, 3);
+        assert_eq!(tuning(&synth), Some((0, 3)));
+
+        // RPN 4 (tuning bank) only applies with the next program
+        cc(&mut synth, 100, 4);
+        cc(&mut synth, 6, 2);
+        assert_eq!(tuning(&synth), Some((0, 3)));
+        cc(&mut synth, 100, 3);
+        cc(&mut synth, 6, 3);
+        assert_eq!(tuning(&synth), Some((2, 3)));
+
+        // A missing tuning leaves the channel tuning as it is
+        cc(&mut synth, 6, 4);
+        assert_eq!(tuning(&synth), Some((2, 3)));
+    }
+
+    #[test]
+    fn rpn_mod_depth_range() {
+        fn render(mod_wheel: u8, depth: Option<u8>) -> Vec<f32> {
+            let mut synth = sin_synth(Default::default());
+            synth.get_reverb_mut().set_active(false);
+            synth.chorus_mut().set_active(false);
+
+            cc(&mut synth, 1, mod_wheel);
+            if let Some(depth) = depth {
+                // RPN 5 (modulation depth range), in semitones
+                cc(&mut synth, 101, 0);
+                cc(&mut synth, 100, 5);
+                cc(&mut synth, 6, depth);
+            }
+            synth
+                .send_event(MidiEvent::NoteOn {
+                    channel: 0,
+                    key: 69,
+                    vel: 127,
+                })
+                .unwrap();
+
+            let mut samples = vec![0f32; 32768];
+            synth.write(samples.as_mut_slice());
+            samples
+        }
+        // Spread of the periods between rising zero crossings, in samples
+        let spread = |samples: &[f32]| {
+            let left: Vec<f32> = samples.iter().step_by(2).copied().collect();
+            let crossings: Vec<f32> = left
+                .windows(2)
+                .enumerate()
+                .filter(|(_, w)| w[0] < 0.0 && w[1] >= 0.0)
+                .map(|(i, w)| i as f32 + w[0] / (w[0] - w[1]))
+                .collect();
+            let periods = crossings.windows(2).map(|w| w[1] - w[0]);
+            let (min, max) = periods.fold((f32::MAX, 0f32), |(min, max), period| {
+                (min.min(period), max.max(period))
+            });
+            max - min
+        };
+
+        let dry = spread(&render(0, None));
+        let default_depth = spread(&render(127, None));
+        let wide_depth = spread(&render(127, Some(2)));
+        assert!(dry < 0.05);
+        assert!(default_depth > 1.0);
+        // 200 cents instead of 50
+        assert!(wide_depth > default_depth * 3.5);
+
+        // No depth, no vibrato
+        assert!(spread(&render(127, Some(0))) < 0.05);
+    }
+
+    #[test]
+    fn gs_nrpn() {
+        let mut synth = Synth::default();
+
+        // Filter cutoff +32
+        cc(&mut synth, 99, 0x01);
+        cc(&mut synth, 98, 0x20);
+        cc(&mut synth, 6, 96);
+        assert_eq!(synth.gen(0, GeneratorType::FilterFc).unwrap(), 2400.0);
+
+        // Center value resets the offset
+        cc(&mut synth, 6, 64);
+        assert_eq!(synth.gen(0, GeneratorType::FilterFc).unwrap(), 0.0);
+
+        // Data increment steps the value read from the MSB
+        cc(&mut synth, 96, 0);
+        assert_eq!(synth.gen(0, GeneratorType::FilterFc).unwrap(), 75.0);
+
+        // Drum instrument edits don't touch the channel-wide offsets
+        cc(&mut synth, 99, 0x18);
+        cc(&mut synth, 98, 36);
+        cc(&mut synth, 6, 76);
+        assert_eq!(synth.gen(0, GeneratorType::CoarseTune).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn drum_sends() {
+        fn render(reverb: bool, send: Option<u8>) -> Vec<f32> {
+            let mut synth = sin_synth(Default::default());
+            synth.get_reverb_mut().set_active(reverb);
+            synth.chorus_mut().set_active(false);
+
+            cc(&mut synth, 91, 127);
+            if let Some(send) = send {
+                // Drum instrument reverb send of key 69
+                cc(&mut synth, 99, 0x1d);
+                cc(&mut synth, 98, 69);
+                cc(&mut synth, 6, send);
+            }
+            synth
+                .send_event(MidiEvent::NoteOn {
+                    channel: 0,
+                    key: 69,
+                    vel: 127,
+                })
+                .unwrap();
+
+            let mut samples = vec![0f32; 8192];
+            synth.write(samples.as_mut_slice());
+            samples
+        }
+
+        let wet = render(true, None);
+        let dry = render(false, None);
+        let diff = |a: &[f32], b: &[f32]| {
+            a.iter()
+                .zip(b.iter())
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f32::max)
+        };
+        assert!(diff(&wet, &dry) > 0.01);
+
+        // 127 leaves the voice send unchanged, 0 silences it
+        assert_eq!(render(true, Some(127)), wet);
+        assert!(diff(&render(true, Some(0)), &dry) < 1e-6);
+    }
+
+    #[test]
+    fn mpe_configuration() {
+        let mut synth = Synth::default();
+
+        // MCM: lower zone with 3 member channels
+        cc(&mut synth, 101, 0);
+        cc(&mut synth, 100, 6);
+        cc(&mut synth, 6, 3);
+
+        let zone = synth.get_mpe_zone(MpeZone::Lower).unwrap();
+        assert_eq!(zone.member_channels(), 1..=3);
+        assert_eq!(synth.get_pitch_wheel_sens(0).unwrap(), 2);
+        assert_eq!(synth.get_pitch_wheel_sens(1).unwrap(), 48);
+        assert_eq!(synth.get_pitch_wheel_sens(4).unwrap(), 2);
+
+        // Pitch bend range sent to one member channel applies to the whole zone
+        cc_on(&mut synth, 2, 101, 0);
+        cc_on(&mut synth, 2, 100, 0);
+        cc_on(&mut synth, 2, 6, 24);
+        assert_eq!(synth.get_pitch_wheel_sens(1).unwrap(), 24);
+        assert_eq!(synth.get_pitch_wheel_sens(3).unwrap(), 24);
+
+        // Zone-wide controllers are forwarded, per-note ones are not
+        cc(&mut synth, 7, 90);
+        cc(&mut synth, 74, 10);
+        assert_eq!(synth.get_cc(3, 7).unwrap(), 90);
+        assert_eq!(synth.get_cc(3, 74).unwrap(), 64);
+
+        synth.set_mpe_zone(MpeZone::Lower, 0);
+        assert!(synth.get_mpe_zone(MpeZone::Lower).is_none());
+    }
+
+    #[test]
+    fn mpe_expression() {
+        fn render(setup: impl FnOnce(&mut Synth)) -> Vec<f32> {
+            let mut synth = sin_synth(Default::default());
+            synth.set_mpe_zone(MpeZone::Lower, 3);
+
+            setup(&mut synth);
+            synth
+                .send_event(MidiEvent::NoteOn {
+                    channel: 1,
+                    key: 69,
+                    vel: 127,
+                })
+                .unwrap();
+
+            let mut samples = vec![0f32; 4096];
+            synth.write(samples.as_mut_slice());
+            samples
+        }
+        fn pressure(synth: &mut Synth, value: u8) {
+            synth
+                .send_event(MidiEvent::ChannelPressure { channel: 1, value })
+                .unwrap();
+        }
+
+        // The manager pitch bend adds up with the Pitch generator of the member channels
+        let raised = render(|synth| {
+            synth.set_gen(1, GeneratorType::Pitch, 100.0).unwrap();
+        });
+        let bent = render(|synth| {
+            synth.set_gen(1, GeneratorType::Pitch, 50.0).unwrap();
+            synth
+                .send_event(MidiEvent::PitchBend {
+                    channel: 0,
+                    value: 0x2800,
+                })
+                .unwrap();
+            assert_eq!(synth.gen(1, GeneratorType::Pitch).unwrap(), 50.0);
+            assert_eq!(synth.get_pitch_bend(1).unwrap(), 0x2000);
+        });
+        assert_eq!(raised, bent);
+
+        // High resolution zone-wide controllers are forwarded as they are
+        let volume = |channel| {
+            move |synth: &mut Synth| {
+                synth
+                    .send_midi2_event(Midi2Event::ControlChange {
+                        channel,
+                        ctrl: 7,
+                        value: 0x8fff_ffff,
+                    })
+                    .unwrap()
+            }
+        };
+        assert_eq!(render(volume(0)), render(volume(1)));
+
+        // Pressure sets the loudness of the notes
+        let rms = |samples: Vec<f32>| samples.iter().map(|v| v * v).sum::<f32>().sqrt();
+        let soft = rms(render(|synth| pressure(synth, 0)));
+        let loud = rms(render(|synth| pressure(synth, 127)));
+        assert!(loud > soft * 10.0, "{} {}", loud, soft);
+    }
+
+    #[test]
+    fn midi2_controllers() {
+        let mut synth = Synth::new(SynthDescriptor {
+            midi_channels: 32,
+            ..Default::default()
+        })
+        .unwrap();
+
+        synth
+            .send_midi2_event(Midi2Event::ControlChange {
+                channel: 0,
+                ctrl: 7,
+                value: 0x8000_0000,
+            })
+            .unwrap();
+        assert_eq!(synth.get_cc(0, 7).unwrap(), 64);
+
+        synth
+            .send_midi2_event(Midi2Event::PitchBend {
+                channel: 0,
+                value: 0x8000_0000,
+            })
+            .unwrap();
+        assert_eq!(synth.get_pitch_bend(0).unwrap(), 0x2000);
+
+        // RPN 0 (pitch bend range), the MIDI 1.0 RPN selection is left untouched
+        synth
+            .send_midi2_event(Midi2Event::RegisteredController {
+                channel: 0,
+                bank: 0,
+                index: 0,
+                value: 12 << 25,
+            })
+            .unwrap();
+        assert_eq!(synth.get_pitch_wheel_sens(0).unwrap(), 12);
+        assert_eq!(synth.get_cc(0, 101).unwrap(), 127);
+        assert_eq!(synth.get_cc(0, 6).unwrap(), 0);
+
+        // MIDI 1.0 control change in a Universal MIDI Packet, group 1
+        synth.send_ump(&[0x21B1_0A20]).unwrap();
+        assert_eq!(synth.get_cc(17, 10).unwrap(), 32);
+    }
+
+    #[test]
+    fn midi2_note_on() {
+        fn render(event: impl FnOnce(&mut Synth)) -> Vec<f32> {
+            let mut synth = sin_synth(Default::default());
+
+            event(&mut synth);
+
+            let mut samples = vec![0f32; 4096];
+            synth.write(samples.as_mut_slice());
+            samples
+        }
+
+        let midi1 = render(|synth| {
+            synth
+                .send_event(MidiEvent::NoteOn {
+                    channel: 0,
+                    key: 62,
+                    vel: 127,
+                })
+                .unwrap()
+        });
+
+        // Full velocity and a Pitch 7.9 attribute matching the key
+        let midi2 = render(|synth| {
+            synth
+                .send_midi2_event(Midi2Event::NoteOn {
+                    channel: 0,
+                    key: 62,
+                    vel: 0xFFFF,
+                    attribute: NoteAttribute::Pitch7_9(62 << 9),
+                })
+                .unwrap()
+        });
+        assert_eq!(midi1, midi2);
+
+        // A pitch attribute a whole tone higher changes the output
+        let detuned = render(|synth| {
+            synth
+                .send_midi2_event(Midi2Event::NoteOn {
+                    channel: 0,
+                    key: 62,
+                    vel: 0xFFFF,
+                    attribute: NoteAttribute::Pitch7_9(64 << 9),
+                })
+                .unwrap()
+        });
+        assert_ne!(midi1, detuned);
+
+        // A per-note controller overrides the channel controller for its key
+        let volume = |synth: &mut Synth, event| {
+            synth.send_midi2_event(event).unwrap();
+            synth
+                .send_midi2_event(Midi2Event::NoteOn {
+                    channel: 0,
+                    key: 62,
+                    vel: 0xFFFF,
+                    attribute: NoteAttribute::None,
+                })
+                .unwrap()
+        };
+        let channel = render(|synth| {
+            volume(
+                synth,
+                Midi2Event::ControlChange {
+                    channel: 0,
+                    ctrl: 7,
+                    value: 0x4000_0000,
+                },
+            )
+        });
+        let note = render(|synth| {
+            volume(
+                synth,
+                Midi2Event::AssignablePerNoteController {
+                    channel: 0,
+                    key: 62,
+                    index: 7,
+                    value: 0x4000_0000,
+                },
+            )
+        });
+        assert_eq!(channel, note);
+        assert_ne!(channel, midi1);
+    }
 }
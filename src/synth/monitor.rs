@@ -0,0 +1,77 @@
+use std::collections::vec_deque::Drain;
+
+use crate::core::{ChannelLevel, OxiError, SynthEvent, VoiceInfo};
+use crate::Synth;
+
+/**
+Voice and channel monitoring
+ */
+impl Synth {
+    /**
+    State of the playing voices, as of the last rendered block
+     */
+    pub fn playing_voices(&self) -> impl Iterator<Item = VoiceInfo<'_>> {
+        self.core.playing_voices()
+    }
+
+    /**
+    Measure the peak and RMS levels of each MIDI channel while rendering (off by default).
+    The voices of a channel are metered before its effects and panning.
+     */
+    pub fn set_channel_meters(&mut self, enabled: bool) {
+        self.core.set_channel_meters(enabled)
+    }
+
+    /**
+    Whether the channel levels are measured
+     */
+    pub fn channel_meters(&self) -> bool {
+        self.core.channel_meters()
+    }
+
+    /**
+    Level of a MIDI channel, zero while the meters are off
+     */
+    pub fn channel_level(&self, chan: u8) -> Result<ChannelLevel, OxiError> {
+        self.core.channel_level(chan as usize)
+    }
+
+    /**
+    Levels of all the MIDI channels, indexed by channel
+     */
+    pub fn channel_levels(&self) -> &[ChannelLevel] {
+        self.core.channel_levels()
+    }
+
+    /**
+    Record up to `capacity` events (none by default) until they are drained,
+    0 to stop recording them. See [`SynthEvent`](crate::SynthEvent) for the events.
+
+    The queue is allocated here: recording the events while rendering or handling
+    MIDI events doesn't allocate, the events past the capacity being dropped.
+     */
+    pub fn set_event_capacity(&mut self, capacity: usize) {
+        self.core.set_event_capacity(capacity)
+    }
+
+    /**
+    Number of events recorded until they are drained
+     */
+    pub fn event_capacity(&self) -> usize {
+        self.core.event_capacity()
+    }
+
+    /**
+    Take the recorded events, oldest first
+     */
+    pub fn drain_events(&mut self) -> Drain<'_, SynthEvent> {
+        self.core.drain_events()
+    }
+
+    /**
+    Number of events dropped since the last drain, the queue being full
+     */
+    pub fn lost_events(&self) -> usize {
+        self.core.lost_events()
+    }
+}
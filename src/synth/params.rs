@@ -2,7 +2,9 @@ use std::sync::Arc;
 
 use crate::{Preset, Synth};
 
-use crate::core::{InterpolationMethod, Settings};
+#[cfg(feature = "parallel")]
+use crate::core::RenderThreads;
+use crate::core::{FilterType, InterpolationMethod, OxiError, Settings, StealPolicy};
 
 /**
 Synthesis parameters
@@ -29,6 +31,56 @@ impl Synth {
         self.core.gain()
     }
 
+    /**
+    Set the time for the voice gains (master gain, volume, pan, sends) and
+    filter to reach a new value, in ms (0-100).
+
+    The changes are ramped over this time to avoid zipper noise,
+    0 applies them at the next block.
+     */
+    pub fn set_ramp_time(&mut self, ramp_time: f32) {
+        self.core.set_ramp_time(ramp_time)
+    }
+
+    /**
+    Get the ramp time of the voice parameters, in ms
+     */
+    pub fn ramp_time(&self) -> f32 {
+        self.core.ramp_time()
+    }
+
+    /**
+    Set the time for the reverb, chorus and built-in effect parameters to
+    reach a new value, in ms (0-100).
+     */
+    pub fn set_effect_ramp_time(&mut self, ramp_time: f32) {
+        self.core.set_effect_ramp_time(ramp_time)
+    }
+
+    /**
+    Get the ramp time of the effect parameters, in ms
+     */
+    pub fn effect_ramp_time(&self) -> f32 {
+        self.core.effect_ramp_time()
+    }
+
+    /**
+    Spread the rendering of the voices over several threads.
+    See [`RenderThreads`](crate::RenderThreads) for the modes.
+     */
+    #[cfg(feature = "parallel")]
+    pub fn set_render_threads(&mut self, threads: RenderThreads) -> Result<(), OxiError> {
+        self.core.set_render_threads(threads)
+    }
+
+    /**
+    Get the threads rendering the voices
+     */
+    #[cfg(feature = "parallel")]
+    pub fn render_threads(&self) -> RenderThreads {
+        self.core.render_threads()
+    }
+
     /**
     Set the polyphony limit
      */
@@ -43,13 +95,61 @@ impl Synth {
         self.core.polyphony()
     }
 
+    /**
+    Select the voice to kill when a new one is needed past the polyphony limit,
+    or past the voice limit of its channel.
+    See [`StealPolicy`](crate::StealPolicy) for the policies.
+     */
+    pub fn set_steal_policy(&mut self, policy: StealPolicy) {
+        self.core.set_steal_policy(policy)
+    }
+
+    /**
+    Get the voice steal policy
+     */
+    pub fn steal_policy(&self) -> StealPolicy {
+        self.core.steal_policy()
+    }
+
+    /**
+    Set the voice priority of a channel (64 by default), the voices of the channels
+    with the lowest priority being stolen first by
+    [`StealPolicy::LowestPriorityChannel`](crate::StealPolicy::LowestPriorityChannel)
+     */
+    pub fn set_channel_voice_priority(&mut self, chan: u8, priority: u8) -> Result<(), OxiError> {
+        self.core
+            .set_channel_voice_priority(chan as usize, priority)
+    }
+
+    /// Voice priority of a channel
+    pub fn channel_voice_priority(&self, chan: u8) -> Result<u8, OxiError> {
+        self.core.channel_voice_priority(chan as usize)
+    }
+
+    /**
+    Limit the number of voices playing at once on a channel, `None` for no limit.
+    Past the limit, a new voice replaces one of the channel chosen by the steal policy.
+     */
+    pub fn set_channel_voice_limit(
+        &mut self,
+        chan: u8,
+        limit: Option<usize>,
+    ) -> Result<(), OxiError> {
+        self.core.set_channel_voice_limit(chan as usize, limit)
+    }
+
+    /// Voice limit of a channel, `None` when it only depends on the polyphony
+    pub fn channel_voice_limit(&self, chan: u8) -> Result<Option<usize>, OxiError> {
+        self.core.channel_voice_limit(chan as usize)
+    }
+
     /**
     Get the internal buffer size. The internal buffer size if not the
     same thing as the buffer size specified in the
     settings. Internally, the synth *always* uses a specific buffer
     size independent of the buffer size used by the audio driver. The
-    internal buffer size is normally 64 samples. The reason why it
-    uses an internal buffer size is to allow audio drivers to call the
+    internal buffer size is `SynthDescriptor::block_size`, 64 samples by default.
+    The reason why it uses an internal buffer size is to allow audio drivers to call the
     synthesizer with a variable buffer length. The internal buffer
     size is useful for client who want to optimize their buffer sizes.
      */
@@ -62,6 +162,24 @@ impl Synth {
         self.core.set_interp_method(chan, interp_method)
     }
 
+    /**
+    Select the filter of the voices started on a channel, overriding the one of
+    the instrument zones, or follow the zones again (`None`).
+    The playing voices keep their filter.
+     */
+    pub fn set_channel_filter(
+        &mut self,
+        chan: u8,
+        filter: Option<FilterType>,
+    ) -> Result<(), OxiError> {
+        self.core.set_channel_filter(chan as usize, filter)
+    }
+
+    /// Filter selected for a channel, `None` when it follows the instrument zones
+    pub fn channel_filter(&self, chan: u8) -> Result<Option<FilterType>, OxiError> {
+        self.core.channel_filter(chan as usize)
+    }
+
     pub fn channel_preset(&self, chan: u8) -> Option<&Arc<Preset>> {
         self.core.channel_preset(chan)
     }
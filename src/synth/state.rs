@@ -0,0 +1,71 @@
+use crate::core::{DspState, OxiError, SynthState};
+use crate::Synth;
+
+/**
+Saving and restoring the synth state
+ */
+impl Synth {
+    /**
+    Save the settings and MIDI state of the synth, to be restored with `restore_state()`,
+    e.g. by a plugin host recalling a project.
+
+    The state refers to the loaded SoundFonts by their
+    [`fingerprint`](crate::SoundFont::fingerprint), without their sample data.
+    With the `serde` feature, the state can be serialized.
+     */
+    pub fn save_state(&self) -> SynthState {
+        self.core.save_state()
+    }
+
+    /**
+    Restore a state saved by `save_state()`.
+
+    The fonts of the state must be loaded first, in any order: the font of each channel
+    is found by its fingerprint, and `OxiError::FontNotLoaded` is returned, without
+    changing the synth, when one is missing.
+
+    The tunings, the polyphony and the reverb engines are checked as well: the
+    reverb instances with an engine other than the built-in ones must be set up
+    beforehand, or `OxiError::ReverbEngineNotSet` is returned.
+
+    The sounding voices are stopped and the reverb and chorus tails cleared.
+    The channels past the ones of the synth are ignored, and reverb and chorus
+    instances are added or removed to match the ones of the state.
+     */
+    pub fn restore_state(&mut self, state: &SynthState) -> Result<(), OxiError> {
+        self.core.restore_state(state)
+    }
+
+    /**
+    Save the complete state of the synth: the state of `save_state()`, along with the
+    running state of the sounding voices and of the effects (delay lines, filters, LFOs...),
+    to checkpoint a render and resume it later with a bit-identical output.
+
+    The state of the custom reverb engines and effects is the one they write in
+    [`ReverbEngine::save_state`](crate::reverb::ReverbEngine::save_state) and
+    [`Effect::save_state`](crate::effects::Effect::save_state).
+    Voices of a SoundFont removed from the synth are not saved.
+     */
+    pub fn save_dsp_state(&self) -> DspState {
+        self.core.save_dsp_state()
+    }
+
+    /**
+    Restore a state saved by `save_dsp_state()`: the next frames are the ones
+    the saved synth rendered after the state was saved.
+
+    The synth must be set up like the saved one: same sample rate and block size,
+    fonts loaded (in any order), same reverb engines and user effects. The plate
+    reverb parameters are part of the state, the parameters of the other engines
+    and of the effects are not.
+
+    Without changing the synth, `OxiError::FontNotLoaded` is returned when a font is
+    missing, and `OxiError::DspStateMismatch` for another sample rate or block size.
+    When the effects or voices don't match the state, `OxiError::DspStateMismatch`
+    is returned after restoring the settings and MIDI state, with the voices
+    stopped and the effects cleared.
+     */
+    pub fn restore_dsp_state(&mut self, state: &DspState) -> Result<(), OxiError> {
+        self.core.restore_dsp_state(state)
+    }
+}
@@ -29,22 +29,14 @@ impl IsSamples for (&mut [i16], &mut [i16]) {
 impl IsSamples for &mut [f32] {
     /// Write samples interleaved
     fn write_samples(self, synth: &mut Synth) {
-        let len = self.len() / 2;
-        synth.write_cb(len, 2, |id, l, r| {
-            self[id] = l;
-            self[id + 1] = r;
-        });
+        synth.write_interleaved(self);
     }
 }
 
 impl IsSamples for (&mut [f32], &mut [f32]) {
     /// Write samples non-interleaved
     fn write_samples(self, synth: &mut Synth) {
-        let len = self.0.len().min(self.1.len());
-        synth.write_cb(len, 1, |id, l, r| {
-            self.0[id] = l;
-            self.1[id] = r;
-        });
+        synth.write_planar(self.0, self.1);
     }
 }
 
@@ -74,6 +66,37 @@ impl Synth {
         self.core.read_next()
     }
 
+    /**
+    Write the main output into separate left and right buffers,
+    copying whole rendered blocks at once.
+     */
+    pub fn write_planar(&mut self, left: &mut [f32], right: &mut [f32]) {
+        self.core.write_planar(left, right)
+    }
+
+    /**
+    Write the main output as interleaved stereo frames.
+     */
+    pub fn write_interleaved(&mut self, out: &mut [f32]) {
+        self.core.write_interleaved(out)
+    }
+
+    /**
+    Write all the outputs (see [`SynthDescriptor::audio_channels`](crate::SynthDescriptor::audio_channels)
+    and [`SynthDescriptor::audio_groups`](crate::SynthDescriptor::audio_groups))
+    as interleaved frames of [`Synth::output_channels`] channels.
+     */
+    pub fn write_channels(&mut self, out: &mut [f32]) {
+        self.core.write_channels(out)
+    }
+
+    /**
+    Number of interleaved channels written by [`Synth::write_channels`].
+     */
+    pub fn output_channels(&self) -> usize {
+        self.core.output_channels()
+    }
+
     pub fn write_cb<F: FnMut(usize, f32, f32)>(&mut self, len: usize, incr: usize, cb: F) {
         self.core.write(len, incr, cb)
     }
@@ -0,0 +1,104 @@
+//! Timings, run with `cargo test --release --test bench -- --ignored --nocapture --test-threads=1`
+
+use std::time::{Duration, Instant};
+
+use oxisynth::{MidiEvent, SoundFont, Synth};
+
+const FONT: &str = "./testdata/Boomwhacker.sf2";
+
+/// Average duration of `f` over `iters` runs
+fn bench<F: FnMut()>(name: &str, iters: u32, mut f: F) -> Duration {
+    // Warm up
+    f();
+
+    let start = Instant::now();
+    for _ in 0..iters {
+        f();
+    }
+    let time = start.elapsed() / iters;
+    println!("{:<24} {:>12.3?}/iter", name, time);
+    time
+}
+
+#[test]
+#[ignore]
+fn only_parse() {
+    bench("only_parse", 20, || {
+        let mut file = std::fs::File::open(FONT).unwrap();
+        let data = soundfont::data::SFData::load(&mut file).unwrap();
+        let _sf2 = soundfont::SoundFont2::from_data(data).sort_presets();
+    });
+}
+
+#[test]
+#[ignore]
+fn full() {
+    bench("full", 20, || {
+        let mut file = std::fs::File::open(FONT).unwrap();
+        let _font = SoundFont::load(&mut file).unwrap();
+    });
+}
+
+fn playing_synth() -> Synth {
+    let mut synth = Synth::default();
+    let mut file = std::fs::File::open(FONT).unwrap();
+    synth.add_font(SoundFont::load(&mut file).unwrap(), true);
+    for key in 40..56 {
+        synth
+            .send_event(MidiEvent::NoteOn {
+                channel: key % 4,
+                key,
+                vel: 100,
+            })
+            .unwrap();
+    }
+    synth
+}
+
+/// One second of stereo at 44.1kHz, in buffers of 512 frames
+#[test]
+#[ignore]
+fn write() {
+    const FRAMES: usize = 512;
+    const BUFFERS: usize = 44100 / FRAMES;
+
+    let mut synth = playing_synth();
+    let mut out = vec![0f32; FRAMES * 2];
+    let per_frame = bench("write_cb interleaved", 20, || {
+        for _ in 0..BUFFERS {
+            synth.write_cb(FRAMES, 2, |id, l, r| {
+                out[id] = l;
+                out[id + 1] = r;
+            });
+        }
+    });
+
+    let mut synth = playing_synth();
+    let interleaved = bench("write_interleaved", 20, || {
+        for _ in 0..BUFFERS {
+            synth.write_interleaved(&mut out);
+        }
+    });
+
+    let mut synth = playing_synth();
+    let (mut left, mut right) = (vec![0f32; FRAMES], vec![0f32; FRAMES]);
+    let planar = bench("write_planar", 20, || {
+        for _ in 0..BUFFERS {
+            synth.write_planar(&mut left, &mut right);
+        }
+    });
+
+    let mut synth = playing_synth();
+    let mut frames = vec![0f32; FRAMES * synth.output_channels()];
+    bench("write_channels", 20, || {
+        for _ in 0..BUFFERS {
+            synth.write_channels(&mut frames);
+        }
+    });
+
+    println!(
+        "per frame / blocks: interleaved {:.2}x, planar {:.2}x",
+        per_frame.as_secs_f64() / interleaved.as_secs_f64(),
+        per_frame.as_secs_f64() / planar.as_secs_f64(),
+    );
+}